@@ -0,0 +1,818 @@
+//! Where source content is kept.
+//!
+//! [`StoreManager`](crate::service::StoreManager) keeps links and sources in
+//! SQLite and hands the stored bytes of each source to a [`BlobBackend`],
+//! addressed by source id. [`FsBackend`] keeps them under `linadata/`;
+//! with the `s3` feature, [`S3Backend`] keeps them in an S3-compatible
+//! bucket. [`TieredBackend`] combines two backends, moving content nobody
+//! reads to the cheaper one.
+
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    future::Future,
+    io::{self, Read},
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::service::GcReport;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Future returned by [`BlobBackend`] methods.
+pub type BlobFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send + 'a>>;
+
+/// Storage for the bytes of each source, addressed by source id.
+///
+/// Reads of a missing source fail with [`io::ErrorKind::NotFound`].
+pub trait BlobBackend: fmt::Debug + Send + Sync {
+    /// The stored bytes of a source.
+    fn get<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, Vec<u8>>;
+
+    /// Store `bytes` for a source, replacing what it held. Readers see either
+    /// the old or the new bytes, never a mix.
+    fn put<'a>(&'a self, source_id: &'a str, bytes: &'a [u8]) -> BlobFuture<'a, ()>;
+
+    /// Remove a source's bytes; removing a missing source succeeds.
+    fn delete<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, ()>;
+
+    fn exists<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, bool>;
+
+    /// Open a source for sequential reading. The reader stays valid after
+    /// the source is deleted.
+    fn open<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, Box<dyn Read + Send>> {
+        Box::pin(async move {
+            let bytes = self.get(source_id).await?;
+            Ok(Box::new(io::Cursor::new(bytes)) as Box<dyn Read + Send>)
+        })
+    }
+
+    /// Remove what interrupted writes and deletes left behind and the
+    /// content of sources not in `known_ids`.
+    fn reconcile<'a>(&'a self, known_ids: &'a HashSet<String>) -> BlobFuture<'a, GcReport>;
+
+    /// Move the content of sources among `source_ids` nobody has accessed
+    /// lately to a cheaper tier, returning how many moved. Backends with a
+    /// single tier move nothing.
+    fn demote_idle<'a>(&'a self, source_ids: &'a [String]) -> BlobFuture<'a, u64> {
+        let _ = source_ids;
+        Box::pin(async { Ok(0) })
+    }
+}
+
+/// Whether `err` reports a missing source.
+pub(crate) fn is_not_found(err: &BoxError) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+/// `<id[0..4]>/<id[4..6]>/<id>`, so no directory or prefix grows too large.
+fn source_key(source_id: &str) -> String {
+    let (outer, inner) = shards(source_id);
+    format!("{}/{}/{}", outer, inner, source_id)
+}
+
+/// The two directory levels a source is kept under. Ids the store hands
+/// out are 20 ASCII characters; any other id shares the `_` shard rather
+/// than being cut mid-character.
+fn shards(source_id: &str) -> (&str, &str) {
+    (
+        source_id.get(..4).unwrap_or("_"),
+        source_id.get(4..6).unwrap_or("_"),
+    )
+}
+
+/// Sources as files under the store's `linadata/` directory.
+#[derive(Debug, Clone)]
+pub struct FsBackend {
+    root: PathBuf,
+}
+
+impl FsBackend {
+    /// Keep sources under `root`, usually `<store root>/linadata`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FsBackend { root: root.into() }
+    }
+
+    pub(crate) fn source_dir(&self, source_id: &str) -> PathBuf {
+        let (outer, inner) = shards(source_id);
+        self.root.join(outer).join(inner)
+    }
+
+    pub(crate) fn source_path(&self, source_id: &str) -> PathBuf {
+        self.root.join(source_key(source_id))
+    }
+}
+
+impl BlobBackend for FsBackend {
+    fn get<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, Vec<u8>> {
+        Box::pin(async move { Ok(fs::read(self.source_path(source_id)).await?) })
+    }
+
+    fn put<'a>(&'a self, source_id: &'a str, bytes: &'a [u8]) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            let source_dir = self.source_dir(source_id);
+            fs::create_dir_all(&source_dir).await?;
+
+            let target_path = source_dir.join(source_id);
+            let tmp_path = source_dir.join(format!("{}.tmp-{}", source_id, Uuid::new_v4()));
+
+            let mut f = fs::File::create(&tmp_path).await?;
+            f.write_all(bytes).await?;
+            f.sync_all().await?;
+            drop(f);
+
+            if let Err(err) = fs::rename(&tmp_path, &target_path).await {
+                let _ = fs::remove_file(&tmp_path).await;
+                return Err(Box::new(err) as BoxError);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn delete<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            match fs::remove_file(self.source_path(source_id)).await {
+                Ok(()) => Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(err) => Err(Box::new(err) as BoxError),
+            }
+        })
+    }
+
+    fn exists<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, bool> {
+        Box::pin(async move { Ok(fs::try_exists(self.source_path(source_id)).await?) })
+    }
+
+    fn open<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, Box<dyn Read + Send>> {
+        // The open handle keeps the content readable once the file is unlinked
+        Box::pin(async move {
+            let file = fs::File::open(self.source_path(source_id)).await?;
+            Ok(Box::new(file.into_std().await) as Box<dyn Read + Send>)
+        })
+    }
+
+    /// Reconcile the on-disk source store with the DB after a (potentially
+    /// crash-interrupted) restart:
+    /// - delete files left over from in-flight writes (`*.tmp-*`),
+    /// - delete stale tombstones from interrupted deletes (`*.deleting`),
+    /// - delete payload files whose source row no longer exists.
+    ///
+    /// The DB is treated as the source of truth.
+    fn reconcile<'a>(&'a self, known_ids: &'a HashSet<String>) -> BlobFuture<'a, GcReport> {
+        Box::pin(async move {
+            let mut removed_tmp = 0u64;
+            let mut removed_tombstone = 0u64;
+            let mut removed_orphan = 0u64;
+
+            // The expected layout is linadata/<id[0..4]>/<id[4..6]>/<id>. Only
+            // descend two levels so we don't accidentally chew on meta.db / logs.
+            let mut top = match fs::read_dir(&self.root).await {
+                Ok(rd) => rd,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(GcReport::default()),
+                Err(err) => return Err(Box::new(err) as BoxError),
+            };
+
+            while let Some(top_entry) = top.next_entry().await? {
+                let top_path = top_entry.path();
+                let top_meta = match top_entry.metadata().await {
+                    Ok(m) => m,
+                    Err(_) => continue,
+                };
+                if !top_meta.is_dir() {
+                    continue;
+                }
+                // Source-id prefix dirs are always 4 chars; skip anything else (e.g. "logs").
+                if top_path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|s| s.len() != 4)
+                    .unwrap_or(true)
+                {
+                    continue;
+                }
+
+                let mut mid = match fs::read_dir(&top_path).await {
+                    Ok(rd) => rd,
+                    Err(_) => continue,
+                };
+                while let Some(mid_entry) = mid.next_entry().await? {
+                    let mid_path = mid_entry.path();
+                    let mid_meta = match mid_entry.metadata().await {
+                        Ok(m) => m,
+                        Err(_) => continue,
+                    };
+                    if !mid_meta.is_dir() {
+                        continue;
+                    }
+                    if mid_path
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .map(|s| s.len() != 2)
+                        .unwrap_or(true)
+                    {
+                        continue;
+                    }
+
+                    let mut leaves = match fs::read_dir(&mid_path).await {
+                        Ok(rd) => rd,
+                        Err(_) => continue,
+                    };
+                    while let Some(leaf) = leaves.next_entry().await? {
+                        let leaf_path = leaf.path();
+                        let leaf_meta = match leaf.metadata().await {
+                            Ok(m) => m,
+                            Err(_) => continue,
+                        };
+                        if !leaf_meta.is_file() {
+                            continue;
+                        }
+                        let name = match leaf_path.file_name().and_then(|n| n.to_str()) {
+                            Some(s) => s.to_string(),
+                            None => continue,
+                        };
+
+                        if name.contains(".tmp-") {
+                            if fs::remove_file(&leaf_path).await.is_ok() {
+                                removed_tmp += 1;
+                            }
+                            continue;
+                        }
+                        if let Some(stem) = name.strip_suffix(".deleting") {
+                            // If the DB still has the source, restore the file;
+                            // otherwise treat the tombstone as garbage.
+                            if known_ids.contains(stem) {
+                                let restored = mid_path.join(stem);
+                                let _ = fs::rename(&leaf_path, &restored).await;
+                            } else if fs::remove_file(&leaf_path).await.is_ok() {
+                                removed_tombstone += 1;
+                            }
+                            continue;
+                        }
+
+                        if !known_ids.contains(&name) && fs::remove_file(&leaf_path).await.is_ok() {
+                            removed_orphan += 1;
+                        }
+                    }
+                }
+            }
+
+            Ok(GcReport {
+                removed_tmp,
+                removed_tombstones: removed_tombstone,
+                removed_orphans: removed_orphan,
+            })
+        })
+    }
+}
+
+type RecallHook = Box<dyn Fn(&str) + Send + Sync>;
+
+/// A fast `hot` tier in front of a cheap `cold` one.
+///
+/// Writes go to the hot tier. [`demote_idle`](BlobBackend::demote_idle)
+/// moves sources not accessed for `cold_after` to the cold tier, and reading
+/// a cold source recalls it to the hot tier first. Each source is held by
+/// exactly one tier. Access times are kept in memory; a source not accessed
+/// since the backend was created counts as accessed at creation.
+pub struct TieredBackend {
+    hot: Arc<dyn BlobBackend>,
+    cold: Arc<dyn BlobBackend>,
+    cold_after: Duration,
+    created: Instant,
+    last_access: Mutex<HashMap<String, Instant>>,
+    on_recall: Option<RecallHook>,
+}
+
+impl fmt::Debug for TieredBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TieredBackend")
+            .field("hot", &self.hot)
+            .field("cold", &self.cold)
+            .field("cold_after", &self.cold_after)
+            .finish()
+    }
+}
+
+impl TieredBackend {
+    pub fn new(hot: Arc<dyn BlobBackend>, cold: Arc<dyn BlobBackend>, cold_after: Duration) -> Self {
+        TieredBackend {
+            hot,
+            cold,
+            cold_after,
+            created: Instant::now(),
+            last_access: Mutex::new(HashMap::new()),
+            on_recall: None,
+        }
+    }
+
+    /// Call `on_recall` with the source id whenever a cold source is read
+    /// back into the hot tier.
+    pub fn set_on_recall(&mut self, on_recall: impl Fn(&str) + Send + Sync + 'static) {
+        self.on_recall = Some(Box::new(on_recall));
+    }
+
+    fn touch(&self, source_id: &str) {
+        if let Ok(mut last_access) = self.last_access.lock() {
+            last_access.insert(source_id.to_string(), Instant::now());
+        }
+    }
+
+    fn is_idle(&self, source_id: &str) -> bool {
+        let last = self
+            .last_access
+            .lock()
+            .ok()
+            .and_then(|last_access| last_access.get(source_id).copied())
+            .unwrap_or(self.created);
+        last.elapsed() >= self.cold_after
+    }
+
+    /// Bring a cold source back to the hot tier and return its bytes.
+    async fn recall(&self, source_id: &str) -> Result<Vec<u8>, BoxError> {
+        let bytes = self.cold.get(source_id).await?;
+        self.hot.put(source_id, &bytes).await?;
+        // A copy left behind is overwritten by the next demotion
+        let _ = self.cold.delete(source_id).await;
+        if let Some(on_recall) = &self.on_recall {
+            on_recall(source_id);
+        }
+        Ok(bytes)
+    }
+}
+
+impl BlobBackend for TieredBackend {
+    fn get<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            self.touch(source_id);
+            match self.hot.get(source_id).await {
+                Err(err) if is_not_found(&err) => self.recall(source_id).await,
+                result => result,
+            }
+        })
+    }
+
+    fn put<'a>(&'a self, source_id: &'a str, bytes: &'a [u8]) -> BlobFuture<'a, ()> {
+        // The store reads a source before replacing it, which recalls it, so
+        // the cold tier never holds an outdated copy.
+        Box::pin(async move {
+            self.touch(source_id);
+            self.hot.put(source_id, bytes).await
+        })
+    }
+
+    fn delete<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, ()> {
+        Box::pin(async move {
+            if let Ok(mut last_access) = self.last_access.lock() {
+                last_access.remove(source_id);
+            }
+            self.hot.delete(source_id).await?;
+            self.cold.delete(source_id).await
+        })
+    }
+
+    fn exists<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, bool> {
+        Box::pin(async move {
+            Ok(self.hot.exists(source_id).await? || self.cold.exists(source_id).await?)
+        })
+    }
+
+    fn open<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, Box<dyn Read + Send>> {
+        Box::pin(async move {
+            self.touch(source_id);
+            match self.hot.open(source_id).await {
+                Err(err) if is_not_found(&err) => {
+                    let bytes = self.recall(source_id).await?;
+                    Ok(Box::new(io::Cursor::new(bytes)) as Box<dyn Read + Send>)
+                }
+                result => result,
+            }
+        })
+    }
+
+    fn reconcile<'a>(&'a self, known_ids: &'a HashSet<String>) -> BlobFuture<'a, GcReport> {
+        Box::pin(async move {
+            let hot = self.hot.reconcile(known_ids).await?;
+            let cold = self.cold.reconcile(known_ids).await?;
+            Ok(GcReport {
+                removed_tmp: hot.removed_tmp + cold.removed_tmp,
+                removed_tombstones: hot.removed_tombstones + cold.removed_tombstones,
+                removed_orphans: hot.removed_orphans + cold.removed_orphans,
+            })
+        })
+    }
+
+    fn demote_idle<'a>(&'a self, source_ids: &'a [String]) -> BlobFuture<'a, u64> {
+        Box::pin(async move {
+            let mut moved = 0;
+            for source_id in source_ids {
+                if !self.is_idle(source_id) || !self.hot.exists(source_id).await? {
+                    continue;
+                }
+                let bytes = self.hot.get(source_id).await?;
+                self.cold.put(source_id, &bytes).await?;
+                self.hot.delete(source_id).await?;
+                if let Ok(mut last_access) = self.last_access.lock() {
+                    last_access.remove(source_id);
+                }
+                moved += 1;
+            }
+            Ok(moved)
+        })
+    }
+}
+
+#[cfg(feature = "s3")]
+pub use s3::{S3Backend, S3Settings};
+
+#[cfg(feature = "s3")]
+mod s3 {
+    use std::{collections::HashSet, error::Error, io, time::Duration};
+
+    use chrono::Utc;
+    use hmac::{Hmac, Mac};
+    use reqwest::{Client, Method, StatusCode, Url};
+    use sha2::{Digest, Sha256};
+
+    use super::{BlobBackend, BlobFuture, source_key};
+    use crate::service::GcReport;
+
+    type BoxError = Box<dyn Error + Send + Sync>;
+
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+    /// Keys asked for per `ListObjectsV2` page.
+    const LIST_PAGE_SIZE: u32 = 1000;
+
+    /// Where and as whom to reach the bucket.
+    #[derive(Debug, Clone)]
+    pub struct S3Settings {
+        /// Base URL of the service, e.g. `https://s3.us-east-1.amazonaws.com`
+        /// or `http://localhost:9000`. Buckets are addressed path-style.
+        pub endpoint: String,
+        pub bucket: String,
+        pub region: String,
+        pub access_key: String,
+        pub secret_key: String,
+        /// Prepended to every object key, e.g. `linastore/`.
+        pub prefix: String,
+    }
+
+    /// Sources as objects in an S3-compatible bucket, signed with AWS
+    /// Signature Version 4.
+    pub struct S3Backend {
+        client: Client,
+        /// `<endpoint>/<bucket>/`
+        base: Url,
+        settings: S3Settings,
+    }
+
+    impl std::fmt::Debug for S3Backend {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("S3Backend")
+                .field("base", &self.base.as_str())
+                .field("prefix", &self.settings.prefix)
+                .finish()
+        }
+    }
+
+    impl S3Backend {
+        pub fn new(settings: S3Settings) -> Result<Self, BoxError> {
+            let mut base = Url::parse(&settings.endpoint)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid S3 endpoint: {}", e)))?;
+            if base.host_str().is_none() || !matches!(base.scheme(), "http" | "https") {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "S3 endpoint must be an http:// or https:// URL",
+                )));
+            }
+            if settings.bucket.is_empty() {
+                return Err(Box::new(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "S3 bucket is not set",
+                )));
+            }
+            let path = format!(
+                "{}/{}/",
+                base.path().trim_end_matches('/'),
+                uri_encode(&settings.bucket, false)
+            );
+            base.set_path(&path);
+            base.set_query(None);
+
+            let client = Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .build()
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            Ok(S3Backend {
+                client,
+                base,
+                settings,
+            })
+        }
+
+        fn object_key(&self, source_id: &str) -> String {
+            format!("{}{}", self.settings.prefix, source_key(source_id))
+        }
+
+        /// Send a signed request for `key` (empty for the bucket itself) with
+        /// the already canonical `query`.
+        async fn send(
+            &self,
+            method: Method,
+            key: &str,
+            query: &str,
+            body: Vec<u8>,
+        ) -> Result<reqwest::Response, BoxError> {
+            let path = format!("{}{}", self.base.path(), uri_encode(key, false));
+            let mut url = self.base.clone();
+            url.set_path(&path);
+            url.set_query((!query.is_empty()).then_some(query));
+
+            let host = match url.port() {
+                Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+                None => url.host_str().unwrap_or_default().to_string(),
+            };
+            let now = Utc::now();
+            let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+            let payload_hash = hex::encode(Sha256::digest(&body));
+            let authorization = self.authorization(
+                method.as_str(),
+                url.path(),
+                query,
+                &host,
+                &amz_date,
+                &payload_hash,
+            );
+
+            let response = self
+                .client
+                .request(method, url)
+                .header("x-amz-date", amz_date)
+                .header("x-amz-content-sha256", payload_hash)
+                .header("authorization", authorization)
+                .body(body)
+                .send()
+                .await
+                .map_err(|e| io::Error::other(format!("S3 request failed: {}", e)))?;
+            Ok(response)
+        }
+
+        fn authorization(
+            &self,
+            method: &str,
+            path: &str,
+            query: &str,
+            host: &str,
+            amz_date: &str,
+            payload_hash: &str,
+        ) -> String {
+            let date = &amz_date[..8];
+            let scope = format!("{}/{}/s3/aws4_request", date, self.settings.region);
+            let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+            let canonical_request = format!(
+                "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+                method, path, query, host, payload_hash, amz_date, signed_headers, payload_hash
+            );
+            let string_to_sign = format!(
+                "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                amz_date,
+                scope,
+                hex::encode(Sha256::digest(canonical_request.as_bytes()))
+            );
+            let key = signing_key(&self.settings.secret_key, date, &self.settings.region, "s3");
+            let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.settings.access_key, scope, signed_headers, signature
+            )
+        }
+
+        /// Every object key under the prefix, page by page.
+        async fn list_keys(&self) -> Result<Vec<String>, BoxError> {
+            let mut keys = Vec::new();
+            let mut continuation: Option<String> = None;
+            loop {
+                // Canonical query: parameters sorted by name
+                let mut query = String::new();
+                if let Some(token) = &continuation {
+                    query.push_str(&format!("continuation-token={}&", uri_encode(token, true)));
+                }
+                query.push_str(&format!(
+                    "list-type=2&max-keys={}&prefix={}",
+                    LIST_PAGE_SIZE,
+                    uri_encode(&self.settings.prefix, true)
+                ));
+
+                let response = self.send(Method::GET, "", &query, Vec::new()).await?;
+                let status = response.status();
+                let body = response
+                    .text()
+                    .await
+                    .map_err(|e| io::Error::other(format!("S3 request failed: {}", e)))?;
+                if !status.is_success() {
+                    return Err(status_error(status, "list"));
+                }
+
+                keys.extend(xml_values(&body, "Key"));
+                let truncated = xml_values(&body, "IsTruncated").first().is_some_and(|v| v == "true");
+                continuation = xml_values(&body, "NextContinuationToken").into_iter().next();
+                if !truncated || continuation.is_none() {
+                    return Ok(keys);
+                }
+            }
+        }
+    }
+
+    impl BlobBackend for S3Backend {
+        fn get<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, Vec<u8>> {
+            Box::pin(async move {
+                let response = self
+                    .send(Method::GET, &self.object_key(source_id), "", Vec::new())
+                    .await?;
+                let status = response.status();
+                if !status.is_success() {
+                    return Err(status_error(status, source_id));
+                }
+                let bytes = response
+                    .bytes()
+                    .await
+                    .map_err(|e| io::Error::other(format!("S3 request failed: {}", e)))?;
+                Ok(bytes.to_vec())
+            })
+        }
+
+        fn put<'a>(&'a self, source_id: &'a str, bytes: &'a [u8]) -> BlobFuture<'a, ()> {
+            Box::pin(async move {
+                let response = self
+                    .send(Method::PUT, &self.object_key(source_id), "", bytes.to_vec())
+                    .await?;
+                let status = response.status();
+                if !status.is_success() {
+                    return Err(status_error(status, source_id));
+                }
+                Ok(())
+            })
+        }
+
+        fn delete<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, ()> {
+            Box::pin(async move {
+                let response = self
+                    .send(Method::DELETE, &self.object_key(source_id), "", Vec::new())
+                    .await?;
+                let status = response.status();
+                if !status.is_success() && status != StatusCode::NOT_FOUND {
+                    return Err(status_error(status, source_id));
+                }
+                Ok(())
+            })
+        }
+
+        fn exists<'a>(&'a self, source_id: &'a str) -> BlobFuture<'a, bool> {
+            Box::pin(async move {
+                let response = self
+                    .send(Method::HEAD, &self.object_key(source_id), "", Vec::new())
+                    .await?;
+                match response.status() {
+                    status if status.is_success() => Ok(true),
+                    StatusCode::NOT_FOUND => Ok(false),
+                    status => Err(status_error(status, source_id)),
+                }
+            })
+        }
+
+        /// Delete objects under the prefix no source refers to. Puts are
+        /// atomic, so there are no temp objects or tombstones to clean up.
+        fn reconcile<'a>(&'a self, known_ids: &'a HashSet<String>) -> BlobFuture<'a, GcReport> {
+            Box::pin(async move {
+                let mut report = GcReport::default();
+                for key in self.list_keys().await? {
+                    let Some(rest) = key.strip_prefix(&self.settings.prefix) else {
+                        continue;
+                    };
+                    // Leave alone what does not follow the source layout
+                    let Some(source_id) = rest.rsplit('/').next() else {
+                        continue;
+                    };
+                    if source_id.len() < 6
+                        || !source_id.bytes().all(|b| b.is_ascii_alphanumeric())
+                        || rest != source_key(source_id)
+                        || known_ids.contains(source_id)
+                    {
+                        continue;
+                    }
+                    if self.delete(source_id).await.is_ok() {
+                        report.removed_orphans += 1;
+                    }
+                }
+                Ok(report)
+            })
+        }
+    }
+
+    fn status_error(status: StatusCode, what: &str) -> BoxError {
+        let kind = match status {
+            StatusCode::NOT_FOUND => io::ErrorKind::NotFound,
+            StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED => io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::Other,
+        };
+        Box::new(io::Error::new(kind, format!("S3 answered {} for {}", status, what)))
+    }
+
+    fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Key that signs requests for one day, region and service.
+    fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+        let k_date = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+        let k_region = hmac_sha256(&k_date, region.as_bytes());
+        let k_service = hmac_sha256(&k_region, service.as_bytes());
+        hmac_sha256(&k_service, b"aws4_request")
+    }
+
+    /// Percent-encode all but unreserved characters, and `/` unless
+    /// `encode_slash`, as SigV4 canonicalization requires.
+    fn uri_encode(raw: &str, encode_slash: bool) -> String {
+        let mut out = String::with_capacity(raw.len());
+        for b in raw.bytes() {
+            match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    out.push(b as char)
+                }
+                b'/' if !encode_slash => out.push('/'),
+                _ => out.push_str(&format!("%{:02X}", b)),
+            }
+        }
+        out
+    }
+
+    /// Text of every `<tag>` element in `xml`, with entities decoded.
+    fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+        let open = format!("<{}>", tag);
+        let close = format!("</{}>", tag);
+        let mut values = Vec::new();
+        let mut rest = xml;
+        while let Some(start) = rest.find(&open) {
+            rest = &rest[start + open.len()..];
+            let Some(end) = rest.find(&close) else {
+                break;
+            };
+            values.push(
+                rest[..end]
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&"),
+            );
+            rest = &rest[end + close.len()..];
+        }
+        values
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_signing_key() {
+            // Example from the AWS Signature Version 4 documentation
+            let key = signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam",
+            );
+            assert_eq!(
+                hex::encode(key),
+                "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+            );
+        }
+
+        #[test]
+        fn test_uri_encode() {
+            assert_eq!(uri_encode("lina/2025/ab c", false), "lina/2025/ab%20c");
+            assert_eq!(uri_encode("lina/", true), "lina%2F");
+        }
+
+        #[test]
+        fn test_xml_values() {
+            let xml = "<ListBucketResult><IsTruncated>false</IsTruncated>\
+                       <Contents><Key>a/b&amp;c</Key></Contents>\
+                       <Contents><Key>d</Key></Contents></ListBucketResult>";
+            assert_eq!(xml_values(xml, "Key"), vec!["a/b&c".to_string(), "d".to_string()]);
+            assert_eq!(xml_values(xml, "IsTruncated"), vec!["false".to_string()]);
+        }
+    }
+}
@@ -1,8 +1,12 @@
 use anyhow::{Context, Result};
-use sqlx::{Pool, Sqlite, Row};
-use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous};
+use sqlx::{Pool, Sqlite, Row, Transaction};
+use sqlx::pool::PoolConnection;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqliteSynchronous};
+use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
 
 const SQL_INIT: &str = r#"
 CREATE TABLE IF NOT EXISTS link (
@@ -36,6 +40,17 @@ CREATE TABLE IF NOT EXISTS dir (
 CREATE INDEX IF NOT EXISTS dir_parent_idx ON dir (parent);
 
 CREATE INDEX IF NOT EXISTS source_size_idx ON source (size);
+
+CREATE TABLE IF NOT EXISTS version (
+    name TEXT NOT NULL,
+    n INTEGER NOT NULL,
+    source_id TEXT NOT NULL,
+    create_at TEXT NOT NULL,
+    PRIMARY KEY (name, n),
+    FOREIGN KEY (source_id) REFERENCES source (id) ON DELETE RESTRICT
+);
+
+CREATE INDEX IF NOT EXISTS version_source_idx ON version (source_id);
 "#;
 
 // Core data models
@@ -61,6 +76,15 @@ pub struct Source {
     pub update_at: String,
 }
 
+/// Content a link held before it was overwritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub name: String,
+    pub n: u64,
+    pub source_id: String,
+    pub create_at: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct DirEntry {
     pub path: String,
@@ -69,9 +93,46 @@ pub struct DirEntry {
 }
 
 // DAO struct for database operations
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub struct Dao {
     pool: Pool<Sqlite>,
+    // Set on handles from `begin`: every statement runs inside this transaction
+    tx: Option<Arc<Mutex<Transaction<'static, Sqlite>>>>,
+}
+
+impl std::fmt::Debug for Dao {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dao")
+            .field("pool", &self.pool)
+            .field("in_transaction", &self.tx.is_some())
+            .finish()
+    }
+}
+
+/// The connection a statement runs on.
+enum DaoConn<'a> {
+    Pooled(PoolConnection<Sqlite>),
+    Tx(MutexGuard<'a, Transaction<'static, Sqlite>>),
+}
+
+impl Deref for DaoConn<'_> {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        match self {
+            DaoConn::Pooled(conn) => conn,
+            DaoConn::Tx(tx) => tx,
+        }
+    }
+}
+
+impl DerefMut for DaoConn<'_> {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        match self {
+            DaoConn::Pooled(conn) => conn,
+            DaoConn::Tx(tx) => tx,
+        }
+    }
 }
 
 // Initialization and schema management.
@@ -99,7 +160,7 @@ impl Dao {
             .await
             .context("Failed to connect to database")?;
 
-        let dao = Self { pool };
+        let dao = Self { pool, tx: None };
 
         // Initialize schema
         dao.init_schema().await?;
@@ -107,6 +168,24 @@ impl Dao {
         Ok(dao)
     }
 
+    /// Open an existing database without writing to it, for readers that
+    /// run beside the store's owner: the schema is left as is, and every
+    /// statement that would write fails.
+    pub async fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let db_url = format!("sqlite://{}", path.as_ref().display());
+        let options = SqliteConnectOptions::from_str(&db_url)
+            .context("Failed to parse SQLite connection URL")?
+            .read_only(true)
+            .foreign_keys(true)
+            .busy_timeout(std::time::Duration::from_secs(5));
+
+        let pool = sqlx::SqlitePool::connect_with(options)
+            .await
+            .context("Failed to connect to database")?;
+
+        Ok(Self { pool, tx: None })
+    }
+
     async fn init_schema(&self) -> Result<()> {
         sqlx::query(SQL_INIT)
             .execute(&self.pool)
@@ -125,6 +204,40 @@ impl Dao {
     }
 }
 
+// Transactions.
+impl Dao {
+    /// A handle whose statements all run in one transaction until
+    /// [`commit`](Self::commit); dropping it uncommitted rolls back.
+    pub async fn begin(&self) -> Result<Dao> {
+        let tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        Ok(Self {
+            pool: self.pool.clone(),
+            tx: Some(Arc::new(Mutex::new(tx))),
+        })
+    }
+
+    /// Commit the transaction of a handle from [`begin`](Self::begin).
+    /// Fails if clones of the handle are still alive.
+    pub async fn commit(self) -> Result<()> {
+        let Some(tx) = self.tx else {
+            return Ok(());
+        };
+        let tx = Arc::try_unwrap(tx)
+            .map_err(|_| anyhow::anyhow!("Transaction is still in use"))?
+            .into_inner();
+        tx.commit().await.context("Failed to commit transaction")
+    }
+
+    async fn conn(&self) -> Result<DaoConn<'_>> {
+        match &self.tx {
+            Some(tx) => Ok(DaoConn::Tx(tx.lock().await)),
+            None => Ok(DaoConn::Pooled(
+                self.pool.acquire().await.context("Failed to acquire connection")?,
+            )),
+        }
+    }
+}
+
 // Link CRUD operations.
 impl Dao {
     pub async fn insert_link_with_id(
@@ -135,6 +248,7 @@ impl Dao {
         source_id: &str,
         mode: u32,
     ) -> Result<()> {
+        let mut conn = self.conn().await?;
         sqlx::query(
             "INSERT INTO link (id, name, ext, source_id, mode) VALUES (?1, ?2, ?3, ?4, ?5)",
         )
@@ -143,23 +257,24 @@ impl Dao {
         .bind(ext)
         .bind(source_id)
         .bind(mode)
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await
         .context("Failed to insert link")?;
         Ok(())
     }
 
     pub async fn get_links_by_name(&self, name: &str, fuzzy: bool) -> Result<Vec<Link>> {
+        let mut conn = self.conn().await?;
         let rows = if fuzzy {
             sqlx::query("SELECT id, name, ext, source_id, mode FROM link WHERE name LIKE ?1")
                 .bind(name)
-                .fetch_all(&self.pool)
+                .fetch_all(&mut *conn)
                 .await
                 .context("Failed to query links by name (fuzzy)")?
         } else {
             sqlx::query("SELECT id, name, ext, source_id, mode FROM link WHERE name = ?1")
                 .bind(name)
-                .fetch_all(&self.pool)
+                .fetch_all(&mut *conn)
                 .await
                 .context("Failed to query links by name")?
         };
@@ -179,9 +294,10 @@ impl Dao {
     }
 
     pub async fn get_links_by_ext(&self, ext: &str) -> Result<Vec<Link>> {
+        let mut conn = self.conn().await?;
         let rows = sqlx::query("SELECT id, name, ext, source_id, mode FROM link WHERE ext = ?1")
             .bind(ext)
-            .fetch_all(&self.pool)
+            .fetch_all(&mut *conn)
             .await
             .context("Failed to query links by ext")?;
 
@@ -200,15 +316,16 @@ impl Dao {
     }
 
     pub async fn get_n_links(&self, n: u64) -> Result<Vec<Link>> {
+        let mut conn = self.conn().await?;
         let rows = if n == 0 {
             sqlx::query("SELECT id, name, ext, source_id, mode FROM link")
-                .fetch_all(&self.pool)
+                .fetch_all(&mut *conn)
                 .await
                 .context("Failed to query all links")?
         } else {
             sqlx::query("SELECT id, name, ext, source_id, mode FROM link LIMIT ?1")
                 .bind(n as i64)
-                .fetch_all(&self.pool)
+                .fetch_all(&mut *conn)
                 .await
                 .context("Failed to query links with limit")?
         };
@@ -228,50 +345,147 @@ impl Dao {
     }
 
     pub async fn set_link_mode(&self, name: &str, mode: u32) -> Result<()> {
+        let mut conn = self.conn().await?;
         sqlx::query("UPDATE link SET mode = ?1 WHERE name = ?2")
             .bind(mode)
             .bind(name)
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await
             .context("Failed to update link mode")?;
         Ok(())
     }
 
     pub async fn delete_link_by_id(&self, id: &str) -> Result<()> {
+        let mut conn = self.conn().await?;
         sqlx::query("DELETE FROM link WHERE id = ?1")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await
             .context("Failed to delete link")?;
         Ok(())
     }
 }
 
+// Version CRUD operations.
+impl Dao {
+    /// Number the next version of `name` gets: one past the newest.
+    pub async fn next_version_n(&self, name: &str) -> Result<u64> {
+        let mut conn = self.conn().await?;
+        let newest = sqlx::query_scalar::<_, Option<i64>>(
+            "SELECT MAX(n) FROM version WHERE name = ?1",
+        )
+        .bind(name)
+        .fetch_one(&mut *conn)
+        .await
+        .context("Failed to query newest version")?;
+        Ok(newest.unwrap_or(0) as u64 + 1)
+    }
+
+    pub async fn insert_version(&self, version: &Version) -> Result<()> {
+        let mut conn = self.conn().await?;
+        sqlx::query("INSERT INTO version (name, n, source_id, create_at) VALUES (?1, ?2, ?3, ?4)")
+            .bind(&version.name)
+            .bind(version.n as i64)
+            .bind(&version.source_id)
+            .bind(&version.create_at)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to insert version")?;
+        Ok(())
+    }
+
+    /// Versions of `name`, oldest first.
+    pub async fn list_versions(&self, name: &str) -> Result<Vec<Version>> {
+        let mut conn = self.conn().await?;
+        let rows = sqlx::query_as::<_, (String, i64, String, String)>(
+            "SELECT name, n, source_id, create_at FROM version WHERE name = ?1 ORDER BY n",
+        )
+        .bind(name)
+        .fetch_all(&mut *conn)
+        .await
+        .context("Failed to list versions")?;
+        Ok(rows
+            .into_iter()
+            .map(|(name, n, source_id, create_at)| Version {
+                name,
+                n: n as u64,
+                source_id,
+                create_at,
+            })
+            .collect())
+    }
+
+    pub async fn get_version(&self, name: &str, n: u64) -> Result<Option<Version>> {
+        let mut conn = self.conn().await?;
+        let row = sqlx::query_as::<_, (String, i64, String, String)>(
+            "SELECT name, n, source_id, create_at FROM version WHERE name = ?1 AND n = ?2",
+        )
+        .bind(name)
+        .bind(n as i64)
+        .fetch_optional(&mut *conn)
+        .await
+        .context("Failed to query version")?;
+        Ok(row.map(|(name, n, source_id, create_at)| Version {
+            name,
+            n: n as u64,
+            source_id,
+            create_at,
+        }))
+    }
+
+    pub async fn delete_version(&self, name: &str, n: u64) -> Result<()> {
+        let mut conn = self.conn().await?;
+        sqlx::query("DELETE FROM version WHERE name = ?1 AND n = ?2")
+            .bind(name)
+            .bind(n as i64)
+            .execute(&mut *conn)
+            .await
+            .context("Failed to delete version")?;
+        Ok(())
+    }
+
+    /// Whether any version holds `source_id`.
+    pub async fn source_has_versions(&self, source_id: &str) -> Result<bool> {
+        let mut conn = self.conn().await?;
+        let held = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM version WHERE source_id = ?1)",
+        )
+        .bind(source_id)
+        .fetch_one(&mut *conn)
+        .await
+        .context("Failed to query versions by source id")?;
+        Ok(held)
+    }
+}
+
 // Directory CRUD operations.
 impl Dao {
     pub async fn insert_dir(&self, path: &str, parent: &str) -> Result<()> {
+        let mut conn = self.conn().await?;
         sqlx::query("INSERT INTO dir (path, parent, mode) VALUES (?1, ?2, 493)")
             .bind(path)
             .bind(parent)
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await
             .context("Failed to insert dir")?;
         Ok(())
     }
 
     pub async fn delete_dir(&self, path: &str) -> Result<()> {
+        let mut conn = self.conn().await?;
         sqlx::query("DELETE FROM dir WHERE path = ?1")
             .bind(path)
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await
             .context("Failed to delete dir")?;
         Ok(())
     }
 
     pub async fn get_dir_by_path(&self, path: &str) -> Result<Option<DirEntry>> {
+        let mut conn = self.conn().await?;
         let row = sqlx::query("SELECT path, parent, mode FROM dir WHERE path = ?1")
             .bind(path)
-            .fetch_optional(&self.pool)
+            .fetch_optional(&mut *conn)
             .await
             .context("Failed to get dir by path")?;
         Ok(row.map(|r: sqlx::sqlite::SqliteRow| DirEntry {
@@ -282,9 +496,10 @@ impl Dao {
     }
 
     pub async fn list_dirs_by_parent(&self, parent: &str) -> Result<Vec<DirEntry>> {
+        let mut conn = self.conn().await?;
         let rows = sqlx::query("SELECT path, parent, mode FROM dir WHERE parent = ?1 ORDER BY path")
             .bind(parent)
-            .fetch_all(&self.pool)
+            .fetch_all(&mut *conn)
             .await
             .context("Failed to list dirs by parent")?;
         Ok(rows
@@ -298,8 +513,9 @@ impl Dao {
     }
 
     pub async fn list_all_dirs(&self) -> Result<Vec<DirEntry>> {
+        let mut conn = self.conn().await?;
         let rows = sqlx::query("SELECT path, parent, mode FROM dir ORDER BY path")
-            .fetch_all(&self.pool)
+            .fetch_all(&mut *conn)
             .await
             .context("Failed to list all dirs")?;
         Ok(rows
@@ -313,10 +529,11 @@ impl Dao {
     }
 
     pub async fn set_dir_mode(&self, path: &str, mode: u32) -> Result<()> {
+        let mut conn = self.conn().await?;
         sqlx::query("UPDATE dir SET mode = ?1 WHERE path = ?2")
             .bind(mode)
             .bind(path)
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await
             .context("Failed to update dir mode")?;
         Ok(())
@@ -343,6 +560,7 @@ impl Dao {
         size: u64,
         count: u64,
     ) -> Result<()> {
+        let mut conn = self.conn().await?;
         let now = chrono::Utc::now().naive_local().format("%Y-%m-%d %H:%M:%S").to_string();
         sqlx::query(
             "INSERT INTO source (id, hash256, compressed, size, count, create_at, update_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
@@ -354,26 +572,53 @@ impl Dao {
         .bind(count as i64)
         .bind(&now)
         .bind(&now)
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await
         .context("Failed to insert source")?;
         Ok(())
     }
 
     pub async fn list_source_ids(&self) -> Result<Vec<String>> {
+        let mut conn = self.conn().await?;
         let rows = sqlx::query_scalar::<_, String>("SELECT id FROM source")
-            .fetch_all(&self.pool)
+            .fetch_all(&mut *conn)
             .await
             .context("Failed to list source ids")?;
         Ok(rows)
     }
 
+    /// Name of every link with the size of the content it points at.
+    pub async fn list_link_sizes(&self) -> Result<Vec<(String, u64)>> {
+        let mut conn = self.conn().await?;
+        let rows = sqlx::query_as::<_, (String, i64)>(
+            "SELECT link.name, source.size FROM link JOIN source ON source.id = link.source_id",
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .context("Failed to list link sizes")?;
+        Ok(rows
+            .into_iter()
+            .map(|(name, size)| (name, size as u64))
+            .collect())
+    }
+
+    pub async fn get_link_names_by_source_id(&self, source_id: &str) -> Result<Vec<String>> {
+        let mut conn = self.conn().await?;
+        let rows = sqlx::query_scalar::<_, String>("SELECT name FROM link WHERE source_id = ?1")
+            .bind(source_id)
+            .fetch_all(&mut *conn)
+            .await
+            .context("Failed to query links by source id")?;
+        Ok(rows)
+    }
+
     pub async fn get_source_by_id(&self, id: &str) -> Result<Option<Source>> {
+        let mut conn = self.conn().await?;
         let row = sqlx::query(
             "SELECT id, hash256, compressed, size, count, create_at, update_at FROM source WHERE id = ?1"
         )
         .bind(id)
-        .fetch_optional(&self.pool)
+        .fetch_optional(&mut *conn)
         .await
         .context("Failed to query source by id")?;
 
@@ -389,11 +634,12 @@ impl Dao {
     }
 
     pub async fn get_source_by_hash256(&self, hash256: &str) -> Result<Option<Source>> {
+        let mut conn = self.conn().await?;
         let row = sqlx::query(
             "SELECT id, hash256, compressed, size, count, create_at, update_at FROM source WHERE hash256 = ?1"
         )
         .bind(hash256)
-        .fetch_optional(&self.pool)
+        .fetch_optional(&mut *conn)
         .await
         .context("Failed to query source by hash256")?;
 
@@ -413,10 +659,11 @@ impl Dao {
         link_id: &str,
         new_source_id: &str,
     ) -> Result<()> {
+        let mut conn = self.conn().await?;
         sqlx::query("UPDATE link SET source_id = ?1 WHERE id = ?2")
             .bind(new_source_id)
             .bind(link_id)
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await
             .context("Failed to update link source_id")?;
         Ok(())
@@ -430,6 +677,7 @@ impl Dao {
         new_size: u64,
         new_count: u64,
     ) -> Result<()> {
+        let mut conn = self.conn().await?;
         sqlx::query(
             "UPDATE source SET hash256 = ?2, compressed = ?3, size = ?4, count = ?5, update_at = datetime('now') WHERE id = ?1",
         )
@@ -438,16 +686,17 @@ impl Dao {
         .bind(new_compressed)
         .bind(new_size as i64)
         .bind(new_count as i64)
-        .execute(&self.pool)
+        .execute(&mut *conn)
         .await
         .context("Failed to update source")?;
         Ok(())
     }
 
     pub async fn delete_source_by_id(&self, id: &str) -> Result<()> {
+        let mut conn = self.conn().await?;
         sqlx::query("DELETE FROM source WHERE id = ?1")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *conn)
             .await
             .context("Failed to delete source")?;
         Ok(())
@@ -468,6 +717,28 @@ mod tests {
         assert!(dao.is_ok());
     }
 
+    #[tokio::test]
+    async fn test_transaction_commit_and_rollback() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let dao = Dao::new(temp_dir.path().join("test.db"))
+            .await
+            .expect("Failed to create DAO");
+
+        let committed = Uuid::new_v4().to_string();
+        let tx = dao.begin().await.expect("Failed to begin");
+        tx.insert_source(&committed, "hash_a", false, 1).await.unwrap();
+        // Reads on the handle see its own uncommitted writes
+        assert!(tx.get_source_by_id(&committed).await.unwrap().is_some());
+        tx.commit().await.expect("Failed to commit");
+        assert!(dao.get_source_by_id(&committed).await.unwrap().is_some());
+
+        let rolled_back = Uuid::new_v4().to_string();
+        let tx = dao.begin().await.expect("Failed to begin");
+        tx.insert_source(&rolled_back, "hash_b", false, 1).await.unwrap();
+        drop(tx);
+        assert!(dao.get_source_by_id(&rolled_back).await.unwrap().is_none());
+    }
+
     #[tokio::test]
     async fn test_insert_link() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
@@ -0,0 +1,108 @@
+//! The error type of the store's public API.
+
+use std::{error::Error, fmt, io};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Why a store operation failed.
+///
+/// Callers tell a missing file from a broken store by variant rather than
+/// by message; messages are for people and may change.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum StoreError {
+    /// No file, directory or content under the name or hash asked for.
+    NotFound(String),
+    /// The request itself is unusable, such as an empty file name or a
+    /// malformed content hash.
+    InvalidInput(String),
+    /// Stored content no longer matches its hash.
+    Corrupt(String),
+    /// The store was opened read-only and the operation would change it.
+    ReadOnly,
+    /// The metadata database failed.
+    Database(BoxError),
+    /// A [`BlobBackend`](crate::blob::BlobBackend) failed with an error of
+    /// its own.
+    Backend(BoxError),
+    /// A local file could not be read or written.
+    Io(io::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::NotFound(what) => write!(f, "{}", what),
+            StoreError::InvalidInput(what) => write!(f, "{}", what),
+            StoreError::Corrupt(what) => write!(f, "{}", what),
+            StoreError::ReadOnly => write!(f, "Store is read-only"),
+            StoreError::Database(e) => write!(f, "Database error: {}", e),
+            StoreError::Backend(e) => write!(f, "Blob backend error: {}", e),
+            StoreError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for StoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StoreError::Database(e) | StoreError::Backend(e) => Some(e.as_ref()),
+            StoreError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StoreError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => StoreError::NotFound(err.to_string()),
+            io::ErrorKind::InvalidInput => StoreError::InvalidInput(err.to_string()),
+            _ => StoreError::Io(err),
+        }
+    }
+}
+
+impl From<anyhow::Error> for StoreError {
+    fn from(err: anyhow::Error) -> Self {
+        StoreError::Database(err.into())
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(err: serde_json::Error) -> Self {
+        StoreError::Io(err.into())
+    }
+}
+
+/// Errors from blob backends: I/O errors map as [`io::Error`]s do, so a
+/// missing source stays [`NotFound`](StoreError::NotFound).
+impl From<BoxError> for StoreError {
+    fn from(err: BoxError) -> Self {
+        let err = match err.downcast::<StoreError>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+        match err.downcast::<io::Error>() {
+            Ok(err) => StoreError::from(*err),
+            Err(err) => StoreError::Backend(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boxed_errors_keep_their_kind() {
+        let missing: BoxError = Box::new(io::Error::new(io::ErrorKind::NotFound, "gone"));
+        assert!(matches!(StoreError::from(missing), StoreError::NotFound(_)));
+
+        let nested: BoxError = Box::new(StoreError::ReadOnly);
+        assert!(matches!(StoreError::from(nested), StoreError::ReadOnly));
+
+        let other: BoxError = "connection refused".into();
+        assert!(matches!(StoreError::from(other), StoreError::Backend(_)));
+    }
+}
@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// What a pending change does to a source's content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum IntentKind {
+    /// Content is written for a new source, whose row and link follow.
+    Create,
+    /// The source row is deleted, then its content.
+    Release,
+}
+
+/// A change to a source's content that its row may not reflect yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Intent {
+    pub id: u64,
+    pub kind: IntentKind,
+    pub source_id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Record {
+    Begin {
+        id: u64,
+        kind: IntentKind,
+        source_id: String,
+    },
+    End {
+        id: u64,
+    },
+}
+
+/// Write-ahead journal of source content changes, one JSON record per line.
+///
+/// An intent is recorded, and synced, before content is written or after
+/// its row is deleted, and ended once the DB and content agree again. The
+/// file is truncated whenever no intent is outstanding.
+#[derive(Debug)]
+pub(crate) struct IntentJournal {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    file: File,
+    next_id: u64,
+    pending: HashSet<u64>,
+}
+
+impl IntentJournal {
+    /// Open (or create) the journal at `path` and return the intents it
+    /// holds that were never ended, oldest first. They stay pending until
+    /// [`end`](Self::end)ed.
+    pub fn open(path: &Path) -> io::Result<(Self, Vec<Intent>)> {
+        let mut outstanding: Vec<Intent> = Vec::new();
+        let mut next_id = 0;
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    // A torn last line is an intent that was never synced,
+                    // so nothing was done under it
+                    let Ok(record) = serde_json::from_str::<Record>(&line?) else {
+                        continue;
+                    };
+                    match record {
+                        Record::Begin {
+                            id,
+                            kind,
+                            source_id,
+                        } => {
+                            next_id = next_id.max(id + 1);
+                            outstanding.push(Intent {
+                                id,
+                                kind,
+                                source_id,
+                            });
+                        }
+                        Record::End { id } => outstanding.retain(|intent| intent.id != id),
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let journal = IntentJournal {
+            inner: Mutex::new(Inner {
+                file,
+                next_id,
+                pending: outstanding.iter().map(|intent| intent.id).collect(),
+            }),
+        };
+        Ok((journal, outstanding))
+    }
+
+    /// Record that `kind` is about to happen to `source_id`; returns once the
+    /// record is on disk.
+    pub fn begin(&self, kind: IntentKind, source_id: &str) -> io::Result<Intent> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let intent = Intent {
+            id: inner.next_id,
+            kind,
+            source_id: source_id.to_string(),
+        };
+        inner.append(&Record::Begin {
+            id: intent.id,
+            kind,
+            source_id: intent.source_id.clone(),
+        })?;
+        inner.file.sync_data()?;
+        inner.next_id += 1;
+        inner.pending.insert(intent.id);
+        Ok(intent)
+    }
+
+    /// Retire `intent`: the DB and content agree on its source again.
+    pub fn end(&self, intent: &Intent) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.pending.remove(&intent.id);
+        if inner.pending.is_empty() {
+            // Nothing is outstanding, so nothing needs replaying
+            inner.file.set_len(0)
+        } else {
+            inner.append(&Record::End { id: intent.id })
+        }
+    }
+}
+
+impl Inner {
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let mut line = serde_json::to_string(record).map_err(io::Error::other)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+/// Where a store keeps its intent journal, next to `meta.db`.
+pub(crate) fn journal_path(linadata: &Path) -> PathBuf {
+    linadata.join("intents.journal")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_open_returns_unended_intents() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = journal_path(temp_dir.path());
+        {
+            let (journal, outstanding) = IntentJournal::open(&path).unwrap();
+            assert!(outstanding.is_empty());
+            let created = journal.begin(IntentKind::Create, "a").unwrap();
+            journal.begin(IntentKind::Release, "b").unwrap();
+            journal.end(&created).unwrap();
+        }
+
+        let (journal, outstanding) = IntentJournal::open(&path).unwrap();
+        assert_eq!(outstanding.len(), 1);
+        assert_eq!(outstanding[0].kind, IntentKind::Release);
+        assert_eq!(outstanding[0].source_id, "b");
+
+        // Ids keep counting past the replayed ones; ending the last
+        // outstanding intent empties the file
+        let next = journal.begin(IntentKind::Create, "c").unwrap();
+        assert!(next.id > outstanding[0].id);
+        journal.end(&outstanding[0]).unwrap();
+        journal.end(&next).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+    }
+}
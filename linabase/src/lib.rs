@@ -1,3 +1,35 @@
-pub mod dao;
+//! A deduplicating file store, embeddable in any tokio program.
+//!
+//! Files are stored under names (links); each distinct content is stored
+//! once (a source), optionally compressed, and shared by every link holding
+//! it. Metadata lives in SQLite under `<root>/linadata`, content in a
+//! [`BlobBackend`](blob::BlobBackend): local files by default, or an S3
+//! bucket with the `s3` feature.
+//!
+//! ```no_run
+//! use bytes::Bytes;
+//! use linabase::{StoreError, service::StoreOptions};
+//!
+//! # async fn run() -> Result<(), StoreError> {
+//! let store = StoreOptions::new("/var/lib/myapp/store").open().await?;
+//! let outcome = store
+//!     .put_binary_data("report.pdf", &Bytes::from_static(b"..."), true, false)
+//!     .await?;
+//! assert_eq!(store.get_binary_data("report.pdf").await?.len() as u64, outcome.size);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every fallible call returns a [`StoreError`], and the crate never prints:
+//! what it has to report goes out as [`tracing`] events. The guarantees the
+//! store keeps are listed on [`StoreManager`](service::StoreManager).
+
+pub mod blob;
+mod dao;
+mod error;
+mod intent;
 pub mod service;
 mod utils;
+
+pub use dao::{DirEntry, Link};
+pub use error::StoreError;
@@ -1,27 +1,31 @@
 use bytes::Bytes;
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use nanoid;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
-    error::Error,
-    fs as stdfs, io,
+    fs as stdfs,
+    io::{self, BufRead, Write},
     path::{Path, PathBuf},
     result::Result,
     sync::Arc,
+    time::SystemTime,
 };
 use tokio::fs;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::RwLock;
+use tokio::sync::{RwLock, mpsc};
 use tokio::task;
+use tracing::{Level, event, instrument};
 use uuid::Uuid;
 
-use crate::utils::BlockManager;
+use crate::blob::{self, BlobBackend, FsBackend};
+use crate::error::StoreError;
+use crate::intent::{self, Intent, IntentJournal, IntentKind};
+use crate::utils::{BlockManager, GzipChunkEncoder};
 
-use super::dao::{Dao, DirEntry, Link, Source};
+use super::dao::{Dao, DirEntry, Link, Source, Version};
 use super::utils;
 
-type BoxError = Box<dyn Error + Send + Sync>;
-
 const NANOID_MAP: [char; 62] = [
     '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i',
     'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z', 'A', 'B',
@@ -29,45 +33,398 @@ const NANOID_MAP: [char; 62] = [
     'V', 'W', 'X', 'Y', 'Z',
 ];
 
-fn boxed_io_error(kind: io::ErrorKind, message: impl Into<String>) -> BoxError {
-    Box::new(io::Error::new(kind, message.into()))
-}
-
-fn dao_to_io_error(err: anyhow::Error) -> io::Error {
-    io::Error::other(err.to_string())
+fn io_error(kind: io::ErrorKind, message: impl Into<String>) -> StoreError {
+    io::Error::new(kind, message.into()).into()
 }
 
+/// A deduplicating file store: named links over content-addressed sources.
+///
+/// Metadata lives in `<root>/linadata/meta.db`, source content in a
+/// [`BlobBackend`]. Open one with [`StoreOptions`], or [`new`](Self::new) for
+/// the defaults. Invariants the store keeps across crashes:
+///
+/// - Every link points at exactly one source, and a source's count is the
+///   number of links and [versions](FileVersion) holding it; a source
+///   nothing holds is removed.
+/// - A new link to content the store already holds shares its source
+///   rather than storing the content again.
+/// - A source row exists only once its content is fully stored, and reads
+///   verify content against its hash before returning it.
+/// - A write never changes content another link shares: it points the link
+///   at a new source instead.
+///
+/// Calls are safe from any number of tasks; writes are serialized. None
+/// blocks the runtime: file and database I/O is async, and hashing,
+/// compression and journal syncs run on blocking threads.
 #[derive(Debug)]
 pub struct StoreManager {
-    root: PathBuf,
     dao: Dao,
+    blobs: Arc<dyn BlobBackend>,
+    /// `None` when read-only: nothing is ever journaled.
+    intents: Option<Arc<IntentJournal>>,
+    /// Intents to settle once the transaction `dao` runs in has committed.
+    deferred: Option<Arc<std::sync::Mutex<Vec<Intent>>>>,
     bm: Arc<BlockManager>,
     operation_lock: Arc<RwLock<()>>,
+    read_only: bool,
+    /// Previous contents kept per file; 0 keeps none.
+    keep_versions: usize,
+}
+
+/// Precondition for [`StoreManager::put_binary_data_if`], evaluated under the
+/// write lock so concurrent writers cannot interleave between check and write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PutCondition {
+    /// Only write when no file with this name exists.
+    IfAbsent,
+    /// Only write when the current file's BLAKE3 hash (lowercase hex) matches.
+    IfHashMatches(String),
+}
+
+/// What a successful write stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PutOutcome {
+    /// BLAKE3 hash (lowercase hex) of the written content.
+    pub hash256: String,
+    /// Content size in bytes, before compression.
+    pub size: u64,
+    /// Whether the content matched an existing source, so no new bytes were stored.
+    pub deduplicated: bool,
+}
+
+/// One write of a [`StoreManager::put_batch`].
+#[derive(Debug, Clone)]
+pub struct BatchPut {
+    pub file_name: String,
+    pub input: Bytes,
+    pub cover: bool,
+    pub compressed: bool,
+    /// Write only when this holds; conditional writes always cover.
+    pub condition: Option<PutCondition>,
+}
+
+/// Stored metadata of a single file, without its content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMeta {
+    pub name: String,
+    pub ext: String,
+    /// Content size in bytes, before compression.
+    pub size: u64,
+    /// BLAKE3 hash (lowercase hex) of the content.
+    pub hash256: String,
+    pub compressed: bool,
+    /// UTC timestamps formatted as `%Y-%m-%d %H:%M:%S`.
+    pub create_at: String,
+    pub update_at: String,
+}
+
+/// A previous content of a file, kept when the file was overwritten; see
+/// [`StoreOptions::keep_versions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileVersion {
+    /// Counts up with each overwrite of the file, so older versions have
+    /// lower numbers.
+    pub n: u64,
+    /// Content size in bytes, before compression.
+    pub size: u64,
+    /// BLAKE3 hash (lowercase hex) of the content.
+    pub hash256: String,
+    /// When the content was replaced, UTC formatted as `%Y-%m-%d %H:%M:%S`.
+    pub create_at: String,
+}
+
+/// What [`StoreManager::collect_garbage`] removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Temp files left behind by interrupted writes.
+    pub removed_tmp: u64,
+    /// Tombstones of deletes whose source is gone.
+    pub removed_tombstones: u64,
+    /// Content files no source refers to.
+    pub removed_orphans: u64,
+}
+
+/// What [`StoreManager::scrub`] found.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrubReport {
+    /// Sources whose content was read back and verified.
+    pub checked: u64,
+    /// Names of the files whose content is missing or fails its hash check.
+    pub damaged: Vec<String>,
+}
+
+/// Content delivered chunk by chunk; see [`StoreManager::get_binary_stream`].
+#[derive(Debug)]
+pub struct DataStream {
+    /// Content size in bytes, before compression.
+    pub size: u64,
+    /// BLAKE3 hash (lowercase hex) the content is verified against.
+    pub hash256: String,
+    /// Whether the chunks form a gzip stream rather than the plain content.
+    pub gzip: bool,
+    /// Chunks in order. An `Err` ends the stream early, including a content
+    /// hash mismatch detected after the last chunk.
+    pub chunks: mpsc::Receiver<io::Result<Bytes>>,
+}
+
+/// Chunks buffered ahead of a slow reader per stream.
+const STREAM_CHANNEL_DEPTH: usize = 4;
+
+/// Which files [`TidyManager::tidy`] considers.
+#[derive(Debug, Clone, Default)]
+pub struct TidyOptions {
+    /// Globs of files and directories to skip, e.g. `.git` or
+    /// `node_modules`; a matching directory is not walked. A glob without `/`
+    /// matches names, one with `/` paths relative to the tidied directory.
+    pub exclude: Vec<String>,
+    /// When not empty, only files matching one of these globs are tidied.
+    pub include: Vec<String>,
+    /// Files smaller than this many bytes are left alone.
+    pub min_size: u64,
+    /// Directories whose copies are kept over copies elsewhere, whatever
+    /// their dates; earlier directories win over later ones.
+    pub prefer_dirs: Vec<PathBuf>,
+    /// Which time of the copies the keep-oldest/keep-newest policy compares.
+    pub order: TidyOrder,
+    /// File to record each replacement in as it is made, one JSON object
+    /// per line, for [`TidyManager::untidy`] to undo the run.
+    pub journal: Option<PathBuf>,
+}
+
+/// What decides which copy of a duplicate group [`TidyManager::tidy`] keeps.
+/// Copies with equal times go to the path that sorts first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TidyOrder {
+    /// When the file was created, or modified where creation times are not
+    /// recorded.
+    #[default]
+    Created,
+    /// When the content was last modified (mtime).
+    Modified,
+    /// When the file's metadata last changed (ctime); mtime where there is
+    /// no such time.
+    Changed,
+    /// Only the path: the one that sorts first is kept, oldest or newest.
+    Path,
+}
+
+/// One duplicate [`TidyManager::tidy`] replaced with a symlink.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TidyReplacement {
+    /// The duplicate, now a symlink.
+    pub path: PathBuf,
+    /// The copy that was kept, which the symlink points at.
+    pub kept: PathBuf,
+    pub size: u64,
+    /// BLAKE3 hash (lowercase hex) of the content.
+    pub hash256: String,
+}
+
+/// What a [`TidyManager::tidy`] run found and replaced.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TidyReport {
+    /// Files the walk found, after filtering.
+    pub files_scanned: u64,
+    /// Sets of two or more identical files.
+    pub duplicate_groups: u64,
+    /// Size of the duplicates replaced with symlinks.
+    pub bytes_reclaimed: u64,
+    /// Files skipped because they could not be read or replaced.
+    pub errors: u64,
+    pub replacements: Vec<TidyReplacement>,
+}
+
+/// How far a [`TidyManager::tidy`] run has got, passed to the callback set
+/// with [`TidyManager::set_progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TidyProgress {
+    /// The walk found this many files.
+    Scanned { files: u64 },
+    /// Files hashed so far, out of those sharing their size with another.
+    Hashed { done: u64, total: u64 },
+}
+
+impl TidyReport {
+    pub fn to_json(&self) -> Result<String, StoreError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// One `path,kept,size,hash256` row per replacement, after a header row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("path,kept,size,hash256\n");
+        for r in &self.replacements {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                csv_field(&r.path.to_string_lossy()),
+                csv_field(&r.kept.to_string_lossy()),
+                r.size,
+                r.hash256
+            ));
+        }
+        csv
+    }
+}
+
+/// When the file's metadata last changed, or its mtime off Unix.
+fn changed_time(metadata: &stdfs::Metadata) -> io::Result<SystemTime> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        use std::time::Duration;
+        let secs = metadata.ctime().max(0) as u64;
+        Ok(SystemTime::UNIX_EPOCH + Duration::new(secs, metadata.ctime_nsec() as u32))
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.modified()
+    }
+}
+
+/// Whether `path` is a regular file itself, not a symlink to one.
+fn is_regular_file(path: &Path) -> bool {
+    stdfs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_file())
+}
+
+/// `field` quoted when it holds a comma, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// A set of identical files, offered to the chooser set with
+/// [`TidyManager::set_chooser`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// BLAKE3 hash (lowercase hex) of the content.
+    pub hash256: String,
+    pub size: u64,
+    /// The identical files, each with the time [`TidyOptions::order`] compares.
+    pub files: Vec<(PathBuf, SystemTime)>,
+    /// Index into `files` of the copy the keep-oldest/keep-newest policy keeps.
+    pub suggested: usize,
+}
+
+/// What to do with a [`DuplicateGroup`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupChoice {
+    /// Keep the file at this index into `files` and link the others to it.
+    Keep(usize),
+    /// Leave the whole group alone.
+    Skip,
 }
 
+type Chooser = Box<dyn FnMut(&DuplicateGroup) -> GroupChoice>;
+
 pub struct TidyManager {
-    map_cache: HashMap<String, Vec<(PathBuf, String)>>,
+    options: TidyOptions,
+    map_cache: HashMap<String, Vec<(PathBuf, SystemTime)>>,
+    chooser: Option<Chooser>,
+    progress: Option<Box<dyn FnMut(TidyProgress)>>,
 }
 
-// Constructor and query-oriented APIs.
-impl StoreManager {
-    pub async fn new<P: AsRef<Path>>(root: P) -> Result<Self, BoxError> {
-        let root_path = root.as_ref().to_path_buf(); // Convert to owning type
-        fs::create_dir_all(root_path.join("linadata")).await?;
+/// How to open a [`StoreManager`]: where the store lives, where its content
+/// goes and whether it may change.
+#[derive(Debug, Clone)]
+pub struct StoreOptions {
+    root: PathBuf,
+    blobs: Option<Arc<dyn BlobBackend>>,
+    read_only: bool,
+    keep_versions: usize,
+}
 
-        let manager = StoreManager {
-            root: root_path.clone(), // Store owned path
-            dao: Dao::new(root_path.join("linadata").join("meta.db"))
-                .await
-                .map_err(dao_to_io_error)?,
-            bm: Arc::new(BlockManager::new()),
+impl StoreOptions {
+    /// A store under `root`, keeping content in `<root>/linadata` and
+    /// created on first open.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StoreOptions {
+            root: root.into(),
+            blobs: None,
+            read_only: false,
+            keep_versions: 0,
+        }
+    }
+
+    /// Keep source content in `blobs` rather than under `<root>/linadata`.
+    pub fn backend(mut self, blobs: Arc<dyn BlobBackend>) -> Self {
+        self.blobs = Some(blobs);
+        self
+    }
+
+    /// Open an existing store without changing it, even while another
+    /// process writes to it. Startup recovery is skipped and every write
+    /// fails with [`StoreError::ReadOnly`].
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Keep up to `n` previous contents of each file when it is overwritten,
+    /// for [`StoreManager::list_versions`] and
+    /// [`restore_version`](StoreManager::restore_version). 0, the default,
+    /// keeps none. A file's versions go when the file is deleted.
+    pub fn keep_versions(mut self, n: usize) -> Self {
+        self.keep_versions = n;
+        self
+    }
+
+    /// Open the store, first finishing or undoing whatever a crash
+    /// interrupted unless it is read-only.
+    pub async fn open(self) -> Result<StoreManager, StoreError> {
+        let linadata = self.root.join("linadata");
+        let meta_db = linadata.join("meta.db");
+        let blobs = self
+            .blobs
+            .unwrap_or_else(|| Arc::new(FsBackend::new(&linadata)));
+        let bm = Arc::new(BlockManager::new()?);
+        let manager = |dao, intents| StoreManager {
+            dao,
+            blobs,
+            intents,
+            deferred: None,
+            bm,
             operation_lock: Arc::new(RwLock::new(())),
+            read_only: self.read_only,
+            keep_versions: self.keep_versions,
         };
 
+        if self.read_only {
+            if !meta_db.is_file() {
+                return Err(StoreError::NotFound(format!(
+                    "No store at {}",
+                    self.root.display()
+                )));
+            }
+            return Ok(manager(Dao::open_read_only(&meta_db).await?, None));
+        }
+
+        fs::create_dir_all(&linadata).await?;
+        let journal_path = intent::journal_path(&linadata);
+        let (intents, outstanding) = IntentJournal::open(&journal_path).map_err(|e| {
+            io_error(
+                e.kind(),
+                format!("Failed to open intent journal {}: {}", journal_path.display(), e),
+            )
+        })?;
+        let manager = manager(Dao::new(&meta_db).await?, Some(Arc::new(intents)));
+
+        // Finish or undo what a crash interrupted before anything else looks
+        // at the store.
+        for intent in outstanding {
+            if let Err(err) = manager.finish_intent(intent).await {
+                return Err(io_error(
+                    io::ErrorKind::Other,
+                    format!("Startup intent replay failed: {}", err),
+                ));
+            }
+        }
+
         // Reconcile filesystem with DB on startup: drop orphan source files,
         // leftover tombstones, and write-in-progress temp files.
         if let Err(err) = manager.reconcile_orphans().await {
-            return Err(boxed_io_error(
+            return Err(io_error(
                 io::ErrorKind::Other,
                 format!("Startup reconciliation failed: {}", err),
             ));
@@ -75,7 +432,7 @@ impl StoreManager {
 
         // Reconcile directory table with existing file paths
         if let Err(err) = manager.sync_dirs_from_links().await {
-            return Err(boxed_io_error(
+            return Err(io_error(
                 io::ErrorKind::Other,
                 format!("Startup dir sync failed: {}", err),
             ));
@@ -83,6 +440,36 @@ impl StoreManager {
 
         Ok(manager)
     }
+}
+
+// Constructor and query-oriented APIs.
+impl StoreManager {
+    /// Open (or create) the store under `root` with default options.
+    pub async fn new<P: AsRef<Path>>(root: P) -> Result<Self, StoreError> {
+        StoreOptions::new(root.as_ref()).open().await
+    }
+
+    /// Keep metadata in `<root>/linadata/meta.db` as [`new`](Self::new) does,
+    /// but source content in `blobs`.
+    pub async fn with_backend<P: AsRef<Path>>(
+        root: P,
+        blobs: Arc<dyn BlobBackend>,
+    ) -> Result<Self, StoreError> {
+        StoreOptions::new(root.as_ref()).backend(blobs).open().await
+    }
+
+    /// Whether the store was opened [read-only](StoreOptions::read_only).
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fail with [`StoreError::ReadOnly`] unless the store may change.
+    fn writable(&self) -> Result<(), StoreError> {
+        if self.read_only {
+            return Err(StoreError::ReadOnly);
+        }
+        Ok(())
+    }
 
     pub async fn list(
         &self,
@@ -90,76 +477,74 @@ impl StoreManager {
         n: u64,
         isext: bool,
         use_regex: bool,
-    ) -> Result<Vec<Link>, BoxError> {
+    ) -> Result<Vec<Link>, StoreError> {
         let _read_guard = self.operation_lock.read().await;
         self.list_locked(pattern, n, isext, use_regex).await
     }
 
-    pub async fn is_dir(&self, path: &str) -> Result<bool, BoxError> {
+    pub async fn is_dir(&self, path: &str) -> Result<bool, StoreError> {
         let _read_guard = self.operation_lock.read().await;
         self.dao
             .get_dir_by_path(path)
             .await
-            .map_err(dao_to_io_error)
+            .map_err(StoreError::from)
             .map(|d| d.is_some())
-            .map_err(|e| Box::new(e) as BoxError)
     }
 
-    pub async fn list_child_dirs(&self, parent: &str) -> Result<Vec<DirEntry>, BoxError> {
+    pub async fn list_child_dirs(&self, parent: &str) -> Result<Vec<DirEntry>, StoreError> {
         let _read_guard = self.operation_lock.read().await;
         self.dao
             .list_dirs_by_parent(parent)
             .await
-            .map_err(dao_to_io_error)
-            .map_err(|e| Box::new(e) as BoxError)
+            .map_err(StoreError::from)
     }
 
-    pub async fn all_dirs(&self) -> Result<Vec<DirEntry>, BoxError> {
+    pub async fn all_dirs(&self) -> Result<Vec<DirEntry>, StoreError> {
         let _read_guard = self.operation_lock.read().await;
         self.dao
             .list_all_dirs()
             .await
-            .map_err(dao_to_io_error)
-            .map_err(|e| Box::new(e) as BoxError)
+            .map_err(StoreError::from)
     }
 
-    pub async fn mkdir(&self, path: &str, parent: &str) -> Result<(), BoxError> {
+    pub async fn mkdir(&self, path: &str, parent: &str) -> Result<(), StoreError> {
+        self.writable()?;
         let _write_guard = self.operation_lock.write().await;
         self.dao
             .insert_dir(path, parent)
             .await
-            .map_err(dao_to_io_error)
-            .map_err(|e| Box::new(e) as BoxError)
+            .map_err(StoreError::from)
     }
 
-    pub async fn rmdir(&self, path: &str) -> Result<(), BoxError> {
+    pub async fn rmdir(&self, path: &str) -> Result<(), StoreError> {
+        self.writable()?;
         let _write_guard = self.operation_lock.write().await;
         self.dao
             .delete_dir(path)
             .await
-            .map_err(dao_to_io_error)
-            .map_err(|e| Box::new(e) as BoxError)
+            .map_err(StoreError::from)
     }
 
-    pub async fn set_file_mode(&self, name: &str, mode: u32) -> Result<(), BoxError> {
+    pub async fn set_file_mode(&self, name: &str, mode: u32) -> Result<(), StoreError> {
+        self.writable()?;
         let _write_guard = self.operation_lock.write().await;
         self.dao
             .set_link_mode(name, mode)
             .await
-            .map_err(dao_to_io_error)
-            .map_err(|e| Box::new(e) as BoxError)
+            .map_err(StoreError::from)
     }
 
-    pub async fn set_dir_mode(&self, path: &str, mode: u32) -> Result<(), BoxError> {
+    pub async fn set_dir_mode(&self, path: &str, mode: u32) -> Result<(), StoreError> {
+        self.writable()?;
         let _write_guard = self.operation_lock.write().await;
         self.dao
             .set_dir_mode(path, mode)
             .await
-            .map_err(dao_to_io_error)
-            .map_err(|e| Box::new(e) as BoxError)
+            .map_err(StoreError::from)
     }
 
-    pub async fn sync_dirs_from_links(&self) -> Result<(), BoxError> {
+    pub async fn sync_dirs_from_links(&self) -> Result<(), StoreError> {
+        self.writable()?;
         let _write_guard = self.operation_lock.write().await;
         let links = self.list_locked("*", 0, false, true).await?;
         for link in &links {
@@ -184,139 +569,521 @@ impl StoreManager {
 
 // Read and write storage APIs.
 impl StoreManager {
-    pub async fn get_binary_data(&self, file_name: &str) -> Result<Bytes, BoxError> {
+    #[instrument(skip(self))]
+    pub async fn get_binary_data(&self, file_name: &str) -> Result<Bytes, StoreError> {
         if file_name.is_empty() {
-            return Err(boxed_io_error(io::ErrorKind::Other, "No filename provided"));
+            return Err(StoreError::InvalidInput("No filename provided".into()));
         }
 
-        let (compressed, source_size, expected_hash, file_bytes) = {
+        let (source, file_bytes) = {
             let _read_guard = self.operation_lock.read().await;
             let links = self
                 .dao
                 .get_links_by_name(file_name, false)
-                .await
-                .map_err(dao_to_io_error)?;
+                .await?;
             let link = links
                 .get(0)
-                .ok_or_else(|| boxed_io_error(io::ErrorKind::NotFound, "File not found"))?;
+                .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
 
             let source = self
                 .dao
                 .get_source_by_id(&link.source_id)
-                .await
-                .map_err(dao_to_io_error)?
-                .ok_or_else(|| boxed_io_error(io::ErrorKind::NotFound, "File not found"))?;
+                .await?
+                .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
 
-            let source_path = self.source_path(&source.id);
-            let file_bytes = fs::read(&source_path).await?;
-            (source.compressed, source.size as usize, source.hash256.clone(), file_bytes)
+            let file_bytes = self.blobs.get(&source.id).await?;
+            (source, file_bytes)
         };
 
-        if compressed {
+        self.decode_source(source, file_bytes).await
+    }
+
+    /// The content `file_bytes` holds as stored for `source`, verified
+    /// against its hash.
+    async fn decode_source(&self, source: Source, file_bytes: Vec<u8>) -> Result<Bytes, StoreError> {
+        let source_size = source.size as usize;
+        let expected_hash = source.hash256;
+        if source.compressed {
             let bm = Arc::clone(&self.bm);
             let decoded = task::spawn_blocking(move || {
                 bm.decompress_all(&file_bytes, source_size)
             })
             .await
-            .map_err(|e| boxed_io_error(io::ErrorKind::Other, format!("decompress task join error: {}", e)))??;
+            .map_err(|e| io_error(io::ErrorKind::Other, format!("decompress task join error: {}", e)))??;
             let actual_hash = utils::get_hash256_from_binary(&decoded);
             if actual_hash != expected_hash {
-                return Err(boxed_io_error(io::ErrorKind::InvalidData, "data integrity check failed"));
+                return Err(StoreError::Corrupt("data integrity check failed".into()));
             }
             Ok(Bytes::from(decoded))
         } else {
             let actual_hash = utils::get_hash256_from_binary(&file_bytes);
             if actual_hash != expected_hash {
-                return Err(boxed_io_error(io::ErrorKind::InvalidData, "data integrity check failed"));
+                return Err(StoreError::Corrupt("data integrity check failed".into()));
             }
             Ok(Bytes::from(file_bytes))
         }
     }
 
+    /// Content as a single gzip member for HTTP `Content-Encoding: gzip`.
+    ///
+    /// Chunks are inflated and re-deflated in parallel without assembling the
+    /// plain content, so unlike [`get_binary_data`](Self::get_binary_data)
+    /// the content hash is not re-verified on this path.
+    pub async fn get_gzip_data(&self, file_name: &str) -> Result<Bytes, StoreError> {
+        if file_name.is_empty() {
+            return Err(StoreError::InvalidInput("No filename provided".into()));
+        }
+
+        let (compressed, file_bytes) = {
+            let _read_guard = self.operation_lock.read().await;
+            let links = self
+                .dao
+                .get_links_by_name(file_name, false)
+                .await?;
+            let link = links
+                .first()
+                .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
+
+            let source = self
+                .dao
+                .get_source_by_id(&link.source_id)
+                .await?
+                .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
+
+            let file_bytes = self.blobs.get(&source.id).await?;
+            (source.compressed, file_bytes)
+        };
+
+        let bm = Arc::clone(&self.bm);
+        let gz = task::spawn_blocking(move || bm.to_gzip_stream(&file_bytes, compressed))
+            .await
+            .map_err(|e| io_error(io::ErrorKind::Other, format!("gzip task join error: {}", e)))??;
+        Ok(Bytes::from(gz))
+    }
+
+    /// `len` bytes of a file's content starting at `offset`, or fewer where
+    /// the file ends first, as for HTTP range requests.
+    ///
+    /// Only the stored chunks holding the range are decompressed, so unlike
+    /// [`get_binary_data`](Self::get_binary_data) the content hash is not
+    /// verified on this path.
+    #[instrument(skip(self))]
+    pub async fn get_binary_range(
+        &self,
+        file_name: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<Bytes, StoreError> {
+        let (source, file, len) = self.open_range(file_name, offset, len).await?;
+        let bm = Arc::clone(&self.bm);
+        let compressed = source.compressed;
+        let data = task::spawn_blocking(move || {
+            let mut data = Vec::with_capacity(len as usize);
+            read_range(file, compressed, offset, len, &bm, |chunk| {
+                data.extend_from_slice(&chunk);
+                true
+            })
+            .map(|()| data)
+        })
+        .await
+        .map_err(|e| io_error(io::ErrorKind::Other, format!("range task join error: {}", e)))??;
+        Ok(Bytes::from(data))
+    }
+
+    /// Like [`get_binary_range`](Self::get_binary_range), but the bytes are
+    /// read chunk by chunk as the stream is consumed, so a large range is
+    /// never held in memory. The stream's `size` is the length of the range.
+    #[instrument(skip(self))]
+    pub async fn get_range_stream(
+        &self,
+        file_name: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<DataStream, StoreError> {
+        let (source, file, len) = self.open_range(file_name, offset, len).await?;
+        let (tx, rx) = mpsc::channel(STREAM_CHANNEL_DEPTH);
+        let bm = Arc::clone(&self.bm);
+        let compressed = source.compressed;
+        task::spawn_blocking(move || {
+            let read = read_range(file, compressed, offset, len, &bm, |chunk| {
+                tx.blocking_send(Ok(Bytes::from(chunk))).is_ok()
+            });
+            if let Err(e) = read {
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+
+        Ok(DataStream {
+            size: len,
+            hash256: source.hash256,
+            gzip: false,
+            chunks: rx,
+        })
+    }
+
+    /// Source and open reader of `file_name` for a range read, with `len`
+    /// cut to where the file ends.
+    async fn open_range(
+        &self,
+        file_name: &str,
+        offset: u64,
+        len: u64,
+    ) -> Result<(Source, Box<dyn io::Read + Send>, u64), StoreError> {
+        if file_name.is_empty() {
+            return Err(StoreError::InvalidInput("No filename provided".into()));
+        }
+
+        let (source, file) = {
+            let _read_guard = self.operation_lock.read().await;
+            let links = self
+                .dao
+                .get_links_by_name(file_name, false)
+                .await?;
+            let link = links
+                .first()
+                .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
+
+            let source = self
+                .dao
+                .get_source_by_id(&link.source_id)
+                .await?
+                .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
+
+            let file = self.blobs.open(&source.id).await?;
+            (source, file)
+        };
+        if offset > source.size {
+            return Err(StoreError::InvalidInput(
+                "Range starts past the end of the file".into(),
+            ));
+        }
+
+        let len = len.min(source.size - offset);
+        Ok((source, file, len))
+    }
+
+    /// Open a file for reading chunk by chunk, so callers serving large
+    /// files don't hold the whole content in memory.
+    ///
+    /// With `gzip`, the chunks form a single gzip member instead of the plain
+    /// content. The content hash is still verified as the stream is read.
+    #[instrument(skip(self))]
+    pub async fn get_binary_stream(&self, file_name: &str, gzip: bool) -> Result<DataStream, StoreError> {
+        if file_name.is_empty() {
+            return Err(StoreError::InvalidInput("No filename provided".into()));
+        }
+
+        let (source, file) = {
+            let _read_guard = self.operation_lock.read().await;
+            let links = self
+                .dao
+                .get_links_by_name(file_name, false)
+                .await?;
+            let link = links
+                .first()
+                .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
+
+            let source = self
+                .dao
+                .get_source_by_id(&link.source_id)
+                .await?
+                .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
+
+            // Keep the open reader: the source may be released once the lock is dropped
+            let file = self.blobs.open(&source.id).await?;
+            (source, file)
+        };
+
+        Ok(self.open_stream(source, file, gzip))
+    }
+
+    /// Like [`get_binary_stream`](Self::get_binary_stream), but addressed by
+    /// the content's BLAKE3 hash (lowercase hex) rather than a file name, so
+    /// the address stays valid across renames and re-uploads.
+    #[instrument(skip(self))]
+    pub async fn get_blob_stream(&self, hash256: &str, gzip: bool) -> Result<DataStream, StoreError> {
+        if hash256.len() != 64 || !hash256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(StoreError::InvalidInput("Invalid content hash".into()));
+        }
+        let hash256 = hash256.to_ascii_lowercase();
+
+        let (source, file) = {
+            let _read_guard = self.operation_lock.read().await;
+            let source = self
+                .dao
+                .get_source_by_hash256(&hash256)
+                .await?
+                .ok_or_else(|| StoreError::NotFound("Blob not found".into()))?;
+
+            let file = self.blobs.open(&source.id).await?;
+            (source, file)
+        };
+
+        Ok(self.open_stream(source, file, gzip))
+    }
+
+    fn open_stream(&self, source: Source, file: Box<dyn io::Read + Send>, gzip: bool) -> DataStream {
+        let (tx, rx) = mpsc::channel(STREAM_CHANNEL_DEPTH);
+        let bm = Arc::clone(&self.bm);
+        let compressed = source.compressed;
+        let expected_hash = source.hash256.clone();
+        task::spawn_blocking(move || {
+            if let Err(e) = stream_source(file, compressed, gzip, &bm, &expected_hash, &tx) {
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+
+        DataStream {
+            size: source.size,
+            hash256: source.hash256,
+            gzip,
+            chunks: rx,
+        }
+    }
+
     pub async fn get_and_save<P: AsRef<Path>>(
         &self,
         files: &Vec<String>,
         dest: P,
-    ) -> Result<(), BoxError> {
+    ) -> Result<(), StoreError> {
         if files.is_empty() {
-            return Err(boxed_io_error(io::ErrorKind::Other, "No files requested"));
+            return Err(StoreError::InvalidInput("No files requested".into()));
         }
         let dest_root = dest.as_ref().to_path_buf();
         fs::create_dir_all(&dest_root).await?;
 
         for file in files {
-            let data = self.get_binary_data(file).await?;
             let file_name = Path::new(file)
                 .file_name()
                 .ok_or_else(|| {
-                    boxed_io_error(io::ErrorKind::InvalidInput, "Invalid file name for save target")
+                    StoreError::InvalidInput("Invalid file name for save target".into())
                 })?;
-            let dest_path = dest_root.join(file_name);
-            if let Some(parent) = dest_path.parent() {
-                fs::create_dir_all(parent).await?;
-            }
-            fs::write(&dest_path, data).await?;
+            self.save_as(file, dest_root.join(file_name)).await?;
         }
 
         Ok(())
     }
 
+    /// Write the content of `file_name` to the local path `dest`, whatever the
+    /// stored name, creating its parent directories.
+    pub async fn save_as<P: AsRef<Path>>(&self, file_name: &str, dest: P) -> Result<(), StoreError> {
+        let data = self.get_binary_data(file_name).await?;
+        let dest = dest.as_ref();
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(dest, data).await?;
+        Ok(())
+    }
+
+    /// BLAKE3 hash (lowercase hex) of the stored file, or `None` if it doesn't exist.
+    pub async fn get_hash256(&self, file_name: &str) -> Result<Option<String>, StoreError> {
+        Ok(self.stat(file_name).await?.map(|meta| meta.hash256))
+    }
+
+    /// Metadata of a stored file without reading its content, or `None` if it doesn't exist.
+    #[instrument(skip(self))]
+    /// Size of every stored file by name, for usage reports.
+    pub async fn sizes(&self) -> Result<HashMap<String, u64>, StoreError> {
+        let _read_guard = self.operation_lock.read().await;
+        let sizes = self.dao.list_link_sizes().await?;
+        Ok(sizes.into_iter().collect())
+    }
+
+    pub async fn stat(&self, file_name: &str) -> Result<Option<FileMeta>, StoreError> {
+        let _read_guard = self.operation_lock.read().await;
+        self.stat_locked(file_name).await
+    }
+
     pub async fn put_binary_data(
         &self,
         file_name: &str,
         input: &Bytes,
         cover: bool,
         compressed: bool,
-    ) -> Result<(), BoxError> {
+    ) -> Result<PutOutcome, StoreError> {
+        self.put_binary_data_checked(file_name, input, cover, compressed, None)
+            .await?
+            .ok_or_else(|| io_error(io::ErrorKind::Other, "Unconditional write was skipped"))
+    }
+
+    /// Conditional variant of [`put_binary_data`](Self::put_binary_data) that
+    /// always covers. Returns `Ok(None)` without writing when `condition`
+    /// does not hold.
+    pub async fn put_binary_data_if(
+        &self,
+        file_name: &str,
+        input: &Bytes,
+        compressed: bool,
+        condition: PutCondition,
+    ) -> Result<Option<PutOutcome>, StoreError> {
+        self.put_binary_data_checked(file_name, input, true, compressed, Some(condition))
+            .await
+    }
+
+    #[instrument(name = "put", skip(self, input, condition), fields(size = input.len()))]
+    async fn put_binary_data_checked(
+        &self,
+        file_name: &str,
+        input: &Bytes,
+        cover: bool,
+        compressed: bool,
+        condition: Option<PutCondition>,
+    ) -> Result<Option<PutOutcome>, StoreError> {
         if file_name.is_empty() {
-            return Err(boxed_io_error(io::ErrorKind::Other, "No filename provided"));
+            return Err(StoreError::InvalidInput("No filename provided".into()));
         }
+        self.writable()?;
 
-        let new_size = input.len() as u64;
-        let ext = Path::new(&file_name)
-            .extension()
-            .unwrap_or_default()
-            .to_str()
-            .unwrap_or("")
-            .to_string();
+        let encoded = self.encode_input(input, compressed).await?;
+        let _write_guard = self.operation_lock.write().await;
+        self.put_encoded_locked(file_name, input, cover, compressed, condition.as_ref(), encoded)
+            .await
+    }
+
+    /// Store every write in `puts` in order under one write lock, with all
+    /// metadata changes in a single database transaction. Returns one result
+    /// per write, like [`put_binary_data_if`](Self::put_binary_data_if) for
+    /// conditional writes; fails as a whole if the transaction cannot commit.
+    #[instrument(skip_all, fields(puts = puts.len()))]
+    pub async fn put_batch(
+        &self,
+        puts: &[BatchPut],
+    ) -> Result<Vec<Result<Option<PutOutcome>, StoreError>>, StoreError> {
+        self.writable()?;
+        let mut encoded = Vec::with_capacity(puts.len());
+        for put in puts {
+            encoded.push(self.encode_input(&put.input, put.compressed).await);
+        }
+
+        let _write_guard = self.operation_lock.write().await;
+        let tx = self.dao.begin().await?;
+        let deferred = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let scoped = StoreManager {
+            dao: tx.clone(),
+            blobs: Arc::clone(&self.blobs),
+            intents: self.intents.clone(),
+            deferred: Some(Arc::clone(&deferred)),
+            bm: Arc::clone(&self.bm),
+            operation_lock: Arc::clone(&self.operation_lock),
+            read_only: false,
+            keep_versions: self.keep_versions,
+        };
+
+        let mut results = Vec::with_capacity(puts.len());
+        for (put, encoded) in puts.iter().zip(encoded) {
+            let result = match encoded {
+                Ok(_) if put.file_name.is_empty() => Err(io_error(
+                    io::ErrorKind::Other,
+                    "No filename provided",
+                )),
+                Ok(encoded) => {
+                    scoped
+                        .put_encoded_locked(
+                            &put.file_name,
+                            &put.input,
+                            put.cover || put.condition.is_some(),
+                            put.compressed,
+                            put.condition.as_ref(),
+                            encoded,
+                        )
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+            results.push(result);
+        }
+
+        drop(scoped);
+        let committed = tx.commit().await.map_err(StoreError::from);
+        // Content follows whatever the transaction left in the DB
+        let deferred = std::mem::take(&mut *deferred.lock().unwrap_or_else(|e| e.into_inner()));
+        for intent in deferred {
+            let _ = self.finish_intent(intent).await;
+        }
+        committed?;
+        Ok(results)
+    }
 
+    /// BLAKE3 hash of `input` and the bytes to store for it; uncompressed
+    /// content shares `input`'s buffer instead of being copied.
+    async fn encode_input(
+        &self,
+        input: &Bytes,
+        compressed: bool,
+    ) -> Result<(String, Bytes), StoreError> {
         // Hash + (optional) compression are CPU-bound; run them off the runtime
         // so we don't block tokio workers on large payloads.
         let bm = Arc::clone(&self.bm);
         let input_for_blocking = input.clone();
-        let (new_hash256, new_storage_bytes) = task::spawn_blocking(move || -> Result<(String, Vec<u8>), BoxError> {
+        task::spawn_blocking(move || -> Result<(String, Bytes), StoreError> {
             let hash = utils::get_hash256_from_binary(&input_for_blocking);
             let encoded = if compressed {
-                bm.compress_all(&input_for_blocking)?
+                Bytes::from(bm.compress_all(&input_for_blocking)?)
             } else {
-                input_for_blocking.to_vec()
+                input_for_blocking
             };
             Ok((hash, encoded))
         })
         .await
-        .map_err(|e| boxed_io_error(io::ErrorKind::Other, format!("encode task join error: {}", e)))??;
-
-        let _write_guard = self.operation_lock.write().await;
-        self.put_binary_data_locked(
-            file_name,
-            cover,
-            compressed,
-            &new_hash256,
-            new_size,
-            &new_storage_bytes,
-            &ext,
-        )
-            .await
+        .map_err(|e| io_error(io::ErrorKind::Other, format!("encode task join error: {}", e)))?
     }
 
-    pub async fn put(
+    /// Check `condition` and store the encoded write; caller holds the write lock.
+    async fn put_encoded_locked(
         &self,
-        files: &Vec<String>,
+        file_name: &str,
+        input: &Bytes,
         cover: bool,
         compressed: bool,
-    ) -> Result<(), BoxError> {
+        condition: Option<&PutCondition>,
+        (new_hash256, new_storage_bytes): (String, Bytes),
+    ) -> Result<Option<PutOutcome>, StoreError> {
+        let new_size = input.len() as u64;
+        let ext = Path::new(&file_name)
+            .extension()
+            .unwrap_or_default()
+            .to_str()
+            .unwrap_or("")
+            .to_string();
+
+        if let Some(condition) = condition {
+            let current = self.stat_locked(file_name).await?.map(|meta| meta.hash256);
+            let holds = match condition {
+                PutCondition::IfAbsent => current.is_none(),
+                PutCondition::IfHashMatches(expected) => {
+                    current.is_some_and(|hash| hash.eq_ignore_ascii_case(expected))
+                }
+            };
+            if !holds {
+                return Ok(None);
+            }
+        }
+        let deduplicated = self.put_binary_data_locked(
+            file_name,
+            cover,
+            compressed,
+            &new_hash256,
+            new_size,
+            &new_storage_bytes,
+            &ext,
+        )
+            .await?;
+        Ok(Some(PutOutcome {
+            hash256: new_hash256,
+            size: new_size,
+            deduplicated,
+        }))
+    }
+
+    pub async fn put(
+        &self,
+        files: &Vec<String>,
+        cover: bool,
+        compressed: bool,
+    ) -> Result<(), StoreError> {
         if files.is_empty() {
-            return Err(boxed_io_error(io::ErrorKind::Other, "No files requested"));
+            return Err(StoreError::InvalidInput("No files requested".into()));
         }
 
         for file in files {
@@ -324,11 +1091,11 @@ impl StoreManager {
             let file_name = file_path
                 .file_name()
                 .ok_or_else(|| {
-                    boxed_io_error(io::ErrorKind::InvalidInput, "Invalid file path format")
+                    StoreError::InvalidInput("Invalid file path format".into())
                 })?
                 .to_str()
                 .ok_or_else(|| {
-                    boxed_io_error(
+                    io_error(
                         io::ErrorKind::InvalidInput,
                         "File name contains invalid UTF-8 characters",
                     )
@@ -338,22 +1105,21 @@ impl StoreManager {
             let input = match fs::read(file_path).await {
                 Ok(bytes) => Bytes::from(bytes),
                 Err(err) if err.kind() == io::ErrorKind::NotFound => {
-                    return Err(Box::new(io::Error::new(
-                        io::ErrorKind::NotFound,
-                        format!("File {} not found", &file),
-                    )));
+                    return Err(StoreError::NotFound(format!("File {} not found", &file)));
                 }
-                Err(err) => return Err(Box::new(err)),
+                Err(err) => return Err(err.into()),
             };
             self.put_binary_data(file_name, &input, cover, compressed).await?;
         }
         Ok(())
     }
 
-    pub async fn delete(&self, pattern: &str, use_regx: bool) -> Result<(), BoxError> {
+    #[instrument(skip(self))]
+    pub async fn delete(&self, pattern: &str, use_regx: bool) -> Result<(), StoreError> {
         if pattern == "" {
-            return Err(boxed_io_error(io::ErrorKind::Other, "No files requested"));
+            return Err(StoreError::InvalidInput("No files requested".into()));
         }
+        self.writable()?;
 
         {
             let _write_guard = self.operation_lock.write().await;
@@ -362,9 +1128,8 @@ impl StoreManager {
                 let source = self
                     .dao
                     .get_source_by_id(&link.source_id)
-                    .await
-                    .map_err(dao_to_io_error)?
-                    .ok_or_else(|| boxed_io_error(io::ErrorKind::NotFound, "File not found"))?;
+                    .await?
+                    .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
 
                 let source_count = source
                     .count
@@ -372,7 +1137,7 @@ impl StoreManager {
                     .ok_or(io::Error::new(io::ErrorKind::Other, "Source count is 0"))?;
 
                 self.dao.delete_link_by_id(&link.id).await?;
-                if let Err(err) = self.release_source(&link, &source, source_count).await {
+                if let Err(err) = self.release_source(&source, source_count).await {
                     let ext = Path::new(&link.name)
                         .extension()
                         .unwrap_or_default()
@@ -382,10 +1147,13 @@ impl StoreManager {
                     let _ = self
                         .dao
                         .insert_link_with_id(&link.id, &link.name, &ext, &link.source_id, link.mode)
-                        .await
-                        .map_err(dao_to_io_error);
+                        .await;
                     return Err(err);
                 }
+                // The delete stands even if its versions linger
+                if let Err(e) = self.trim_versions(&link.name, 0).await {
+                    event!(Level::WARN, "Failed to trim versions of {}: {}", link.name, e);
+                }
             }
         }
 
@@ -393,6 +1161,220 @@ impl StoreManager {
     }
 }
 
+// Version APIs.
+impl StoreManager {
+    /// The kept previous contents of `file_name`, oldest first; empty when
+    /// it has none or doesn't exist.
+    pub async fn list_versions(&self, file_name: &str) -> Result<Vec<FileVersion>, StoreError> {
+        let _read_guard = self.operation_lock.read().await;
+        let mut versions = Vec::new();
+        for version in self.dao.list_versions(file_name).await? {
+            let source = self
+                .dao
+                .get_source_by_id(&version.source_id)
+                .await?
+                .ok_or_else(|| StoreError::NotFound("Source not found".into()))?;
+            versions.push(FileVersion {
+                n: version.n,
+                size: source.size,
+                hash256: source.hash256,
+                create_at: version.create_at,
+            });
+        }
+        Ok(versions)
+    }
+
+    /// Content of version `n` of `file_name`, verified against its hash.
+    pub async fn get_version(&self, file_name: &str, n: u64) -> Result<Bytes, StoreError> {
+        let (source, file_bytes) = {
+            let _read_guard = self.operation_lock.read().await;
+            let source = self.version_source(file_name, n).await?;
+            let file_bytes = self.blobs.get(&source.id).await?;
+            (source, file_bytes)
+        };
+        self.decode_source(source, file_bytes).await
+    }
+
+    /// Make version `n` of `file_name` its content again. What it held
+    /// until now is kept as its newest version, so a restore can itself be
+    /// undone. Nothing is copied: the file shares the version's source.
+    #[instrument(skip(self))]
+    pub async fn restore_version(&self, file_name: &str, n: u64) -> Result<PutOutcome, StoreError> {
+        self.writable()?;
+        let _write_guard = self.operation_lock.write().await;
+        let link = self
+            .dao
+            .get_links_by_name(file_name, false)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
+        let source = self.version_source(file_name, n).await?;
+        let outcome = PutOutcome {
+            hash256: source.hash256.clone(),
+            size: source.size,
+            deduplicated: true,
+        };
+        if link.source_id == source.id {
+            return Ok(outcome);
+        }
+
+        self.dao
+            .update_source(
+                &source.id,
+                &source.hash256,
+                source.compressed,
+                source.size,
+                source.count + 1,
+            )
+            .await?;
+        let restored = match self.dao.update_link_source_id(&link.id, &source.id).await {
+            Ok(()) => self.keep_version(file_name, &link.source_id).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = restored {
+            let _ = self.dao.update_link_source_id(&link.id, &link.source_id).await;
+            let _ = self
+                .dao
+                .update_source(
+                    &source.id,
+                    &source.hash256,
+                    source.compressed,
+                    source.size,
+                    source.count,
+                )
+                .await;
+            return Err(err);
+        }
+        // The restore stands; versions over the limit go on the next write
+        if let Err(e) = self.trim_versions(file_name, self.keep_versions).await {
+            event!(Level::WARN, "Failed to trim versions of {}: {}", file_name, e);
+        }
+        Ok(outcome)
+    }
+
+    /// The source version `n` of `file_name` holds; caller holds a lock.
+    async fn version_source(&self, file_name: &str, n: u64) -> Result<Source, StoreError> {
+        let version = self
+            .dao
+            .get_version(file_name, n)
+            .await?
+            .ok_or_else(|| StoreError::NotFound(format!("No version {} of {}", n, file_name)))?;
+        self.dao
+            .get_source_by_id(&version.source_id)
+            .await?
+            .ok_or_else(|| StoreError::NotFound("Source not found".into()))
+    }
+}
+
+// Maintenance APIs.
+impl StoreManager {
+    /// Remove what interrupted writes and deletes left behind, as is done on
+    /// startup. Runs under the write lock, so no write is half done.
+    #[instrument(skip(self))]
+    pub async fn collect_garbage(&self) -> Result<GcReport, StoreError> {
+        self.writable()?;
+        let _write_guard = self.operation_lock.write().await;
+        self.reconcile_orphans().await
+    }
+
+    /// Let a tiered blob backend move content nobody has accessed lately to
+    /// its cold tier; see [`BlobBackend::demote_idle`]. Each source moves
+    /// under its own read lock, so writes go on in between.
+    #[instrument(skip(self))]
+    pub async fn demote_idle(&self) -> Result<u64, StoreError> {
+        self.writable()?;
+        let source_ids = {
+            let _read_guard = self.operation_lock.read().await;
+            self.dao.list_source_ids().await?
+        };
+
+        let mut moved = 0;
+        for source_id in source_ids {
+            let _read_guard = self.operation_lock.read().await;
+            // Deleted since the listing
+            if self
+                .dao
+                .get_source_by_id(&source_id)
+                .await?
+                .is_none()
+            {
+                continue;
+            }
+            moved += self.blobs.demote_idle(std::slice::from_ref(&source_id)).await?;
+        }
+        Ok(moved)
+    }
+
+    /// Read back every source and verify it against its hash. Each source is
+    /// checked under its own read lock, so writes go on in between.
+    #[instrument(skip(self))]
+    pub async fn scrub(&self) -> Result<ScrubReport, StoreError> {
+        let source_ids = {
+            let _read_guard = self.operation_lock.read().await;
+            self.dao.list_source_ids().await?
+        };
+
+        let mut report = ScrubReport::default();
+        for source_id in source_ids {
+            let (source, file_bytes) = {
+                let _read_guard = self.operation_lock.read().await;
+                // Deleted since the listing
+                let Some(source) = self
+                    .dao
+                    .get_source_by_id(&source_id)
+                    .await?
+                else {
+                    continue;
+                };
+                let file_bytes = match self.blobs.get(&source_id).await {
+                    Ok(bytes) => Some(bytes),
+                    Err(err) if blob::is_not_found(&err) => None,
+                    Err(err) => return Err(err.into()),
+                };
+                (source, file_bytes)
+            };
+
+            report.checked += 1;
+            let intact = match file_bytes {
+                Some(file_bytes) => self.source_intact(&source, file_bytes).await?,
+                None => false,
+            };
+            if !intact {
+                let _read_guard = self.operation_lock.read().await;
+                let names = self
+                    .dao
+                    .get_link_names_by_source_id(&source_id)
+                    .await?;
+                report.damaged.extend(names);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Whether `file_bytes`, as stored for `source`, decode to content
+    /// matching its hash.
+    async fn source_intact(&self, source: &Source, file_bytes: Vec<u8>) -> Result<bool, StoreError> {
+        let bm = Arc::clone(&self.bm);
+        let compressed = source.compressed;
+        let size = source.size as usize;
+        let expected_hash = source.hash256.clone();
+        task::spawn_blocking(move || {
+            let actual_hash = if compressed {
+                match bm.decompress_all(&file_bytes, size) {
+                    Ok(decoded) => utils::get_hash256_from_binary(&decoded),
+                    Err(_) => return false,
+                }
+            } else {
+                utils::get_hash256_from_binary(&file_bytes)
+            };
+            actual_hash == expected_hash
+        })
+        .await
+        .map_err(|e| io_error(io::ErrorKind::Other, format!("scrub task join error: {}", e)))
+    }
+}
+
 // Source lifecycle and consistency helpers.
 impl StoreManager {
     async fn list_locked(
@@ -401,27 +1383,48 @@ impl StoreManager {
         n: u64,
         isext: bool,
         use_regex: bool,
-    ) -> Result<Vec<Link>, BoxError> {
+    ) -> Result<Vec<Link>, StoreError> {
         let links = if isext {
-            self.dao.get_links_by_ext(pattern).await.map_err(dao_to_io_error)?
+            self.dao.get_links_by_ext(pattern).await?
         } else if (pattern == "" || pattern == "*") && use_regex {
-            self.dao.get_n_links(n).await.map_err(dao_to_io_error)?
+            self.dao.get_n_links(n).await?
         } else if pattern.contains('*') && use_regex {
             let sql_pattern = pattern.replace('*', "%");
             self.dao
                 .get_links_by_name(&sql_pattern, true)
-                .await
-                .map_err(dao_to_io_error)?
+                .await?
         } else {
             self.dao
                 .get_links_by_name(pattern, false)
-                .await
-                .map_err(dao_to_io_error)?
+                .await?
         };
 
         Ok(links)
     }
 
+    async fn stat_locked(&self, file_name: &str) -> Result<Option<FileMeta>, StoreError> {
+        let links = self
+            .dao
+            .get_links_by_name(file_name, false)
+            .await?;
+        let Some(link) = links.first() else {
+            return Ok(None);
+        };
+        let source = self
+            .dao
+            .get_source_by_id(&link.source_id)
+            .await?;
+        Ok(source.map(|source| FileMeta {
+            name: link.name.clone(),
+            ext: link.ext.clone(),
+            size: source.size,
+            hash256: source.hash256,
+            compressed: source.compressed,
+            create_at: source.create_at,
+            update_at: source.update_at,
+        }))
+    }
+
     async fn put_binary_data_locked(
         &self,
         file_name: &str,
@@ -431,137 +1434,47 @@ impl StoreManager {
         new_size: u64,
         new_storage_bytes: &[u8],
         ext: &str,
-    ) -> Result<(), BoxError> {
+    ) -> Result<bool, StoreError> {
         let links = self
             .dao
             .get_links_by_name(file_name, false)
-            .await
-            .map_err(dao_to_io_error)?;
+            .await?;
 
         if links.len() > 0 {
             let link = links
                 .get(0)
-                .ok_or_else(|| boxed_io_error(io::ErrorKind::NotFound, "File not found"))?;
+                .ok_or_else(|| StoreError::NotFound("File not found".into()))?;
 
             let source = self
                 .dao
                 .get_source_by_id(&link.source_id)
-                .await
-                .map_err(dao_to_io_error)?
-                .ok_or_else(|| boxed_io_error(io::ErrorKind::NotFound, "Source not found"))?;
-
-            if cover {
-                // If this source is shared, don't overwrite in place.
-                // Create a new source so other links pointing to the same
-                // content are not affected by this write/truncate.
-                if source.count > 1 {
-                    let new_source_id = Self::file_name_gen();
-                    self.persist_source_bytes(&new_source_id, new_storage_bytes).await?;
-
-                    if let Err(err) = self
-                        .dao
-                        .insert_source(&new_source_id, new_hash256, compressed, new_size)
-                        .await
-                    {
-                        let _ = self.remove_source_file_if_exists(&new_source_id).await;
-                        return Err(Box::new(dao_to_io_error(err)));
-                    }
-
-                    if let Err(err) = self.dao.update_link_source_id(&link.id, &new_source_id).await {
-                        let _ = self.dao.delete_source_by_id(&new_source_id).await.map_err(dao_to_io_error);
-                        let _ = self.remove_source_file_if_exists(&new_source_id).await;
-                        return Err(Box::new(dao_to_io_error(err)));
-                    }
-
-                    let source_count = source
-                        .count
-                        .checked_sub(1)
-                        .ok_or(io::Error::new(io::ErrorKind::Other, "Source count is 0"))?;
-                    if let Err(err) = self.release_source(&link, &source, source_count).await {
-                        let _ = self.dao.update_link_source_id(&link.id, &source.id).await.map_err(dao_to_io_error);
-                        let _ = self.dao.delete_source_by_id(&new_source_id).await.map_err(dao_to_io_error);
-                        let _ = self.remove_source_file_if_exists(&new_source_id).await;
-                        return Err(Box::new(io::Error::other(err.to_string())));
-                    }
-                } else {
-                    let source_path = self.source_path(&link.source_id);
-                    let previous_storage_bytes = fs::read(&source_path).await?;
-
-                    self.persist_source_bytes(&link.source_id, new_storage_bytes).await?;
-                    if let Err(err) = self
-                        .dao
-                        .update_source(
-                            &link.source_id,
-                            new_hash256,
-                            compressed,
-                            new_size,
-                            source.count,
-                        )
-                        .await
-                    {
-                        let _ = self.persist_source_bytes(&link.source_id, &previous_storage_bytes).await;
-                        return Err(Box::new(dao_to_io_error(err)));
-                    }
-                }
-            } else {
-                if new_hash256 == source.hash256 && source.compressed == compressed {
-                    return Ok(());
-                }
-
-                let new_source_id = Self::file_name_gen();
-                self.persist_source_bytes(&new_source_id, new_storage_bytes).await?;
-
-                if let Err(err) = self
-                    .dao
-                    .insert_source(&new_source_id, new_hash256, compressed, new_size)
-                    .await
-                {
-                    let _ = self.remove_source_file_if_exists(&new_source_id).await;
-                    return Err(Box::new(dao_to_io_error(err)));
-                }
-
-                if let Err(err) = self.dao.update_link_source_id(&link.id, &new_source_id).await {
-                    let _ = self
-                        .dao
-                        .delete_source_by_id(&new_source_id)
-                        .await
-                        .map_err(dao_to_io_error);
-                    let _ = self.remove_source_file_if_exists(&new_source_id).await;
-                    return Err(Box::new(dao_to_io_error(err)));
-                }
-
-                let source_count = source
-                    .count
-                    .checked_sub(1)
-                    .ok_or(io::Error::new(io::ErrorKind::Other, "Source count is 0"))?;
+                .await?
+                .ok_or_else(|| StoreError::NotFound("Source not found".into()))?;
 
-                if let Err(err) = self.release_source(link, &source, source_count).await {
-                    let _ = self
-                        .dao
-                        .update_link_source_id(&link.id, &source.id)
-                        .await
-                        .map_err(dao_to_io_error);
-                    let _ = self
-                        .dao
-                        .delete_source_by_id(&new_source_id)
-                        .await
-                        .map_err(dao_to_io_error);
-                    let _ = self.remove_source_file_if_exists(&new_source_id).await;
-                    return Err(Box::new(io::Error::other(err.to_string())));
-                }
+            if !cover && new_hash256 == source.hash256 && source.compressed == compressed {
+                return Ok(true);
             }
+
+            // Content always goes to a new source rather than over the old
+            // one: other links may share it, and a crash must not leave a
+            // row describing content that was half replaced.
+            let new_source_id = Self::file_name_gen();
+            let intent = self.begin_intent(IntentKind::Create, &new_source_id).await?;
+            let replaced = self
+                .replace_source(link, &source, &new_source_id, new_hash256, compressed, new_size, new_storage_bytes)
+                .await;
+            self.finish_intent(intent).await?;
+            replaced?;
         } else {
             if let Some(source) = self
                 .dao
                 .get_source_by_hash256(new_hash256)
-                .await
-                .map_err(dao_to_io_error)?
+                .await?
             {
                 let link_id = Uuid::new_v4().to_string();
                 self.dao
                     .insert_link_with_id(&link_id, file_name, ext, &source.id, 420)
-                    .await
-                    .map_err(dao_to_io_error)?;
+                    .await?;
 
                 if let Err(err) = self
                     .dao
@@ -574,51 +1487,167 @@ impl StoreManager {
                     )
                     .await
                 {
-                    let _ = self.dao.delete_link_by_id(&link_id).await.map_err(dao_to_io_error);
-                    return Err(Box::new(dao_to_io_error(err)));
+                    let _ = self.dao.delete_link_by_id(&link_id).await;
+                    return Err(err.into());
                 }
 
-                return Ok(());
+                return Ok(true);
             }
 
             let source_id = Self::file_name_gen();
-            let link_id = Uuid::new_v4().to_string();
+            let intent = self.begin_intent(IntentKind::Create, &source_id).await?;
+            let created = self
+                .create_source(file_name, ext, &source_id, new_hash256, compressed, new_size, new_storage_bytes)
+                .await;
+            self.finish_intent(intent).await?;
+            created?;
+        }
+
+        Ok(false)
+    }
+
+    /// Store content as the new source `source_id` and link `file_name` to it.
+    #[allow(clippy::too_many_arguments)]
+    async fn create_source(
+        &self,
+        file_name: &str,
+        ext: &str,
+        source_id: &str,
+        new_hash256: &str,
+        compressed: bool,
+        new_size: u64,
+        new_storage_bytes: &[u8],
+    ) -> Result<(), StoreError> {
+        let link_id = Uuid::new_v4().to_string();
 
-            self.persist_source_bytes(&source_id, new_storage_bytes).await?;
+        self.persist_source_bytes(source_id, new_storage_bytes).await?;
+
+        if let Err(err) = self
+            .dao
+            .insert_source(source_id, new_hash256, compressed, new_size)
+            .await
+        {
+            let _ = self.remove_source_file_if_exists(source_id).await;
+            return Err(err.into());
+        }
 
-            if let Err(err) = self
+        if let Err(err) = self
+            .dao
+            .insert_link_with_id(&link_id, file_name, ext, source_id, 420)
+            .await
+        {
+            let _ = self
                 .dao
-                .insert_source(&source_id, new_hash256, compressed, new_size)
-                .await
-            {
-                let _ = self.remove_source_file_if_exists(&source_id).await;
-                return Err(Box::new(dao_to_io_error(err)));
-            }
+                .delete_source_by_id(source_id)
+                .await;
+            let _ = self.remove_source_file_if_exists(source_id).await;
+            return Err(err.into());
+        }
+        Ok(())
+    }
 
-            if let Err(err) = self
+    /// Store content as the new source `new_source_id`, point `link` at it
+    /// and release the link's old `source`.
+    #[allow(clippy::too_many_arguments)]
+    async fn replace_source(
+        &self,
+        link: &Link,
+        source: &Source,
+        new_source_id: &str,
+        new_hash256: &str,
+        compressed: bool,
+        new_size: u64,
+        new_storage_bytes: &[u8],
+    ) -> Result<(), StoreError> {
+        self.persist_source_bytes(new_source_id, new_storage_bytes).await?;
+
+        if let Err(err) = self
+            .dao
+            .insert_source(new_source_id, new_hash256, compressed, new_size)
+            .await
+        {
+            let _ = self.remove_source_file_if_exists(new_source_id).await;
+            return Err(err.into());
+        }
+
+        if let Err(err) = self.dao.update_link_source_id(&link.id, new_source_id).await {
+            let _ = self
                 .dao
-                .insert_link_with_id(&link_id, file_name, ext, &source_id, 420)
-                .await
-            {
-                let _ = self
-                    .dao
-                    .delete_source_by_id(&source_id)
-                    .await
-                    .map_err(dao_to_io_error);
-                let _ = self.remove_source_file_if_exists(&source_id).await;
-                return Err(Box::new(dao_to_io_error(err)));
+                .delete_source_by_id(new_source_id)
+                .await;
+            let _ = self.remove_source_file_if_exists(new_source_id).await;
+            return Err(err.into());
+        }
+
+        let kept = if self.keep_versions > 0 {
+            self.keep_version(&link.name, &source.id).await
+        } else {
+            match source.count.checked_sub(1) {
+                Some(source_count) => self.release_source(source, source_count).await,
+                None => Err(io::Error::other("Source count is 0").into()),
             }
+        };
+        if let Err(err) = kept {
+            let _ = self
+                .dao
+                .update_link_source_id(&link.id, &source.id)
+                .await;
+            let _ = self
+                .dao
+                .delete_source_by_id(new_source_id)
+                .await;
+            let _ = self.remove_source_file_if_exists(new_source_id).await;
+            return Err(err);
+        }
+        // The write stands; versions over the limit go on the next one
+        if let Err(e) = self.trim_versions(&link.name, self.keep_versions).await {
+            event!(Level::WARN, "Failed to trim versions of {}: {}", link.name, e);
         }
+        Ok(())
+    }
 
+    /// Keep `source_id` as the newest version of `name`. The version takes
+    /// over the hold the link had on the source, so its count is unchanged.
+    async fn keep_version(&self, name: &str, source_id: &str) -> Result<(), StoreError> {
+        let version = Version {
+            name: name.to_string(),
+            n: self.dao.next_version_n(name).await?,
+            source_id: source_id.to_string(),
+            create_at: Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        };
+        Ok(self.dao.insert_version(&version).await?)
+    }
+
+    /// Drop the oldest versions of `name` until at most `keep` are left,
+    /// releasing their sources.
+    async fn trim_versions(&self, name: &str, keep: usize) -> Result<(), StoreError> {
+        let versions = self.dao.list_versions(name).await?;
+        let excess = versions.len().saturating_sub(keep);
+        for version in versions.into_iter().take(excess) {
+            let source = self
+                .dao
+                .get_source_by_id(&version.source_id)
+                .await?
+                .ok_or_else(|| StoreError::NotFound("Source not found".into()))?;
+            let source_count = source
+                .count
+                .checked_sub(1)
+                .ok_or(io::Error::other("Source count is 0"))?;
+
+            self.dao.delete_version(name, version.n).await?;
+            if let Err(err) = self.release_source(&source, source_count).await {
+                let _ = self.dao.insert_version(&version).await;
+                return Err(err);
+            }
+        }
         Ok(())
     }
 
     async fn release_source(
         &self,
-        link: &Link,
         source: &Source,
         source_count: u64,
-    ) -> Result<(), BoxError> {
+    ) -> Result<(), StoreError> {
         // Delete source if count is 0
         if source_count > 0 {
             self.dao
@@ -629,38 +1658,73 @@ impl StoreManager {
                     source.size,
                     source_count as u64,
                 )
-                .await
-                .map_err(dao_to_io_error)?;
+                .await?;
         } else {
-            let source_path = self.source_path(&link.source_id);
-            let source_dir = self.source_dir(&link.source_id);
-            let tombstone_path = source_dir.join(format!("{}.deleting", link.source_id));
+            // The row goes first, under an intent: content left behind by a
+            // crash in between is removed when the intent is replayed.
+            let intent = self.begin_intent(IntentKind::Release, &source.id).await?;
+            let deleted = self
+                .dao
+                .delete_source_by_id(&source.id)
+                .await
+                .map_err(StoreError::from);
+            // Once the row is gone the release stands; content that cannot
+            // be removed now stays journaled for the next startup
+            let _ = self.finish_intent(intent).await;
+            deleted?;
+        }
+        Ok(())
+    }
 
-            fs::rename(&source_path, &tombstone_path).await?;
+    /// Journal `kind` on `source_id` before its content changes.
+    async fn begin_intent(&self, kind: IntentKind, source_id: &str) -> Result<Intent, StoreError> {
+        let intents = Arc::clone(self.intents.as_ref().ok_or(StoreError::ReadOnly)?);
+        let source_id = source_id.to_string();
+        task::spawn_blocking(move || intents.begin(kind, &source_id))
+            .await
+            .map_err(|e| io_error(io::ErrorKind::Other, format!("intent task join error: {}", e)))?
+            .map_err(StoreError::from)
+    }
 
-            if let Err(err) = self.dao.delete_source_by_id(&source.id).await {
-                let _ = fs::rename(&tombstone_path, &source_path).await;
-                return Err(Box::new(dao_to_io_error(err)));
-            }
+    /// Make `intent`'s source content agree with its row, then retire the
+    /// intent. Inside a transaction this waits until the commit, since
+    /// until then the row may still roll back.
+    async fn finish_intent(&self, intent: Intent) -> Result<(), StoreError> {
+        if let Some(deferred) = &self.deferred {
+            deferred.lock().unwrap_or_else(|e| e.into_inner()).push(intent);
+            return Ok(());
+        }
 
-            if let Err(err) = fs::remove_file(&tombstone_path).await {
-                // Best-effort rollback: restore the file and the source row with
-                // its ORIGINAL count, not a default of 1.
-                let _ = fs::rename(&tombstone_path, &source_path).await;
-                let _ = self
+        let source = self
+            .dao
+            .get_source_by_id(&intent.source_id)
+            .await?;
+        match (intent.kind, source) {
+            // A created source nothing links to is a write that never
+            // finished
+            (IntentKind::Create, Some(_)) => {
+                let linked = self
                     .dao
-                    .insert_source_with_count(
-                        &source.id,
-                        &source.hash256,
-                        source.compressed,
-                        source.size,
-                        source.count,
-                    )
-                    .await;
-                return Err(Box::new(err));
+                    .get_link_names_by_source_id(&intent.source_id)
+                    .await?;
+                if linked.is_empty() && !self.dao.source_has_versions(&intent.source_id).await? {
+                    self.dao
+                        .delete_source_by_id(&intent.source_id)
+                        .await?;
+                    self.blobs.delete(&intent.source_id).await?;
+                }
+            }
+            (IntentKind::Create, None) | (IntentKind::Release, None) => {
+                self.blobs.delete(&intent.source_id).await?;
             }
+            // The release rolled back
+            (IntentKind::Release, Some(_)) => {}
         }
-        Ok(())
+        let intents = Arc::clone(self.intents.as_ref().ok_or(StoreError::ReadOnly)?);
+        task::spawn_blocking(move || intents.end(&intent))
+            .await
+            .map_err(|e| io_error(io::ErrorKind::Other, format!("intent task join error: {}", e)))?
+            .map_err(StoreError::from)
     }
 }
 
@@ -675,276 +1739,580 @@ impl StoreManager {
         format!("{}{}", utc_time_formated, nano_id)
     }
 
-    fn source_dir(&self, source_id: &str) -> PathBuf {
-        self.root
-            .join("linadata")
-            .join(&source_id[..4])
-            .join(&source_id[4..6])
+    async fn persist_source_bytes(&self, source_id: &str, bytes: &[u8]) -> Result<(), StoreError> {
+        Ok(self.blobs.put(source_id, bytes).await?)
     }
 
-    fn source_path(&self, source_id: &str) -> PathBuf {
-        self.source_dir(source_id).join(source_id)
+    async fn remove_source_file_if_exists(&self, source_id: &str) -> Result<(), StoreError> {
+        Ok(self.blobs.delete(source_id).await?)
     }
 
-    async fn persist_source_bytes(&self, source_id: &str, bytes: &[u8]) -> Result<(), BoxError> {
-        let source_dir = self.source_dir(source_id);
-        fs::create_dir_all(&source_dir).await?;
-
-        let target_path = source_dir.join(source_id);
-        let tmp_path = source_dir.join(format!("{}.tmp-{}", source_id, Uuid::new_v4()));
-
-        let mut f = fs::File::create(&tmp_path).await?;
-        f.write_all(bytes).await?;
-        f.sync_all().await?;
-        drop(f);
-
-        if let Err(err) = fs::rename(&tmp_path, &target_path).await {
-            let _ = fs::remove_file(&tmp_path).await;
-            return Err(Box::new(err));
-        }
-
-        Ok(())
-    }
-
-    async fn remove_source_file_if_exists(&self, source_id: &str) -> Result<(), BoxError> {
-        let source_path = self.source_path(source_id);
-        match fs::remove_file(source_path).await {
-            Ok(()) => Ok(()),
-            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
-            Err(err) => Err(Box::new(err)),
-        }
-    }
-
-    /// Reconcile the on-disk source store with the DB after a (potentially
-    /// crash-interrupted) restart:
-    /// - delete files left over from in-flight writes (`*.tmp-*`),
-    /// - delete stale tombstones from interrupted deletes (`*.deleting`),
-    /// - delete payload files whose source row no longer exists.
+    /// Reconcile the blob backend with the DB after a (potentially
+    /// crash-interrupted) restart, dropping leftovers of in-flight writes
+    /// and deletes and content whose source row no longer exists.
+    ///
     /// The DB is treated as the source of truth.
-    async fn reconcile_orphans(&self) -> Result<(), BoxError> {
+    async fn reconcile_orphans(&self) -> Result<GcReport, StoreError> {
         let known_ids: HashSet<String> = self
             .dao
             .list_source_ids()
-            .await
-            .map_err(dao_to_io_error)?
+            .await?
             .into_iter()
             .collect();
 
-        let linadata_root = self.root.join("linadata");
-        let mut removed_tmp = 0u64;
-        let mut removed_tombstone = 0u64;
-        let mut removed_orphan = 0u64;
-
-        // The expected layout is linadata/<id[0..4]>/<id[4..6]>/<id>. Only
-        // descend two levels so we don't accidentally chew on meta.db / logs.
-        let mut top = match stdfs::read_dir(&linadata_root) {
-            Ok(rd) => rd,
-            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
-            Err(err) => return Err(Box::new(err)),
-        };
+        let report = self.blobs.reconcile(&known_ids).await?;
+        if report.removed_tmp | report.removed_tombstones | report.removed_orphans > 0 {
+            event!(
+                Level::INFO,
+                removed_tmp = report.removed_tmp,
+                removed_tombstones = report.removed_tombstones,
+                removed_orphans = report.removed_orphans,
+                "Reconciled blob storage with the database"
+            );
+        }
+        Ok(report)
+    }
+}
 
-        while let Some(top_entry) = top.next() {
-            let top_entry = top_entry?;
-            let top_path = top_entry.path();
-            let top_meta = match top_entry.metadata() {
-                Ok(m) => m,
-                Err(_) => continue,
-            };
-            if !top_meta.is_dir() {
-                continue;
-            }
-            // Source-id prefix dirs are always 4 chars; skip anything else (e.g. "logs").
-            if top_path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .map(|s| s.len() != 4)
-                .unwrap_or(true)
-            {
-                continue;
+/// Read a source file frame by frame and send its chunks down `tx`.
+///
+/// Returns early without error once the receiver is gone.
+fn stream_source(
+    file: Box<dyn io::Read + Send>,
+    compressed: bool,
+    gzip: bool,
+    bm: &BlockManager,
+    expected_hash: &str,
+    tx: &mpsc::Sender<io::Result<Bytes>>,
+) -> io::Result<()> {
+    use std::io::Read;
+
+    let mut reader = io::BufReader::new(file);
+    let chunk_size = bm.chunk_size();
+    let mut next_chunk = || -> io::Result<Option<Vec<u8>>> {
+        if compressed {
+            let mut header = [0u8; 3];
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
             }
+            let mut frame = vec![0u8; u16::from_le_bytes([header[1], header[2]]) as usize];
+            reader.read_exact(&mut frame)?;
+            bm.decode_frame(header[0], frame)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+        } else {
+            let mut chunk = Vec::with_capacity(chunk_size);
+            (&mut reader).take(chunk_size as u64).read_to_end(&mut chunk)?;
+            Ok((!chunk.is_empty()).then_some(chunk))
+        }
+    };
+
+    let mut hasher = blake3::Hasher::new();
+    let mut encoder = gzip.then(GzipChunkEncoder::new);
+    let mut current = next_chunk()?;
+    if current.is_none() {
+        // An empty file still has to produce a valid gzip member
+        current = encoder.as_ref().map(|_| Vec::new());
+    }
+    while let Some(chunk) = current {
+        hasher.update(&chunk);
+        let next = next_chunk()?;
+        let out = match encoder.as_mut() {
+            Some(encoder) => encoder
+                .encode(&chunk, next.is_none())
+                .map_err(|e| io::Error::other(e.to_string()))?,
+            None => chunk,
+        };
+        if tx.blocking_send(Ok(Bytes::from(out))).is_err() {
+            return Ok(());
+        }
+        current = next;
+    }
 
-            let mid = match stdfs::read_dir(&top_path) {
-                Ok(rd) => rd,
-                Err(_) => continue,
-            };
-            for mid_entry in mid {
-                let mid_entry = mid_entry?;
-                let mid_path = mid_entry.path();
-                let mid_meta = match mid_entry.metadata() {
-                    Ok(m) => m,
-                    Err(_) => continue,
-                };
-                if !mid_meta.is_dir() {
-                    continue;
-                }
-                if mid_path
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .map(|s| s.len() != 2)
-                    .unwrap_or(true)
-                {
-                    continue;
-                }
-
-                let leaves = match stdfs::read_dir(&mid_path) {
-                    Ok(rd) => rd,
-                    Err(_) => continue,
-                };
-                for leaf in leaves {
-                    let leaf = leaf?;
-                    let leaf_path = leaf.path();
-                    let leaf_meta = match leaf.metadata() {
-                        Ok(m) => m,
-                        Err(_) => continue,
-                    };
-                    if !leaf_meta.is_file() {
-                        continue;
-                    }
-                    let name = match leaf_path.file_name().and_then(|n| n.to_str()) {
-                        Some(s) => s.to_string(),
-                        None => continue,
-                    };
-
-                    if name.contains(".tmp-") {
-                        if fs::remove_file(&leaf_path).await.is_ok() {
-                            removed_tmp += 1;
-                        }
-                        continue;
-                    }
-                    if let Some(stem) = name.strip_suffix(".deleting") {
-                        // If the DB still has the source, restore the file;
-                        // otherwise treat the tombstone as garbage.
-                        if known_ids.contains(stem) {
-                            let restored = mid_path.join(stem);
-                            let _ = fs::rename(&leaf_path, &restored).await;
-                        } else if fs::remove_file(&leaf_path).await.is_ok() {
-                            removed_tombstone += 1;
-                        }
-                        continue;
-                    }
+    if hasher.finalize().to_hex().as_str() != expected_hash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "data integrity check failed",
+        ));
+    }
+    Ok(())
+}
 
-                    if !known_ids.contains(&name) {
-                        if fs::remove_file(&leaf_path).await.is_ok() {
-                            removed_orphan += 1;
-                        }
-                    }
+/// Hand `emit` the `len` bytes from `offset` of the content `file` holds as
+/// stored, a chunk at a time, until it returns `false`. Chunks before the
+/// range are read past without being decoded.
+fn read_range(
+    file: Box<dyn io::Read + Send>,
+    compressed: bool,
+    offset: u64,
+    len: u64,
+    bm: &BlockManager,
+    mut emit: impl FnMut(Vec<u8>) -> bool,
+) -> io::Result<()> {
+    use std::io::Read;
+
+    let mut reader = io::BufReader::new(file);
+    let chunk_size = bm.chunk_size() as u64;
+    let mut read = 0;
+    if compressed {
+        // Every frame but the last holds a full chunk of content
+        let end = offset + len;
+        let mut pos = 0;
+        while pos < end {
+            let mut header = [0u8; 3];
+            reader.read_exact(&mut header)?;
+            let frame_len = u16::from_le_bytes([header[1], header[2]]) as u64;
+            if pos + chunk_size <= offset {
+                if io::copy(&mut (&mut reader).take(frame_len), &mut io::sink())? < frame_len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
                 }
+                pos += chunk_size;
+                continue;
             }
+            let mut frame = vec![0u8; frame_len as usize];
+            reader.read_exact(&mut frame)?;
+            let mut chunk = bm
+                .decode_frame(header[0], frame)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            let chunk_len = chunk.len() as u64;
+            let from = offset.saturating_sub(pos) as usize;
+            let to = ((end - pos) as usize).min(chunk.len());
+            if from > to {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stored chunk is too short",
+                ));
+            }
+            chunk.truncate(to);
+            chunk.drain(..from);
+            read += chunk.len() as u64;
+            if !emit(chunk) {
+                return Ok(());
+            }
+            pos += chunk_len;
         }
-
-        if removed_tmp | removed_tombstone | removed_orphan > 0 {
-            eprintln!(
-                "[linastore] reconcile: removed_tmp={} removed_tombstone={} removed_orphan={}",
-                removed_tmp, removed_tombstone, removed_orphan
-            );
+    } else {
+        io::copy(&mut (&mut reader).take(offset), &mut io::sink())?;
+        let mut reader = reader.take(len);
+        loop {
+            let mut chunk = Vec::with_capacity(chunk_size as usize);
+            (&mut reader).take(chunk_size).read_to_end(&mut chunk)?;
+            if chunk.is_empty() {
+                break;
+            }
+            read += chunk.len() as u64;
+            if !emit(chunk) {
+                return Ok(());
+            }
         }
-        Ok(())
     }
+
+    if read != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "stored content is shorter than its size",
+        ));
+    }
+    Ok(())
 }
 
 impl TidyManager {
     pub fn new() -> Self {
+        Self::with_options(TidyOptions::default())
+    }
+
+    pub fn with_options(options: TidyOptions) -> Self {
         TidyManager {
+            options,
             map_cache: HashMap::with_capacity(0x8000),
+            chooser: None,
+            progress: None,
         }
     }
 
+    /// Have `progress` called as the walk and the hashing advance.
+    pub fn set_progress(&mut self, progress: impl FnMut(TidyProgress) + 'static) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    /// Have `chooser` decide which copy of each duplicate group to keep, or
+    /// to skip the group, instead of the keep-oldest/keep-newest policy.
+    pub fn set_chooser(&mut self, chooser: impl FnMut(&DuplicateGroup) -> GroupChoice + 'static) {
+        self.chooser = Some(Box::new(chooser));
+    }
+
     pub fn tidy<P: AsRef<Path>>(
         &mut self,
         target_path: P,
         keep_new: bool,
-    ) -> Result<(), BoxError> {
-        let paths = utils::path_walk(target_path)?;
-
-        for path in paths {
-            if let Err(e) = self.file_info_collector(&path) {
-                eprintln!("[linastore] tidy: skipping {}: {}", path.display(), e);
-                continue;
-            }
+    ) -> Result<TidyReport, StoreError> {
+        let filter = utils::WalkFilter {
+            exclude: self.options.exclude.clone(),
+            include: self.options.include.clone(),
+            min_size: self.options.min_size,
+        };
+        let walk = utils::path_walk(target_path, &filter)?;
+        for (path, e) in &walk.skipped {
+            event!(Level::WARN, "Tidy skipping {}: {}", path.display(), e);
         }
+        let paths = walk.files;
+        let files_scanned = paths.len() as u64;
+        if let Some(progress) = self.progress.as_mut() {
+            progress(TidyProgress::Scanned {
+                files: files_scanned,
+            });
+        }
+
+        let (candidates, unreadable) = Self::same_size_files(paths);
+        let unhashed = self.file_info_collector(&candidates);
+
+        let mut journal = match &self.options.journal {
+            Some(path) => Some(stdfs::File::create(path).map_err(|e| {
+                io_error(
+                    e.kind(),
+                    format!("Failed to create tidy journal {}: {}", path.display(), e),
+                )
+            })?),
+            None => None,
+        };
+        let mut report = TidyReport {
+            files_scanned,
+            errors: walk.skipped.len() as u64 + unreadable + unhashed,
+            ..Default::default()
+        };
+        let prefer_dirs: Vec<PathBuf> = self
+            .options
+            .prefer_dirs
+            .iter()
+            .map(|dir| stdfs::canonicalize(dir).unwrap_or_else(|_| dir.clone()))
+            .collect();
 
         for key in self.map_cache.keys() {
             let file_infos = match self.map_cache.get(key) {
-                Some(files) if !files.is_empty() => files,
+                Some(files) if files.len() > 1 => files,
                 _ => continue,
             };
 
-            let target_file_info = if keep_new {
-                self.find_extreme_file(file_infos, |a, b| a > b)
+            report.duplicate_groups += 1;
+
+            let candidates = Self::preferred_files(file_infos, &prefer_dirs);
+            let kept = if keep_new {
+                self.find_extreme_file(&candidates, |a, b| a > b)
             } else {
-                self.find_extreme_file(file_infos, |a, b| a < b)
+                self.find_extreme_file(&candidates, |a, b| a < b)
+            };
+            let Some(kept) = kept.and_then(|kept| file_infos.iter().find(|f| f.0 == *kept.0)) else {
+                continue;
             };
+            let mut target_file_info = (&kept.0, &kept.1);
+
+            if let Some(chooser) = self.chooser.as_mut() {
+                let group = DuplicateGroup {
+                    hash256: key.clone(),
+                    size: stdfs::metadata(&file_infos[0].0).map(|m| m.len()).unwrap_or(0),
+                    files: file_infos.clone(),
+                    suggested: file_infos
+                        .iter()
+                        .position(|f| f.0 == *target_file_info.0)
+                        .unwrap_or(0),
+                };
+                match chooser(&group) {
+                    GroupChoice::Skip => continue,
+                    GroupChoice::Keep(index) => match file_infos.get(index) {
+                        Some(file_info) => target_file_info = (&file_info.0, &file_info.1),
+                        None => {
+                            event!(Level::WARN, "Tidy found no file #{} in group {}", index, key);
+                            continue;
+                        }
+                    },
+                }
+            }
 
             for file_info in file_infos {
-                if file_info.1 != *target_file_info.1 && file_info.0 != *target_file_info.0 {
+                if file_info.0 != *target_file_info.0 {
+                    // Either may have changed since the walk; a symlink is
+                    // never replaced nor linked to
+                    if !is_regular_file(&file_info.0) || !is_regular_file(target_file_info.0) {
+                        event!(
+                            Level::WARN,
+                            "Tidy skipping {}: no longer a regular file",
+                            file_info.0.display()
+                        );
+                        report.errors += 1;
+                        continue;
+                    }
                     let relative_file_path =
                         self.relative_path_with_same_root(&file_info.0, target_file_info.0);
+                    let replacement = TidyReplacement {
+                        path: file_info.0.clone(),
+                        kept: target_file_info.0.clone(),
+                        size: stdfs::metadata(&file_info.0).map(|m| m.len()).unwrap_or(0),
+                        hash256: key.clone(),
+                    };
+
+                    // Journaled before the file is removed, so an interrupted
+                    // run can still be undone
+                    if let Some(journal) = journal.as_mut() {
+                        let mut line = serde_json::to_string(&replacement)?;
+                        line.push('\n');
+                        journal.write_all(line.as_bytes())?;
+                    }
 
                     match stdfs::remove_file(&file_info.0) {
                         Ok(_) => {}
                         Err(_) => {
-                            eprintln!("Failed to tidy with file: {}", relative_file_path.display());
+                            event!(
+                                Level::WARN,
+                                "Failed to tidy with file: {}",
+                                relative_file_path.display()
+                            );
+                            report.errors += 1;
                             continue;
                         }
                     }
-                    utils::create_symlink(relative_file_path, &file_info.0)?;
-                    // Result output visible for users
-                    println!(
+                    if let Err(e) = utils::create_symlink(&relative_file_path, &file_info.0) {
+                        // Put the content back rather than leave the file missing
+                        event!(
+                            Level::WARN,
+                            "Tidy failed to link {}: {}",
+                            file_info.0.display(),
+                            e
+                        );
+                        if let Err(e) = stdfs::copy(target_file_info.0, &file_info.0) {
+                            event!(
+                                Level::ERROR,
+                                "Tidy failed to restore {}: {}",
+                                file_info.0.display(),
+                                e
+                            );
+                        }
+                        report.errors += 1;
+                        continue;
+                    }
+                    event!(
+                        Level::INFO,
                         "{} -> {}",
                         file_info.0.display(),
                         target_file_info.0.display()
                     );
+                    report.bytes_reclaimed += replacement.size;
+                    report.replacements.push(replacement);
                 }
             }
         }
 
-        Ok(())
+        event!(
+            Level::INFO,
+            "Scanned {} files: {} duplicate groups, {} files linked, {} bytes reclaimed, {} errors",
+            report.files_scanned,
+            report.duplicate_groups,
+            report.replacements.len(),
+            report.bytes_reclaimed,
+            report.errors
+        );
+        Ok(report)
+    }
+
+    /// Undo a tidy run from its journal: each symlink it made is replaced by
+    /// a copy of the file it points at. Returns the restored paths; entries
+    /// whose path is a regular file again, or whose kept copy is gone or
+    /// changed, are skipped with a warning.
+    pub fn untidy<P: AsRef<Path>>(journal: P) -> Result<Vec<PathBuf>, StoreError> {
+        let journal = journal.as_ref();
+        let file = stdfs::File::open(journal).map_err(|e| {
+            io_error(
+                e.kind(),
+                format!("Failed to open tidy journal {}: {}", journal.display(), e),
+            )
+        })?;
+
+        let mut restored = Vec::new();
+        for line in io::BufReader::new(file).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let replacement: TidyReplacement = serde_json::from_str(&line).map_err(|e| {
+                io_error(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid tidy journal entry {:?}: {}", line, e),
+                )
+            })?;
+            match Self::restore(&replacement) {
+                Ok(true) => restored.push(replacement.path),
+                Ok(false) => {}
+                Err(e) => event!(
+                    Level::WARN,
+                    "Untidy skipping {}: {}",
+                    replacement.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(restored)
+    }
+
+    /// Put a copy of the kept file back in place of the symlink; false when
+    /// there is nothing to restore.
+    fn restore(replacement: &TidyReplacement) -> Result<bool, StoreError> {
+        let path = &replacement.path;
+        match stdfs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.file_type().is_symlink() => {
+                event!(Level::WARN, "Untidy skipping {}: no longer a link", path.display());
+                return Ok(false);
+            }
+            // Removed but not yet linked when the run was interrupted
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+            Ok(_) => {}
+        }
+        if utils::get_hash256_from_file(&replacement.kept)? != replacement.hash256 {
+            return Err(io_error(
+                io::ErrorKind::InvalidData,
+                format!("{} changed since it was kept", replacement.kept.display()),
+            ));
+        }
+
+        let file_name = path.file_name().ok_or_else(|| {
+            StoreError::InvalidInput("Journal entry has no file name".into())
+        })?;
+        let tmp = path.with_file_name(format!(".{}.untidy", file_name.to_string_lossy()));
+        stdfs::copy(&replacement.kept, &tmp)?;
+        if let Err(e) = stdfs::rename(&tmp, path) {
+            let _ = stdfs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        Ok(true)
+    }
+
+    /// The copies in the first of `prefer_dirs` that holds any, or all of
+    /// them when none does.
+    fn preferred_files(
+        file_infos: &[(PathBuf, SystemTime)],
+        prefer_dirs: &[PathBuf],
+    ) -> Vec<(PathBuf, SystemTime)> {
+        let rank = |path: &PathBuf| {
+            let path = stdfs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            prefer_dirs.iter().position(|dir| path.starts_with(dir))
+        };
+        let ranks: Vec<_> = file_infos.iter().map(|f| rank(&f.0)).collect();
+        let Some(best) = ranks.iter().flatten().min() else {
+            return file_infos.to_vec();
+        };
+        file_infos
+            .iter()
+            .zip(&ranks)
+            .filter(|(_, rank)| *rank == &Some(*best))
+            .map(|(f, _)| f.clone())
+            .collect()
+    }
+
+    /// The files that share their size with another; only those can have
+    /// duplicates, so the rest are never hashed. Also returns how many files
+    /// were skipped as unreadable.
+    fn same_size_files(paths: Vec<PathBuf>) -> (Vec<PathBuf>, u64) {
+        let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        let mut skipped = 0;
+        for path in paths {
+            match stdfs::metadata(&path) {
+                Ok(metadata) => by_size.entry(metadata.len()).or_default().push(path),
+                Err(e) => {
+                    event!(Level::WARN, "Tidy skipping {}: {}", path.display(), e);
+                    skipped += 1;
+                }
+            }
+        }
+        let candidates = by_size
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .flatten()
+            .collect();
+        (candidates, skipped)
+    }
+
+    /// Hash `paths` in parallel and group them by content, returning how
+    /// many files were skipped as unreadable.
+    fn file_info_collector(&mut self, paths: &[PathBuf]) -> u64 {
+        let total = paths.len() as u64;
+        let mut done = 0;
+        let mut skipped = 0;
+        // Hashed a batch at a time so progress can be reported in between
+        let order = self.options.order;
+        for batch in paths.chunks(rayon::current_num_threads() * 4) {
+            let infos: Vec<_> = batch
+                .par_iter()
+                .map(|path| (path, Self::file_info(path, order)))
+                .collect();
+            for (path, info) in infos {
+                match info {
+                    Ok((hash_code, time)) => self
+                        .map_cache
+                        .entry(hash_code)
+                        .or_default()
+                        .push((path.clone(), time)),
+                    Err(e) => {
+                        event!(Level::WARN, "Tidy skipping {}: {}", path.display(), e);
+                        skipped += 1;
+                    }
+                }
+            }
+            done += batch.len() as u64;
+            if let Some(progress) = self.progress.as_mut() {
+                progress(TidyProgress::Hashed { done, total });
+            }
+        }
+        skipped
     }
 
-    fn file_info_collector(&mut self, path: &Path) -> Result<(), BoxError> {
+    /// Content hash of the file at `path` and the time `order` compares.
+    fn file_info(path: &Path, order: TidyOrder) -> Result<(String, SystemTime), StoreError> {
         let hash_code = utils::get_hash256_from_file(path).map_err(|e| {
-            boxed_io_error(
+            io_error(
                 io::ErrorKind::Other,
                 format!("Hash of file {} generate error: {}", path.display(), e),
             )
         })?;
 
-        let created_date = stdfs::metadata(path)
-            .and_then(|metadata| metadata.created())
+        let time = stdfs::metadata(path)
+            .and_then(|metadata| match order {
+                // Not every filesystem records when a file was created
+                TidyOrder::Created => metadata.created().or_else(|_| metadata.modified()),
+                TidyOrder::Modified => metadata.modified(),
+                TidyOrder::Changed => changed_time(&metadata),
+                TidyOrder::Path => Ok(SystemTime::UNIX_EPOCH),
+            })
             .map_err(|e| {
-                boxed_io_error(
+                io_error(
                     io::ErrorKind::Other,
                     format!("Get file {} metadata/date error: {}", path.display(), e),
                 )
             })?;
 
-        let formated_created_date = DateTime::<Utc>::from(created_date)
-            .format("%Y%m%d%H%M%S")
-            .to_string();
-
-        self.map_cache
-            .entry(hash_code)
-            .or_insert_with(Vec::new)
-            .push((path.to_path_buf(), formated_created_date));
-
-        Ok(())
+        Ok((hash_code, time))
     }
 
+    /// The file `compare` ranks first, `None` when there are none.
     fn find_extreme_file<'a, F>(
         &self,
-        file_infos: &'a [(PathBuf, String)],
+        file_infos: &'a [(PathBuf, SystemTime)],
         compare: F,
-    ) -> (&'a PathBuf, &'a String)
+    ) -> Option<(&'a PathBuf, &'a SystemTime)>
     where
-        F: Fn(&String, &String) -> bool,
+        F: Fn(&SystemTime, &SystemTime) -> bool,
     {
-        let mut extreme = (&file_infos[0].0, &file_infos[0].1);
-        for file_info in &file_infos[1..] {
-            if compare(&file_info.1, extreme.1) {
+        let (first, rest) = file_infos.split_first()?;
+        let mut extreme = (&first.0, &first.1);
+        for file_info in rest {
+            // Equal times go to the path that sorts first, so the pick never
+            // depends on the order the walk found the copies in
+            let tie = file_info.1 == *extreme.1 && file_info.0 < *extreme.0;
+            if compare(&file_info.1, extreme.1) || tie {
                 extreme = (&file_info.0, &file_info.1);
             }
         }
-        extreme
+        Some(extreme)
     }
 
     fn relative_path_with_same_root<P: AsRef<Path>>(&self, from: P, to: P) -> PathBuf {
@@ -967,98 +2335,383 @@ impl TidyManager {
             result.push(".");
         }
 
-        for comp in &to_components[common..] {
-            result.push(comp.as_os_str());
+        for comp in &to_components[common..] {
+            result.push(comp.as_os_str());
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn generate_random_binary(size: usize) -> Bytes {
+        let mut rng = rand::rng();
+        let mut data = vec![0u8; size];
+        rng.fill(&mut data[..]);
+        Bytes::from(data)
+    }
+
+    #[tokio::test]
+    async fn test_data_flow_store() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let data = generate_random_binary(64 * 1024);
+        let sm = StoreManager::new(temp_dir.path()).await.unwrap();
+        sm.put_binary_data("random.txt", &data, true, true).await.unwrap();
+        let data_get = sm.get_binary_data("random.txt").await.unwrap();
+        assert_eq!(data, data_get, "Data flow test failed");
+    }
+
+    #[tokio::test]
+    async fn test_store_manager_new() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await;
+        assert!(sm.is_ok());
+
+        // Verify linadata directory was created
+        let linadata_path = temp_dir.path().join("linadata");
+        assert!(linadata_path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_put_binary_data_new_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data = Bytes::from(vec![1, 2, 3, 4, 5]);
+
+        let result = sm.put_binary_data("test.txt", &data, false, false).await;
+        assert!(result.is_ok());
+
+        // Verify file can be retrieved
+        let retrieved = sm.get_binary_data("test.txt").await.expect("Failed to get data");
+        assert_eq!(data, retrieved);
+    }
+
+    #[tokio::test]
+    async fn test_put_binary_data_compressed() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data = Bytes::from(vec![42u8; 10000]); // Highly compressible data
+
+        let result = sm.put_binary_data("compressed.txt", &data, false, true).await;
+        assert!(result.is_ok());
+
+        let retrieved = sm
+            .get_binary_data("compressed.txt")
+            .await
+            .expect("Failed to get data");
+        assert_eq!(data, retrieved);
+    }
+
+    #[tokio::test]
+    async fn test_put_binary_data_cover() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data1 = Bytes::from(vec![1, 2, 3, 4, 5]);
+        let data2 = Bytes::from(vec![6, 7, 8, 9, 10]);
+
+        // Put initial data
+        sm.put_binary_data("test.txt", &data1, false, false)
+            .await
+            .expect("Failed to put data");
+
+        // Cover with new data
+        sm.put_binary_data("test.txt", &data2, true, false)
+            .await
+            .expect("Failed to cover data");
+
+        let retrieved = sm.get_binary_data("test.txt").await.expect("Failed to get data");
+        assert_eq!(data2, retrieved);
+    }
+
+    #[tokio::test]
+    async fn test_put_binary_data_reports_outcome() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data = Bytes::from(vec![9u8; 2048]);
+
+        let first = sm
+            .put_binary_data("first.bin", &data, false, true)
+            .await
+            .expect("Failed to put data");
+        assert_eq!(first.hash256, utils::get_hash256_from_binary(&data));
+        assert_eq!(first.size, 2048);
+        assert!(!first.deduplicated);
+
+        // Same content under another name links to the existing source
+        let second = sm
+            .put_binary_data("second.bin", &data, false, true)
+            .await
+            .expect("Failed to put data");
+        assert_eq!(second.hash256, first.hash256);
+        assert!(second.deduplicated);
+
+        // Re-putting identical content without cover is a no-op
+        let again = sm
+            .put_binary_data("first.bin", &data, false, true)
+            .await
+            .expect("Failed to put data");
+        assert!(again.deduplicated);
+    }
+
+    #[tokio::test]
+    async fn test_get_gzip_data() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data = Bytes::from(b"compressible ".repeat(20_000));
+
+        sm.put_binary_data("packed.txt", &data, false, true).await.unwrap();
+        sm.put_binary_data("plain.txt", &data, false, false).await.unwrap();
+
+        for name in ["packed.txt", "plain.txt"] {
+            let gz = sm.get_gzip_data(name).await.expect("Failed to get gzip data");
+            assert!(gz.len() < data.len());
+            let mut decoded = Vec::new();
+            GzDecoder::new(&gz[..]).read_to_end(&mut decoded).unwrap();
+            assert_eq!(&decoded[..], &data[..]);
+        }
+
+        assert!(sm.get_gzip_data("missing.txt").await.is_err());
+    }
+
+    async fn collect_stream(mut stream: DataStream) -> Vec<u8> {
+        let mut content = Vec::new();
+        while let Some(chunk) = stream.chunks.recv().await {
+            content.extend_from_slice(&chunk.expect("Failed to read chunk"));
+        }
+        content
+    }
+
+    #[tokio::test]
+    async fn test_get_binary_stream() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data = generate_random_binary(300_000);
+
+        sm.put_binary_data("packed.bin", &data, false, true).await.unwrap();
+        sm.put_binary_data("plain.bin", &data, false, false).await.unwrap();
+        sm.put_binary_data("empty.bin", &Bytes::new(), false, true).await.unwrap();
+
+        for name in ["packed.bin", "plain.bin"] {
+            let stream = sm.get_binary_stream(name, false).await.expect("Failed to open stream");
+            assert_eq!(stream.size, data.len() as u64);
+            assert_eq!(stream.hash256, utils::get_hash256_from_binary(&data));
+            assert!(!stream.gzip);
+            assert_eq!(&collect_stream(stream).await[..], &data[..]);
+
+            let gz = collect_stream(sm.get_binary_stream(name, true).await.unwrap()).await;
+            let mut decoded = Vec::new();
+            GzDecoder::new(&gz[..]).read_to_end(&mut decoded).unwrap();
+            assert_eq!(&decoded[..], &data[..]);
         }
 
-        result
-    }
-}
+        assert!(collect_stream(sm.get_binary_stream("empty.bin", false).await.unwrap()).await.is_empty());
+        let gz = collect_stream(sm.get_binary_stream("empty.bin", true).await.unwrap()).await;
+        let mut decoded = Vec::new();
+        GzDecoder::new(&gz[..]).read_to_end(&mut decoded).unwrap();
+        assert!(decoded.is_empty());
 
-#[cfg(test)]
-mod tests {
-    use rand::Rng;
-    use std::sync::Arc;
-    use tempfile::TempDir;
+        assert!(sm.get_binary_stream("missing.bin", false).await.is_err());
+    }
 
-    use super::*;
+    #[tokio::test]
+    async fn test_get_binary_range() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data: Bytes = (0..300_000u32).map(|i| (i / 7 % 251) as u8).collect::<Vec<_>>().into();
+
+        sm.put_binary_data("packed.bin", &data, false, true).await.unwrap();
+        sm.put_binary_data("plain.bin", &data, false, false).await.unwrap();
+
+        let chunk = sm.bm.chunk_size() as u64;
+        for name in ["packed.bin", "plain.bin"] {
+            // Within the first chunk, across chunk boundaries, and past the end
+            for (offset, len) in [(0, 10), (chunk - 5, 10), (chunk * 2 + 1, chunk * 2), (299_990, 100)] {
+                let range = sm.get_binary_range(name, offset, len).await.expect("Failed to read range");
+                let end = (offset + len).min(data.len() as u64);
+                assert_eq!(&range[..], &data[offset as usize..end as usize], "{} {}+{}", name, offset, len);
+            }
+            assert!(sm.get_binary_range(name, 300_000, 5).await.unwrap().is_empty());
+            assert!(matches!(
+                sm.get_binary_range(name, 300_001, 5).await,
+                Err(StoreError::InvalidInput(_))
+            ));
+        }
 
-    fn generate_random_binary(size: usize) -> Bytes {
-        let mut rng = rand::rng();
-        let mut data = vec![0u8; size];
-        rng.fill(&mut data[..]);
-        Bytes::from(data)
+        assert!(matches!(
+            sm.get_binary_range("missing.bin", 0, 1).await,
+            Err(StoreError::NotFound(_))
+        ));
     }
 
     #[tokio::test]
-    async fn test_data_flow_store() {
-        let data = generate_random_binary(64 * 1024);
-        let sm = StoreManager::new(".").await.unwrap();
-        let _ = sm.put_binary_data("random.txt", &data, true, true).await;
-        let data_get = sm.get_binary_data("random.txt").await.unwrap();
-        assert_eq!(data, data_get, "Data flow test failed");
+    async fn test_get_range_stream() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data: Bytes = (0..300_000u32).map(|i| (i / 7 % 251) as u8).collect::<Vec<_>>().into();
+
+        sm.put_binary_data("packed.bin", &data, false, true).await.unwrap();
+        sm.put_binary_data("plain.bin", &data, false, false).await.unwrap();
+
+        let chunk = sm.bm.chunk_size() as u64;
+        for name in ["packed.bin", "plain.bin"] {
+            for (offset, len) in [(0, 300_000), (chunk - 5, chunk * 2), (299_990, 100), (300_000, 5)] {
+                let stream = sm.get_range_stream(name, offset, len).await.expect("Failed to open range");
+                let end = (offset + len).min(data.len() as u64);
+                assert_eq!(stream.size, end - offset);
+                let range = collect_stream(stream).await;
+                assert_eq!(&range[..], &data[offset as usize..end as usize], "{} {}+{}", name, offset, len);
+            }
+            assert!(matches!(
+                sm.get_range_stream(name, 300_001, 5).await,
+                Err(StoreError::InvalidInput(_))
+            ));
+        }
     }
 
     #[tokio::test]
-    async fn test_store_manager_new() {
+    async fn test_get_blob_stream() {
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
-        let sm = StoreManager::new(temp_dir.path()).await;
-        assert!(sm.is_ok());
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data = generate_random_binary(100_000);
+        let outcome = sm.put_binary_data("a.bin", &data, false, true).await.unwrap();
 
-        // Verify linadata directory was created
-        let linadata_path = temp_dir.path().join("linadata");
-        assert!(linadata_path.exists());
+        let stream = sm.get_blob_stream(&outcome.hash256.to_ascii_uppercase(), false).await.unwrap();
+        assert_eq!(stream.hash256, outcome.hash256);
+        assert_eq!(&collect_stream(stream).await[..], &data[..]);
+
+        // The blob outlives the name it was uploaded under while another name references it
+        sm.put_binary_data("b.bin", &data, false, true).await.unwrap();
+        sm.delete("a.bin", false).await.unwrap();
+        assert!(sm.get_blob_stream(&outcome.hash256, false).await.is_ok());
+
+        assert!(sm.get_blob_stream("not-a-hash", false).await.is_err());
+        assert!(sm.get_blob_stream(&"0".repeat(64), false).await.is_err());
     }
 
     #[tokio::test]
-    async fn test_put_binary_data_new_file() {
+    async fn test_stat() {
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
         let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
-        let data = Bytes::from(vec![1, 2, 3, 4, 5]);
-
-        let result = sm.put_binary_data("test.txt", &data, false, false).await;
-        assert!(result.is_ok());
-
-        // Verify file can be retrieved
-        let retrieved = sm.get_binary_data("test.txt").await.expect("Failed to get data");
-        assert_eq!(data, retrieved);
+        let data = Bytes::from(vec![7u8; 4096]);
+
+        assert_eq!(sm.stat("meta.txt").await.unwrap(), None);
+
+        sm.put_binary_data("meta.txt", &data, false, true).await.unwrap();
+        let meta = sm.stat("meta.txt").await.unwrap().expect("meta missing");
+        assert_eq!(meta.name, "meta.txt");
+        assert_eq!(meta.ext, "txt");
+        assert_eq!(meta.size, 4096);
+        assert_eq!(meta.hash256, utils::get_hash256_from_binary(&data));
+        assert!(meta.compressed);
+        assert!(!meta.create_at.is_empty());
     }
 
     #[tokio::test]
-    async fn test_put_binary_data_compressed() {
+    async fn test_put_binary_data_if_absent() {
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
         let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
-        let data = Bytes::from(vec![42u8; 10000]); // Highly compressible data
+        let data1 = Bytes::from(vec![1, 2, 3]);
+        let data2 = Bytes::from(vec![4, 5, 6]);
 
-        let result = sm.put_binary_data("compressed.txt", &data, false, true).await;
-        assert!(result.is_ok());
+        let written = sm
+            .put_binary_data_if("cond.txt", &data1, false, PutCondition::IfAbsent)
+            .await
+            .expect("Failed to put data");
+        assert!(written.is_some());
 
-        let retrieved = sm
-            .get_binary_data("compressed.txt")
+        let written = sm
+            .put_binary_data_if("cond.txt", &data2, false, PutCondition::IfAbsent)
             .await
-            .expect("Failed to get data");
-        assert_eq!(data, retrieved);
+            .expect("Failed to evaluate precondition");
+        assert!(written.is_none());
+        assert_eq!(sm.get_binary_data("cond.txt").await.unwrap(), data1);
     }
 
     #[tokio::test]
-    async fn test_put_binary_data_cover() {
+    async fn test_put_binary_data_if_hash_matches() {
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
         let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
-        let data1 = Bytes::from(vec![1, 2, 3, 4, 5]);
-        let data2 = Bytes::from(vec![6, 7, 8, 9, 10]);
+        let data1 = Bytes::from(vec![1, 2, 3]);
+        let data2 = Bytes::from(vec![4, 5, 6]);
+        let data3 = Bytes::from(vec![7, 8, 9]);
 
-        // Put initial data
-        sm.put_binary_data("test.txt", &data1, false, false)
+        // Missing files never match a hash
+        let stale = utils::get_hash256_from_binary(&data1);
+        let written = sm
+            .put_binary_data_if("cas.txt", &data1, false, PutCondition::IfHashMatches(stale.clone()))
             .await
-            .expect("Failed to put data");
+            .unwrap();
+        assert!(written.is_none());
+        assert_eq!(sm.get_hash256("cas.txt").await.unwrap(), None);
 
-        // Cover with new data
-        sm.put_binary_data("test.txt", &data2, true, false)
+        sm.put_binary_data("cas.txt", &data1, false, false).await.unwrap();
+        let current = sm.get_hash256("cas.txt").await.unwrap().expect("hash missing");
+        assert_eq!(current, stale);
+
+        let written = sm
+            .put_binary_data_if("cas.txt", &data2, true, PutCondition::IfHashMatches(current.to_uppercase()))
             .await
-            .expect("Failed to cover data");
+            .unwrap();
+        assert!(written.is_some());
+        assert_eq!(sm.get_binary_data("cas.txt").await.unwrap(), data2);
 
-        let retrieved = sm.get_binary_data("test.txt").await.expect("Failed to get data");
-        assert_eq!(data2, retrieved);
+        // The old hash is now stale
+        let written = sm
+            .put_binary_data_if("cas.txt", &data3, false, PutCondition::IfHashMatches(current))
+            .await
+            .unwrap();
+        assert!(written.is_none());
+        assert_eq!(sm.get_binary_data("cas.txt").await.unwrap(), data2);
+    }
+
+    #[tokio::test]
+    async fn test_put_batch() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        sm.put_binary_data("taken.txt", &Bytes::from_static(b"old"), false, false)
+            .await
+            .unwrap();
+
+        let put = |name: &str, data: &'static [u8], condition| BatchPut {
+            file_name: name.to_string(),
+            input: Bytes::from_static(data),
+            cover: false,
+            compressed: false,
+            condition,
+        };
+        let results = sm
+            .put_batch(&[
+                put("a.txt", b"first", None),
+                put("b.txt", b"first", None),
+                put("taken.txt", b"new", Some(PutCondition::IfAbsent)),
+                put("", b"nameless", None),
+            ])
+            .await
+            .expect("Failed to commit batch");
+
+        assert_eq!(results.len(), 4);
+        assert!(!results[0].as_ref().unwrap().as_ref().unwrap().deduplicated);
+        // Later writes in the batch see the earlier ones
+        assert!(results[1].as_ref().unwrap().as_ref().unwrap().deduplicated);
+        assert!(results[2].as_ref().unwrap().is_none());
+        assert!(results[3].is_err());
+
+        assert_eq!(sm.get_binary_data("b.txt").await.unwrap(), Bytes::from_static(b"first"));
+        assert_eq!(sm.get_binary_data("taken.txt").await.unwrap(), Bytes::from_static(b"old"));
     }
 
     #[tokio::test]
@@ -1068,7 +2721,7 @@ mod tests {
         let data = Bytes::from(vec![1, 2, 3, 4, 5]);
 
         let result = sm.put_binary_data("", &data, false, false).await;
-        assert!(result.is_err());
+        assert!(matches!(result, Err(StoreError::InvalidInput(_))));
     }
 
     #[tokio::test]
@@ -1077,7 +2730,7 @@ mod tests {
         let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
 
         let result = sm.get_binary_data("nonexistent.txt").await;
-        assert!(result.is_err());
+        assert!(matches!(result, Err(StoreError::NotFound(_))));
     }
 
     #[tokio::test]
@@ -1086,7 +2739,80 @@ mod tests {
         let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
 
         let result = sm.get_binary_data("").await;
-        assert!(result.is_err());
+        assert!(matches!(result, Err(StoreError::InvalidInput(_))));
+    }
+
+    #[tokio::test]
+    async fn test_versions_kept_on_overwrite() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreOptions::new(temp_dir.path())
+            .keep_versions(2)
+            .open()
+            .await
+            .expect("Failed to create StoreManager");
+        let contents: Vec<Bytes> = (1..=4).map(|i| Bytes::from(format!("version {}", i))).collect();
+        for content in &contents {
+            sm.put_binary_data("a.txt", content, true, false).await.unwrap();
+        }
+
+        // Three overwrites, of which the newest two are kept
+        let versions = sm.list_versions("a.txt").await.unwrap();
+        assert_eq!(versions.iter().map(|v| v.n).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(versions[1].hash256, utils::get_hash256_from_binary(&contents[2]));
+        assert_eq!(sm.get_version("a.txt", 3).await.unwrap(), contents[2]);
+        assert!(matches!(sm.get_version("a.txt", 1).await, Err(StoreError::NotFound(_))));
+
+        // Restoring keeps what it replaces as the newest version
+        let outcome = sm.restore_version("a.txt", 2).await.unwrap();
+        assert!(outcome.deduplicated);
+        assert_eq!(sm.get_binary_data("a.txt").await.unwrap(), contents[1]);
+        let versions = sm.list_versions("a.txt").await.unwrap();
+        assert_eq!(versions.iter().map(|v| v.n).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(sm.get_version("a.txt", 4).await.unwrap(), contents[3]);
+
+        // Versions go with their file, and so does their content
+        sm.delete("a.txt", false).await.unwrap();
+        assert!(sm.list_versions("a.txt").await.unwrap().is_empty());
+        assert!(sm.dao.list_source_ids().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_no_versions_by_default() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        sm.put_binary_data("a.txt", &Bytes::from_static(b"one"), true, false).await.unwrap();
+        sm.put_binary_data("a.txt", &Bytes::from_static(b"two"), true, false).await.unwrap();
+
+        assert!(sm.list_versions("a.txt").await.unwrap().is_empty());
+        assert_eq!(sm.dao.list_source_ids().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_only_store() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let missing = StoreOptions::new(temp_dir.path()).read_only(true).open().await;
+        assert!(matches!(missing, Err(StoreError::NotFound(_))));
+
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let data = Bytes::from_static(b"read me");
+        sm.put_binary_data("a.txt", &data, false, false).await.unwrap();
+
+        // Opened beside the writer, it reads but never writes
+        let reader = StoreOptions::new(temp_dir.path())
+            .read_only(true)
+            .open()
+            .await
+            .expect("Failed to open read-only");
+        assert!(reader.is_read_only());
+        assert_eq!(reader.get_binary_data("a.txt").await.unwrap(), data);
+        assert_eq!(reader.sizes().await.unwrap()["a.txt"], data.len() as u64);
+        assert!(matches!(
+            reader.put_binary_data("b.txt", &data, false, false).await,
+            Err(StoreError::ReadOnly)
+        ));
+        assert!(matches!(reader.delete("a.txt", false).await, Err(StoreError::ReadOnly)));
+        assert!(matches!(reader.collect_garbage().await, Err(StoreError::ReadOnly)));
+        assert!(sm.stat("a.txt").await.unwrap().is_some());
     }
 
     #[tokio::test]
@@ -1280,6 +3006,24 @@ mod tests {
         assert_eq!(&data[..], &saved_data);
     }
 
+    #[tokio::test]
+    async fn test_save_as() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        let save_dir = TempDir::new().expect("Failed to create save dir");
+        let data = Bytes::from(vec![1, 2, 3, 4, 5]);
+
+        sm.put_binary_data("dir/test.txt", &data, false, false)
+            .await
+            .expect("Failed to put data");
+
+        let dest = save_dir.path().join("copies").join("renamed.bin");
+        sm.save_as("dir/test.txt", &dest).await.expect("Failed to save");
+        assert_eq!(std::fs::read(&dest).expect("Failed to read saved file"), &data[..]);
+
+        assert!(sm.save_as("missing.txt", save_dir.path().join("missing")).await.is_err());
+    }
+
     #[tokio::test]
     async fn test_get_and_save_empty_files() {
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
@@ -1372,6 +3116,142 @@ mod tests {
         assert_eq!(source.count, 2);
     }
 
+    /// Keeps sources in memory, to check the manager goes through its backend.
+    #[derive(Debug, Default)]
+    struct MemoryBackend {
+        blobs: std::sync::Mutex<HashMap<String, Vec<u8>>>,
+    }
+
+    impl BlobBackend for MemoryBackend {
+        fn get<'a>(&'a self, source_id: &'a str) -> crate::blob::BlobFuture<'a, Vec<u8>> {
+            Box::pin(async move {
+                self.blobs
+                    .lock()
+                    .unwrap()
+                    .get(source_id)
+                    .cloned()
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such blob").into())
+            })
+        }
+
+        fn put<'a>(&'a self, source_id: &'a str, bytes: &'a [u8]) -> crate::blob::BlobFuture<'a, ()> {
+            Box::pin(async move {
+                self.blobs.lock().unwrap().insert(source_id.to_string(), bytes.to_vec());
+                Ok(())
+            })
+        }
+
+        fn delete<'a>(&'a self, source_id: &'a str) -> crate::blob::BlobFuture<'a, ()> {
+            Box::pin(async move {
+                self.blobs.lock().unwrap().remove(source_id);
+                Ok(())
+            })
+        }
+
+        fn exists<'a>(&'a self, source_id: &'a str) -> crate::blob::BlobFuture<'a, bool> {
+            Box::pin(async move { Ok(self.blobs.lock().unwrap().contains_key(source_id)) })
+        }
+
+        fn reconcile<'a>(&'a self, known_ids: &'a HashSet<String>) -> crate::blob::BlobFuture<'a, GcReport> {
+            Box::pin(async move {
+                let mut blobs = self.blobs.lock().unwrap();
+                let before = blobs.len();
+                blobs.retain(|id, _| known_ids.contains(id));
+                Ok(GcReport {
+                    removed_orphans: (before - blobs.len()) as u64,
+                    ..GcReport::default()
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_blob_backend() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let backend = Arc::new(MemoryBackend::default());
+        let sm = StoreManager::with_backend(temp_dir.path(), backend.clone())
+            .await
+            .expect("Failed to create StoreManager");
+        let data = generate_random_binary(4096);
+
+        sm.put_binary_data("mem.bin", &data, false, true).await.expect("put");
+        let source_id = sm.dao.get_links_by_name("mem.bin", false).await.expect("links")[0]
+            .source_id
+            .clone();
+        assert!(backend.exists(&source_id).await.unwrap());
+        assert!(!FsBackend::new(temp_dir.path().join("linadata")).source_path(&source_id).exists());
+        assert_eq!(sm.get_binary_data("mem.bin").await.expect("get"), data);
+        assert_eq!(collect_stream(sm.get_binary_stream("mem.bin", false).await.unwrap()).await, data);
+
+        sm.delete("mem.bin", false).await.expect("delete");
+        assert!(!backend.exists(&source_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_intent_replay_undoes_interrupted_write() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let backend = Arc::new(MemoryBackend::default());
+        let data = generate_random_binary(1024);
+        let orphan_id = StoreManager::file_name_gen();
+        {
+            let sm = StoreManager::with_backend(temp_dir.path(), backend.clone())
+                .await
+                .expect("Failed to create StoreManager");
+            sm.put_binary_data("kept.bin", &data, false, false).await.expect("put");
+            assert_eq!(
+                std::fs::metadata(intent::journal_path(&temp_dir.path().join("linadata")))
+                    .unwrap()
+                    .len(),
+                0
+            );
+
+            // Crash after the source row landed but before its link did
+            sm.intents.as_ref().unwrap().begin(IntentKind::Create, &orphan_id).unwrap();
+            sm.blobs.put(&orphan_id, b"partial").await.unwrap();
+            sm.dao.insert_source(&orphan_id, "partial", false, 7).await.unwrap();
+        }
+
+        let sm = StoreManager::with_backend(temp_dir.path(), backend.clone())
+            .await
+            .expect("Failed to reopen StoreManager");
+        assert!(sm.dao.get_source_by_id(&orphan_id).await.unwrap().is_none());
+        assert!(!backend.exists(&orphan_id).await.unwrap());
+        assert_eq!(sm.get_binary_data("kept.bin").await.expect("get"), data);
+    }
+
+    #[tokio::test]
+    async fn test_tiered_backend_demotes_and_recalls() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let hot = Arc::new(FsBackend::new(temp_dir.path().join("linadata")));
+        let cold = Arc::new(MemoryBackend::default());
+        let recalls = Arc::new(AtomicU64::new(0));
+        let mut tiered =
+            crate::blob::TieredBackend::new(hot.clone(), cold.clone(), Duration::ZERO);
+        let counter = Arc::clone(&recalls);
+        tiered.set_on_recall(move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let sm = StoreManager::with_backend(temp_dir.path(), Arc::new(tiered))
+            .await
+            .expect("Failed to create StoreManager");
+        let data = generate_random_binary(1024);
+        sm.put_binary_data("tier.bin", &data, false, false).await.expect("put");
+        let source_id = sm.dao.get_links_by_name("tier.bin", false).await.expect("links")[0]
+            .source_id
+            .clone();
+
+        assert_eq!(sm.demote_idle().await.expect("demote"), 1);
+        assert!(!hot.source_path(&source_id).exists());
+        assert!(cold.exists(&source_id).await.unwrap());
+
+        assert_eq!(sm.get_binary_data("tier.bin").await.expect("get"), data);
+        assert_eq!(recalls.load(Ordering::Relaxed), 1);
+        assert!(hot.source_path(&source_id).exists());
+        assert!(!cold.exists(&source_id).await.unwrap());
+    }
+
     #[tokio::test]
     async fn test_large_file() {
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
@@ -1406,7 +3286,8 @@ mod tests {
             .await
             .expect("links");
         let kept_id = links[0].source_id.clone();
-        let kept_dir = sm.source_dir(&kept_id);
+        let blobs = FsBackend::new(temp_dir.path().join("linadata"));
+        let kept_dir = blobs.source_dir(&kept_id);
 
         // Plant garbage neighbors next to the real file.
         let orphan_id = "20991231235959aaaaaaaa";
@@ -1418,7 +3299,7 @@ mod tests {
         stdfs::write(&tmp_path, b"tmp").unwrap();
 
         // Plant a tombstone for the KEPT source — reconciliation should resurrect it.
-        let kept_path = sm.source_path(&kept_id);
+        let kept_path = blobs.source_path(&kept_id);
         let kept_tombstone = kept_dir.join(format!("{}.deleting", kept_id));
         stdfs::rename(&kept_path, &kept_tombstone).unwrap();
 
@@ -1434,6 +3315,38 @@ mod tests {
         assert_eq!(roundtrip, data);
     }
 
+    #[tokio::test]
+    async fn test_collect_garbage_and_scrub() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let sm = StoreManager::new(temp_dir.path()).await.expect("Failed to create StoreManager");
+        sm.put_binary_data("good.bin", &Bytes::from_static(b"good"), false, false)
+            .await
+            .expect("put");
+        sm.put_binary_data("bad.bin", &Bytes::from_static(b"bad"), false, true)
+            .await
+            .expect("put");
+
+        let bad_id = sm.dao.get_links_by_name("bad.bin", false).await.expect("links")[0]
+            .source_id
+            .clone();
+        let blobs = FsBackend::new(temp_dir.path().join("linadata"));
+        let tmp_path = blobs.source_dir(&bad_id).join(format!("{}.tmp-cafe", bad_id));
+        stdfs::write(&tmp_path, b"tmp").unwrap();
+        let report = sm.collect_garbage().await.expect("gc");
+        assert_eq!(report.removed_tmp, 1);
+        assert_eq!(report.removed_orphans, 0);
+        assert!(!tmp_path.exists());
+
+        let report = sm.scrub().await.expect("scrub");
+        assert_eq!(report.checked, 2);
+        assert!(report.damaged.is_empty());
+
+        stdfs::write(blobs.source_path(&bad_id), b"garbage").unwrap();
+        let report = sm.scrub().await.expect("scrub");
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.damaged, vec!["bad.bin".to_string()]);
+    }
+
     #[test]
     fn test_relative_path_with_same_root() {
         let tm = TidyManager::new();
@@ -1545,6 +3458,198 @@ mod tests {
         assert_eq!(stdfs::read(dir.join("g2_a.txt")).unwrap(), b"group2");
     }
 
+    #[test]
+    fn test_tidy_skips_filtered_files() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        stdfs::create_dir_all(dir.join(".git")).unwrap();
+        stdfs::write(dir.join("a.txt"), b"same content").unwrap();
+        stdfs::write(dir.join(".git").join("a.txt"), b"same content").unwrap();
+        stdfs::write(dir.join("a.log"), b"same content").unwrap();
+        stdfs::write(dir.join("x1.txt"), b"x").unwrap();
+        stdfs::write(dir.join("x2.txt"), b"x").unwrap();
+
+        let mut tm = TidyManager::with_options(TidyOptions {
+            exclude: vec![".git".to_string()],
+            include: vec!["*.txt".to_string()],
+            min_size: 2,
+            ..Default::default()
+        });
+        tm.tidy(&dir, false).unwrap();
+
+        let is_symlink =
+            |name: &str| dir.join(name).symlink_metadata().unwrap().file_type().is_symlink();
+        for name in ["a.txt", ".git/a.txt", "a.log", "x1.txt", "x2.txt"] {
+            assert!(!is_symlink(name), "{} should be left alone", name);
+        }
+    }
+
+    #[test]
+    fn test_same_size_files() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        stdfs::write(dir.join("a.txt"), b"four").unwrap();
+        stdfs::write(dir.join("b.txt"), b"4444").unwrap();
+        stdfs::write(dir.join("c.txt"), b"three").unwrap();
+        let paths = vec![
+            dir.join("a.txt"),
+            dir.join("b.txt"),
+            dir.join("c.txt"),
+            dir.join("gone.txt"),
+        ];
+
+        let (mut candidates, skipped) = TidyManager::same_size_files(paths);
+        candidates.sort();
+        assert_eq!(candidates, vec![dir.join("a.txt"), dir.join("b.txt")]);
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn test_tidy_report_and_untidy() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().join("files");
+        stdfs::create_dir_all(&dir).unwrap();
+        let journal = tmp.path().join("tidy.journal");
+
+        stdfs::write(dir.join("a.txt"), b"same content").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        stdfs::write(dir.join("b,c.txt"), b"same content").unwrap();
+
+        let mut tm = TidyManager::with_options(TidyOptions {
+            journal: Some(journal.clone()),
+            ..Default::default()
+        });
+        let report = tm.tidy(&dir, false).unwrap();
+        assert_eq!(report.replacements.len(), 1);
+        let replacement = &report.replacements[0];
+        assert_eq!(replacement.path, dir.join("b,c.txt"));
+        assert_eq!(replacement.kept, dir.join("a.txt"));
+        assert_eq!(replacement.size, 12);
+
+        let csv = report.to_csv();
+        assert!(csv.starts_with("path,kept,size,hash256\n"));
+        assert!(csv.contains(&format!("\"{}\",", dir.join("b,c.txt").display())));
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["replacements"][0]["size"], 12);
+
+        let restored = TidyManager::untidy(&journal).unwrap();
+        assert_eq!(restored, vec![dir.join("b,c.txt")]);
+        let metadata = dir.join("b,c.txt").symlink_metadata().unwrap();
+        assert!(!metadata.file_type().is_symlink());
+        assert_eq!(stdfs::read(dir.join("b,c.txt")).unwrap(), b"same content");
+
+        // Already restored, so a second run has nothing to do
+        assert!(TidyManager::untidy(&journal).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tidy_chooser_picks_copy_or_skips() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        stdfs::write(dir.join("g1_old.txt"), b"group1").unwrap();
+        stdfs::write(dir.join("g2_old.txt"), b"group2").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        stdfs::write(dir.join("g1_new.txt"), b"group1").unwrap();
+        stdfs::write(dir.join("g2_new.txt"), b"group2").unwrap();
+
+        let offered = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut tm = TidyManager::new();
+        let seen = Arc::clone(&offered);
+        tm.set_chooser(move |group| {
+            seen.lock().unwrap().push(group.clone());
+            let newest = group.files.iter().position(|f| f.0.ends_with("g1_new.txt"));
+            match newest {
+                Some(index) => GroupChoice::Keep(index),
+                None => GroupChoice::Skip,
+            }
+        });
+        let report = tm.tidy(&dir, false).unwrap();
+
+        let offered = offered.lock().unwrap();
+        assert_eq!(offered.len(), 2);
+        for group in offered.iter() {
+            assert_eq!(group.size, 6);
+            assert!(group.files[group.suggested].0.to_string_lossy().ends_with("_old.txt"));
+        }
+        assert_eq!(report.replacements.len(), 1);
+        assert_eq!(report.replacements[0].path, dir.join("g1_old.txt"));
+        assert_eq!(report.replacements[0].kept, dir.join("g1_new.txt"));
+        assert!(!dir.join("g2_new.txt").symlink_metadata().unwrap().file_type().is_symlink());
+    }
+
+    #[test]
+    fn test_tidy_keeps_copy_in_preferred_dir() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().to_path_buf();
+        stdfs::create_dir_all(dir.join("downloads")).unwrap();
+        stdfs::create_dir_all(dir.join("originals")).unwrap();
+
+        stdfs::write(dir.join("downloads").join("photo.jpg"), b"pixels").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        stdfs::write(dir.join("originals").join("photo.jpg"), b"pixels").unwrap();
+
+        let mut tm = TidyManager::with_options(TidyOptions {
+            prefer_dirs: vec![dir.join("originals")],
+            ..Default::default()
+        });
+        let report = tm.tidy(&dir, false).unwrap();
+
+        assert_eq!(report.replacements.len(), 1);
+        assert_eq!(report.replacements[0].kept, dir.join("originals").join("photo.jpg"));
+        assert_eq!(report.replacements[0].path, dir.join("downloads").join("photo.jpg"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tidy_leaves_existing_symlinks() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        stdfs::write(dir.join("a.txt"), b"same content").unwrap();
+        utils::create_symlink("a.txt", dir.join("link.txt")).unwrap();
+        utils::create_symlink(".", dir.join("loop")).unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        stdfs::write(dir.join("b.txt"), b"same content").unwrap();
+
+        let mut tm = TidyManager::new();
+        let report = tm.tidy(&dir, false).unwrap();
+
+        assert_eq!(report.replacements.len(), 1);
+        assert_eq!(report.replacements[0].path, dir.join("b.txt"));
+        assert_eq!(stdfs::read_link(dir.join("link.txt")).unwrap(), PathBuf::from("a.txt"));
+    }
+
+    #[test]
+    fn test_tidy_reports_progress_and_summary() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        stdfs::write(dir.join("a.txt"), b"same content").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        stdfs::write(dir.join("b.txt"), b"same content").unwrap();
+        stdfs::write(dir.join("c.txt"), b"samE content").unwrap();
+        stdfs::write(dir.join("d.txt"), b"unique").unwrap();
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut tm = TidyManager::new();
+        let seen = Arc::clone(&events);
+        tm.set_progress(move |progress| seen.lock().unwrap().push(progress));
+        let report = tm.tidy(&dir, false).unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.first(), Some(&TidyProgress::Scanned { files: 4 }));
+        assert_eq!(events.last(), Some(&TidyProgress::Hashed { done: 3, total: 3 }));
+        assert_eq!(report.files_scanned, 4);
+        assert_eq!(report.duplicate_groups, 1);
+        assert_eq!(report.bytes_reclaimed, 12);
+        assert_eq!(report.errors, 0);
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["bytes_reclaimed"], 12);
+    }
+
     #[test]
     fn test_file_info_collector() {
         let tmp = TempDir::new().unwrap();
@@ -1553,7 +3658,7 @@ mod tests {
         stdfs::write(dir.join("test.txt"), b"hello").unwrap();
 
         let mut tm = TidyManager::new();
-        tm.file_info_collector(&dir.join("test.txt")).unwrap();
+        tm.file_info_collector(&[dir.join("test.txt")]);
 
         assert_eq!(tm.map_cache.len(), 1);
         let hash = utils::get_hash256_from_file(&dir.join("test.txt")).unwrap();
@@ -1565,16 +3670,64 @@ mod tests {
     #[test]
     fn test_find_extreme_file() {
         let tm = TidyManager::new();
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
         let files = vec![
-            (PathBuf::from("/a/old.txt"), "20200101000000".to_string()),
-            (PathBuf::from("/a/mid.txt"), "20220101000000".to_string()),
-            (PathBuf::from("/a/new.txt"), "20240101000000".to_string()),
+            (PathBuf::from("/a/old.txt"), at(1_577_836_800)),
+            (PathBuf::from("/a/mid.txt"), at(1_640_995_200)),
+            (PathBuf::from("/a/new.txt"), at(1_704_067_200)),
         ];
 
-        let oldest = tm.find_extreme_file(&files, |a, b| a < b);
+        let oldest = tm.find_extreme_file(&files, |a, b| a < b).unwrap();
         assert_eq!(oldest.0, &PathBuf::from("/a/old.txt"));
 
-        let newest = tm.find_extreme_file(&files, |a, b| a > b);
+        let newest = tm.find_extreme_file(&files, |a, b| a > b).unwrap();
         assert_eq!(newest.0, &PathBuf::from("/a/new.txt"));
+
+        // Equal times go to the path that sorts first, whatever the order
+        let tied = vec![
+            (PathBuf::from("/a/z.txt"), at(1)),
+            (PathBuf::from("/a/b.txt"), at(1)),
+            (PathBuf::from("/a/m.txt"), at(1)),
+        ];
+        assert_eq!(tm.find_extreme_file(&tied, |a, b| a < b).unwrap().0, &PathBuf::from("/a/b.txt"));
+        assert_eq!(tm.find_extreme_file(&tied, |a, b| a > b).unwrap().0, &PathBuf::from("/a/b.txt"));
+    }
+
+    #[test]
+    fn test_tidy_order_by_mtime_and_path() {
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        stdfs::write(dir.join("a.txt"), b"same content").unwrap();
+        stdfs::write(dir.join("b.txt"), b"same content").unwrap();
+        // a.txt was created first but modified last
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        stdfs::File::options()
+            .write(true)
+            .open(dir.join("a.txt"))
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        let mut tm = TidyManager::with_options(TidyOptions {
+            order: TidyOrder::Modified,
+            ..Default::default()
+        });
+        let report = tm.tidy(&dir, false).unwrap();
+        assert_eq!(report.replacements.len(), 1);
+        assert_eq!(report.replacements[0].kept, dir.join("b.txt"));
+
+        let tmp = TempDir::new().unwrap();
+        let dir = tmp.path().to_path_buf();
+        stdfs::write(dir.join("z.txt"), b"same content").unwrap();
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        stdfs::write(dir.join("m.txt"), b"same content").unwrap();
+        let mut tm = TidyManager::with_options(TidyOptions {
+            order: TidyOrder::Path,
+            ..Default::default()
+        });
+        let report = tm.tidy(&dir, true).unwrap();
+        assert_eq!(report.replacements.len(), 1);
+        assert_eq!(report.replacements[0].kept, dir.join("m.txt"));
     }
 }
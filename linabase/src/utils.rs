@@ -1,12 +1,15 @@
 use blake3::Hasher;
-use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use flate2::{
+    Compress, Compression, Crc, FlushCompress, Status, read::GzDecoder, write::GzEncoder,
+};
 use rayon::{
     ThreadPool, ThreadPoolBuilder,
-    iter::{IntoParallelRefIterator, ParallelIterator},
+    iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator},
     slice::ParallelSlice,
 };
 use std::{
     borrow::Cow,
+    collections::HashSet,
     error::Error,
     fs,
     io::{self, Read, Write},
@@ -16,6 +19,9 @@ use std::{
 
 type BoxError = Box<dyn Error + Send + Sync>;
 
+/// Fixed gzip member header: deflate, no flags, no mtime, unknown OS.
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0xff];
+
 const BUFFER_SIZE: usize = 0x80000;
 
 pub fn get_hash256_from_file<P: AsRef<Path>>(file_path: P) -> Result<String, BoxError> {
@@ -50,22 +56,167 @@ pub fn get_hash256_from_binary(input: &[u8]) -> String {
     hasher.finalize().to_hex().to_string()
 }
 
-pub fn path_walk<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, BoxError> {
-    let path = Path::new(path.as_ref());
-    let mut result: Vec<PathBuf> = Vec::new();
+/// Which files [`path_walk`] yields.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WalkFilter {
+    /// Globs of files and directories to skip; see [`WalkFilter::excludes`].
+    pub exclude: Vec<String>,
+    /// When not empty, only files matching one of these globs are yielded.
+    pub include: Vec<String>,
+    /// Files smaller than this many bytes are skipped.
+    pub min_size: u64,
+}
 
-    if path.is_dir() {
-        for entry in fs::read_dir(path)? {
-            let current_entry = entry?.path();
-            if current_entry.is_dir() {
-                result.extend_from_slice(&path_walk(current_entry)?);
-            } else {
-                result.push(current_entry);
+impl WalkFilter {
+    /// Whether the entry at `rel`, relative to the walk's root, is excluded.
+    /// A glob without `/` matches the entry's name, one with `/` its path.
+    fn excludes(&self, rel: &Path) -> bool {
+        self.exclude.iter().any(|glob| glob_matches_path(glob, rel))
+    }
+
+    fn includes(&self, rel: &Path) -> bool {
+        self.include.is_empty() || self.include.iter().any(|glob| glob_matches_path(glob, rel))
+    }
+}
+
+fn glob_matches_path(glob: &str, rel: &Path) -> bool {
+    if glob.contains('/') {
+        let rel = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        glob_match(glob.trim_start_matches("./"), &rel)
+    } else {
+        rel.file_name()
+            .is_some_and(|name| glob_match(glob, &name.to_string_lossy()))
+    }
+}
+
+/// Whether `text` matches the glob `pattern`: `*` is any run of characters
+/// but `/`, `**` any run at all, and `?` any one character but `/`.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // matched[j]: the pattern so far matches the first j characters of text
+    let mut matched = vec![false; text.len() + 1];
+    matched[0] = true;
+    let mut i = 0;
+    while i < pattern.len() {
+        let mut next = vec![false; text.len() + 1];
+        match pattern[i] {
+            '*' if pattern.get(i + 1) == Some(&'*') => {
+                let mut any = false;
+                for j in 0..=text.len() {
+                    any |= matched[j];
+                    next[j] = any;
+                }
+                i += 1;
+            }
+            '*' => {
+                for j in 0..=text.len() {
+                    next[j] = matched[j] || (j > 0 && next[j - 1] && text[j - 1] != '/');
+                }
+            }
+            '?' => {
+                for j in 1..=text.len() {
+                    next[j] = matched[j - 1] && text[j - 1] != '/';
+                }
+            }
+            c => {
+                for j in 1..=text.len() {
+                    next[j] = matched[j - 1] && text[j - 1] == c;
+                }
             }
         }
+        matched = next;
+        i += 1;
     }
+    matched[text.len()]
+}
+
+/// What [`path_walk`] found.
+#[derive(Debug, Default)]
+pub(crate) struct Walk {
+    pub files: Vec<PathBuf>,
+    /// Directories and entries that could not be read, left out of `files`.
+    pub skipped: Vec<(PathBuf, io::Error)>,
+}
 
-    Ok(result)
+/// Every file under `path`, recursively, except what `filter` leaves out.
+/// Excluded directories are not descended into, and symlinks are neither
+/// followed nor yielded. Only an unreadable `path` itself is an error.
+pub(crate) fn path_walk<P: AsRef<Path>>(path: P, filter: &WalkFilter) -> Result<Walk, BoxError> {
+    let root = path.as_ref();
+    if root.is_dir() {
+        fs::read_dir(root)?;
+    }
+    let mut walk = Walk::default();
+    walk_filtered(root, root, filter, &mut HashSet::new(), &mut walk);
+    Ok(walk)
+}
+
+fn walk_filtered(
+    root: &Path,
+    dir: &Path,
+    filter: &WalkFilter,
+    visited: &mut HashSet<PathBuf>,
+    walk: &mut Walk,
+) {
+    if !dir.is_dir() {
+        return;
+    }
+    let entries = match fs::canonicalize(dir).and_then(|real| Ok((real, fs::read_dir(dir)?))) {
+        Ok((real, entries)) => {
+            // A directory mounted inside itself would otherwise be walked forever
+            if !visited.insert(real) {
+                return;
+            }
+            entries
+        }
+        Err(e) => {
+            walk.skipped.push((dir.to_path_buf(), e));
+            return;
+        }
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                walk.skipped.push((dir.to_path_buf(), e));
+                continue;
+            }
+        };
+        let path = entry.path();
+        let rel = path.strip_prefix(root).unwrap_or(&path);
+        if filter.excludes(rel) {
+            continue;
+        }
+        // A linked directory can lead back up the tree, and a linked file
+        // is not a copy of its own
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(e) => {
+                walk.skipped.push((path, e));
+                continue;
+            }
+        };
+        if file_type.is_dir() {
+            walk_filtered(root, &path, filter, visited, walk);
+        } else if file_type.is_file() && filter.includes(rel) {
+            if filter.min_size > 0 {
+                match entry.metadata() {
+                    Ok(metadata) if metadata.len() < filter.min_size => continue,
+                    Ok(_) => {}
+                    Err(e) => {
+                        walk.skipped.push((path, e));
+                        continue;
+                    }
+                }
+            }
+            walk.files.push(path);
+        }
+    }
 }
 
 pub fn create_symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> io::Result<()> {
@@ -115,30 +266,27 @@ impl BlockManager {
     ///
     /// Uses system-appropriate thread count and optimal chunk size
     ///
-    /// # Panics
-    /// Panics if thread pool creation fails
-    pub fn new() -> Self {
+    /// # Errors
+    /// Fails if the compression thread pool cannot be started
+    pub fn new() -> io::Result<Self> {
         // Use number of available CPU cores for optimal performance
         let max_threads = std::thread::available_parallelism()
             .map(|n| n.get())
             .unwrap_or(4)
             .min(4); // Cap at 4 threads to avoid excessive resource usage
 
-        let thread_pool = match ThreadPoolBuilder::new()
+        let thread_pool = ThreadPoolBuilder::new()
             .num_threads(max_threads)
             .thread_name(|index| format!("linastore-compress-{}", index))
             .build()
-        {
-            Ok(pool) => pool,
-            Err(err) => panic!("Failed to create thread pool: {}", err),
-        };
+            .map_err(|e| io::Error::other(format!("Failed to create thread pool: {}", e)))?;
 
-        BlockManager {
+        Ok(BlockManager {
             chunk_size: 0x10000 - 0x400, // 63KiB for optimal compression
             thread_pool,
             multi_thread_threshold: 1024 * 1024, // 1MB threshold for multi-threading
             max_threads,
-        }
+        })
     }
 
     /// Determine the number of threads to use based on input size
@@ -226,11 +374,8 @@ impl BlockManager {
         Ok(result)
     }
 
-    pub fn decompress_all(
-        &self,
-        input: &[u8],
-        original_size: usize,
-    ) -> Result<Vec<u8>, BoxError> {
+    /// Split `compress_all` output into `(flag, start, end)` chunk frames.
+    fn parse_frames(input: &[u8]) -> Result<Vec<(u8, usize, usize)>, BoxError> {
         let mut i = 0;
         let mut chunks_with_flag = Vec::with_capacity(0x400000);
 
@@ -261,6 +406,16 @@ impl BlockManager {
             i += chunk_len;
         }
 
+        Ok(chunks_with_flag)
+    }
+
+    pub fn decompress_all(
+        &self,
+        input: &[u8],
+        original_size: usize,
+    ) -> Result<Vec<u8>, BoxError> {
+        let chunks_with_flag = Self::parse_frames(input)?;
+
         let decompressed_chunks = self.thread_pool.install(|| {
             chunks_with_flag
                 .par_iter()
@@ -302,6 +457,105 @@ impl BlockManager {
 
         Ok(result)
     }
+    /// Turn stored bytes into a single-member gzip stream.
+    ///
+    /// Stored gzip chunks can't be spliced as-is: each one ends with a final
+    /// deflate block, and most HTTP clients stop after the first gzip member.
+    /// Instead every chunk is deflated in parallel with a sync flush (as pigz
+    /// does), so the raw streams concatenate into one member whose CRC is
+    /// combined from the per-chunk CRCs.
+    pub fn to_gzip_stream(&self, stored: &[u8], compressed: bool) -> Result<Vec<u8>, BoxError> {
+        let raw_chunks: Vec<Cow<'_, [u8]>> = if compressed {
+            let frames = Self::parse_frames(stored)?;
+            self.thread_pool.install(|| {
+                frames
+                    .par_iter()
+                    .map(|(flag, start, end)| -> Result<Cow<'_, [u8]>, BoxError> {
+                        match flag {
+                            0 => Ok(Cow::Borrowed(&stored[*start..*end])),
+                            1 => Ok(Cow::Owned(self.__decode(&stored[*start..*end])?)),
+                            _ => Err(Box::new(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("Unknown chunk flag: {}", flag),
+                            ))),
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })?
+        } else {
+            stored.chunks(self.chunk_size).map(Cow::Borrowed).collect()
+        };
+
+        let last = raw_chunks.len().saturating_sub(1);
+        let deflated: Vec<(Vec<u8>, Crc)> = if raw_chunks.is_empty() {
+            vec![(Self::__deflate_raw(&[], true)?, Crc::new())]
+        } else {
+            self.thread_pool.install(|| {
+                raw_chunks
+                    .par_iter()
+                    .enumerate()
+                    .map(|(i, chunk)| -> Result<(Vec<u8>, Crc), BoxError> {
+                        let mut crc = Crc::new();
+                        crc.update(chunk);
+                        Ok((Self::__deflate_raw(chunk, i == last)?, crc))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })?
+        };
+
+        let body_len: usize = deflated.iter().map(|(d, _)| d.len()).sum();
+        let mut result = Vec::with_capacity(GZIP_HEADER.len() + body_len + 8);
+        result.extend_from_slice(&GZIP_HEADER);
+        let mut crc = Crc::new();
+        for (data, chunk_crc) in &deflated {
+            result.extend_from_slice(data);
+            crc.combine(chunk_crc);
+        }
+        result.extend_from_slice(&crc.sum().to_le_bytes());
+        result.extend_from_slice(&crc.amount().to_le_bytes());
+        Ok(result)
+    }
+
+    /// Bytes per chunk when splitting plain content.
+    pub(crate) fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Plain content of one stored frame.
+    pub(crate) fn decode_frame(&self, flag: u8, data: Vec<u8>) -> Result<Vec<u8>, BoxError> {
+        match flag {
+            0 => Ok(data),
+            1 => self.__decode(&data),
+            _ => Err(Box::new(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown chunk flag: {}", flag),
+            ))),
+        }
+    }
+
+    /// Raw deflate of one chunk, ending in a sync flush or, for the last
+    /// chunk, the final block.
+    fn __deflate_raw(chunk: &[u8], last: bool) -> Result<Vec<u8>, BoxError> {
+        let mut compressor = Compress::new(Compression::fast(), false);
+        let flush = if last { FlushCompress::Finish } else { FlushCompress::Sync };
+        let mut result = Vec::with_capacity(chunk.len() / 2 + 64);
+        loop {
+            result.reserve(chunk.len() / 2 + 64);
+            let consumed = compressor.total_in() as usize;
+            let status = compressor
+                .compress_vec(&chunk[consumed..], &mut result, flush)
+                .map_err(io::Error::other)?;
+            let drained = compressor.total_in() as usize == chunk.len()
+                && result.len() < result.capacity();
+            match status {
+                Status::StreamEnd => break,
+                _ if !last && drained => break,
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+
     // Input bytes less than 0x10000 (64KiB) - 0xa
     fn __encode(&self, chunk: &[u8]) -> Result<Vec<u8>, BoxError> {
         let result = Vec::with_capacity(u16::MAX as usize);
@@ -320,6 +574,38 @@ impl BlockManager {
     }
 }
 
+/// Sequential counterpart of [`BlockManager::to_gzip_stream`]: emits one
+/// gzip member piece by piece as plain chunks arrive.
+pub(crate) struct GzipChunkEncoder {
+    crc: Crc,
+    started: bool,
+}
+
+impl GzipChunkEncoder {
+    pub(crate) fn new() -> Self {
+        GzipChunkEncoder {
+            crc: Crc::new(),
+            started: false,
+        }
+    }
+
+    /// Encode the next chunk; `last` also closes the member with its trailer.
+    pub(crate) fn encode(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>, BoxError> {
+        let mut result = Vec::new();
+        if !self.started {
+            result.extend_from_slice(&GZIP_HEADER);
+            self.started = true;
+        }
+        result.extend_from_slice(&BlockManager::__deflate_raw(chunk, last)?);
+        self.crc.update(chunk);
+        if last {
+            result.extend_from_slice(&self.crc.sum().to_le_bytes());
+            result.extend_from_slice(&self.crc.amount().to_le_bytes());
+        }
+        Ok(result)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Instant;
@@ -332,7 +618,7 @@ mod tests {
     #[test]
     fn test_encode_consistency() {
         // Create a compressor with matching chunk size
-        let manager = BlockManager::new();
+        let manager = BlockManager::new().unwrap();
         let data = vec![1u8; 100000]; // Use test data instead of external file
 
         // Encode the data
@@ -364,7 +650,7 @@ mod tests {
     #[test]
     fn test_path_recursive() {
         let path = Path::new(".");
-        let paths = path_walk(path).expect("Failed to walk path");
+        let paths = path_walk(path, &WalkFilter::default()).expect("Failed to walk path").files;
         for path in paths {
             println!("{}", path.display());
         }
@@ -372,7 +658,7 @@ mod tests {
 
     #[test]
     fn test_dynamic_thread_selection() {
-        let manager = BlockManager::new();
+        let manager = BlockManager::new().unwrap();
 
         // Test small file (< 256KB) - should use 1 thread
         let small_data = vec![0u8; 100 * 1024]; // 100KB
@@ -405,7 +691,7 @@ mod tests {
 
     #[test]
     fn test_thread_count_determination() {
-        let manager = BlockManager::new();
+        let manager = BlockManager::new().unwrap();
 
         // Small file should use 1 thread
         assert_eq!(
@@ -477,7 +763,7 @@ mod tests {
 
     #[test]
     fn test_compress_decompress_empty() {
-        let manager = BlockManager::new();
+        let manager = BlockManager::new().unwrap();
         let data = vec![];
 
         let compressed = manager
@@ -493,7 +779,7 @@ mod tests {
 
     #[test]
     fn test_compress_decompress_single_byte() {
-        let manager = BlockManager::new();
+        let manager = BlockManager::new().unwrap();
         let data = vec![42];
 
         let compressed = manager
@@ -508,7 +794,7 @@ mod tests {
 
     #[test]
     fn test_compress_decompress_repeated_data() {
-        let manager = BlockManager::new();
+        let manager = BlockManager::new().unwrap();
         let data = vec![42u8; 10000]; // Highly compressible data
 
         let compressed = manager.compress_all(&data).expect("Failed to compress");
@@ -523,7 +809,7 @@ mod tests {
 
     #[test]
     fn test_compress_decompress_random_data() {
-        let manager = BlockManager::new();
+        let manager = BlockManager::new().unwrap();
         let mut data = vec![0u8; 10000];
         for i in 0..data.len() {
             data[i] = (i % 256) as u8;
@@ -539,7 +825,7 @@ mod tests {
 
     #[test]
     fn test_decompress_invalid_data() {
-        let manager = BlockManager::new();
+        let manager = BlockManager::new().unwrap();
         let invalid_data = vec![0, 1, 2, 3, 4, 5]; // Invalid chunk format
 
         let result = manager.decompress_all(&invalid_data, 100);
@@ -548,7 +834,7 @@ mod tests {
 
     #[test]
     fn test_decompress_incomplete_chunk() {
-        let manager = BlockManager::new();
+        let manager = BlockManager::new().unwrap();
         let incomplete_data = vec![1, 0, 100]; // Flag=1, length=100, but no data
 
         let result = manager.decompress_all(&incomplete_data, 100);
@@ -558,7 +844,8 @@ mod tests {
     #[test]
     fn test_path_walk_empty_directory() {
         let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
-        let paths = path_walk(temp_dir.path()).expect("Failed to walk path");
+        let paths =
+            path_walk(temp_dir.path(), &WalkFilter::default()).expect("Failed to walk path").files;
 
         assert!(paths.is_empty());
     }
@@ -571,7 +858,8 @@ mod tests {
         fs::write(temp_dir.path().join("file1.txt"), b"content1").expect("Failed to write file");
         fs::write(temp_dir.path().join("file2.txt"), b"content2").expect("Failed to write file");
 
-        let paths = path_walk(temp_dir.path()).expect("Failed to walk path");
+        let paths =
+            path_walk(temp_dir.path(), &WalkFilter::default()).expect("Failed to walk path").files;
 
         assert_eq!(paths.len(), 2);
     }
@@ -586,17 +874,110 @@ mod tests {
         fs::write(subdir.join("nested.txt"), b"nested content").expect("Failed to write file");
         fs::write(temp_dir.path().join("root.txt"), b"root content").expect("Failed to write file");
 
-        let paths = path_walk(temp_dir.path()).expect("Failed to walk path");
+        let paths =
+            path_walk(temp_dir.path(), &WalkFilter::default()).expect("Failed to walk path").files;
 
         assert_eq!(paths.len(), 2);
     }
 
     #[test]
     fn test_path_walk_nonexistent() {
-        let result = path_walk("/nonexistent/path/that/does/not/exist");
+        let result = path_walk("/nonexistent/path/that/does/not/exist", &WalkFilter::default());
         // path_walk returns empty result for nonexistent paths, not an error
         assert!(result.is_ok());
-        assert!(result.unwrap().is_empty());
+        assert!(result.unwrap().files.is_empty());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.log", "app.log"));
+        assert!(!glob_match("*.log", "logs/app.log"));
+        assert!(glob_match("**.log", "logs/app.log"));
+        assert!(glob_match("logs/*", "logs/app.log"));
+        assert!(glob_match("src/**/mod.rs", "src/a/b/mod.rs"));
+        assert!(glob_match("file?.txt", "file1.txt"));
+        assert!(!glob_match("file?.txt", "file10.txt"));
+        assert!(glob_match("node_modules", "node_modules"));
+        assert!(!glob_match("node_modules", "node_modules2"));
+    }
+
+    #[test]
+    fn test_path_walk_filtered() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join(".git/objects")).unwrap();
+        fs::create_dir_all(root.join("photos/raw")).unwrap();
+        fs::write(root.join(".git/objects/pack"), b"packed objects").unwrap();
+        fs::write(root.join("photos/a.jpg"), b"jpeg bytes").unwrap();
+        fs::write(root.join("photos/raw/b.jpg"), b"raw jpeg bytes").unwrap();
+        fs::write(root.join("photos/tiny.jpg"), b"x").unwrap();
+        fs::write(root.join("notes.txt"), b"some notes").unwrap();
+
+        let walk = |filter: &WalkFilter| {
+            let mut paths: Vec<String> = path_walk(root, filter)
+                .unwrap()
+                .files
+                .iter()
+                .map(|p| p.strip_prefix(root).unwrap().to_string_lossy().into_owned())
+                .collect();
+            paths.sort();
+            paths
+        };
+
+        let filter = WalkFilter {
+            exclude: vec![".git".to_string(), "photos/raw".to_string()],
+            include: vec!["*.jpg".to_string()],
+            min_size: 2,
+        };
+        assert_eq!(walk(&filter), vec!["photos/a.jpg"]);
+
+        let filter = WalkFilter {
+            exclude: vec![".git".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            walk(&filter),
+            vec!["notes.txt", "photos/a.jpg", "photos/raw/b.jpg", "photos/tiny.jpg"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_walk_skips_symlinks() {
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/file.txt"), b"content").unwrap();
+        create_symlink("../file.txt", root.join("sub/linked.txt")).unwrap();
+        // A link back up the tree would loop forever if followed
+        create_symlink("..", root.join("sub/loop")).unwrap();
+
+        let paths = path_walk(root, &WalkFilter::default()).expect("Failed to walk path").files;
+        assert_eq!(paths, vec![root.join("sub/file.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_walk_skips_unreadable_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let root = temp_dir.path();
+        fs::create_dir_all(root.join("locked")).unwrap();
+        fs::write(root.join("locked/hidden.txt"), b"hidden").unwrap();
+        fs::write(root.join("open.txt"), b"open").unwrap();
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+        // Root ignores permissions, so there is nothing to observe
+        if fs::read_dir(root.join("locked")).is_ok() {
+            fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+            return;
+        }
+
+        let walk = path_walk(root, &WalkFilter::default()).expect("Failed to walk path");
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(walk.files, vec![root.join("open.txt")]);
+        assert_eq!(walk.skipped.len(), 1);
+        assert_eq!(walk.skipped[0].0, root.join("locked"));
     }
 
     #[test]
@@ -605,4 +986,42 @@ mod tests {
         assert_eq!(GROUP_SIZE, 64);
         assert_eq!(BUFFER_SIZE, 0x80000); // 512KB
     }
+
+    #[test]
+    fn test_to_gzip_stream_decodes_to_original() {
+        let bm = BlockManager::new().unwrap();
+        let text: Vec<u8> = b"lina store ".iter().cycle().take(300_000).copied().collect();
+        let random: Vec<u8> = (0..100_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+
+        for data in [text, random, Vec::new()] {
+            let stored = bm.compress_all(&data).unwrap();
+            for (bytes, compressed) in [(&stored, true), (&data, false)] {
+                let gz = bm.to_gzip_stream(bytes, compressed).unwrap();
+                // A single-member decoder must see the whole content
+                let mut decoded = Vec::new();
+                GzDecoder::new(&gz[..]).read_to_end(&mut decoded).unwrap();
+                assert_eq!(decoded, data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_gzip_chunk_encoder_single_member() {
+        let data: Vec<u8> = b"chunked gzip ".iter().cycle().take(150_000).copied().collect();
+        let chunks: Vec<&[u8]> = data.chunks(0x10000).collect();
+
+        let mut encoder = GzipChunkEncoder::new();
+        let mut gz = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            gz.extend(encoder.encode(chunk, i == chunks.len() - 1).unwrap());
+        }
+        let mut decoded = Vec::new();
+        GzDecoder::new(&gz[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+
+        let empty = GzipChunkEncoder::new().encode(&[], true).unwrap();
+        let mut decoded = Vec::new();
+        GzDecoder::new(&empty[..]).read_to_end(&mut decoded).unwrap();
+        assert!(decoded.is_empty());
+    }
 }
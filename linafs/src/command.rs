@@ -30,6 +30,13 @@ pub struct MountArgs {
         help = "Store file content compressed (default: uncompressed)"
     )]
     pub compressed: bool,
+
+    #[arg(
+        long = "read-only",
+        action = clap::ArgAction::SetTrue,
+        help = "Mount read-only, refusing every change (default: read-write)"
+    )]
+    pub read_only: bool,
 }
 
 /// Arguments for the umount command
@@ -13,7 +13,7 @@ use fuser::{
     ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyLseek,
     ReplyOpen, ReplyStatfs, ReplyWrite, Request, TimeOrNow,
 };
-use linabase::{dao::Link, service::StoreManager};
+use linabase::{Link, service::StoreManager};
 
 fn file_ino(name: &str) -> u64 {
     let mut h = std::collections::hash_map::DefaultHasher::new();
@@ -107,16 +107,23 @@ pub struct LinaFs {
     rt: tokio::runtime::Handle,
     write_buf: RwLock<HashMap<u64, Vec<u8>>>,
     compressed: bool,
+    /// Refuse every change with `EROFS`.
+    read_only: bool,
 }
 
 impl LinaFs {
-    pub async fn new(root: &str, compressed: bool) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub async fn new(
+        root: &str,
+        compressed: bool,
+        read_only: bool,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         let store = StoreManager::new(root).await?;
         Ok(Self {
             store: Arc::new(store),
             rt: tokio::runtime::Handle::current(),
             write_buf: RwLock::new(HashMap::new()),
             compressed,
+            read_only,
         })
     }
 
@@ -245,6 +252,9 @@ impl Filesystem for LinaFs {
         _flags: Option<fuser::BsdFileFlags>,
         reply: ReplyAttr,
     ) {
+        if self.read_only && (mode.is_some() || size.is_some()) {
+            return reply.error(fuser::Errno::EROFS);
+        }
         let ino_val: u64 = ino.into();
 
         // Root directory
@@ -394,9 +404,12 @@ impl Filesystem for LinaFs {
         &self,
         _req: &Request,
         _ino: fuser::INodeNo,
-        _flags: OpenFlags,
+        flags: OpenFlags,
         reply: ReplyOpen,
     ) {
+        if self.read_only && flags.acc_mode() != fuser::OpenAccMode::O_RDONLY {
+            return reply.error(fuser::Errno::EROFS);
+        }
         reply.opened(FileHandle(0), fuser::FopenFlags::empty());
     }
 
@@ -458,6 +471,9 @@ impl Filesystem for LinaFs {
         _lock_owner: Option<LockOwner>,
         reply: ReplyWrite,
     ) {
+        if self.read_only {
+            return reply.error(fuser::Errno::EROFS);
+        }
         let ino_val: u64 = ino.into();
         let mut buf = self.write_buf.write().unwrap();
 
@@ -619,6 +635,9 @@ impl Filesystem for LinaFs {
         _flags: i32,
         reply: ReplyCreate,
     ) {
+        if self.read_only {
+            return reply.error(fuser::Errno::EROFS);
+        }
         let parent_val: u64 = parent.into();
         let mode = S_IFREG | (_mode & 0o777);
 
@@ -671,6 +690,9 @@ impl Filesystem for LinaFs {
         link: &Path,
         reply: ReplyEntry,
     ) {
+        if self.read_only {
+            return reply.error(fuser::Errno::EROFS);
+        }
         let parent_val: u64 = parent.into();
 
         let parent_path = if parent_val == ROOT_INO {
@@ -746,6 +768,9 @@ impl Filesystem for LinaFs {
         _umask: u32,
         reply: ReplyEntry,
     ) {
+        if self.read_only {
+            return reply.error(fuser::Errno::EROFS);
+        }
         let parent_val: u64 = parent.into();
         let mode = S_IFDIR | (_mode & 0o777);
 
@@ -787,6 +812,9 @@ impl Filesystem for LinaFs {
         name: &OsStr,
         reply: ReplyEmpty,
     ) {
+        if self.read_only {
+            return reply.error(fuser::Errno::EROFS);
+        }
         let parent_val: u64 = parent.into();
 
         let parent_path = if parent_val == ROOT_INO {
@@ -834,6 +862,9 @@ impl Filesystem for LinaFs {
         name: &OsStr,
         reply: ReplyEmpty,
     ) {
+        if self.read_only {
+            return reply.error(fuser::Errno::EROFS);
+        }
         let parent_val: u64 = parent.into();
 
         let parent_path = if parent_val == ROOT_INO {
@@ -872,6 +903,9 @@ impl Filesystem for LinaFs {
         _flags: fuser::RenameFlags,
         reply: ReplyEmpty,
     ) {
+        if self.read_only {
+            return reply.error(fuser::Errno::EROFS);
+        }
         let (parent_val, newparent_val): (u64, u64) = (parent.into(), newparent.into());
 
         let parent_path = if parent_val == ROOT_INO {
@@ -936,6 +970,9 @@ impl Filesystem for LinaFs {
         _mode: i32,
         reply: ReplyEmpty,
     ) {
+        if self.read_only {
+            return reply.error(fuser::Errno::EROFS);
+        }
         reply.ok();
     }
 
@@ -9,13 +9,14 @@ use std::process::Command;
 use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 
-pub async fn handle_mount(root: &str, args: &command::MountArgs) -> Result<(), Box<dyn Error>> {
-    let root = root.to_string();
+pub async fn handle_mount(cwd: &str, args: &command::MountArgs) -> Result<(), Box<dyn Error>> {
+    let root = Path::new(cwd).join(&args.root);
+    let root = root.to_str().ok_or("Storage root contains invalid UTF-8 characters")?;
     let mp = args.mount_point.clone();
 
     ensure_fuse_available()?;
 
-    let fs = LinaFs::new(&root, args.compressed)
+    let fs = LinaFs::new(root, args.compressed, args.read_only)
         .await
         .map_err(|e| format!("Failed to initialize filesystem: {}", e))?;
     let mut config = Config::default();
@@ -24,6 +25,9 @@ pub async fn handle_mount(root: &str, args: &command::MountArgs) -> Result<(), B
         MountOption::Subtype("linafs".to_string()),
         MountOption::CUSTOM("volname=linafs".to_string()),
     ];
+    if args.read_only {
+        config.mount_options.push(MountOption::RO);
+    }
 
     let bg = fuser::spawn_mount2(fs, Path::new(&mp), &config)
         .map_err(|e| format!("Failed to mount at {}: {}", mp, e))?;
@@ -0,0 +1,67 @@
+//! Runtime state behind the HTTP admin API: the read-only switch and a
+//! snapshot of what the server is doing.
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Instant,
+};
+
+use serde::Serialize;
+
+use crate::{conveyer::ConveyQueue, metrics, shutdown::Shutdown};
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+
+/// Note the time the server started, for the uptime in [`stats`].
+pub fn mark_started() {
+    STARTED_AT.get_or_init(Instant::now);
+}
+
+/// Whether writes and deletes are refused.
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::SeqCst)
+}
+
+/// Turn read-only mode on or off, returning whether it was on.
+pub fn set_read_only(on: bool) -> bool {
+    READ_ONLY.swap(on, Ordering::SeqCst)
+}
+
+/// What the server is doing right now.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub uptime_secs: u64,
+    pub read_only: bool,
+    pub shutting_down: bool,
+    /// Orders waiting for the porter.
+    pub queue_depth: usize,
+    /// Requests waiting for their answer, queued or being worked on.
+    pub pending_requests: usize,
+    /// Open client connections across the services.
+    pub connections: usize,
+    /// The counters `/metrics` exports, by name.
+    pub counters: BTreeMap<&'static str, u64>,
+}
+
+pub fn stats() -> Stats {
+    let queue = ConveyQueue::get_instance();
+    let shutdown = Shutdown::get_instance();
+    Stats {
+        uptime_secs: STARTED_AT
+            .get()
+            .map(|started| started.elapsed().as_secs())
+            .unwrap_or(0),
+        read_only: is_read_only(),
+        shutting_down: shutdown.is_shutdown(),
+        queue_depth: queue.depth(),
+        pending_requests: queue.waiting(),
+        connections: shutdown.in_flight(),
+        counters: metrics::values().collect(),
+    }
+}
@@ -28,28 +28,38 @@
 //!
 //! The session token is used to encrypt the data payload for security.
 //!
+//! An API key (`lina_…`, issued by `linastore-server key create`) can stand in
+//! for the session token anywhere; it does not expire until revoked. Keys with
+//! the `read` scope are refused on writes and deletes.
+//!
 //! # Session Management
 //!
 //! - Sessions expire after 1 hour (3600 seconds)
-//! - Expired sessions are automatically cleaned up every hour
-//! - When `LINASTORE_AUTH_REQUIRED` is not set, authentication is disabled (open access mode)
+//! - Sessions are stored in the database by the SHA-256 digest of their token,
+//!   so they survive a restart
+//! - Expired sessions are automatically cleaned up at startup and every hour
+//! - `LINASTORE_AUTH_POLICY` decides which requests need credentials: none
+//!   (`open`, the default), writes only (`read-open`) or all (`auth`)
 
 use crate::db::DbConnection;
 use crate::error::{err_msg, Result};
-use crate::vars::EnvVar;
+use crate::vars::{AuthPolicy, EnvVar};
 use aes_gcm::{
     aead::{Aead, KeyInit},
     Aes256Gcm, Nonce,
 };
 use argon2::{
-    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
+    password_hash::{
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+        rand_core::{OsRng, RngCore},
+    },
     Argon2,
 };
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::net::IpAddr;
-use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 use tokio::time::{Duration, Instant};
 use tracing::{Level, event};
 use uuid::Uuid;
@@ -135,6 +145,16 @@ impl Session {
 
 }
 
+/// A session as listed to admins; the token itself is never stored.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveSession {
+    pub id: String,
+    pub username: String,
+    /// Unix timestamps in seconds.
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
 /// Hash a password using Argon2id and return its PHC-encoded string form.
 ///
 /// The PHC string self-describes its salt and parameters, so it can be stored
@@ -159,13 +179,152 @@ pub fn verify_password_argon2(password: &str, phc: &str) -> bool {
     }
 }
 
+/// Prefix that tells an API key apart from a session token.
+pub const API_KEY_PREFIX: &str = "lina_";
+
+/// What a credential may do. Sessions always carry `ReadWrite`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyScope {
+    Read,
+    ReadWrite,
+}
+
+impl KeyScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyScope::Read => "read",
+            KeyScope::ReadWrite => "read-write",
+        }
+    }
+}
+
+impl std::str::FromStr for KeyScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(KeyScope::Read),
+            "read-write" => Ok(KeyScope::ReadWrite),
+            _ => Err(format!("unknown scope '{}', expected read or read-write", s)),
+        }
+    }
+}
+
+impl KeyScope {
+    /// The role a key of this scope acts with for a user holding `role`.
+    pub fn cap(&self, role: Role) -> Role {
+        match self {
+            KeyScope::Read => role.min(Role::Reader),
+            KeyScope::ReadWrite => role,
+        }
+    }
+}
+
+/// What a user may do, from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Reader,
+    Writer,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Reader => "reader",
+            Role::Writer => "writer",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// Readers may fetch and list, writers may also store and delete named
+    /// files, and only admins may delete by wildcard.
+    pub fn permits(&self, action: Action) -> bool {
+        match action {
+            Action::Read => true,
+            Action::Write | Action::Delete => *self >= Role::Writer,
+            Action::PatternDelete => *self == Role::Admin,
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "reader" => Ok(Role::Reader),
+            "writer" => Ok(Role::Writer),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role '{}', expected reader, writer or admin", s)),
+        }
+    }
+}
+
+/// An operation the fronts check against the caller's role before queuing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Read,
+    Write,
+    Delete,
+    PatternDelete,
+}
+
+/// Who a session token or API key authenticates, the role it acts with and
+/// the buckets it may reach.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub user_id: String,
+    pub role: Role,
+    /// Buckets granted to the user; `None` when it was granted none and so
+    /// may reach every bucket.
+    pub buckets: Option<Vec<String>>,
+}
+
+impl Principal {
+    /// Everyone, when authentication is off. Wildcard deletes stay refused.
+    pub fn anonymous() -> Self {
+        Principal {
+            user_id: "anonymous".to_string(),
+            role: Role::Writer,
+            buckets: None,
+        }
+    }
+
+    /// A caller without credentials under the `read-open` policy.
+    fn anonymous_reader() -> Self {
+        Principal {
+            role: Role::Reader,
+            ..Principal::anonymous()
+        }
+    }
+
+    /// Whether requests may address `bucket`. Admins reach every bucket;
+    /// `None` stands for requests outside any bucket, such as fetching a blob
+    /// by hash, which only unrestricted principals may make.
+    pub fn may_access(&self, bucket: Option<&str>) -> bool {
+        if self.role == Role::Admin {
+            return true;
+        }
+        match (&self.buckets, bucket) {
+            (None, _) => true,
+            (Some(granted), Some(bucket)) => granted.iter().any(|b| b == bucket),
+            (Some(_), None) => false,
+        }
+    }
+}
+
 /// Database-backed Auth Manager
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct AuthManager {
     db_conn: Option<Arc<DbConnection>>,
     auth_required: bool,
-    /// Argon2id PHC string of the admin password, computed once at startup.
-    password_phc: Option<String>,
+    policy: AuthPolicy,
+    /// Account that `LINASTORE_ADMIN_PASSWORD` signs in when there is no database.
+    admin_username: String,
+    /// Argon2id PHC string of the admin password, computed at startup and
+    /// replaced when credentials are reloaded.
+    password_phc: RwLock<Option<String>>,
 }
 
 impl AuthManager {
@@ -192,7 +351,9 @@ impl AuthManager {
         AuthManager {
             db_conn,
             auth_required,
-            password_phc,
+            policy: env.auth_policy,
+            admin_username: env.admin_username.clone(),
+            password_phc: RwLock::new(password_phc),
         }
     }
 
@@ -200,14 +361,50 @@ impl AuthManager {
         self.auth_required
     }
 
+    /// Who a caller without credentials acts as for `action`, or `None` when
+    /// the policy demands credentials for it.
+    pub fn anonymous(&self, action: Action) -> Option<Principal> {
+        if !self.auth_required {
+            return Some(Principal::anonymous());
+        }
+        match (self.policy, action) {
+            (AuthPolicy::ReadOpen, Action::Read) => Some(Principal::anonymous_reader()),
+            _ => None,
+        }
+    }
+
     pub fn verify_password(&self, password: &str) -> bool {
-        match &self.password_phc {
-            Some(phc) => verify_password_argon2(password, phc),
-            None => false,
+        match self.password_phc.read().ok().as_deref() {
+            Some(Some(phc)) => verify_password_argon2(password, phc),
+            _ => false,
         }
     }
 
 
+    /// Check `password` against the account `username` and return who it is.
+    /// Without a database only the admin account exists.
+    pub async fn verify_credentials(&self, username: &str, password: &str) -> Option<Principal> {
+        let Some(db_conn) = &self.db_conn else {
+            return (username == self.admin_username && self.verify_password(password)).then(
+                || Principal {
+                    user_id: username.to_string(),
+                    role: Role::Admin,
+                    buckets: None,
+                },
+            );
+        };
+        match db_conn.auth_get_user_credentials(username).await {
+            Ok(Some((user_id, phc))) if verify_password_argon2(password, &phc) => {
+                self.principal(user_id, KeyScope::ReadWrite).await
+            }
+            Ok(_) => None,
+            Err(e) => {
+                event!(Level::ERROR, "Failed to look up user '{}': {}", username, e);
+                None
+            }
+        }
+    }
+
     /// Create a new session and store it in the database
     pub async fn create_session(&self, user_id: &str) -> Result<Session> {
         let token = Uuid::new_v4().to_string();
@@ -227,7 +424,7 @@ impl AuthManager {
             db_conn
                 .auth_insert_session(
                     &Uuid::new_v4().to_string(),
-                    &session.token,
+                    &credential_digest(&session.token),
                     &session.user_id,
                     session.expires_at_timestamp as i64,
                     now,
@@ -241,9 +438,12 @@ impl AuthManager {
 
     /// Validate a session token against the database
     ///
+    /// Sessions are stored by the digest of their token, so they survive a
+    /// restart without the database ever holding a usable token.
+    ///
     /// # Arguments
     /// * `token` - The session token to validate
-    /// * `grace_period_seconds` - Grace period in seconds after expiration (default: 0)
+    /// * `grace_period_seconds` - How long past its expiry the session is still accepted (default: 0)
     pub async fn validate_session(&self, token: &str, grace_period_seconds: i64) -> Option<String> {
         // If auth is not required, allow access without session
         if !self.auth_required {
@@ -257,18 +457,112 @@ impl AuthManager {
                 .unwrap_or_default()
                 .as_secs() as i64;
             
-            // Add grace period to current time for decryption validation
-            let now_with_grace = now + grace_period_seconds;
-            
-            match db_conn.auth_get_user_id_by_token(token, now_with_grace).await {
-                Ok(user_id) => user_id,
-                Err(_) => None,
-            }
+            // Accept sessions that expired within the grace period, for
+            // payloads encrypted just before the token expired
+            let now_with_grace = now - grace_period_seconds;
+
+            db_conn
+                .auth_get_user_id_by_token(&credential_digest(token), now_with_grace)
+                .await
+                .unwrap_or_default()
         } else {
             None
         }
     }
 
+    /// Resolve a session token or an API key to the principal it authenticates.
+    /// `grace_period_seconds` only applies to session tokens; keys do not expire.
+    pub async fn validate_credential(
+        &self,
+        credential: &str,
+        grace_period_seconds: i64,
+    ) -> Option<Principal> {
+        if !self.auth_required {
+            return Some(Principal::anonymous());
+        }
+        if credential.starts_with(API_KEY_PREFIX) {
+            return self.validate_api_key(credential).await;
+        }
+        let user_id = self.validate_session(credential, grace_period_seconds).await?;
+        self.principal(user_id, KeyScope::ReadWrite).await
+    }
+
+    async fn validate_api_key(&self, key: &str) -> Option<Principal> {
+        let db_conn = self.db_conn.as_ref()?;
+        match db_conn.auth_get_api_key(&credential_digest(key)).await {
+            Ok(Some((user_id, scope))) => self.principal(user_id, scope.parse().ok()?).await,
+            Ok(None) => None,
+            Err(e) => {
+                event!(Level::ERROR, "Failed to look up API key: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The user `username` itself, for clients that proved who they are by
+    /// other means than a password, such as a TLS client certificate.
+    pub async fn principal_for_user(&self, username: &str) -> Option<Principal> {
+        if !self.auth_required {
+            return Some(Principal::anonymous());
+        }
+        let db_conn = self.db_conn.as_ref()?;
+        match db_conn.auth_get_user_id_by_username(username).await {
+            Ok(Some(user_id)) => self.principal(user_id, KeyScope::ReadWrite).await,
+            Ok(None) => None,
+            Err(e) => {
+                event!(Level::ERROR, "Failed to look up user '{}': {}", username, e);
+                None
+            }
+        }
+    }
+
+    /// `user_id` acting with its stored role, limited by `scope`, and its
+    /// bucket grants.
+    async fn principal(&self, user_id: String, scope: KeyScope) -> Option<Principal> {
+        let db_conn = self.db_conn.as_ref()?;
+        let lookup = async {
+            let role = db_conn.auth_get_user_role(&user_id).await?;
+            let grants = db_conn.auth_list_grants(&user_id).await?;
+            Ok::<_, crate::error::Error>((role, grants))
+        };
+        match lookup.await {
+            Ok((Some(role), grants)) => Some(Principal {
+                role: scope.cap(role.parse().ok()?),
+                buckets: (!grants.is_empty()).then_some(grants),
+                user_id,
+            }),
+            Ok((None, _)) => None,
+            Err(e) => {
+                event!(Level::ERROR, "Failed to look up access of user {}: {}", user_id, e);
+                None
+            }
+        }
+    }
+
+    /// Sessions that have not expired yet, newest first.
+    pub async fn active_sessions(&self) -> Result<Vec<ActiveSession>> {
+        let Some(db_conn) = &self.db_conn else {
+            return Ok(Vec::new());
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let sessions = db_conn
+            .auth_list_sessions(now)
+            .await
+            .map_err(|e| err_msg(format!("Failed to list sessions: {}", e)))?;
+        Ok(sessions
+            .into_iter()
+            .map(|(id, username, created_at, expires_at)| ActiveSession {
+                id,
+                username,
+                created_at,
+                expires_at,
+            })
+            .collect())
+    }
+
     /// Cleanup expired sessions from the database
     pub async fn cleanup_expired_sessions(&self) {
         if let Some(db_conn) = &self.db_conn {
@@ -287,7 +581,8 @@ impl AuthManager {
     /// Handle authentication handshake request
     ///
     /// This method processes the authentication handshake request from a client.
-    /// It verifies the password and creates a new session if authentication succeeds.
+    /// It verifies the user's password and creates a new session bound to the
+    /// user's id if authentication succeeds.
     ///
     /// # Arguments
     /// * `username` - The username (sent in the identifier field of LiNaProtocol)
@@ -320,48 +615,22 @@ impl AuthManager {
             return Err(HandshakeStatus::AuthDisabled);
         }
 
-        if self.verify_password(password) {
-            // Ensure user exists in database
-            if let Some(db_conn) = &self.db_conn {
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs() as i64;
-
-                // Try to get user by username
-                let user_id = match db_conn.auth_get_user_id_by_username(username).await {
-                    Ok(Some(id)) => id.to_string(),
-                    Ok(None) => {
-                        let user_id = Uuid::new_v4().to_string();
-                        // We just verified the password against the in-memory PHC;
-                        // generate a fresh per-row PHC (independent salt) for storage.
-                        let user_phc = hash_password_argon2(password)
-                            .map_err(|_| HandshakeStatus::InternalError)?;
-                        db_conn
-                            .auth_insert_user(&user_id, username, &user_phc, now)
-                            .await
-                            .map_err(|_| HandshakeStatus::InternalError)?;
-                        user_id.to_string()
-                    }
-                    Err(_) => return Err(HandshakeStatus::InternalError),
-                };
-
-                // Create session
-                match self.create_session(&user_id).await {
-                    Ok(session) => Ok((session.token, session.expires_at_timestamp)),
-                    Err(_) => Err(HandshakeStatus::InternalError),
-                }
-            } else {
-                // Fallback to username as user_id if no database
-                let session = self
-                    .create_session(username)
-                    .await
-                    .map_err(|_| HandshakeStatus::InternalError)?;
-                Ok((session.token, session.expires_at_timestamp))
-            }
-        } else {
-            Err(HandshakeStatus::InvalidPassword)
-        }
+        let Some(principal) = self.verify_credentials(username, password).await else {
+            event!(Level::WARN, "Failed sign-in for user '{}'", username);
+            return Err(HandshakeStatus::InvalidPassword);
+        };
+        let session = self
+            .create_session(&principal.user_id)
+            .await
+            .map_err(|_| HandshakeStatus::InternalError)?;
+        event!(
+            Level::INFO,
+            "User '{}' ({}) signed in as {}",
+            username,
+            principal.user_id,
+            principal.role.as_str()
+        );
+        Ok((session.token, session.expires_at_timestamp))
     }
 }
 
@@ -471,7 +740,8 @@ pub fn init_auth_manager(db_conn: Option<Arc<DbConnection>>) -> Arc<AuthManager>
 ///
 /// This function checks if password protection is enabled via LINASTORE_AUTH_REQUIRED,
 /// and if so, creates an admin user using LINASTORE_ADMIN_USER and
-/// LINASTORE_ADMIN_PASSWORD. The admin user is only created if it doesn't already exist.
+/// LINASTORE_ADMIN_PASSWORD. An existing admin user gets its password reset
+/// when LINASTORE_ADMIN_PASSWORD has changed.
 pub async fn init_admin_user(db_conn: &Arc<DbConnection>) -> Result<()> {
     let env_vars = EnvVar::get_instance();
 
@@ -488,10 +758,60 @@ pub async fn init_admin_user(db_conn: &Arc<DbConnection>) -> Result<()> {
         .as_deref()
         .ok_or_else(|| err_msg("Missing admin password while authentication is enabled"))?;
 
+    sync_admin_user(db_conn, admin_username, admin_password).await
+}
+
+/// Re-read the admin password and apply it without a restart. Sessions and
+/// API keys stay valid; other accounts are looked up in the database on
+/// every request and so never need reloading.
+pub async fn reload_credentials() -> Result<()> {
+    let env_vars = EnvVar::get_instance();
+    if !env_vars.auth_required {
+        event!(Level::INFO, "Password protection disabled, nothing to reload");
+        return Ok(());
+    }
+
+    let admin_password = env_vars
+        .current_admin_password()?
+        .ok_or_else(|| err_msg("Missing admin password while authentication is enabled"))?;
+    let password_phc = hash_password_argon2(&admin_password)?;
+
+    let auth_manager = get_auth_manager();
+    if let Some(db_conn) = &auth_manager.db_conn {
+        sync_admin_user(db_conn, &env_vars.admin_username, &admin_password).await?;
+    }
+    match auth_manager.password_phc.write() {
+        Ok(mut phc) => *phc = Some(password_phc),
+        Err(_) => return Err(err_msg("Admin password lock poisoned")),
+    }
+    event!(Level::INFO, "Credentials reloaded");
+    Ok(())
+}
+
+/// Create the admin account, or bring its password and role in line with
+/// `admin_password`.
+async fn sync_admin_user(
+    db_conn: &DbConnection,
+    admin_username: &str,
+    admin_password: &str,
+) -> Result<()> {
     // Check if admin user already exists
-    match db_conn.auth_get_user_id_by_username(admin_username).await {
-        Ok(Some(_)) => {
+    match db_conn.auth_get_user_credentials(admin_username).await {
+        Ok(Some((_, phc))) if verify_password_argon2(admin_password, &phc) => {
             event!(Level::INFO, "Admin user '{}' already exists, skipping creation", admin_username);
+            db_conn
+                .auth_set_user_role(admin_username, Role::Admin.as_str(), unix_now())
+                .await?;
+        }
+        Ok(Some((user_id, _))) => {
+            let password_phc = hash_password_argon2(admin_password)?;
+            db_conn
+                .auth_update_password(&user_id, &password_phc, unix_now())
+                .await?;
+            db_conn
+                .auth_set_user_role(admin_username, Role::Admin.as_str(), unix_now())
+                .await?;
+            event!(Level::INFO, "Admin user '{}' password updated", admin_username);
         }
         Ok(None) => {
             // Hash the admin password with Argon2id (per-row salt embedded in PHC).
@@ -506,7 +826,13 @@ pub async fn init_admin_user(db_conn: &Arc<DbConnection>) -> Result<()> {
 
             // Insert admin user into database
             db_conn
-                .auth_insert_user(&user_id, admin_username, &password_phc, now)
+                .auth_insert_user(
+                    &user_id,
+                    admin_username,
+                    &password_phc,
+                    Role::Admin.as_str(),
+                    now,
+                )
                 .await?;
 
             event!(Level::INFO, "Admin user '{}' created successfully", admin_username);
@@ -520,6 +846,137 @@ pub async fn init_admin_user(db_conn: &Arc<DbConnection>) -> Result<()> {
     Ok(())
 }
 
+/// Add a user account with its own password; fails if the name is taken.
+pub async fn add_user(
+    db_conn: &DbConnection,
+    username: &str,
+    password: &str,
+    role: Role,
+) -> Result<()> {
+    if username.is_empty() || username.len() > u8::MAX as usize || username.contains('\0') {
+        return Err(err_msg("Username must be 1-255 bytes without NUL"));
+    }
+    if password.is_empty() {
+        return Err(err_msg("Password must not be empty"));
+    }
+    if db_conn.auth_get_user_id_by_username(username).await?.is_some() {
+        return Err(err_msg(format!("User '{}' already exists", username)));
+    }
+    let password_phc = hash_password_argon2(password)?;
+    db_conn
+        .auth_insert_user(
+            &Uuid::new_v4().to_string(),
+            username,
+            &password_phc,
+            role.as_str(),
+            unix_now(),
+        )
+        .await
+}
+
+/// Change the role of an existing user; it applies to its sessions and keys
+/// from their next request.
+pub async fn set_user_role(db_conn: &DbConnection, username: &str, role: Role) -> Result<()> {
+    if !db_conn
+        .auth_set_user_role(username, role.as_str(), unix_now())
+        .await?
+    {
+        return Err(err_msg(format!("User '{}' does not exist", username)));
+    }
+    Ok(())
+}
+
+/// Remove a user account and end its sessions.
+pub async fn remove_user(db_conn: &DbConnection, username: &str) -> Result<()> {
+    if !db_conn.auth_delete_user(username).await? {
+        return Err(err_msg(format!("User '{}' does not exist", username)));
+    }
+    Ok(())
+}
+
+/// Let `username` reach `bucket`. Once a user holds any grant it is limited
+/// to its granted buckets.
+pub async fn grant_bucket(db_conn: &DbConnection, username: &str, bucket: &str) -> Result<()> {
+    if bucket.is_empty() || bucket.len() > 255 || bucket.contains('/') {
+        return Err(err_msg("Bucket name must be 1-255 bytes without '/'"));
+    }
+    let user_id = db_conn
+        .auth_get_user_id_by_username(username)
+        .await?
+        .ok_or_else(|| err_msg(format!("User '{}' does not exist", username)))?;
+    db_conn.auth_insert_grant(&user_id, bucket, unix_now()).await
+}
+
+/// Withdraw the grant of `bucket` from `username`.
+pub async fn revoke_bucket(db_conn: &DbConnection, username: &str, bucket: &str) -> Result<()> {
+    let user_id = db_conn
+        .auth_get_user_id_by_username(username)
+        .await?
+        .ok_or_else(|| err_msg(format!("User '{}' does not exist", username)))?;
+    if !db_conn.auth_delete_grant(&user_id, bucket).await? {
+        return Err(err_msg(format!(
+            "User '{}' holds no grant for bucket '{}'",
+            username, bucket
+        )));
+    }
+    Ok(())
+}
+
+/// Issue a new API key for `username` and return its id and secret. Only the
+/// digest of the secret is stored, so it cannot be shown again.
+pub async fn create_api_key(
+    db_conn: &DbConnection,
+    username: &str,
+    name: &str,
+    scope: KeyScope,
+) -> Result<(String, String)> {
+    if name.len() > 255 {
+        return Err(err_msg("Key name must be at most 255 bytes"));
+    }
+    let user_id = db_conn
+        .auth_get_user_id_by_username(username)
+        .await?
+        .ok_or_else(|| err_msg(format!("User '{}' does not exist", username)))?;
+
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let key = format!("{}{}", API_KEY_PREFIX, hex::encode(secret));
+    let key_id = Uuid::new_v4().to_string();
+    db_conn
+        .auth_insert_api_key(
+            &key_id,
+            &user_id,
+            name,
+            &credential_digest(&key),
+            scope.as_str(),
+            unix_now(),
+        )
+        .await?;
+    Ok((key_id, key))
+}
+
+/// Revoke the API key `key_id`.
+pub async fn revoke_api_key(db_conn: &DbConnection, key_id: &str) -> Result<()> {
+    if !db_conn.auth_delete_api_key(key_id).await? {
+        return Err(err_msg(format!("API key '{}' does not exist", key_id)));
+    }
+    Ok(())
+}
+
+/// How session tokens and API keys are stored. Both are random (122 and 256
+/// bits), so a plain digest is enough to keep them useless to whoever reads
+/// the database.
+fn credential_digest(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
 /// Get the AuthManager instance
 pub fn get_auth_manager() -> Arc<AuthManager> {
     AUTH_MANAGER
@@ -676,6 +1133,202 @@ mod tests {
         assert!(!auth_manager.is_password_enabled());
     }
 
+    #[tokio::test]
+    async fn test_users_sign_in_with_their_own_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}", dir.path().join("auth.db").display());
+        let db_conn = Arc::new(DbConnection::new(&url).await.unwrap());
+        add_user(&db_conn, "alice", "alice-pw", Role::Writer).await.unwrap();
+        add_user(&db_conn, "bob", "bob-pw", Role::Writer).await.unwrap();
+        assert!(add_user(&db_conn, "alice", "other", Role::Writer).await.is_err());
+
+        let auth_manager = AuthManager::new(Some(db_conn.clone()));
+        let alice = auth_manager.verify_credentials("alice", "alice-pw").await;
+        assert!(alice.is_some());
+        assert_ne!(alice, auth_manager.verify_credentials("bob", "bob-pw").await);
+        assert_eq!(auth_manager.verify_credentials("alice", "bob-pw").await, None);
+        assert_eq!(auth_manager.verify_credentials("carol", "alice-pw").await, None);
+
+        remove_user(&db_conn, "alice").await.unwrap();
+        assert_eq!(auth_manager.verify_credentials("alice", "alice-pw").await, None);
+        assert!(remove_user(&db_conn, "alice").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_scopes() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}", dir.path().join("auth.db").display());
+        let db_conn = Arc::new(DbConnection::new(&url).await.unwrap());
+        add_user(&db_conn, "ci", "ci-pw", Role::Admin).await.unwrap();
+        assert!(
+            create_api_key(&db_conn, "nobody", "x", KeyScope::Read)
+                .await
+                .is_err()
+        );
+
+        let (read_id, read_key) = create_api_key(&db_conn, "ci", "nightly", KeyScope::Read)
+            .await
+            .unwrap();
+        let (_, write_key) = create_api_key(&db_conn, "ci", "deploy", KeyScope::ReadWrite)
+            .await
+            .unwrap();
+        assert!(read_key.starts_with(API_KEY_PREFIX));
+
+        let mut auth_manager = AuthManager::new(Some(db_conn.clone()));
+        auth_manager.auth_required = true;
+        let reader = auth_manager.validate_credential(&read_key, 0).await.unwrap();
+        assert_eq!(reader.role, Role::Reader);
+        let writer = auth_manager.validate_credential(&write_key, 0).await.unwrap();
+        assert_eq!(writer.role, Role::Admin);
+        assert_eq!(reader.user_id, writer.user_id);
+        assert_eq!(
+            auth_manager
+                .validate_credential(&format!("{}00", API_KEY_PREFIX), 0)
+                .await,
+            None
+        );
+
+        revoke_api_key(&db_conn, &read_id).await.unwrap();
+        assert_eq!(auth_manager.validate_credential(&read_key, 0).await, None);
+        assert!(revoke_api_key(&db_conn, &read_id).await.is_err());
+    }
+
+    #[test]
+    fn test_role_permissions() {
+        use Action::*;
+        assert!(Role::Reader.permits(Read));
+        assert!(!Role::Reader.permits(Write));
+        assert!(!Role::Reader.permits(Delete));
+        assert!(Role::Writer.permits(Write));
+        assert!(Role::Writer.permits(Delete));
+        assert!(!Role::Writer.permits(PatternDelete));
+        assert!(Role::Admin.permits(PatternDelete));
+        assert!(!Principal::anonymous().role.permits(PatternDelete));
+        assert_eq!(KeyScope::Read.cap(Role::Admin), Role::Reader);
+        assert_eq!(KeyScope::ReadWrite.cap(Role::Reader), Role::Reader);
+        assert_eq!("admin".parse::<Role>(), Ok(Role::Admin));
+        assert!("root".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn test_anonymous_callers_follow_policy() {
+        use Action::*;
+        let mut auth_manager = AuthManager::new(None);
+        auth_manager.auth_required = false;
+        assert_eq!(auth_manager.anonymous(Write), Some(Principal::anonymous()));
+
+        auth_manager.auth_required = true;
+        auth_manager.policy = AuthPolicy::ReadOpen;
+        assert_eq!(auth_manager.anonymous(Read).unwrap().role, Role::Reader);
+        assert_eq!(auth_manager.anonymous(Write), None);
+
+        auth_manager.policy = AuthPolicy::Auth;
+        assert_eq!(auth_manager.anonymous(Read), None);
+    }
+
+    #[tokio::test]
+    async fn test_bucket_grants_limit_access() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}", dir.path().join("auth.db").display());
+        let db_conn = Arc::new(DbConnection::new(&url).await.unwrap());
+        add_user(&db_conn, "tenant", "pw", Role::Writer).await.unwrap();
+
+        let mut auth_manager = AuthManager::new(Some(db_conn.clone()));
+        auth_manager.auth_required = true;
+        let (token, _) = auth_manager.handle_handshake("tenant", "pw").await.unwrap();
+        let principal = auth_manager.validate_credential(&token, 0).await.unwrap();
+        assert!(principal.may_access(Some("anything")));
+        assert!(principal.may_access(None));
+
+        grant_bucket(&db_conn, "tenant", "acme").await.unwrap();
+        let (_, key) = create_api_key(&db_conn, "tenant", "ci", KeyScope::Read)
+            .await
+            .unwrap();
+        for credential in [&token, &key] {
+            let principal = auth_manager.validate_credential(credential, 0).await.unwrap();
+            assert!(principal.may_access(Some("acme")));
+            assert!(!principal.may_access(Some("globex")));
+            assert!(!principal.may_access(None));
+        }
+
+        revoke_bucket(&db_conn, "tenant", "acme").await.unwrap();
+        assert!(revoke_bucket(&db_conn, "tenant", "acme").await.is_err());
+        assert!(grant_bucket(&db_conn, "tenant", "a/b").await.is_err());
+        let principal = auth_manager.validate_credential(&token, 0).await.unwrap();
+        assert!(principal.may_access(Some("globex")));
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_stored_hashed_and_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}", dir.path().join("auth.db").display());
+        let db_conn = Arc::new(DbConnection::new(&url).await.unwrap());
+        add_user(&db_conn, "frank", "pw", Role::Writer).await.unwrap();
+
+        let mut auth_manager = AuthManager::new(Some(db_conn.clone()));
+        auth_manager.auth_required = true;
+        let (token, _) = auth_manager.handle_handshake("frank", "pw").await.unwrap();
+        // The database holds the digest, never the token itself
+        assert_eq!(db_conn.auth_get_user_id_by_token(&token, 0).await.unwrap(), None);
+        assert!(
+            db_conn
+                .auth_get_user_id_by_token(&credential_digest(&token), 0)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        drop(auth_manager);
+        drop(db_conn);
+
+        // A new process opening the same database still knows the session
+        let db_conn = Arc::new(DbConnection::new(&url).await.unwrap());
+        let mut auth_manager = AuthManager::new(Some(db_conn));
+        auth_manager.auth_required = true;
+        assert!(auth_manager.validate_session(&token, 0).await.is_some());
+        assert!(auth_manager.validate_session("not-a-token", 0).await.is_none());
+
+        let sessions = auth_manager.active_sessions().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].username, "frank");
+    }
+
+    #[tokio::test]
+    async fn test_admin_password_change_keeps_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}", dir.path().join("auth.db").display());
+        let db_conn = Arc::new(DbConnection::new(&url).await.unwrap());
+        sync_admin_user(&db_conn, "root", "old-pw").await.unwrap();
+
+        let mut auth_manager = AuthManager::new(Some(db_conn.clone()));
+        auth_manager.auth_required = true;
+        let (token, _) = auth_manager.handle_handshake("root", "old-pw").await.unwrap();
+
+        sync_admin_user(&db_conn, "root", "new-pw").await.unwrap();
+        assert_eq!(auth_manager.verify_credentials("root", "old-pw").await, None);
+        let admin = auth_manager.verify_credentials("root", "new-pw").await.unwrap();
+        assert_eq!(admin.role, Role::Admin);
+        assert!(auth_manager.validate_session(&token, 0).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_role_changes_apply_to_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}", dir.path().join("auth.db").display());
+        let db_conn = Arc::new(DbConnection::new(&url).await.unwrap());
+        add_user(&db_conn, "dana", "dana-pw", Role::Reader).await.unwrap();
+
+        let mut auth_manager = AuthManager::new(Some(db_conn.clone()));
+        auth_manager.auth_required = true;
+        let (token, _) = auth_manager.handle_handshake("dana", "dana-pw").await.unwrap();
+        let principal = auth_manager.validate_credential(&token, 0).await.unwrap();
+        assert_eq!(principal.role, Role::Reader);
+
+        set_user_role(&db_conn, "dana", Role::Writer).await.unwrap();
+        let principal = auth_manager.validate_credential(&token, 0).await.unwrap();
+        assert_eq!(principal.role, Role::Writer);
+        assert!(set_user_role(&db_conn, "erin", Role::Admin).await.is_err());
+    }
+
     #[test]
     fn test_auth_manager_is_password_enabled() {
         // Test without environment variable
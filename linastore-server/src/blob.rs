@@ -0,0 +1,178 @@
+//! Where the porter's store keeps file content: `linadata/` by default, an
+//! S3-compatible bucket with `LINASTORE_BLOB_BACKEND=s3`, or both with
+//! `tiered`, where content idle for `LINASTORE_BLOB_COLD_AFTER` seconds
+//! moves to the bucket. Metadata always stays in `linadata/meta.db`.
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use linabase::blob::BlobBackend;
+
+use crate::{
+    config,
+    error::{Result, err_msg},
+};
+
+/// Which backend holds file content.
+#[derive(Debug, Clone)]
+pub enum BlobSettings {
+    /// Files under `linadata/`.
+    Local,
+    #[cfg(feature = "s3-backend")]
+    S3(linabase::blob::S3Settings),
+    /// Files under `linadata/`, moved to the bucket once idle.
+    #[cfg(feature = "s3-backend")]
+    Tiered {
+        s3: linabase::blob::S3Settings,
+        cold_after: Duration,
+        /// How often idle content is looked for.
+        interval: Duration,
+    },
+}
+
+#[cfg(feature = "s3-backend")]
+const DEFAULT_COLD_AFTER: Duration = Duration::from_secs(7 * 24 * 3600);
+#[cfg(feature = "s3-backend")]
+const DEFAULT_TIER_INTERVAL: Duration = Duration::from_secs(3600);
+
+impl BlobSettings {
+    pub fn load() -> Result<Self> {
+        let raw = config::var("LINASTORE_BLOB_BACKEND").unwrap_or_default();
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "" | "local" | "fs" => Ok(BlobSettings::Local),
+            #[cfg(feature = "s3-backend")]
+            "s3" => Ok(BlobSettings::S3(Self::load_s3()?)),
+            #[cfg(feature = "s3-backend")]
+            "tiered" => Ok(BlobSettings::Tiered {
+                s3: Self::load_s3()?,
+                cold_after: seconds("LINASTORE_BLOB_COLD_AFTER", DEFAULT_COLD_AFTER)?,
+                interval: seconds("LINASTORE_BLOB_TIER_INTERVAL", DEFAULT_TIER_INTERVAL)?,
+            }),
+            #[cfg(not(feature = "s3-backend"))]
+            "s3" | "tiered" => Err(err_msg(config::attribute(format!(
+                "LINASTORE_BLOB_BACKEND={} needs a server built with the s3-backend feature",
+                raw.trim()
+            )))),
+            _ => Err(err_msg(config::attribute(format!(
+                "LINASTORE_BLOB_BACKEND must be local, s3 or tiered: {:?}",
+                raw
+            )))),
+        }
+    }
+
+    #[cfg(feature = "s3-backend")]
+    fn load_s3() -> Result<linabase::blob::S3Settings> {
+        fn required(name: &str) -> Result<String> {
+            config::var(name)
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| v.trim().to_string())
+                .ok_or_else(|| {
+                    err_msg(format!(
+                        "{} must be set when LINASTORE_BLOB_BACKEND is s3 or tiered",
+                        name
+                    ))
+                })
+        }
+
+        let mut prefix = config::var("LINASTORE_BLOB_S3_PREFIX")
+            .unwrap_or_default()
+            .trim()
+            .trim_start_matches('/')
+            .to_string();
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+        Ok(linabase::blob::S3Settings {
+            endpoint: required("LINASTORE_BLOB_S3_ENDPOINT")?,
+            bucket: required("LINASTORE_BLOB_S3_BUCKET")?,
+            region: config::var("LINASTORE_BLOB_S3_REGION")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| v.trim().to_string())
+                .unwrap_or_else(|| "us-east-1".to_string()),
+            access_key: required("LINASTORE_BLOB_S3_ACCESS_KEY")?,
+            secret_key: required("LINASTORE_BLOB_S3_SECRET_KEY")?,
+            prefix,
+        })
+    }
+
+    /// The backend to open the store at `root` with; `None` keeps the default.
+    pub fn backend(&self, root: &Path) -> Result<Option<Arc<dyn BlobBackend>>> {
+        let _ = root;
+        match self {
+            BlobSettings::Local => Ok(None),
+            #[cfg(feature = "s3-backend")]
+            BlobSettings::S3(settings) => Ok(Some(Arc::new(open_s3(settings)?))),
+            #[cfg(feature = "s3-backend")]
+            BlobSettings::Tiered { s3, cold_after, .. } => {
+                let hot = linabase::blob::FsBackend::new(root.join("linadata"));
+                let mut tiered = linabase::blob::TieredBackend::new(
+                    Arc::new(hot),
+                    Arc::new(open_s3(s3)?),
+                    *cold_after,
+                );
+                tiered.set_on_recall(|_| crate::metrics::TIER_RECALLS.inc());
+                Ok(Some(Arc::new(tiered)))
+            }
+        }
+    }
+
+    /// How often to move idle content to the cold tier; `None` without one.
+    pub fn demote_interval(&self) -> Option<Duration> {
+        match self {
+            #[cfg(feature = "s3-backend")]
+            BlobSettings::Tiered { interval, .. } => Some(*interval),
+            _ => None,
+        }
+    }
+
+    /// Where content is kept, for the startup log.
+    pub fn describe(&self) -> String {
+        match self {
+            BlobSettings::Local => "linadata/".to_string(),
+            #[cfg(feature = "s3-backend")]
+            BlobSettings::S3(settings) => describe_s3(settings),
+            #[cfg(feature = "s3-backend")]
+            BlobSettings::Tiered { s3, cold_after, .. } => format!(
+                "linadata/, moved to {} after {}s idle",
+                describe_s3(s3),
+                cold_after.as_secs()
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "s3-backend")]
+fn open_s3(settings: &linabase::blob::S3Settings) -> Result<linabase::blob::S3Backend> {
+    linabase::blob::S3Backend::new(settings.clone())
+        .map_err(|e| err_msg(config::attribute(format!("LINASTORE_BLOB_S3_ENDPOINT: {}", e))))
+}
+
+#[cfg(feature = "s3-backend")]
+fn describe_s3(settings: &linabase::blob::S3Settings) -> String {
+    format!(
+        "s3 bucket {} at {}{}",
+        settings.bucket,
+        settings.endpoint,
+        if settings.prefix.is_empty() {
+            String::new()
+        } else {
+            format!(", prefix {}", settings.prefix)
+        }
+    )
+}
+
+/// A positive number of seconds from `name`, else `default`.
+#[cfg(feature = "s3-backend")]
+fn seconds(name: &str, default: Duration) -> Result<Duration> {
+    match config::var(name) {
+        Ok(raw) => match raw.trim().parse::<u64>() {
+            Ok(secs) if secs > 0 => Ok(Duration::from_secs(secs)),
+            _ => Err(err_msg(config::attribute(format!(
+                "{} must be a positive number of seconds: {:?}",
+                name, raw
+            )))),
+        },
+        Err(_) => Ok(default),
+    }
+}
@@ -0,0 +1,293 @@
+//! Settings from `linastore.toml` and `--set` flags.
+//!
+//! Every key in the file stands for one `LINASTORE_*` variable, so the rest
+//! of the server keeps reading plain variables through [`var`]. A value is
+//! taken from the first of: a `--set section.key=value` flag, the
+//! environment, the config file.
+
+use std::{collections::HashMap, env::VarError, path::Path, sync::OnceLock};
+
+use toml_edit::{Document, Item, Value};
+
+use crate::error::{Context, Result, err_msg};
+
+/// Config file read from the working directory when no path is given.
+pub const DEFAULT_CONFIG_FILE: &str = "linastore.toml";
+
+/// `section.key` in the config file and the variable it sets.
+const KEYS: &[(&str, &str)] = &[
+    ("server.root", "LINASTORE_ROOT"),
+    ("server.ip", "LINASTORE_IP"),
+    ("server.http_port", "LINASTORE_HTTP_PORT"),
+    ("server.advanced_port", "LINASTORE_ADVANCED_PORT"),
+    ("server.s3_port", "LINASTORE_S3_PORT"),
+    ("server.http_bind", "LINASTORE_HTTP_BIND"),
+    ("server.advanced_bind", "LINASTORE_ADVANCED_BIND"),
+    ("server.s3_bind", "LINASTORE_S3_BIND"),
+    (
+        "server.allow_insecure_public",
+        "LINASTORE_ALLOW_INSECURE_PUBLIC",
+    ),
+    ("server.max_payload_size", "LINASTORE_MAX_PAYLOAD_SIZE"),
+    (
+        "server.order_queue_capacity",
+        "LINASTORE_ORDER_QUEUE_CAPACITY",
+    ),
+    ("server.durable_queue", "LINASTORE_DURABLE_QUEUE"),
+    (
+        "server.shutdown_grace_secs",
+        "LINASTORE_SHUTDOWN_GRACE_SECS",
+    ),
+    ("server.worker_threads", "LINASTORE_WORKER_THREADS"),
+    ("database.url", "LINASTORE_DB_URL"),
+    ("store.keep_versions", "LINASTORE_KEEP_VERSIONS"),
+    ("blob.backend", "LINASTORE_BLOB_BACKEND"),
+    ("blob.s3_endpoint", "LINASTORE_BLOB_S3_ENDPOINT"),
+    ("blob.s3_bucket", "LINASTORE_BLOB_S3_BUCKET"),
+    ("blob.s3_region", "LINASTORE_BLOB_S3_REGION"),
+    ("blob.s3_access_key", "LINASTORE_BLOB_S3_ACCESS_KEY"),
+    ("blob.s3_secret_key", "LINASTORE_BLOB_S3_SECRET_KEY"),
+    ("blob.s3_prefix", "LINASTORE_BLOB_S3_PREFIX"),
+    ("blob.cold_after", "LINASTORE_BLOB_COLD_AFTER"),
+    ("blob.tier_interval", "LINASTORE_BLOB_TIER_INTERVAL"),
+    ("auth.policy", "LINASTORE_AUTH_POLICY"),
+    ("auth.admin_user", "LINASTORE_ADMIN_USER"),
+    ("auth.admin_password", "LINASTORE_ADMIN_PASSWORD"),
+    ("auth.admin_password_file", "LINASTORE_ADMIN_PASSWORD_FILE"),
+    ("tls.http_cert", "LINASTORE_HTTP_TLS_CERT"),
+    ("tls.http_key", "LINASTORE_HTTP_TLS_KEY"),
+    ("tls.advanced_cert", "LINASTORE_ADVANCED_TLS_CERT"),
+    ("tls.advanced_key", "LINASTORE_ADVANCED_TLS_KEY"),
+    ("tls.client_ca", "LINASTORE_TLS_CLIENT_CA"),
+    ("http.cache_control", "LINASTORE_HTTP_CACHE_CONTROL"),
+    (
+        "http.blob_cache_control",
+        "LINASTORE_HTTP_BLOB_CACHE_CONTROL",
+    ),
+    ("http.access_log", "LINASTORE_HTTP_ACCESS_LOG"),
+    ("http.rate_limit", "LINASTORE_HTTP_RATE_LIMIT"),
+    ("http.token_rate_limit", "LINASTORE_HTTP_TOKEN_RATE_LIMIT"),
+    ("http.rate_burst", "LINASTORE_HTTP_RATE_BURST"),
+    ("cors.origins", "LINASTORE_CORS_ORIGINS"),
+    ("cors.methods", "LINASTORE_CORS_METHODS"),
+    ("cors.headers", "LINASTORE_CORS_HEADERS"),
+    ("cors.max_age", "LINASTORE_CORS_MAX_AGE"),
+    ("log.dir", "LINASTORE_LOG_DIR"),
+    ("log.output", "LINASTORE_LOG_OUTPUT"),
+    ("log.level", "LINASTORE_LOG_LEVEL"),
+    ("log.format", "LINASTORE_LOG_FORMAT"),
+    ("log.rotation", "LINASTORE_LOG_ROTATION"),
+    ("log.max_size", "LINASTORE_LOG_MAX_SIZE"),
+    ("log.retention", "LINASTORE_LOG_RETENTION"),
+    ("metrics.push", "LINASTORE_METRICS_PUSH"),
+    ("metrics.push_interval", "LINASTORE_METRICS_PUSH_INTERVAL"),
+    ("replication.leader", "LINASTORE_REPLICA_OF"),
+    ("replication.interval", "LINASTORE_REPLICA_INTERVAL"),
+    ("replication.user", "LINASTORE_REPLICA_USER"),
+    ("replication.password", "LINASTORE_REPLICA_PASSWORD"),
+    ("federation.peers", "LINASTORE_PEERS"),
+    ("federation.user", "LINASTORE_PEER_USER"),
+    ("federation.password", "LINASTORE_PEER_PASSWORD"),
+    ("federation.cache", "LINASTORE_PEER_CACHE"),
+    ("telemetry.otlp_endpoint", "LINASTORE_OTLP_ENDPOINT"),
+    ("telemetry.service_name", "LINASTORE_OTLP_SERVICE_NAME"),
+];
+
+/// Where a setting came from, for naming it in errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Origin {
+    File(String),
+    Flag,
+}
+
+/// One setting given by the config file or a flag.
+#[derive(Debug, Clone)]
+struct Setting {
+    key: &'static str,
+    value: String,
+    origin: Origin,
+}
+
+#[derive(Debug, Default)]
+struct Config {
+    /// Settings by variable name
+    file: HashMap<&'static str, Setting>,
+    flags: HashMap<&'static str, Setting>,
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Load the config file and `--set` overrides; call once, before anything
+/// reads settings. Without `path`, `linastore.toml` in the working
+/// directory is read if it exists.
+pub fn install(path: Option<&Path>, overrides: &[String]) -> Result<()> {
+    let mut config = Config::default();
+    let path = match path {
+        Some(path) => Some(path),
+        None => Some(Path::new(DEFAULT_CONFIG_FILE)).filter(|p| p.exists()),
+    };
+    if let Some(path) = path {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        config.file = parse_file(&text, &path.display().to_string())?;
+    }
+    for raw in overrides {
+        let (key, value) = raw
+            .split_once('=')
+            .ok_or_else(|| err_msg(format!("--set {:?} is not of the form key=value", raw)))?;
+        let (key, name) = lookup(key.trim())
+            .ok_or_else(|| err_msg(format!("--set: unknown setting `{}`", key.trim())))?;
+        config.flags.insert(
+            name,
+            Setting {
+                key,
+                value: value.to_string(),
+                origin: Origin::Flag,
+            },
+        );
+    }
+    CONFIG
+        .set(config)
+        .map_err(|_| err_msg("Configuration was already loaded"))
+}
+
+/// The value of the variable `name`, like `std::env::var` but also
+/// consulting `--set` flags and the config file.
+pub fn var(name: &str) -> std::result::Result<String, VarError> {
+    let config = CONFIG.get();
+    if let Some(setting) = config.and_then(|c| c.flags.get(name)) {
+        return Ok(setting.value.clone());
+    }
+    match std::env::var(name) {
+        Err(VarError::NotPresent) => config
+            .and_then(|c| c.file.get(name))
+            .map(|setting| setting.value.clone())
+            .ok_or(VarError::NotPresent),
+        found => found,
+    }
+}
+
+/// Name the config key behind the variable an error message starts with,
+/// when its value came from the file or a flag rather than the environment.
+pub fn attribute(error: String) -> String {
+    let Some(config) = CONFIG.get() else {
+        return error;
+    };
+    let name = error
+        .split(|c: char| !(c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'))
+        .next()
+        .unwrap_or_default();
+    let setting = config.flags.get(name).or_else(|| {
+        config
+            .file
+            .get(name)
+            .filter(|_| std::env::var_os(name).is_none())
+    });
+    match setting {
+        Some(Setting {
+            key,
+            origin: Origin::File(path),
+            ..
+        }) => format!("{} (`{}` in {})", error, key, path),
+        Some(Setting { key, .. }) => format!("{} (`--set {}`)", error, key),
+        None => error,
+    }
+}
+
+fn lookup(key: &str) -> Option<(&'static str, &'static str)> {
+    KEYS.iter().find(|(k, _)| *k == key).copied()
+}
+
+/// Settings by variable name. Scalars become their text and arrays of
+/// strings are joined with commas; anything else is refused by key.
+fn parse_file(text: &str, path: &str) -> Result<HashMap<&'static str, Setting>> {
+    let doc = Document::parse(text)
+        .map_err(|e| err_msg(format!("Invalid config file {}: {}", path, e.message())))?;
+
+    let mut settings = HashMap::new();
+    for (section, item) in doc.as_table().iter() {
+        let Item::Table(table) = item else {
+            return Err(err_msg(format!(
+                "{}: `{}` must be a [section] of settings",
+                path, section
+            )));
+        };
+        for (field, item) in table.iter() {
+            let full = format!("{}.{}", section, field);
+            let (key, name) = lookup(&full)
+                .ok_or_else(|| err_msg(format!("{}: unknown setting `{}`", path, full)))?;
+            let value = item.as_value().and_then(value_text).ok_or_else(|| {
+                err_msg(format!(
+                    "{}: `{}` must be a string, number, boolean or list of strings",
+                    path, full
+                ))
+            })?;
+            settings.insert(
+                name,
+                Setting {
+                    key,
+                    value,
+                    origin: Origin::File(path.to_string()),
+                },
+            );
+        }
+    }
+    Ok(settings)
+}
+
+fn value_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.value().clone()),
+        Value::Integer(i) => Some(i.value().to_string()),
+        Value::Float(f) => Some(f.value().to_string()),
+        Value::Boolean(b) => Some(b.value().to_string()),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| item.as_str().map(str::to_string))
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(", ")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_maps_keys_to_variables() {
+        let settings = parse_file(
+            r#"
+            [server]
+            http_port = 9000
+            durable_queue = true
+
+            [cors]
+            origins = ["https://a.example", "https://b.example"]
+            "#,
+            DEFAULT_CONFIG_FILE,
+        )
+        .unwrap();
+        assert_eq!(settings["LINASTORE_HTTP_PORT"].value, "9000");
+        assert_eq!(settings["LINASTORE_HTTP_PORT"].key, "server.http_port");
+        assert_eq!(settings["LINASTORE_DURABLE_QUEUE"].value, "true");
+        assert_eq!(
+            settings["LINASTORE_CORS_ORIGINS"].value,
+            "https://a.example, https://b.example"
+        );
+    }
+
+    #[test]
+    fn test_parse_file_names_bad_keys() {
+        let err = parse_file("[server]\nhttp_prot = 1\n", DEFAULT_CONFIG_FILE).unwrap_err();
+        assert!(err.to_string().contains("`server.http_prot`"), "{}", err);
+
+        let err = parse_file("[server]\nhttp_port = { a = 1 }\n", DEFAULT_CONFIG_FILE).unwrap_err();
+        assert!(err.to_string().contains("`server.http_port`"), "{}", err);
+
+        let err = parse_file("ip = \"0.0.0.0\"\n", DEFAULT_CONFIG_FILE).unwrap_err();
+        assert!(err.to_string().contains("`ip`"), "{}", err);
+
+        assert!(parse_file("[server\n", DEFAULT_CONFIG_FILE).is_err());
+    }
+}
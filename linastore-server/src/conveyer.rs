@@ -1,25 +1,97 @@
-use std::collections::{HashMap, VecDeque};
+use linabase::service::DataStream;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
 use std::sync::{Arc, Mutex, OnceLock};
 use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
+use tracing::{Level, event};
 
-use crate::dtos::Package;
+use crate::dtos::{Package, Status};
+use crate::journal::OrderJournal;
+use crate::metrics;
 
-const ORDER_QUEUE_CAPACITY: usize = 32;
-const WAITERS_TTL: Duration = Duration::from_secs(20);
 const WAITERS_CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
+// Order timeouts: a base allowance plus time per MiB of payload, capped
+const ORDER_TIMEOUT_BASE: Duration = Duration::from_secs(10);
+const ORDER_TIMEOUT_PER_MIB: Duration = Duration::from_secs(1);
+const ORDER_TIMEOUT_MAX: Duration = Duration::from_secs(120);
+
+/// Deadline for an order carrying `payload_len` bytes, giving large
+/// payloads time to be hashed, compressed and written.
+pub fn order_deadline(payload_len: usize) -> Instant {
+    let mib = u32::try_from(payload_len >> 20).unwrap_or(u32::MAX);
+    let timeout = ORDER_TIMEOUT_BASE
+        .saturating_add(ORDER_TIMEOUT_PER_MIB.saturating_mul(mib))
+        .min(ORDER_TIMEOUT_MAX);
+    Instant::now() + timeout
+}
+
+/// Why [`ConveyQueue::request`] got no response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    /// Another request with the same uni_id is still waiting.
+    DuplicateId,
+    /// The order queue is full; the caller should retry later.
+    Busy,
+    /// The order writes while the server is read-only.
+    ReadOnly,
+    /// The order could not be queued.
+    Enqueue(String),
+    /// The response channel was dropped without an answer.
+    Closed,
+    /// No response arrived in time.
+    Timeout,
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::DuplicateId => write!(f, "a request with this id is already waiting"),
+            RequestError::Busy => write!(f, "order queue is full"),
+            RequestError::ReadOnly => write!(f, "server is read-only"),
+            RequestError::Enqueue(err) => write!(f, "failed to queue order: {}", err),
+            RequestError::Closed => write!(f, "response channel closed unexpectedly"),
+            RequestError::Timeout => write!(f, "timed out waiting for the response"),
+        }
+    }
+}
 
 struct WaiterEntry {
     sender: oneshot::Sender<Package>,
-    created_at: Instant,
+    deadline: Instant,
 }
 
 pub struct ConveyQueue {
     order_queue: Arc<Mutex<VecDeque<Package>>>,
+    // Orders beyond this are refused with `RequestError::Busy`
+    capacity: usize,
     // Maps uni_id to a channel sender for transaction-based responses
     waiters: Arc<Mutex<HashMap<[u8; 16], WaiterEntry>>>,
+    // Streamed content handed over alongside a response, keyed by uni_id
+    streams: Arc<Mutex<HashMap<[u8; 16], DataStream>>>,
     // Channel for notifying when new orders are available
     order_notifier: tokio::sync::watch::Sender<usize>,
+    // Orders the porter already took whose client stopped waiting
+    cancelled: Mutex<HashSet<[u8; 16]>>,
+    // Set when the durable queue is enabled; holds puts until they are stored
+    journal: OnceLock<OrderJournal>,
+}
+
+/// Withdraws a request's order when the request future is dropped before it
+/// got its response, e.g. because the client disconnected.
+struct PendingRequest<'a> {
+    queue: &'a ConveyQueue,
+    uni_id: [u8; 16],
+    armed: bool,
+}
+
+impl Drop for PendingRequest<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            metrics::QUEUE_CANCELLED.inc();
+            self.queue.withdraw(self.uni_id);
+        }
+    }
 }
 
 // Lazy singleton initialization
@@ -34,30 +106,83 @@ impl ConveyQueue {
     pub fn get_instance() -> Arc<ConveyQueue> {
         INSTANCE
             .get_or_init(|| {
-                let (order_notifier, _) = tokio::sync::watch::channel(0usize);
-                let instance = Arc::new(ConveyQueue {
-                    order_queue: Arc::new(Mutex::new(VecDeque::new())),
-                    waiters: Arc::new(Mutex::new(HashMap::new())),
-                    order_notifier,
-                });
+                let capacity = crate::vars::EnvVar::get_instance().order_queue_capacity;
+                let instance = Arc::new(ConveyQueue::with_capacity(capacity));
                 Self::start_waiter_cleanup_task(&instance);
                 instance
             })
             .clone()
     }
 
-    pub fn produce_order(&self, order: Package) -> Result<(), String> {
+    fn with_capacity(capacity: usize) -> Self {
+        let (order_notifier, _) = tokio::sync::watch::channel(0usize);
+        ConveyQueue {
+            order_queue: Arc::new(Mutex::new(VecDeque::new())),
+            capacity,
+            waiters: Arc::new(Mutex::new(HashMap::new())),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            order_notifier,
+            cancelled: Mutex::new(HashSet::new()),
+            journal: OnceLock::new(),
+        }
+    }
+
+    /// Journal accepted puts at `path` from now on, and queue the puts a
+    /// previous run accepted but never stored. Returns how many were
+    /// replayed.
+    pub fn open_journal(&self, path: &Path) -> crate::error::Result<usize> {
+        let (journal, backlog) = OrderJournal::open(path)?;
+        if self.journal.set(journal).is_err() {
+            return Err(crate::error::err_msg("Order journal is already open"));
+        }
+
+        let replayed = backlog.len();
+        if replayed > 0 {
+            let queue_len = {
+                let mut queue = self
+                    .order_queue
+                    .lock()
+                    .map_err(|_| crate::error::err_msg("Failed to lock order queue"))?;
+                queue.extend(backlog);
+                queue.len()
+            };
+            let _ = self.order_notifier.send(queue_len);
+        }
+        Ok(replayed)
+    }
+
+    /// Retire an order once the porter has handled or skipped it.
+    pub fn complete_order(&self, uni_id: [u8; 16]) {
+        if let Ok(mut cancelled) = self.cancelled.lock() {
+            cancelled.remove(&uni_id);
+        }
+        if let Some(journal) = self.journal.get()
+            && let Err(e) = journal.complete(uni_id)
+        {
+            event!(Level::WARN, "Failed to retire journaled order: {}", e);
+        }
+    }
+
+    /// Queue `order` for the porter, or refuse it with `Busy` when the queue
+    /// is full so payloads are not buffered without bound.
+    pub fn produce_order(&self, order: Package) -> Result<(), RequestError> {
         let queue_len = {
             let mut queue = self
                 .order_queue
                 .lock()
-                .map_err(|_| "Failed to lock order queue".to_string())?;
+                .map_err(|_| RequestError::Enqueue("Failed to lock order queue".to_string()))?;
 
-            if queue.len() >= ORDER_QUEUE_CAPACITY {
-                // DropOldest policy: remove the oldest order when queue is full.
-                if let Some(dropped) = queue.pop_front() {
-                    self.unregister_waiter(dropped.uni_id);
-                }
+            if queue.len() >= self.capacity {
+                return Err(RequestError::Busy);
+            }
+
+            // Durable before it is queued, so an accepted put survives a crash
+            if let Some(journal) = self.journal.get()
+                && OrderJournal::covers(&order.behavior)
+            {
+                journal.append(&order).map_err(|e| {
+                    RequestError::Enqueue(format!("Failed to journal order: {}", e))
+                })?;
             }
 
             queue.push_back(order);
@@ -70,6 +195,98 @@ impl ConveyQueue {
         Ok(())
     }
 
+    /// Queue `order` and wait until its deadline (by default
+    /// [`order_deadline`]) for the porter's response, delivered through a
+    /// oneshot channel keyed by the order's uni_id.
+    /// On failure, or when the returned future is dropped first, the order,
+    /// its waiter and any attached stream are withdrawn.
+    pub async fn request(&self, order: Package) -> Result<Package, RequestError> {
+        // Orders accepted before the switch, such as replayed puts, still run
+        if order.behavior.writes() && crate::admin::is_read_only() {
+            return Err(RequestError::ReadOnly);
+        }
+        self.request_replicated(order).await
+    }
+
+    /// Like [`request`](Self::request), but writes are taken in read-only
+    /// mode too: they keep a read-only follower in step with its leader.
+    pub async fn request_replicated(&self, mut order: Package) -> Result<Package, RequestError> {
+        let uni_id = order.uni_id;
+        // Open from queueing until the porter is done with the order
+        order.span = tracing::info_span!("conveyer_order", behavior = ?order.behavior);
+        let deadline = *order
+            .deadline
+            .get_or_insert_with(|| order_deadline(order.content.data.len()));
+        let receiver = self
+            .register_waiter(uni_id, deadline)
+            .ok_or(RequestError::DuplicateId)?;
+
+        if let Err(err) = self.produce_order(order) {
+            self.unregister_waiter(uni_id);
+            return Err(err);
+        }
+
+        let mut pending = PendingRequest {
+            queue: self,
+            uni_id,
+            armed: true,
+        };
+        let deadline = tokio::time::Instant::from_std(deadline);
+        let err = match tokio::time::timeout_at(deadline, receiver).await {
+            Ok(Ok(pkg)) if pkg.status == Status::Timeout => RequestError::Timeout,
+            Ok(Ok(pkg)) => {
+                pending.armed = false;
+                return Ok(pkg);
+            }
+            Ok(Err(_)) => RequestError::Closed,
+            Err(_) => {
+                metrics::QUEUE_EXPIRED.inc();
+                RequestError::Timeout
+            }
+        };
+        pending.armed = false;
+        self.withdraw(uni_id);
+        Err(err)
+    }
+
+    /// Drop everything left of an unanswered request. An order the porter
+    /// already took is marked cancelled so it is skipped if not yet started.
+    fn withdraw(&self, uni_id: [u8; 16]) {
+        let was_waiting = self.unregister_waiter(uni_id);
+        let was_queued = self.remove_order(uni_id);
+        if was_waiting
+            && !was_queued
+            && let Ok(mut cancelled) = self.cancelled.lock()
+        {
+            cancelled.insert(uni_id);
+        }
+        self.take_stream(uni_id);
+    }
+
+    /// Whether the client behind the order with `uni_id` stopped waiting.
+    pub fn is_cancelled(&self, uni_id: [u8; 16]) -> bool {
+        self.cancelled
+            .lock()
+            .map(|cancelled| cancelled.contains(&uni_id))
+            .unwrap_or(false)
+    }
+
+    /// Orders waiting for the porter.
+    pub fn depth(&self) -> usize {
+        self.order_queue
+            .lock()
+            .map(|queue| queue.len())
+            .unwrap_or(0)
+    }
+
+    /// Requests waiting for their response, queued or being worked on.
+    pub fn waiting(&self) -> usize {
+        self.waiters
+            .lock()
+            .map(|waiters| waiters.len())
+            .unwrap_or(0)
+    }
+
     /// Get a receiver for order notifications
     pub fn subscribe_orders(&self) -> tokio::sync::watch::Receiver<usize> {
         self.order_notifier.subscribe()
@@ -88,6 +305,25 @@ impl ConveyQueue {
         Ok(guard.pop_front())
     }
 
+    /// Take up to `max` orders from the front of the queue for as long as
+    /// `same_kind` accepts them, so they can be handled together.
+    pub fn consume_orders_while(
+        &self,
+        max: usize,
+        same_kind: impl Fn(&Package) -> bool,
+    ) -> Result<Vec<Package>, String> {
+        let mut guard = self
+            .order_queue
+            .lock()
+            .map_err(|_| "Failed to lock order queue".to_string())?;
+
+        let mut orders = Vec::new();
+        while orders.len() < max && guard.front().is_some_and(&same_kind) {
+            orders.extend(guard.pop_front());
+        }
+        Ok(orders)
+    }
+
     pub fn produce_service(&self, order: Package) -> Result<(), String> {
         let uni_id = order.uni_id;
 
@@ -96,19 +332,28 @@ impl ConveyQueue {
             .waiters
             .lock()
             .map_err(|_| "Failed to lock waiter registry".to_string())?;
-        if let Some(entry) = waiters.remove(&uni_id) {
-            // Send through channel, ignore error if receiver is dropped
-            let _ = entry.sender.send(order);
+        if let Some(entry) = waiters.remove(&uni_id)
+            && entry.sender.send(order).is_ok()
+        {
             return Ok(());
         }
+        drop(waiters);
 
+        // Dead letter: the waiter gave up, so its content has no reader either
+        metrics::QUEUE_UNDELIVERABLE.inc();
+        self.take_stream(uni_id);
         Err("No waiter registered for this response".to_string())
     }
 
-    /// Register a waiter for a response with given uni_id.
+    /// Register a waiter for a response with given uni_id, answered with a
+    /// `Timeout` response if nothing arrives by `deadline`.
     /// Returns oneshot receiver that will receive the response package.
     /// If a waiter already exists for this uni_id, returns None.
-    pub fn register_waiter(&self, uni_id: [u8; 16]) -> Option<oneshot::Receiver<Package>> {
+    pub fn register_waiter(
+        &self,
+        uni_id: [u8; 16],
+        deadline: Instant,
+    ) -> Option<oneshot::Receiver<Package>> {
         let (sender, receiver) = oneshot::channel();
         let mut waiters = self.waiters.lock().ok()?;
 
@@ -116,27 +361,40 @@ impl ConveyQueue {
             return None;
         }
 
-        waiters.insert(
-            uni_id,
-            WaiterEntry {
-                sender,
-                created_at: Instant::now(),
-            },
-        );
+        waiters.insert(uni_id, WaiterEntry { sender, deadline });
         Some(receiver)
     }
 
-    pub fn unregister_waiter(&self, uni_id: [u8; 16]) {
-        if let Ok(mut waiters) = self.waiters.lock() {
-            waiters.remove(&uni_id);
+    /// Attach streamed content to the response with `uni_id`; must happen
+    /// before the response itself is produced.
+    pub fn attach_stream(&self, uni_id: [u8; 16], stream: DataStream) {
+        if let Ok(mut streams) = self.streams.lock() {
+            streams.insert(uni_id, stream);
         }
     }
 
+    /// Claim the content attached to the response with `uni_id`.
+    pub fn take_stream(&self, uni_id: [u8; 16]) -> Option<DataStream> {
+        self.streams.lock().ok()?.remove(&uni_id)
+    }
+
+    pub fn unregister_waiter(&self, uni_id: [u8; 16]) -> bool {
+        self.waiters
+            .lock()
+            .map(|mut waiters| waiters.remove(&uni_id).is_some())
+            .unwrap_or(false)
+    }
+
     pub fn remove_order(&self, uni_id: [u8; 16]) -> bool {
         if let Ok(mut guard) = self.order_queue.lock() {
             let before = guard.len();
             guard.retain(|pkg| pkg.uni_id != uni_id);
-            return guard.len() != before;
+            let removed = guard.len() != before;
+            drop(guard);
+            if removed {
+                self.complete_order(uni_id);
+            }
+            return removed;
         }
         false
     }
@@ -160,29 +418,262 @@ impl ConveyQueue {
         });
     }
 
+    /// Answer waiters past their deadline with a `Timeout` response and
+    /// withdraw their orders, so no client waits on a package that was lost.
     async fn cleanup_expired_waiters(&self) {
         let now = Instant::now();
-        let mut expired: Vec<[u8; 16]> = Vec::new();
+        let mut expired: Vec<([u8; 16], WaiterEntry)> = Vec::new();
 
         {
             let mut waiters = match self.waiters.lock() {
                 Ok(waiters) => waiters,
                 Err(_) => return,
             };
-            waiters.retain(|uni_id, entry| {
-                let is_expired = now.duration_since(entry.created_at) > WAITERS_TTL;
-                if is_expired {
-                    expired.push(*uni_id);
+            let expired_ids: Vec<[u8; 16]> = waiters
+                .iter()
+                .filter(|(_, entry)| now >= entry.deadline)
+                .map(|(uni_id, _)| *uni_id)
+                .collect();
+            for uni_id in expired_ids {
+                if let Some(entry) = waiters.remove(&uni_id) {
+                    expired.push((uni_id, entry));
                 }
-                !is_expired
-            });
+            }
         }
 
-        for uni_id in expired {
+        for (uni_id, entry) in expired {
+            metrics::QUEUE_EXPIRED.inc();
             let _ = self.remove_order(uni_id);
+            self.take_stream(uni_id);
+            let mut response = Package::new();
+            response.uni_id = uni_id;
+            response.status = Status::Timeout;
+            let _ = entry.sender.send(response);
         }
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn new_queue() -> Arc<ConveyQueue> {
+        Arc::new(ConveyQueue::with_capacity(8))
+    }
+
+    fn due_in(mut order: Package, timeout: Duration) -> Package {
+        order.deadline = Some(Instant::now() + timeout);
+        order
+    }
+
+    #[test]
+    fn test_order_deadline_grows_with_payload() {
+        let timeout = |len| order_deadline(len).duration_since(Instant::now());
+        assert!(timeout(0) <= ORDER_TIMEOUT_BASE);
+        assert!(timeout(8 << 20) > ORDER_TIMEOUT_BASE + 7 * ORDER_TIMEOUT_PER_MIB);
+        assert!(timeout(usize::MAX) <= ORDER_TIMEOUT_MAX);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_refuses_orders() {
+        let queue = Arc::new(ConveyQueue::with_capacity(1));
+        queue
+            .produce_order(Package::new_with_id(&Uuid::new_v4()))
+            .unwrap();
+
+        let order = Package::new_with_id(&Uuid::new_v4());
+        let uni_id = order.uni_id;
+        let result = queue.request(due_in(order, Duration::from_secs(1))).await;
+        assert!(matches!(result, Err(RequestError::Busy)));
+
+        // The refused order left no waiter behind
+        let mut response = Package::new();
+        response.uni_id = uni_id;
+        assert!(queue.produce_service(response).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_receives_its_response() {
+        let queue = new_queue();
+        let mut orders = queue.subscribe_orders();
+        let porter = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            async move {
+                orders.changed().await.unwrap();
+                let order = queue.consume_order().unwrap().unwrap();
+                let mut response = Package::new();
+                response.uni_id = order.uni_id;
+                queue.produce_service(response).unwrap();
+            }
+        });
+
+        let order = Package::new_with_id(&Uuid::new_v4());
+        let uni_id = order.uni_id;
+        let response = queue
+            .request(due_in(order, Duration::from_secs(1)))
+            .await
+            .unwrap();
+        assert_eq!(response.uni_id, uni_id);
+        porter.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_out_of_order_responses_reach_their_waiters() {
+        let queue = new_queue();
+        let first = Package::new_with_id(&Uuid::new_v4());
+        let second = Package::new_with_id(&Uuid::new_v4());
+        let (first_id, second_id) = (first.uni_id, second.uni_id);
+
+        let waiters = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            async move {
+                tokio::join!(
+                    queue.request(due_in(first, Duration::from_secs(1))),
+                    queue.request(due_in(second, Duration::from_secs(1))),
+                )
+            }
+        });
+        while queue.order_queue.lock().unwrap().len() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        // Answer the later order first
+        let mut orders = Vec::new();
+        while let Some(order) = queue.consume_order().unwrap() {
+            orders.push(order);
+        }
+        for order in orders.into_iter().rev() {
+            let mut response = Package::new();
+            response.uni_id = order.uni_id;
+            queue.produce_service(response).unwrap();
+        }
+
+        let (first, second) = waiters.await.unwrap();
+        assert_eq!(first.unwrap().uni_id, first_id);
+        assert_eq!(second.unwrap().uni_id, second_id);
+    }
+
+    #[tokio::test]
+    async fn test_expired_waiters_get_a_timeout_response() {
+        let queue = new_queue();
+        let uni_id = *Uuid::new_v4().as_bytes();
+        let receiver = queue.register_waiter(uni_id, Instant::now()).unwrap();
+        let _pending = queue
+            .register_waiter(*Uuid::new_v4().as_bytes(), order_deadline(0))
+            .unwrap();
+
+        queue.cleanup_expired_waiters().await;
+        let response = receiver.await.unwrap();
+        assert_eq!(response.uni_id, uni_id);
+        assert_eq!(response.status, Status::Timeout);
+        // Waiters still within their deadline are left alone
+        assert_eq!(queue.waiters.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_consume_orders_while_stops_at_other_kinds() {
+        let queue = new_queue();
+        let behaviors = [
+            crate::dtos::Behavior::PutFile,
+            crate::dtos::Behavior::PutFile,
+            crate::dtos::Behavior::GetFile,
+            crate::dtos::Behavior::PutFile,
+        ];
+        for behavior in behaviors {
+            let mut order = Package::new_with_id(&Uuid::new_v4());
+            order.behavior = behavior;
+            queue.produce_order(order).unwrap();
+        }
+
+        let is_put = |pkg: &Package| pkg.behavior == crate::dtos::Behavior::PutFile;
+        assert_eq!(queue.consume_orders_while(1, is_put).unwrap().len(), 1);
+        assert_eq!(queue.consume_orders_while(8, is_put).unwrap().len(), 1);
+        assert!(queue.consume_orders_while(8, is_put).unwrap().is_empty());
+        assert!(queue.consume_order().unwrap().is_some());
+        assert_eq!(queue.consume_orders_while(8, is_put).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_request_is_withdrawn() {
+        let queue = new_queue();
+
+        // Dropped while still queued: the order goes away
+        let order = Package::new_with_id(&Uuid::new_v4());
+        let waiting = tokio::time::timeout(
+            Duration::from_millis(10),
+            queue.request(due_in(order, Duration::from_secs(5))),
+        );
+        assert!(waiting.await.is_err());
+        assert!(queue.consume_order().unwrap().is_none());
+
+        // Dropped after the porter took it: the order is marked cancelled
+        let order = Package::new_with_id(&Uuid::new_v4());
+        let uni_id = order.uni_id;
+        let request = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            async move { queue.request(due_in(order, Duration::from_secs(5))).await }
+        });
+        let taken = loop {
+            if let Some(order) = queue.consume_order().unwrap() {
+                break order;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert!(!queue.is_cancelled(uni_id));
+        request.abort();
+        let _ = request.await;
+        assert!(queue.is_cancelled(taken.uni_id));
+
+        queue.complete_order(uni_id);
+        assert!(!queue.is_cancelled(uni_id));
+    }
+
+    #[tokio::test]
+    async fn test_journal_replays_unstored_puts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("orders.journal");
+
+        let queue = new_queue();
+        assert_eq!(queue.open_journal(&path).unwrap(), 0);
+        let mut stored = Package::new_with_id(&Uuid::new_v4());
+        stored.behavior = crate::dtos::Behavior::PutFile;
+        let mut lost = Package::new_with_id(&Uuid::new_v4());
+        lost.behavior = crate::dtos::Behavior::PutFile;
+        let lost_id = lost.uni_id;
+        queue.produce_order(stored).unwrap();
+        queue.produce_order(lost).unwrap();
+        // Reads are not journaled
+        queue
+            .produce_order(Package::new_with_id(&Uuid::new_v4()))
+            .unwrap();
+
+        let order = queue.consume_order().unwrap().unwrap();
+        queue.complete_order(order.uni_id);
+        drop(queue);
+
+        // A fresh queue after the "crash" picks up the unstored put
+        let queue = new_queue();
+        assert_eq!(queue.open_journal(&path).unwrap(), 1);
+        let replayed = queue.consume_order().unwrap().unwrap();
+        assert_eq!(replayed.uni_id, lost_id);
+        assert!(queue.consume_order().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_withdraws_order() {
+        let queue = new_queue();
+        let order = Package::new_with_id(&Uuid::new_v4());
+        let uni_id = order.uni_id;
+
+        let result = queue
+            .request(due_in(order, Duration::from_millis(10)))
+            .await;
+        assert!(matches!(result, Err(RequestError::Timeout)));
+        assert!(queue.consume_order().unwrap().is_none());
+
+        let mut response = Package::new();
+        response.uni_id = uni_id;
+        assert!(queue.produce_service(response).is_err());
+    }
+}
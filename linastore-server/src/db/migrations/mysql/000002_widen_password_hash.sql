@@ -0,0 +1,8 @@
+-- Migration: Widen password_hash
+-- Argon2id PHC strings are longer than the 64 characters reserved by 000001
+
+ALTER TABLE users MODIFY password_hash VARCHAR(255) NOT NULL;
+
+-- Record this migration as applied
+INSERT IGNORE INTO mig_records (version, applied_at)
+VALUES ('000002_widen_password_hash', UNIX_TIMESTAMP());
@@ -0,0 +1,21 @@
+-- Migration: API keys
+-- Long-lived keys for cron jobs and CI, accepted wherever a session token is
+
+-- API keys table: long-lived credentials for automation, stored as the
+-- SHA-256 hex digest of the key
+CREATE TABLE api_keys (
+    id CHAR(36) PRIMARY KEY,
+    user_id CHAR(36) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    key_hash CHAR(64) NOT NULL UNIQUE,
+    scope VARCHAR(16) NOT NULL,
+    created_at BIGINT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Create index on user_id for removing a user's keys
+CREATE INDEX idx_api_keys_user_id ON api_keys(user_id);
+
+-- Record this migration as applied
+INSERT IGNORE INTO mig_records (version, applied_at)
+VALUES ('000003_api_keys', UNIX_TIMESTAMP());
@@ -0,0 +1,15 @@
+-- Migration: Bucket grants
+-- A user with grants may only reach the buckets listed for it; a user
+-- without any keeps access to every bucket
+
+CREATE TABLE bucket_grants (
+    user_id CHAR(36) NOT NULL,
+    bucket VARCHAR(255) NOT NULL,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (user_id, bucket),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Record this migration as applied
+INSERT IGNORE INTO mig_records (version, applied_at)
+VALUES ('000005_bucket_grants', UNIX_TIMESTAMP());
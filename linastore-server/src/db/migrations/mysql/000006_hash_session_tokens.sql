@@ -0,0 +1,12 @@
+-- Migration: Hash session tokens
+-- Sessions now store the SHA-256 hex digest of their token. Sessions stored
+-- with a plain token can no longer be matched, so they are dropped and their
+-- clients sign in again once
+
+DELETE FROM sessions;
+
+ALTER TABLE sessions MODIFY token CHAR(64) NOT NULL;
+
+-- Record this migration as applied
+INSERT IGNORE INTO mig_records (version, applied_at)
+VALUES ('000006_hash_session_tokens', UNIX_TIMESTAMP());
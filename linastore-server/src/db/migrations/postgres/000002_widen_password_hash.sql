@@ -0,0 +1,9 @@
+-- Migration: Widen password_hash
+-- Argon2id PHC strings are longer than the 64 characters reserved by 000001
+
+ALTER TABLE users ALTER COLUMN password_hash TYPE VARCHAR(255);
+
+-- Record this migration as applied
+INSERT INTO mig_records (version, applied_at)
+VALUES ('000002_widen_password_hash', EXTRACT(EPOCH FROM NOW())::BIGINT)
+ON CONFLICT (version) DO NOTHING;
@@ -0,0 +1,13 @@
+-- Migration: Hash session tokens
+-- Sessions now store the SHA-256 hex digest of their token. Sessions stored
+-- with a plain token can no longer be matched, so they are dropped and their
+-- clients sign in again once
+
+DELETE FROM sessions;
+
+ALTER TABLE sessions ALTER COLUMN token TYPE CHAR(64);
+
+-- Record this migration as applied
+INSERT INTO mig_records (version, applied_at)
+VALUES ('000006_hash_session_tokens', EXTRACT(EPOCH FROM NOW())::BIGINT)
+ON CONFLICT (version) DO NOTHING;
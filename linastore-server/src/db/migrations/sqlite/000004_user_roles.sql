@@ -0,0 +1,8 @@
+-- Migration: User roles
+-- Each user is a reader, writer or admin; existing users keep write access
+
+ALTER TABLE users ADD COLUMN role VARCHAR(16) NOT NULL DEFAULT 'writer';
+
+-- Record this migration as applied
+INSERT OR IGNORE INTO mig_records (version, applied_at)
+VALUES ('000004_user_roles', CAST(strftime('%s','now') AS BIGINT));
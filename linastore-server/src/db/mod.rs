@@ -184,16 +184,18 @@ impl DbConnection {
         user_id: &str,
         username: &str,
         password_hash: &str,
+        role: &str,
         now: i64,
     ) -> Result<()> {
         match self.pool.as_ref() {
             DbPool::Sqlite(pool) => {
                 sqlx::query(
-                    "INSERT INTO users (id, username, password_hash, created_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+                    "INSERT INTO users (id, username, password_hash, role, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)",
                 )
                 .bind(user_id)
                 .bind(username)
                 .bind(password_hash)
+                .bind(role)
                 .bind(now)
                 .bind(now)
                 .execute(pool)
@@ -203,11 +205,12 @@ impl DbConnection {
             #[cfg(feature = "mysql")]
             DbPool::MySql(pool) => {
                 sqlx::query(
-                    "INSERT INTO users (id, username, password_hash, created_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+                    "INSERT INTO users (id, username, password_hash, role, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?)",
                 )
                 .bind(user_id)
                 .bind(username)
                 .bind(password_hash)
+                .bind(role)
                 .bind(now)
                 .bind(now)
                 .execute(pool)
@@ -217,11 +220,12 @@ impl DbConnection {
             #[cfg(feature = "postgres")]
             DbPool::Postgres(pool) => {
                 sqlx::query(
-                    "INSERT INTO users (id, username, password_hash, created_at, updated_at) VALUES ($1, $2, $3, $4, $5)",
+                    "INSERT INTO users (id, username, password_hash, role, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $6)",
                 )
                 .bind(user_id)
                 .bind(username)
                 .bind(password_hash)
+                .bind(role)
                 .bind(now)
                 .bind(now)
                 .execute(pool)
@@ -231,6 +235,490 @@ impl DbConnection {
         }
     }
 
+    /// The role of the user with id `user_id`, if that user exists.
+    pub async fn auth_get_user_role(&self, user_id: &str) -> Result<Option<String>> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                let row = sqlx::query_as::<_, (String,)>("SELECT role FROM users WHERE id = ?")
+                    .bind(user_id)
+                    .fetch_optional(pool)
+                    .await?;
+                Ok(row.map(|(role,)| role))
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                let row = sqlx::query_as::<_, (String,)>("SELECT role FROM users WHERE id = ?")
+                    .bind(user_id)
+                    .fetch_optional(pool)
+                    .await?;
+                Ok(row.map(|(role,)| role))
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                let row = sqlx::query_as::<_, (String,)>("SELECT role FROM users WHERE id = $1")
+                    .bind(user_id)
+                    .fetch_optional(pool)
+                    .await?;
+                Ok(row.map(|(role,)| role))
+            }
+        }
+    }
+
+    /// Change the role of `username`. Returns whether the user exists.
+    pub async fn auth_set_user_role(&self, username: &str, role: &str, now: i64) -> Result<bool> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                let result = sqlx::query(
+                    "UPDATE users SET role = ?, updated_at = ? WHERE username = ?",
+                )
+                .bind(role)
+                .bind(now)
+                .bind(username)
+                .execute(pool)
+                .await?;
+                Ok(result.rows_affected() > 0)
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                let result = sqlx::query(
+                    "UPDATE users SET role = ?, updated_at = ? WHERE username = ?",
+                )
+                .bind(role)
+                .bind(now)
+                .bind(username)
+                .execute(pool)
+                .await?;
+                Ok(result.rows_affected() > 0)
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                let result = sqlx::query(
+                    "UPDATE users SET role = $1, updated_at = $2 WHERE username = $3",
+                )
+                .bind(role)
+                .bind(now)
+                .bind(username)
+                .execute(pool)
+                .await?;
+                Ok(result.rows_affected() > 0)
+            }
+        }
+    }
+
+    /// The id and password hash of `username`, if that user exists.
+    pub async fn auth_get_user_credentials(
+        &self,
+        username: &str,
+    ) -> Result<Option<(String, String)>> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                let row = sqlx::query_as::<_, (String, String)>(
+                    "SELECT id, password_hash FROM users WHERE username = ?",
+                )
+                .bind(username)
+                .fetch_optional(pool)
+                .await?;
+                Ok(row)
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                let row = sqlx::query_as::<_, (String, String)>(
+                    "SELECT id, password_hash FROM users WHERE username = ?",
+                )
+                .bind(username)
+                .fetch_optional(pool)
+                .await?;
+                Ok(row)
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                let row = sqlx::query_as::<_, (String, String)>(
+                    "SELECT id, password_hash FROM users WHERE username = $1",
+                )
+                .bind(username)
+                .fetch_optional(pool)
+                .await?;
+                Ok(row)
+            }
+        }
+    }
+
+    pub async fn auth_update_password(
+        &self,
+        user_id: &str,
+        password_hash: &str,
+        now: i64,
+    ) -> Result<()> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                sqlx::query("UPDATE users SET password_hash = ?, updated_at = ? WHERE id = ?")
+                    .bind(password_hash)
+                    .bind(now)
+                    .bind(user_id)
+                    .execute(pool)
+                    .await?;
+                Ok(())
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                sqlx::query("UPDATE users SET password_hash = ?, updated_at = ? WHERE id = ?")
+                    .bind(password_hash)
+                    .bind(now)
+                    .bind(user_id)
+                    .execute(pool)
+                    .await?;
+                Ok(())
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                sqlx::query("UPDATE users SET password_hash = $1, updated_at = $2 WHERE id = $3")
+                    .bind(password_hash)
+                    .bind(now)
+                    .bind(user_id)
+                    .execute(pool)
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Delete `username` with its sessions, API keys and grants. Returns
+    /// whether the user existed.
+    pub async fn auth_delete_user(&self, username: &str) -> Result<bool> {
+        let Some(user_id) = self.auth_get_user_id_by_username(username).await? else {
+            return Ok(false);
+        };
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM api_keys WHERE user_id = ?")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM bucket_grants WHERE user_id = ?")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM users WHERE id = ?")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                let mut tx = pool.begin().await?;
+                sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM api_keys WHERE user_id = ?")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM bucket_grants WHERE user_id = ?")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM users WHERE id = ?")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                sqlx::query("DELETE FROM sessions WHERE user_id = $1")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM api_keys WHERE user_id = $1")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM bucket_grants WHERE user_id = $1")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("DELETE FROM users WHERE id = $1")
+                    .bind(&user_id)
+                    .execute(&mut *tx)
+                    .await?;
+                tx.commit().await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Every username with its role and creation time, ordered by name.
+    pub async fn auth_list_users(&self) -> Result<Vec<(String, String, i64)>> {
+        const SQL: &str = "SELECT username, role, created_at FROM users ORDER BY username";
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => Ok(sqlx::query_as::<_, (String, String, i64)>(SQL)
+                .fetch_all(pool)
+                .await?),
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => Ok(sqlx::query_as::<_, (String, String, i64)>(SQL)
+                .fetch_all(pool)
+                .await?),
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => Ok(sqlx::query_as::<_, (String, String, i64)>(SQL)
+                .fetch_all(pool)
+                .await?),
+        }
+    }
+
+    /// Grant `user_id` access to `bucket`. Granting twice is a no-op.
+    pub async fn auth_insert_grant(&self, user_id: &str, bucket: &str, now: i64) -> Result<()> {
+        if self.auth_list_grants(user_id).await?.iter().any(|b| b == bucket) {
+            return Ok(());
+        }
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                sqlx::query(
+                    "INSERT INTO bucket_grants (user_id, bucket, created_at) VALUES (?, ?, ?)",
+                )
+                .bind(user_id)
+                .bind(bucket)
+                .bind(now)
+                .execute(pool)
+                .await?;
+                Ok(())
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                sqlx::query(
+                    "INSERT INTO bucket_grants (user_id, bucket, created_at) VALUES (?, ?, ?)",
+                )
+                .bind(user_id)
+                .bind(bucket)
+                .bind(now)
+                .execute(pool)
+                .await?;
+                Ok(())
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                sqlx::query(
+                    "INSERT INTO bucket_grants (user_id, bucket, created_at) VALUES ($1, $2, $3)",
+                )
+                .bind(user_id)
+                .bind(bucket)
+                .bind(now)
+                .execute(pool)
+                .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Withdraw a grant. Returns whether it existed.
+    pub async fn auth_delete_grant(&self, user_id: &str, bucket: &str) -> Result<bool> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                let result = sqlx::query(
+                    "DELETE FROM bucket_grants WHERE user_id = ? AND bucket = ?",
+                )
+                .bind(user_id)
+                .bind(bucket)
+                .execute(pool)
+                .await?;
+                Ok(result.rows_affected() > 0)
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                let result = sqlx::query(
+                    "DELETE FROM bucket_grants WHERE user_id = ? AND bucket = ?",
+                )
+                .bind(user_id)
+                .bind(bucket)
+                .execute(pool)
+                .await?;
+                Ok(result.rows_affected() > 0)
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                let result = sqlx::query(
+                    "DELETE FROM bucket_grants WHERE user_id = $1 AND bucket = $2",
+                )
+                .bind(user_id)
+                .bind(bucket)
+                .execute(pool)
+                .await?;
+                Ok(result.rows_affected() > 0)
+            }
+        }
+    }
+
+    /// Buckets `user_id` has been granted, ordered by name.
+    pub async fn auth_list_grants(&self, user_id: &str) -> Result<Vec<String>> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                Ok(sqlx::query_scalar::<_, String>(
+                    "SELECT bucket FROM bucket_grants WHERE user_id = ? ORDER BY bucket",
+                )
+                .bind(user_id)
+                .fetch_all(pool)
+                .await?)
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                Ok(sqlx::query_scalar::<_, String>(
+                    "SELECT bucket FROM bucket_grants WHERE user_id = ? ORDER BY bucket",
+                )
+                .bind(user_id)
+                .fetch_all(pool)
+                .await?)
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                Ok(sqlx::query_scalar::<_, String>(
+                    "SELECT bucket FROM bucket_grants WHERE user_id = $1 ORDER BY bucket",
+                )
+                .bind(user_id)
+                .fetch_all(pool)
+                .await?)
+            }
+        }
+    }
+
+    /// Store an API key by the digest of its secret.
+    pub async fn auth_insert_api_key(
+        &self,
+        key_id: &str,
+        user_id: &str,
+        name: &str,
+        key_hash: &str,
+        scope: &str,
+        created_at: i64,
+    ) -> Result<()> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                sqlx::query(
+                    "INSERT INTO api_keys (id, user_id, name, key_hash, scope, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(key_id)
+                .bind(user_id)
+                .bind(name)
+                .bind(key_hash)
+                .bind(scope)
+                .bind(created_at)
+                .execute(pool)
+                .await?;
+                Ok(())
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                sqlx::query(
+                    "INSERT INTO api_keys (id, user_id, name, key_hash, scope, created_at) VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(key_id)
+                .bind(user_id)
+                .bind(name)
+                .bind(key_hash)
+                .bind(scope)
+                .bind(created_at)
+                .execute(pool)
+                .await?;
+                Ok(())
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                sqlx::query(
+                    "INSERT INTO api_keys (id, user_id, name, key_hash, scope, created_at) VALUES ($1, $2, $3, $4, $5, $6)",
+                )
+                .bind(key_id)
+                .bind(user_id)
+                .bind(name)
+                .bind(key_hash)
+                .bind(scope)
+                .bind(created_at)
+                .execute(pool)
+                .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// The owner and scope of the API key whose digest is `key_hash`.
+    pub async fn auth_get_api_key(&self, key_hash: &str) -> Result<Option<(String, String)>> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                Ok(sqlx::query_as::<_, (String, String)>(
+                    "SELECT user_id, scope FROM api_keys WHERE key_hash = ?",
+                )
+                .bind(key_hash)
+                .fetch_optional(pool)
+                .await?)
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                Ok(sqlx::query_as::<_, (String, String)>(
+                    "SELECT user_id, scope FROM api_keys WHERE key_hash = ?",
+                )
+                .bind(key_hash)
+                .fetch_optional(pool)
+                .await?)
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                Ok(sqlx::query_as::<_, (String, String)>(
+                    "SELECT user_id, scope FROM api_keys WHERE key_hash = $1",
+                )
+                .bind(key_hash)
+                .fetch_optional(pool)
+                .await?)
+            }
+        }
+    }
+
+    /// Revoke the API key `key_id`. Returns whether it existed.
+    pub async fn auth_delete_api_key(&self, key_id: &str) -> Result<bool> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => {
+                let result = sqlx::query("DELETE FROM api_keys WHERE id = ?")
+                    .bind(key_id)
+                    .execute(pool)
+                    .await?;
+                Ok(result.rows_affected() > 0)
+            }
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => {
+                let result = sqlx::query("DELETE FROM api_keys WHERE id = ?")
+                    .bind(key_id)
+                    .execute(pool)
+                    .await?;
+                Ok(result.rows_affected() > 0)
+            }
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => {
+                let result = sqlx::query("DELETE FROM api_keys WHERE id = $1")
+                    .bind(key_id)
+                    .execute(pool)
+                    .await?;
+                Ok(result.rows_affected() > 0)
+            }
+        }
+    }
+
+    /// Every API key as `(id, username, name, scope, created_at)`, oldest first.
+    pub async fn auth_list_api_keys(&self) -> Result<Vec<(String, String, String, String, i64)>> {
+        const SQL: &str = "SELECT k.id, u.username, k.name, k.scope, k.created_at \
+            FROM api_keys k JOIN users u ON u.id = k.user_id ORDER BY k.created_at, k.id";
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => Ok(sqlx::query_as(SQL).fetch_all(pool).await?),
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => Ok(sqlx::query_as(SQL).fetch_all(pool).await?),
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => Ok(sqlx::query_as(SQL).fetch_all(pool).await?),
+        }
+    }
+
     pub async fn auth_insert_session(
         &self,
         session_id: &str,
@@ -321,6 +809,39 @@ impl DbConnection {
         }
     }
 
+    /// Sessions still valid at `now` as `(id, username, created_at,
+    /// expires_at)`, newest first.
+    pub async fn auth_list_sessions(&self, now: i64) -> Result<Vec<(String, String, i64, i64)>> {
+        match self.pool.as_ref() {
+            DbPool::Sqlite(pool) => Ok(sqlx::query_as::<_, (String, String, i64, i64)>(
+                "SELECT s.id, u.username, s.created_at, s.expires_at FROM sessions s \
+                 JOIN users u ON u.id = s.user_id WHERE s.expires_at > ? \
+                 ORDER BY s.created_at DESC, s.id",
+            )
+            .bind(now)
+            .fetch_all(pool)
+            .await?),
+            #[cfg(feature = "mysql")]
+            DbPool::MySql(pool) => Ok(sqlx::query_as::<_, (String, String, i64, i64)>(
+                "SELECT s.id, u.username, s.created_at, s.expires_at FROM sessions s \
+                 JOIN users u ON u.id = s.user_id WHERE s.expires_at > ? \
+                 ORDER BY s.created_at DESC, s.id",
+            )
+            .bind(now)
+            .fetch_all(pool)
+            .await?),
+            #[cfg(feature = "postgres")]
+            DbPool::Postgres(pool) => Ok(sqlx::query_as::<_, (String, String, i64, i64)>(
+                "SELECT s.id, u.username, s.created_at, s.expires_at FROM sessions s \
+                 JOIN users u ON u.id = s.user_id WHERE s.expires_at > $1 \
+                 ORDER BY s.created_at DESC, s.id",
+            )
+            .bind(now)
+            .fetch_all(pool)
+            .await?),
+        }
+    }
+
     pub async fn auth_delete_expired_sessions(&self, now: i64) -> Result<u64> {
         match self.pool.as_ref() {
             DbPool::Sqlite(pool) => {
@@ -581,3 +1102,112 @@ pub async fn init_database(db_url: &str) -> Result<DbConnection> {
 pub async fn get_db_connection(db_url: &str) -> Result<DbConnection> {
     init_database(db_url).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn temp_db(dir: &tempfile::TempDir) -> DbConnection {
+        let url = format!("sqlite://{}", dir.path().join("auth.db").display());
+        DbConnection::new(&url).await.expect("Failed to open database")
+    }
+
+    #[tokio::test]
+    async fn test_user_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+
+        db.auth_insert_user("id-alice", "alice", "phc-1", "writer", 10).await.unwrap();
+        db.auth_insert_user("id-bob", "bob", "phc-2", "reader", 20).await.unwrap();
+        db.auth_insert_session("s-1", "token-1", "id-alice", 100, 10)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.auth_get_user_credentials("alice").await.unwrap(),
+            Some(("id-alice".to_string(), "phc-1".to_string()))
+        );
+
+        db.auth_update_password("id-alice", "phc-3", 30).await.unwrap();
+        assert!(db.auth_set_user_role("alice", "admin", 30).await.unwrap());
+        assert!(!db.auth_set_user_role("carol", "admin", 30).await.unwrap());
+        assert_eq!(
+            db.auth_get_user_role("id-alice").await.unwrap(),
+            Some("admin".to_string())
+        );
+        assert_eq!(
+            db.auth_get_user_credentials("alice").await.unwrap().unwrap().1,
+            "phc-3"
+        );
+
+        assert!(db.auth_delete_user("alice").await.unwrap());
+        assert!(!db.auth_delete_user("alice").await.unwrap());
+        // Removing a user ends its sessions
+        assert_eq!(db.auth_get_user_id_by_token("token-1", 0).await.unwrap(), None);
+        assert_eq!(
+            db.auth_list_users().await.unwrap(),
+            vec![("bob".to_string(), "reader".to_string(), 20)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bucket_grants() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+
+        db.auth_insert_user("id-alice", "alice", "phc-1", "writer", 10).await.unwrap();
+        db.auth_insert_grant("id-alice", "photos", 20).await.unwrap();
+        db.auth_insert_grant("id-alice", "docs", 20).await.unwrap();
+        db.auth_insert_grant("id-alice", "docs", 30).await.unwrap();
+        assert_eq!(
+            db.auth_list_grants("id-alice").await.unwrap(),
+            vec!["docs".to_string(), "photos".to_string()]
+        );
+
+        assert!(db.auth_delete_grant("id-alice", "docs").await.unwrap());
+        assert!(!db.auth_delete_grant("id-alice", "docs").await.unwrap());
+        assert_eq!(
+            db.auth_list_grants("id-alice").await.unwrap(),
+            vec!["photos".to_string()]
+        );
+
+        assert!(db.auth_delete_user("alice").await.unwrap());
+        assert!(db.auth_list_grants("id-alice").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_api_key_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = temp_db(&dir).await;
+
+        db.auth_insert_user("id-alice", "alice", "phc-1", "writer", 10).await.unwrap();
+        db.auth_insert_api_key("k-1", "id-alice", "ci", "digest-1", "read", 20)
+            .await
+            .unwrap();
+        db.auth_insert_api_key("k-2", "id-alice", "backup", "digest-2", "read-write", 30)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.auth_get_api_key("digest-1").await.unwrap(),
+            Some(("id-alice".to_string(), "read".to_string()))
+        );
+        assert_eq!(db.auth_get_api_key("digest-3").await.unwrap(), None);
+
+        assert!(db.auth_delete_api_key("k-1").await.unwrap());
+        assert!(!db.auth_delete_api_key("k-1").await.unwrap());
+        assert_eq!(db.auth_get_api_key("digest-1").await.unwrap(), None);
+        assert_eq!(
+            db.auth_list_api_keys().await.unwrap(),
+            vec![(
+                "k-2".to_string(),
+                "alice".to_string(),
+                "backup".to_string(),
+                "read-write".to_string(),
+                30
+            )]
+        );
+
+        // Removing a user revokes its keys
+        assert!(db.auth_delete_user("alice").await.unwrap());
+        assert_eq!(db.auth_get_api_key("digest-2").await.unwrap(), None);
+    }
+}
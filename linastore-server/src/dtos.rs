@@ -1,5 +1,9 @@
 use bytes::{Bytes, BytesMut};
 use chrono::Utc;
+use linabase::service::{FileMeta, GcReport, PutCondition, PutOutcome, ScrubReport};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use tracing::Span;
 use uuid::Uuid;
 
 #[derive(Clone, PartialEq)]
@@ -14,10 +18,24 @@ pub struct PayLoad {
 /// Flags Definition
 /// ---
 /// ```markdown
-/// | File Operation | Communicate Options | Reserved | Reserved | Cover | Compress |
-/// |----------------|----------|----------|----------|----------|-------|----------|
-/// | 0xC0 - 0x40    |     0x30 - 0x10     | 0x08     | 0x04     | 0x02  | 0x01     |
+/// | File Operation | Conditional | Batch    | Pattern  | Cover | Compress |
+/// |----------------|-------------|----------|----------|-------|----------|
+/// | 0xE0 (3 bits)  | 0x10        | 0x08     | 0x04     | 0x02  | 0x01     |
 /// ```
+///
+/// `Conditional` only applies to `Write`: the (decrypted) data starts with a
+/// [`CONDITION_LEN`]-byte BLAKE3 hash the current object must have, or all
+/// zeros to write only if the object is absent. A failed check answers
+/// `PreconditionFailed`.
+///
+/// `Batch` and `Pattern` only apply to `Delete`. `Batch` carries a list of
+/// NUL-separated keys in the data field (after the session token) and the
+/// bucket in the identifier; `Pattern` treats the key (or each batch key) as
+/// a glob where `*` matches any run of characters and `?` a single one.
+/// Pattern deletes are refused unless authentication is enabled.
+///
+/// `Batch` on a `Read` asks for a [`ManifestPage`] of every bucket instead of
+/// a file; the data carries the cursor after the session token.
 #[derive(Clone, PartialEq)]
 pub struct LiNaProtocol {
     pub flags: u8,
@@ -72,6 +90,20 @@ impl LiNaProtocol {
         buf.extend_from_slice(&self.payload.data);
         buf.freeze()
     }
+
+    /// Like [`serialize_protocol_message`](Self::serialize_protocol_message),
+    /// but led by the flags as a request is.
+    pub fn serialize_request_message(&self) -> Bytes {
+        let cap = 1 + 1 + self.payload.identifier.len() + 4 + 4 + self.payload.data.len();
+        let mut buf = BytesMut::with_capacity(cap);
+
+        buf.extend_from_slice(&[self.flags, self.payload.ilen]);
+        buf.extend_from_slice(&self.payload.identifier);
+        buf.extend_from_slice(&self.payload.dlen.to_le_bytes());
+        buf.extend_from_slice(&self.payload.checksum.to_le_bytes());
+        buf.extend_from_slice(&self.payload.data);
+        buf.freeze()
+    }
 }
 
 #[allow(dead_code)]
@@ -80,11 +112,19 @@ pub enum FlagType {
     Write = 0x80,
     Auth = 0x60,
     Read = 0x40,
+    Conditional = 0x10,
+    Batch = 0x08,
+    Pattern = 0x04,
     Cover = 0x02,
     Compress = 0x01,
     None = 0x00,
 }
 
+#[inline]
+pub fn flag_set(flags: u8, bit: FlagType) -> bool {
+    (flags & bit as u8) != 0
+}
+
 /// Parsed file-operation field — the top 3 bits of `flags`.
 ///
 /// The bitmask layout used `Delete=0xC0=0b110_xxxxx`, `Write=0xB80=0b100`,
@@ -125,6 +165,11 @@ pub struct Package {
     pub behavior: Behavior,
     pub content: Content,
     pub created_at: i64,
+    /// When the producer stops waiting for the response; the porter skips
+    /// orders past it. `None` waits for as long as it takes.
+    pub deadline: Option<Instant>,
+    /// The request this order serves, for tracing the porter's work.
+    pub span: Span,
 }
 
 impl Package {
@@ -139,6 +184,8 @@ impl Package {
                 data: Bytes::new(),
             },
             created_at: Utc::now().timestamp(),
+            deadline: None,
+            span: Span::none(),
         }
     }
 
@@ -153,6 +200,8 @@ impl Package {
                 data: Bytes::new(),
             },
             created_at: Utc::now().timestamp(),
+            deadline: None,
+            span: Span::none(),
         }
     }
 }
@@ -173,18 +222,301 @@ pub enum Status {
     FileNameInvalid = 3,
     Unauthorized = 4,
     BadRequest = 5,
+    PreconditionFailed = 6,
+    /// The server is too busy to queue the request; retry later.
+    Throttled = 7,
+    /// No response was produced before the request expired.
+    Timeout = 8,
+    /// The server is in read-only mode and refuses writes.
+    ReadOnly = 9,
     InternalError = 127,
     None = 255,
 }
 
+impl Status {
+    /// Whether a write answered with this status was certainly never
+    /// applied, as opposed to one (such as `Timeout`) that may still land.
+    pub fn write_refused(&self) -> bool {
+        matches!(
+            self,
+            Status::BadRequest | Status::PreconditionFailed | Status::Throttled | Status::ReadOnly
+        )
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum Behavior {
     GetFile,
+    /// Like `GetFile`, but the content is attached to the queue as a
+    /// [`DataStream`](linabase::service::DataStream) for
+    /// [`ConveyQueue::take_stream`](crate::conveyer::ConveyQueue::take_stream)
+    /// instead of being carried in `content.data`.
+    StreamFile,
+    /// Like `StreamFile`, but streams gzip (and sets the `Compress` flag in the
+    /// response) when the source is stored compressed, or when the request
+    /// sets `Compress` to ask for on-the-fly compression.
+    StreamFileGzip,
+    /// Like `StreamFile`, but the identifier is the content's BLAKE3 hash
+    /// (lowercase hex) instead of a file name.
+    StreamBlob,
+    /// Like `StreamFile`, but streams part of a file; `content.data` is an
+    /// [`encode_read_range`] request. The response data is the file's JSON
+    /// [`FileMetaDto`], and no stream is attached when no byte of the range
+    /// exists or the file changed between the lookup and the read.
+    StreamFileRange,
     PutFile,
     DeleteFile,
+    /// Delete every NUL-separated internal name in `content.data`; the
+    /// response data carries one `status(1) + name + '\0'` entry per item.
+    DeleteFiles,
+    /// Look up metadata only; the response data is a JSON [`FileMetaDto`].
+    StatFile,
+    /// Stat every NUL-separated internal name in `content.data`; the response
+    /// data is a JSON array of [`FileMetaDto`] for the names that exist.
+    ListFiles,
+    /// Remove leftovers of interrupted writes and deletes; the response data
+    /// is a JSON [`GcReportDto`].
+    CollectGarbage,
+    /// Verify every stored source against its hash; the response data is a
+    /// JSON [`ScrubReportDto`].
+    Scrub,
+    /// Size of every stored file; the response data is a JSON object from
+    /// internal name to size in bytes.
+    Sizes,
     None,
 }
 
+impl Behavior {
+    /// Whether orders with this behavior change what is stored.
+    pub fn writes(&self) -> bool {
+        matches!(
+            self,
+            Behavior::PutFile | Behavior::DeleteFile | Behavior::DeleteFiles
+        )
+    }
+}
+
+/// File metadata as exchanged between the porter and the fronts.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FileMetaDto {
+    pub name: String,
+    pub size: u64,
+    pub ext: String,
+    pub hash: String,
+    pub compressed: bool,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl From<FileMeta> for FileMetaDto {
+    fn from(meta: FileMeta) -> Self {
+        FileMetaDto {
+            name: meta.name,
+            size: meta.size,
+            ext: meta.ext,
+            hash: meta.hash256,
+            compressed: meta.compressed,
+            created_at: meta.create_at,
+            updated_at: meta.update_at,
+        }
+    }
+}
+
+/// What a garbage collection removed, as sent to the fronts.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct GcReportDto {
+    pub removed_tmp: u64,
+    pub removed_tombstones: u64,
+    pub removed_orphans: u64,
+}
+
+impl From<GcReport> for GcReportDto {
+    fn from(report: GcReport) -> Self {
+        GcReportDto {
+            removed_tmp: report.removed_tmp,
+            removed_tombstones: report.removed_tombstones,
+            removed_orphans: report.removed_orphans,
+        }
+    }
+}
+
+/// What a scrub found, as sent to the fronts. `damaged` holds internal
+/// file names.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ScrubReportDto {
+    pub checked: u64,
+    pub damaged: Vec<String>,
+}
+
+impl From<ScrubReport> for ScrubReportDto {
+    fn from(report: ScrubReport) -> Self {
+        ScrubReportDto {
+            checked: report.checked,
+            damaged: report.damaged,
+        }
+    }
+}
+
+/// Sent after the session token of a read to have the server answer from
+/// its own store, without asking its federation peers on a miss; keeps
+/// peers that list each other from forwarding a miss in circles.
+pub const LOCAL_ONLY: &[u8] = b"local";
+
+/// Keys a manifest read answers with at most.
+pub const MANIFEST_PAGE_LEN: usize = 1000;
+
+/// One key in a manifest read and what it holds.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub bucket: String,
+    pub key: String,
+    pub hash: String,
+    pub size: u64,
+    pub compressed: bool,
+}
+
+/// Answer to a manifest read: keys ordered by bucket and key, and where the
+/// next page starts when there may be more.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ManifestPage {
+    pub entries: Vec<ManifestEntry>,
+    /// `(bucket, key)` of the last entry considered, to send as the next cursor.
+    pub next: Option<(String, String)>,
+}
+
+/// Length of the expected-hash header carried by a `Conditional` write.
+pub const CONDITION_LEN: usize = 32;
+
+/// Split a `Conditional` write payload into its precondition and file data.
+///
+/// Returns `None` when the payload is too short to carry the header.
+pub fn parse_put_condition(data: &Bytes) -> Option<(PutCondition, Bytes)> {
+    if data.len() < CONDITION_LEN {
+        return None;
+    }
+    let header = &data[..CONDITION_LEN];
+    let condition = if header.iter().all(|&b| b == 0) {
+        PutCondition::IfAbsent
+    } else {
+        PutCondition::IfHashMatches(hex::encode(header))
+    };
+    Some((condition, data.slice(CONDITION_LEN..)))
+}
+
+/// Bytes a ranged read asks for, before the file's size is known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadRange {
+    /// `len` bytes from `offset`, or fewer where the file ends first.
+    From { offset: u64, len: u64 },
+    /// The last `n` bytes, or the whole file when it is shorter.
+    Last(u64),
+}
+
+impl ReadRange {
+    /// `(offset, len)` of the range within a file of `size` bytes, or `None`
+    /// when no byte of it exists.
+    pub fn resolve(self, size: u64) -> Option<(u64, u64)> {
+        match self {
+            ReadRange::From { offset, len } if offset < size && len > 0 => {
+                Some((offset, len.min(size - offset)))
+            }
+            ReadRange::Last(n) if n > 0 && size > 0 => Some((size.saturating_sub(n), n.min(size))),
+            _ => None,
+        }
+    }
+}
+
+/// Length of a `StreamFileRange` request: `kind(1) + a(8, u64 LE) + b(8, u64 LE)`,
+/// where kind 0 is [`ReadRange::From`] with `a` the offset and `b` the length,
+/// and kind 1 is [`ReadRange::Last`] with `a` the length and `b` zero.
+pub const READ_RANGE_LEN: usize = 17;
+
+/// Encode the request payload of a `StreamFileRange` order.
+pub fn encode_read_range(range: ReadRange) -> Bytes {
+    let (kind, a, b) = match range {
+        ReadRange::From { offset, len } => (0u8, offset, len),
+        ReadRange::Last(n) => (1u8, n, 0),
+    };
+    let mut buf = BytesMut::with_capacity(READ_RANGE_LEN);
+    buf.extend_from_slice(&[kind]);
+    buf.extend_from_slice(&a.to_le_bytes());
+    buf.extend_from_slice(&b.to_le_bytes());
+    buf.freeze()
+}
+
+/// The range a `StreamFileRange` request payload asks for.
+pub fn parse_read_range(data: &[u8]) -> Option<ReadRange> {
+    if data.len() != READ_RANGE_LEN {
+        return None;
+    }
+    let a = u64::from_le_bytes(data[1..9].try_into().ok()?);
+    let b = u64::from_le_bytes(data[9..].try_into().ok()?);
+    match data[0] {
+        0 => Some(ReadRange::From { offset: a, len: b }),
+        1 => Some(ReadRange::Last(a)),
+        _ => None,
+    }
+}
+
+/// Length of a put receipt: `hash(32) + size(8, u64 LE) + deduplicated(1)`.
+pub const PUT_RECEIPT_LEN: usize = 41;
+
+/// Encode the response payload of a successful write.
+pub fn encode_put_receipt(outcome: &PutOutcome) -> Bytes {
+    let mut buf = BytesMut::with_capacity(PUT_RECEIPT_LEN);
+    let mut hash = [0u8; 32];
+    // Stored hashes are always 64 hex chars; fall back to zeros rather than fail the write
+    let _ = hex::decode_to_slice(&outcome.hash256, &mut hash);
+    buf.extend_from_slice(&hash);
+    buf.extend_from_slice(&outcome.size.to_le_bytes());
+    buf.extend_from_slice(&[outcome.deduplicated as u8]);
+    buf.freeze()
+}
+
+/// The size of the stored content in a write's receipt.
+pub fn put_receipt_size(receipt: &[u8]) -> Option<u64> {
+    let size = receipt.get(32..40)?;
+    Some(u64::from_le_bytes(size.try_into().ok()?))
+}
+
+/// Upper bound on the number of entries a single bulk delete may touch.
+pub const MAX_BATCH_ITEMS: usize = 1000;
+
+/// Split a NUL-separated name list, skipping empty and non-UTF-8 entries.
+pub fn split_batch_names(data: &[u8]) -> Vec<String> {
+    data.split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| std::str::from_utf8(name).ok())
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Encode per-item results as `status(1) + name + '\0'` entries.
+pub fn encode_batch_statuses(items: &[(Status, String)]) -> Bytes {
+    let cap = items.iter().map(|(_, name)| name.len() + 2).sum();
+    let mut buf = BytesMut::with_capacity(cap);
+    for (status, name) in items {
+        buf.extend_from_slice(&[status.clone() as u8]);
+        buf.extend_from_slice(name.as_bytes());
+        buf.extend_from_slice(&[0]);
+    }
+    buf.freeze()
+}
+
+/// Inverse of [`encode_batch_statuses`]; truncated trailing entries are dropped.
+pub fn decode_batch_statuses(data: &[u8]) -> Vec<(u8, String)> {
+    let mut items = Vec::new();
+    let mut rest = data;
+    while let Some((&status, tail)) = rest.split_first() {
+        let Some(end) = tail.iter().position(|&b| b == 0) else {
+            break;
+        };
+        items.push((status, String::from_utf8_lossy(&tail[..end]).into_owned()));
+        rest = &tail[end + 1..];
+    }
+    items
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -265,12 +597,31 @@ mod tests {
         assert_eq!(serialized[0], Status::Success as u8);
     }
 
+    #[test]
+    fn test_serialize_request_message() {
+        let mut protocol = LiNaProtocol::new();
+        protocol.flags = FlagType::Read as u8 | FlagType::Batch as u8;
+        protocol.payload.identifier = Bytes::from(&b"test"[..]);
+        protocol.payload.ilen = 4;
+        protocol.payload.data = Bytes::from(vec![1, 2, 3]);
+        protocol.payload.dlen = 3;
+        protocol.payload.checksum = protocol.calculate_checksum();
+
+        let request = protocol.serialize_request_message();
+        let response = protocol.serialize_protocol_message();
+        assert_eq!(request[0], 0x48);
+        assert_eq!(request[1..], response[1..]);
+    }
+
     #[test]
     fn test_flag_type_values() {
         assert_eq!(FlagType::Delete as u8, 0xC0);
         assert_eq!(FlagType::Write as u8, 0x80);
         assert_eq!(FlagType::Auth as u8, 0x60);
         assert_eq!(FlagType::Read as u8, 0x40);
+        assert_eq!(FlagType::Conditional as u8, 0x10);
+        assert_eq!(FlagType::Batch as u8, 0x08);
+        assert_eq!(FlagType::Pattern as u8, 0x04);
         assert_eq!(FlagType::Cover as u8, 0x02);
         assert_eq!(FlagType::Compress as u8, 0x01);
         assert_eq!(FlagType::None as u8, 0x00);
@@ -284,6 +635,10 @@ mod tests {
         assert_eq!(Status::FileNameInvalid as u8, 3);
         assert_eq!(Status::Unauthorized as u8, 4);
         assert_eq!(Status::BadRequest as u8, 5);
+        assert_eq!(Status::PreconditionFailed as u8, 6);
+        assert_eq!(Status::Throttled as u8, 7);
+        assert_eq!(Status::Timeout as u8, 8);
+        assert_eq!(Status::ReadOnly as u8, 9);
         assert_eq!(Status::InternalError as u8, 127);
         assert_eq!(Status::None as u8, 255);
     }
@@ -404,4 +759,134 @@ mod tests {
         let data_start = 1 + 1 + 4 + 4 + 4; // status + ilen + identifier + dlen + checksum
         assert_eq!(&serialized[data_start..], &[10, 20, 30, 40, 50][..]);
     }
+
+    #[test]
+    fn test_batch_and_pattern_flags_keep_delete_op() {
+        let flags = FlagType::Delete as u8 | FlagType::Batch as u8 | FlagType::Pattern as u8;
+        assert_eq!(Op::from_flags(flags), Op::Delete);
+        assert!(flag_set(flags, FlagType::Batch));
+        assert!(flag_set(flags, FlagType::Pattern));
+        assert!(!flag_set(flags, FlagType::Cover));
+    }
+
+    #[test]
+    fn test_split_batch_names() {
+        let names = split_batch_names(b"a.txt\0\0b/c.bin\0");
+        assert_eq!(names, vec!["a.txt".to_string(), "b/c.bin".to_string()]);
+        assert!(split_batch_names(b"").is_empty());
+    }
+
+    #[test]
+    fn test_batch_statuses_roundtrip() {
+        let items = vec![
+            (Status::Success, "a.txt".to_string()),
+            (Status::FileNotFound, "missing".to_string()),
+        ];
+        let encoded = encode_batch_statuses(&items);
+        assert_eq!(encoded[0], Status::Success as u8);
+
+        let decoded = decode_batch_statuses(&encoded);
+        assert_eq!(
+            decoded,
+            vec![
+                (Status::Success as u8, "a.txt".to_string()),
+                (Status::FileNotFound as u8, "missing".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_batch_statuses_drops_truncated_entry() {
+        let mut data = encode_batch_statuses(&[(Status::Success, "ok".to_string())]).to_vec();
+        data.extend_from_slice(&[Status::FileNotFound as u8, b'x']);
+        assert_eq!(decode_batch_statuses(&data), vec![(0, "ok".to_string())]);
+    }
+
+    #[test]
+    fn test_read_range_round_trip() {
+        let from = ReadRange::From { offset: 7, len: u64::MAX };
+        let data = encode_read_range(from);
+        assert_eq!(data.len(), READ_RANGE_LEN);
+        assert_eq!(parse_read_range(&data), Some(from));
+        assert_eq!(parse_read_range(&data[1..]), None);
+        let last = encode_read_range(ReadRange::Last(10));
+        assert_eq!(parse_read_range(&last), Some(ReadRange::Last(10)));
+        let mut unknown = last.to_vec();
+        unknown[0] = 2;
+        assert_eq!(parse_read_range(&unknown), None);
+    }
+
+    #[test]
+    fn test_read_range_resolve() {
+        let from = |offset, len| ReadRange::From { offset, len };
+        assert_eq!(from(0, 10).resolve(100), Some((0, 10)));
+        assert_eq!(from(90, u64::MAX).resolve(100), Some((90, 10)));
+        assert_eq!(from(100, 5).resolve(100), None);
+        assert_eq!(from(0, 0).resolve(100), None);
+        assert_eq!(ReadRange::Last(10).resolve(100), Some((90, 10)));
+        assert_eq!(ReadRange::Last(500).resolve(100), Some((0, 100)));
+        assert_eq!(ReadRange::Last(0).resolve(100), None);
+        assert_eq!(ReadRange::Last(5).resolve(0), None);
+    }
+
+    #[test]
+    fn test_parse_put_condition_if_absent() {
+        let mut data = vec![0u8; CONDITION_LEN];
+        data.extend_from_slice(b"body");
+        let (condition, body) = parse_put_condition(&Bytes::from(data)).unwrap();
+        assert_eq!(condition, PutCondition::IfAbsent);
+        assert_eq!(&body[..], b"body");
+    }
+
+    #[test]
+    fn test_parse_put_condition_if_hash_matches() {
+        let mut data = vec![0xabu8; CONDITION_LEN];
+        data.extend_from_slice(b"body");
+        let (condition, body) = parse_put_condition(&Bytes::from(data)).unwrap();
+        assert_eq!(condition, PutCondition::IfHashMatches("ab".repeat(CONDITION_LEN)));
+        assert_eq!(&body[..], b"body");
+    }
+
+    #[test]
+    fn test_parse_put_condition_too_short() {
+        assert!(parse_put_condition(&Bytes::from(vec![0u8; CONDITION_LEN - 1])).is_none());
+        // An empty body after the header is a valid (empty) object
+        let (_, body) = parse_put_condition(&Bytes::from(vec![0u8; CONDITION_LEN])).unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn test_encode_put_receipt() {
+        let outcome = PutOutcome {
+            hash256: "0f".repeat(32),
+            size: 0x0102,
+            deduplicated: true,
+        };
+        let receipt = encode_put_receipt(&outcome);
+        assert_eq!(receipt.len(), PUT_RECEIPT_LEN);
+        assert!(receipt[..32].iter().all(|&b| b == 0x0f));
+        assert_eq!(&receipt[32..40], &0x0102u64.to_le_bytes());
+        assert_eq!(receipt[40], 1);
+        assert_eq!(put_receipt_size(&receipt), Some(0x0102));
+        assert_eq!(put_receipt_size(&receipt[..8]), None);
+    }
+
+    #[test]
+    fn test_file_meta_dto_from_meta() {
+        let dto = FileMetaDto::from(FileMeta {
+            name: "a.txt".to_string(),
+            ext: "txt".to_string(),
+            size: 3,
+            hash256: "ab".repeat(32),
+            compressed: false,
+            create_at: "2024-01-01 00:00:00".to_string(),
+            update_at: "2024-01-02 00:00:00".to_string(),
+        });
+        assert_eq!(dto.hash, "ab".repeat(32));
+        assert_eq!(dto.created_at, "2024-01-01 00:00:00");
+
+        let json = serde_json::to_vec(&dto).unwrap();
+        let back: FileMetaDto = serde_json::from_slice(&json).unwrap();
+        assert_eq!(back, dto);
+    }
 }
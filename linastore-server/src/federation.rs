@@ -0,0 +1,171 @@
+//! Federation: reads that miss locally are tried on an ordered list of peer
+//! servers over the advanced protocol, so several servers answer as one
+//! namespace. What a peer returns can be cached in the local store.
+
+use std::sync::OnceLock;
+
+use bytes::Bytes;
+use tracing::{Level, event};
+use uuid::Uuid;
+
+use crate::{
+    config,
+    conveyer::ConveyQueue,
+    dtos::{Behavior, Content, FlagType, Package, Status},
+    error::{Result, err_msg},
+    replica::Peer,
+};
+
+const DEFAULT_PEER_PORT: &str = "8096";
+
+static FEDERATION: OnceLock<FederationSettings> = OnceLock::new();
+
+/// Which peers to ask on a miss; off without `LINASTORE_PEERS`.
+#[derive(Debug, Clone)]
+pub struct FederationSettings {
+    /// `host:port` of each peer's advanced service, asked in this order
+    peers: Vec<String>,
+    /// Account to sign in to the peers with, when they require credentials
+    credentials: Option<(String, String)>,
+    /// Store what a peer returns, so the next read is answered locally
+    cache: bool,
+}
+
+impl FederationSettings {
+    pub fn load() -> Result<Option<Self>> {
+        let peers: Vec<String> = config::var("LINASTORE_PEERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+            .map(|peer| crate::vars::with_port(peer, DEFAULT_PEER_PORT))
+            .collect();
+        if peers.is_empty() {
+            return Ok(None);
+        }
+        let user = config::var("LINASTORE_PEER_USER")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+        let password = config::var("LINASTORE_PEER_PASSWORD")
+            .ok()
+            .filter(|v| !v.is_empty());
+        let credentials = match (user, password) {
+            (Some(user), Some(password)) => Some((user, password)),
+            (None, None) => None,
+            _ => {
+                return Err(err_msg(config::attribute(
+                    "LINASTORE_PEER_USER and LINASTORE_PEER_PASSWORD must be set together"
+                        .to_string(),
+                )));
+            }
+        };
+        let cache = match config::var("LINASTORE_PEER_CACHE") {
+            Ok(raw) => crate::vars::parse_truthy(&raw).ok_or_else(|| {
+                err_msg(config::attribute(format!(
+                    "LINASTORE_PEER_CACHE must be a boolean: {:?}",
+                    raw
+                )))
+            })?,
+            Err(_) => false,
+        };
+        Ok(Some(FederationSettings {
+            peers,
+            credentials,
+            cache,
+        }))
+    }
+
+    /// Which peers are asked, for the startup log.
+    pub fn describe(&self) -> String {
+        format!(
+            "{}{}",
+            self.peers.join(", "),
+            if self.cache { ", caching locally" } else { "" }
+        )
+    }
+}
+
+/// Ask `settings`' peers on every local miss from now on.
+pub fn init(settings: FederationSettings) {
+    let _ = FEDERATION.set(settings);
+}
+
+/// Whether misses are tried on peers at all.
+pub fn enabled() -> bool {
+    FEDERATION.get().is_some()
+}
+
+/// What the first peer holding `bucket`/`key` has there, or `None` when no
+/// peer has it or federation is off. A peer that cannot be reached is
+/// skipped.
+pub async fn lookup(bucket: &str, key: &str) -> Option<Bytes> {
+    let settings = FEDERATION.get()?;
+    for address in &settings.peers {
+        let fetched = match Peer::connect(address, settings.credentials.as_ref()).await {
+            Ok(mut peer) => peer.fetch(bucket, key).await,
+            Err(e) => Err(e),
+        };
+        match fetched {
+            Ok(Some(content)) => {
+                event!(
+                    Level::DEBUG,
+                    "Fetched {}/{} from peer {}",
+                    bucket,
+                    key,
+                    address
+                );
+                crate::metrics::FEDERATION_HITS.inc();
+                if settings.cache
+                    && let Err(e) = cache(bucket, key, content.clone()).await
+                {
+                    event!(
+                        Level::WARN,
+                        "Failed to cache {}/{} from peer {}: {}",
+                        bucket,
+                        key,
+                        address,
+                        e
+                    );
+                }
+                return Some(content);
+            }
+            Ok(None) => {}
+            Err(e) => event!(
+                Level::WARN,
+                "Peer {} failed to look up {}/{}: {}",
+                address,
+                bucket,
+                key,
+                e
+            ),
+        }
+    }
+    None
+}
+
+/// Store `content` under `bucket`/`key` as a client's write would.
+async fn cache(bucket: &str, key: &str, content: Bytes) -> Result<()> {
+    let mapper = crate::mapper::get_mapper().ok_or_else(|| err_msg("bucket mapper unavailable"))?;
+    // A concurrent writer may have won the insert; write to what readers see
+    let mut internal_name = Uuid::new_v4().to_string();
+    let _ = mapper.register(bucket, key, &internal_name).await;
+    if let Ok(Some(winner)) = mapper.resolve(bucket, key).await {
+        internal_name = winner;
+    }
+
+    let mut order = Package::new_with_id(&Uuid::new_v4());
+    order.behavior = Behavior::PutFile;
+    order.content = Content {
+        flags: FlagType::Write as u8,
+        identifier: Bytes::from(internal_name),
+        data: content,
+    };
+    let response = ConveyQueue::get_instance()
+        .request(order)
+        .await
+        .map_err(|e| err_msg(e.to_string()))?;
+    if response.status != Status::Success {
+        return Err(err_msg(format!("{:?}", response.status)));
+    }
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::{net::IpAddr, time::Duration};
+
+use hyper::{HeaderMap, Request, StatusCode, header::HeaderValue};
+use tracing::{Level, event};
+use uuid::Uuid;
+
+use crate::vars::AccessLogFormat;
+
+pub(super) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Request details captured before the request is consumed by its handler.
+#[derive(Debug)]
+pub(super) struct AccessEntry {
+    pub(super) request_id: HeaderValue,
+    client: IpAddr,
+    method: String,
+    target: String,
+    version: &'static str,
+    referer: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessEntry {
+    /// Keep the client's `X-Request-Id` when it is short and printable,
+    /// otherwise assign a fresh one.
+    pub(super) fn new<B>(req: &Request<B>, client: IpAddr) -> Self {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .filter(|v| {
+                !v.is_empty() && v.len() <= 128 && v.as_bytes().iter().all(u8::is_ascii_graphic)
+            })
+            .cloned()
+            .unwrap_or_else(|| {
+                HeaderValue::from_str(&Uuid::new_v4().to_string())
+                    .expect("a UUID is a valid header value")
+            });
+        let target = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.uri().path().to_string(), |pq| pq.to_string());
+        Self {
+            request_id,
+            client,
+            method: req.method().to_string(),
+            target,
+            version: http_version(req.version()),
+            referer: header_string(req.headers(), hyper::header::REFERER),
+            user_agent: header_string(req.headers(), hyper::header::USER_AGENT),
+        }
+    }
+
+    /// Emit the access line on the `access` target; `bytes` is `None` when the
+    /// body length is not known up front (chunked responses).
+    pub(super) fn log(
+        &self,
+        format: AccessLogFormat,
+        status: StatusCode,
+        bytes: Option<u64>,
+        latency: Duration,
+    ) {
+        match format {
+            AccessLogFormat::Off => {}
+            AccessLogFormat::Structured => event!(
+                target: "access",
+                Level::INFO,
+                request_id = self.request_id.to_str().unwrap_or("-"),
+                client = %self.client,
+                method = %self.method,
+                path = %self.target,
+                status = status.as_u16(),
+                bytes = bytes,
+                latency_ms = latency.as_secs_f64() * 1000.0,
+                "request"
+            ),
+            AccessLogFormat::Combined => event!(
+                target: "access",
+                Level::INFO,
+                "{}",
+                self.combined(status, bytes, chrono::Local::now())
+            ),
+        }
+    }
+
+    /// Apache/NCSA combined log format.
+    fn combined<Tz>(
+        &self,
+        status: StatusCode,
+        bytes: Option<u64>,
+        now: chrono::DateTime<Tz>,
+    ) -> String
+    where
+        Tz: chrono::TimeZone,
+        Tz::Offset: std::fmt::Display,
+    {
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"",
+            self.client,
+            now.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            escape(&self.target),
+            self.version,
+            status.as_u16(),
+            bytes.map_or_else(|| "-".to_string(), |b| b.to_string()),
+            self.referer
+                .as_deref()
+                .map_or_else(|| "-".to_string(), escape),
+            self.user_agent
+                .as_deref()
+                .map_or_else(|| "-".to_string(), escape),
+        )
+    }
+}
+
+fn header_string(headers: &HeaderMap, name: hyper::header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn http_version(version: hyper::Version) -> &'static str {
+    match version {
+        hyper::Version::HTTP_09 => "HTTP/0.9",
+        hyper::Version::HTTP_10 => "HTTP/1.0",
+        hyper::Version::HTTP_2 => "HTTP/2.0",
+        hyper::Version::HTTP_3 => "HTTP/3.0",
+        _ => "HTTP/1.1",
+    }
+}
+
+/// Keep quoted fields on one line and unambiguous for log parsers.
+fn escape(value: &str) -> String {
+    value
+        .chars()
+        .flat_map(|c| match c {
+            '"' => vec!['\\', '"'],
+            '\\' => vec!['\\', '\\'],
+            c if c.is_control() => vec!['?'],
+            c => vec![c],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_request_id_is_kept_or_generated() {
+        let req = Request::builder()
+            .uri("/a")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .body(())
+            .unwrap();
+        let entry = AccessEntry::new(&req, "127.0.0.1".parse().unwrap());
+        assert_eq!(entry.request_id, "abc-123");
+
+        let req = Request::builder()
+            .uri("/a")
+            .header(REQUEST_ID_HEADER, "has space")
+            .body(())
+            .unwrap();
+        let entry = AccessEntry::new(&req, "127.0.0.1".parse().unwrap());
+        assert_eq!(entry.request_id.len(), 36);
+    }
+
+    #[test]
+    fn test_combined_format() {
+        let req = Request::builder()
+            .method("GET")
+            .uri("/docs/a.txt?download=1")
+            .header(hyper::header::USER_AGENT, "curl/8.0 \"x\"")
+            .body(())
+            .unwrap();
+        let entry = AccessEntry::new(&req, "10.0.0.1".parse().unwrap());
+        let now = chrono::FixedOffset::east_opt(3600)
+            .unwrap()
+            .with_ymd_and_hms(2026, 3, 4, 5, 6, 7)
+            .unwrap();
+        assert_eq!(
+            entry.combined(StatusCode::OK, Some(42), now),
+            "10.0.0.1 - - [04/Mar/2026:05:06:07 +0100] \"GET /docs/a.txt?download=1 HTTP/1.1\" 200 42 \"-\" \"curl/8.0 \\\"x\\\"\""
+        );
+        assert!(
+            entry
+                .combined(StatusCode::NOT_FOUND, None, now)
+                .contains("\" 404 - \"")
+        );
+    }
+}
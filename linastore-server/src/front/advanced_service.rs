@@ -1,20 +1,34 @@
 use bytes::{Bytes, BytesMut};
-use std::{io, net::SocketAddr, time::Duration};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    path::Path,
+    time::{Duration, Instant},
+};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpListener;
+use tokio::net::TcpStream;
 use tracing::{Level, event, instrument};
 use uuid::Uuid;
 
 const READ_TIMEOUT: Duration = Duration::from_secs(5);
 
+use super::manager::listen;
+use super::tls;
 use crate::vars;
 use crate::{
     auth::{
-        HandshakeStatus, decrypt_with_token, extract_password, extract_username, get_auth_manager,
-        get_handshake_rate_limiter,
+        Action, HandshakeStatus, decrypt_with_token, extract_password, extract_username,
+        get_auth_manager, get_handshake_rate_limiter,
+    },
+    conveyer::{ConveyQueue, RequestError, order_deadline},
+    dtos::{
+        Behavior, Content, FileMetaDto, FlagType, LOCAL_ONLY, LiNaProtocol, MANIFEST_PAGE_LEN,
+        MAX_BATCH_ITEMS,
+        ManifestEntry, ManifestPage, Op, Package, Status, decode_batch_statuses,
+        encode_batch_statuses, flag_set, split_batch_names,
     },
-    conveyer::ConveyQueue,
-    dtos::{Behavior, Content, LiNaProtocol, Op, Package, Status},
+    mapper::BucketMapper,
     shutdown::Shutdown,
 };
 
@@ -45,6 +59,303 @@ async fn write_error_response<T: AsyncWriteExt + Unpin>(
     }
 }
 
+/// Split a `bucket\0key` identifier; without a NUL the whole identifier is the
+/// key in the default bucket.
+fn split_bucket_key(id_bytes: &[u8]) -> (String, String) {
+    if let Some(null_pos) = id_bytes.iter().position(|&b| b == 0) {
+        let b = String::from_utf8_lossy(&id_bytes[..null_pos]);
+        let k = String::from_utf8_lossy(&id_bytes[null_pos + 1..]);
+        (b.into_owned(), k.into_owned())
+    } else {
+        let k = String::from_utf8_lossy(id_bytes).to_string();
+        (crate::mapper::DEFAULT_BUCKET.to_string(), k)
+    }
+}
+
+/// Bucket named by a batch identifier (up to the first NUL), or the default one.
+fn batch_bucket(id_bytes: &[u8]) -> String {
+    let end = id_bytes.iter().position(|&b| b == 0).unwrap_or(id_bytes.len());
+    if end == 0 {
+        crate::mapper::DEFAULT_BUCKET.to_string()
+    } else {
+        String::from_utf8_lossy(&id_bytes[..end]).into_owned()
+    }
+}
+
+/// The bucket a delete with `flags` acts in: a batch names it alone in
+/// `identifier`, any other delete as the bucket half of `bucket\0key`.
+fn delete_bucket(flags: u8, identifier: &[u8]) -> String {
+    if flag_set(flags, FlagType::Batch) {
+        batch_bucket(identifier)
+    } else {
+        split_bucket_key(identifier).0
+    }
+}
+
+/// Hand an order to the porter and wait for its reply.
+#[instrument(
+    name = "advanced_order",
+    parent = None,
+    skip_all,
+    fields(request_id = log_id, behavior = ?order_pkg.behavior)
+)]
+async fn dispatch_order(log_id: &str, mut order_pkg: Package) -> Result<Package, Status> {
+    order_pkg.deadline = Some(order_deadline(order_pkg.content.data.len()));
+    ConveyQueue::get_instance()
+        .request(order_pkg)
+        .await
+        .map_err(|err| match err {
+            RequestError::Busy => {
+                event!(Level::WARN, "[waitress {}] {}", log_id, err);
+                Status::Throttled
+            }
+            RequestError::ReadOnly => {
+                event!(Level::INFO, "[waitress {}] {}", log_id, err);
+                Status::ReadOnly
+            }
+            RequestError::Timeout => {
+                event!(Level::ERROR, "[waitress {}] {}", log_id, err);
+                Status::Timeout
+            }
+            _ => {
+                event!(Level::ERROR, "[waitress {}] {}", log_id, err);
+                Status::InternalError
+            }
+        })
+}
+
+async fn write_package_response<T: AsyncWriteExt + Unpin>(
+    stream: &mut T,
+    status: Status,
+    identifier: Bytes,
+    data: Bytes,
+) {
+    let mut response = LiNaProtocol::new();
+    response.status = status;
+    response.payload.identifier = identifier;
+    response.payload.ilen = response.payload.identifier.len() as u8;
+    response.payload.dlen = data.len() as u32;
+    response.payload.data = data;
+    // Calculate checksum after setting all the data
+    response.payload.checksum = response.calculate_checksum();
+    let resp_data = response.serialize_protocol_message();
+
+    if let Err(e) = stream.write_all(&resp_data).await {
+        event!(tracing::Level::ERROR, "Error writing to stream: {}", e);
+    }
+}
+
+/// Resolve a `Batch` and/or `Pattern` delete through the bucket mapper, run it
+/// as a single porter order and reply with one `status + key` entry per key.
+///
+/// Returns `false` when an error response was written and the connection
+/// should be closed.
+async fn handle_bulk_delete<T: AsyncWriteExt + Unpin>(
+    stream: &mut T,
+    log_id: &str,
+    flags: u8,
+    identifier: &[u8],
+    names_data: &[u8],
+) -> bool {
+    let pattern = flag_set(flags, FlagType::Pattern);
+    let bucket = delete_bucket(flags, identifier);
+    let keys = if flag_set(flags, FlagType::Batch) {
+        split_batch_names(names_data)
+    } else {
+        vec![split_bucket_key(identifier).1]
+    };
+
+    if keys.is_empty() || keys.len() > MAX_BATCH_ITEMS || keys.iter().any(|k| k.is_empty()) {
+        event!(
+            Level::WARN,
+            "[waitress {}] Rejecting bulk delete with {} keys",
+            log_id,
+            keys.len()
+        );
+        write_error_response(stream, log_id, Status::BadRequest, None).await;
+        return false;
+    }
+
+    let Some(mapper) = crate::mapper::get_mapper() else {
+        event!(Level::ERROR, "[waitress {}] Mapper unavailable", log_id);
+        write_error_response(stream, log_id, Status::InternalError, None).await;
+        return false;
+    };
+
+    // (key, internal name) pairs; keys without a mapping are reported as missing
+    let mut targets: Vec<(String, Option<String>)> = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+    for key in keys {
+        if pattern {
+            let remaining = MAX_BATCH_ITEMS.saturating_sub(targets.len());
+            if remaining == 0 {
+                break;
+            }
+            match mapper.match_keys(&bucket, &key, remaining).await {
+                Ok(matched) => {
+                    for (matched_key, internal) in matched {
+                        if seen.insert(internal.clone()) {
+                            targets.push((matched_key, Some(internal)));
+                        }
+                    }
+                }
+                Err(e) => {
+                    event!(
+                        Level::ERROR,
+                        "[waitress {}] Pattern lookup failed for {}/{}: {}",
+                        log_id, &bucket, &key, e
+                    );
+                    write_error_response(stream, log_id, Status::InternalError, None).await;
+                    return false;
+                }
+            }
+        } else {
+            match mapper.resolve(&bucket, &key).await {
+                Ok(Some(internal)) if seen.insert(internal.clone()) => {
+                    targets.push((key, Some(internal)))
+                }
+                _ => targets.push((key, None)),
+            }
+        }
+    }
+
+    let internal_names: Vec<&str> = targets
+        .iter()
+        .filter_map(|(_, internal)| internal.as_deref())
+        .collect();
+
+    let results: HashMap<String, u8> = if internal_names.is_empty() {
+        HashMap::new()
+    } else {
+        let mut order_pkg = Package::new_with_id(&Uuid::new_v4());
+        order_pkg.behavior = Behavior::DeleteFiles;
+        order_pkg.content = Content {
+            flags,
+            identifier: Bytes::from(bucket.clone()),
+            data: Bytes::from(internal_names.join("\0")),
+        };
+        match dispatch_order(log_id, order_pkg).await {
+            Ok(pkg) => decode_batch_statuses(&pkg.content.data)
+                .into_iter()
+                .map(|(status, name)| (name, status))
+                .collect(),
+            Err(status) => {
+                write_error_response(stream, log_id, status, None).await;
+                return false;
+            }
+        }
+    };
+
+    let mut items = Vec::with_capacity(targets.len());
+    for (key, internal) in targets {
+        let deleted = internal
+            .as_ref()
+            .and_then(|name| results.get(name))
+            .is_some_and(|&status| status == Status::Success as u8);
+        if deleted {
+            if let Err(e) = mapper.delete(&bucket, &key).await {
+                event!(
+                    Level::WARN,
+                    "[waitress {}] Failed to remove mapping {}/{}: {}",
+                    log_id, &bucket, &key, e
+                );
+            }
+            items.push((Status::Success, key));
+        } else {
+            items.push((Status::FileNotFound, key));
+        }
+    }
+
+    event!(
+        Level::INFO,
+        "[waitress {}] Bulk delete in bucket {}: {} of {} removed",
+        log_id,
+        &bucket,
+        items.iter().filter(|(status, _)| *status == Status::Success).count(),
+        items.len()
+    );
+    let bucket_id = Bytes::copy_from_slice(&identifier[..identifier.len().min(u8::MAX as usize)]);
+    write_package_response(stream, Status::Success, bucket_id, encode_batch_statuses(&items)).await;
+    true
+}
+
+/// Answer a manifest read with the page of keys after `cursor` (`bucket\0key`,
+/// empty for the first page) and the hash and size of what each holds.
+///
+/// Returns `false` when an error response was written and the connection
+/// should be closed.
+async fn handle_manifest<T: AsyncWriteExt + Unpin>(
+    stream: &mut T,
+    log_id: &str,
+    cursor: &[u8],
+) -> bool {
+    let Some(mapper) = crate::mapper::get_mapper() else {
+        event!(Level::ERROR, "[waitress {}] Mapper unavailable", log_id);
+        write_error_response(stream, log_id, Status::InternalError, None).await;
+        return false;
+    };
+
+    let after = (!cursor.is_empty()).then(|| split_bucket_key(cursor));
+    let after = after.as_ref().map(|(bucket, key)| (bucket.as_str(), key.as_str()));
+    let rows = match mapper.list_after(after, MANIFEST_PAGE_LEN).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            event!(Level::ERROR, "[waitress {}] Manifest lookup failed: {}", log_id, e);
+            write_error_response(stream, log_id, Status::InternalError, None).await;
+            return false;
+        }
+    };
+
+    let metas: HashMap<String, FileMetaDto> = if rows.is_empty() {
+        HashMap::new()
+    } else {
+        let names: Vec<&str> = rows.iter().map(|(_, _, internal)| internal.as_str()).collect();
+        let mut order_pkg = Package::new_with_id(&Uuid::new_v4());
+        order_pkg.behavior = Behavior::ListFiles;
+        order_pkg.content.data = Bytes::from(names.join("\0"));
+        match dispatch_order(log_id, order_pkg).await {
+            Ok(pkg) => serde_json::from_slice::<Vec<FileMetaDto>>(&pkg.content.data)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|meta| (meta.name.clone(), meta))
+                .collect(),
+            Err(status) => {
+                write_error_response(stream, log_id, status, None).await;
+                return false;
+            }
+        }
+    };
+
+    let next = (rows.len() == MANIFEST_PAGE_LEN)
+        .then(|| rows.last().map(|(bucket, key, _)| (bucket.clone(), key.clone())))
+        .flatten();
+    // Mappings whose file is gone are left out
+    let entries = rows
+        .into_iter()
+        .filter_map(|(bucket, key, internal)| {
+            metas.get(&internal).map(|meta| ManifestEntry {
+                bucket,
+                key,
+                hash: meta.hash.clone(),
+                size: meta.size,
+                compressed: meta.compressed,
+            })
+        })
+        .collect();
+
+    match serde_json::to_vec(&ManifestPage { entries, next }) {
+        Ok(json) => {
+            write_package_response(stream, Status::Success, Bytes::new(), json.into()).await;
+            true
+        }
+        Err(e) => {
+            event!(Level::ERROR, "[waitress {}] Failed to encode manifest: {}", log_id, e);
+            write_error_response(stream, log_id, Status::InternalError, None).await;
+            false
+        }
+    }
+}
+
 enum ProtocolReadError {
     Disconnected,
     Other(String),
@@ -160,16 +471,26 @@ impl LiNaProtocol {
 async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
     mut stream: T,
     peer_addr: SocketAddr,
+    client_cert: Option<String>,
 ) {
     let log_id = Uuid::new_v4().to_string();
 
     let auth_manager = get_auth_manager();
     let auth_required = auth_manager.is_password_enabled();
+    let shutdown = Shutdown::get_instance();
 
     // Loop to handle multiple requests on the same connection
     loop {
         let mut message = LiNaProtocol::new();
-        match message.parse_protocol_message(&mut stream).await {
+        // Requests already read are answered; idle connections close on shutdown
+        let parsed = tokio::select! {
+            parsed = message.parse_protocol_message(&mut stream) => parsed,
+            _ = shutdown.wait() => {
+                event!(Level::INFO, "[waitress {}] Closing connection for shutdown", &log_id);
+                return;
+            }
+        };
+        match parsed {
             Ok(()) => {}
             Err(ProtocolReadError::Disconnected) => {
                 event!(Level::INFO, "[waitress {}] Client disconnected", &log_id);
@@ -186,6 +507,8 @@ async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
             }
         }
 
+        let started = Instant::now();
+
         // Decode the operation once; downstream branches dispatch on this enum
         // instead of order-sensitive bitwise checks.
         let op = message.op();
@@ -298,10 +621,44 @@ async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
         }
 
         let uuid = Uuid::new_v4();
-        let uni_id = uuid.into_bytes();
 
-        // Extract session token from payload data for write operations
-        let (session_token, file_data) = if op == Op::Write
+        let batch_delete = op == Op::Delete && flag_set(message.flags, FlagType::Batch);
+        let pattern_delete = op == Op::Delete && flag_set(message.flags, FlagType::Pattern);
+        let manifest = op == Op::Read && flag_set(message.flags, FlagType::Batch);
+
+        // Wildcard deletes are opt-in per request and only honoured for
+        // authenticated sessions.
+        if pattern_delete && !auth_required {
+            event!(
+                Level::WARN,
+                "[waitress {}] Pattern delete refused: authentication is disabled",
+                &log_id
+            );
+            write_error_response(&mut stream, &log_id, Status::Unauthorized, None).await;
+            return;
+        }
+
+        let action = match op {
+            Op::Delete if pattern_delete => Action::PatternDelete,
+            Op::Delete => Action::Delete,
+            Op::Write => Action::Write,
+            _ => Action::Read,
+        };
+
+        // Misses are asked of federation peers unless a peer is asking
+        let federate = op == Op::Read
+            && !manifest
+            && crate::federation::enabled()
+            && message
+                .payload
+                .data
+                .iter()
+                .position(|&b| b == 0)
+                .is_none_or(|null_pos| &message.payload.data[null_pos + 1..] != LOCAL_ONLY);
+
+        // Extract session token from payload data for write, batch delete and
+        // manifest operations
+        let (session_token, file_data) = if (op == Op::Write || batch_delete || manifest)
             && !message.payload.data.is_empty()
         {
             // Extract session token and file data without cloning large buffers.
@@ -344,23 +701,20 @@ async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
 
         // Validate session if authentication is required and we have a token
         // Use a 60-second grace period for decryption to handle race conditions
-        // where data is encrypted before token expires but arrives after expiration
-        let valid_token = if auth_required {
-            match session_token {
-                Some(token) => match auth_manager.validate_session(&token, 60).await {
-                    Some(valid_user_id) => {
-                        event!(
-                            Level::DEBUG,
-                            "[waitress {}] Session validated for user: {}",
-                            &log_id,
-                            &valid_user_id
-                        );
-                        Some(token)
-                    }
+        // where data is encrypted before token expires but arrives after expiration.
+        // A client certificate stands in for the token; its payload is sent
+        // in the clear under TLS with an empty token.
+        let session_token = session_token.filter(|token| !token.is_empty());
+        let (valid_token, caller) = if auth_required {
+            let authenticated = match (&session_token, &client_cert) {
+                (Some(token), _) => auth_manager.validate_credential(token, 60).await,
+                (None, Some(name)) => auth_manager.principal_for_user(name).await,
+                (None, None) => match auth_manager.anonymous(action) {
+                    Some(principal) => Some(principal),
                     None => {
                         event!(
                             Level::WARN,
-                            "[waitress {}] Invalid or expired session token, rejecting",
+                            "[waitress {}] No session token provided, rejecting",
                             &log_id
                         );
                         write_error_response(&mut stream, &log_id, Status::Unauthorized, None)
@@ -368,24 +722,74 @@ async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
                         return;
                     }
                 },
+            };
+            match authenticated {
+                Some(principal) if !principal.role.permits(action) => {
+                    event!(
+                        Level::WARN,
+                        "[waitress {}] User {} with role {} may not {:?}",
+                        &log_id,
+                        &principal.user_id,
+                        principal.role.as_str(),
+                        action
+                    );
+                    write_error_response(&mut stream, &log_id, Status::Unauthorized, None)
+                        .await;
+                    return;
+                }
+                Some(principal) => {
+                    event!(
+                        Level::DEBUG,
+                        "[waitress {}] Session validated for user: {}",
+                        &log_id,
+                        &principal.user_id
+                    );
+                    (session_token, Some(principal))
+                }
                 None => {
                     event!(
                         Level::WARN,
-                        "[waitress {}] No session token provided, rejecting",
+                        "[waitress {}] Invalid or expired credentials, rejecting",
                         &log_id
                     );
-                    write_error_response(&mut stream, &log_id, Status::Unauthorized, None).await;
+                    write_error_response(&mut stream, &log_id, Status::Unauthorized, None)
+                        .await;
                     return;
                 }
             }
         } else {
             // Authentication not required, but use token for decryption if provided
-            session_token
+            (session_token, None)
         };
 
+        let owner = caller
+            .as_ref()
+            .map_or_else(|| crate::auth::Principal::anonymous().user_id, |p| p.user_id.clone());
+
+        // Users holding bucket grants may only address those buckets, and
+        // only users reaching every bucket may list them all
+        let bucket = if manifest {
+            None
+        } else if op == Op::Delete {
+            Some(delete_bucket(message.flags, &message.payload.identifier))
+        } else {
+            Some(split_bucket_key(&message.payload.identifier).0)
+        };
+        if let Some(principal) = caller.filter(|p| !p.may_access(bucket.as_deref())) {
+            event!(
+                Level::WARN,
+                "[waitress {}] User {} has no grant for bucket {}",
+                &log_id,
+                &principal.user_id,
+                bucket.as_deref().unwrap_or("*")
+            );
+            write_error_response(&mut stream, &log_id, Status::Unauthorized, None).await;
+            return;
+        }
+
         // Decrypt file data if a session token is provided and this is a write operation.
         // When auth is not required, decryption failure falls back to original data for compatibility.
-        let file_data: Bytes = if let Some(token) = valid_token {
+        let file_data: Bytes = if let Some(token) = valid_token.filter(|_| op == Op::Write) {
             if !file_data.is_empty() {
                 match decrypt_with_token(&token, &file_data) {
                     Ok(decrypted) => {
@@ -424,6 +828,28 @@ async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
             file_data
         };
 
+        if manifest {
+            if !handle_manifest(&mut stream, &log_id, &file_data).await {
+                return;
+            }
+            continue;
+        }
+
+        if batch_delete || pattern_delete {
+            if !handle_bulk_delete(
+                &mut stream,
+                &log_id,
+                message.flags,
+                &message.payload.identifier,
+                &file_data,
+            )
+            .await
+            {
+                return;
+            }
+            continue;
+        }
+
         // Order generation
         let mut order_pkg = Package::new_with_id(&uuid);
         order_pkg.behavior = match op {
@@ -435,21 +861,22 @@ async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
             Op::Auth | Op::None => Behavior::None,
         };
 
-        let id_bytes = &message.payload.identifier;
-        let (bucket, key) = if let Some(null_pos) = id_bytes.iter().position(|&b| b == 0) {
-            let b = String::from_utf8_lossy(&id_bytes[..null_pos]);
-            let k = String::from_utf8_lossy(&id_bytes[null_pos + 1..]);
-            (b.into_owned(), k.into_owned())
-        } else {
-            let k = String::from_utf8_lossy(id_bytes).to_string();
-            (crate::mapper::DEFAULT_BUCKET.to_string(), k)
-        };
+        let (bucket, key) = split_bucket_key(&message.payload.identifier);
+        // Internal name of a mapping this write created, to undo if it fails
+        let mut new_mapping = None;
 
         let resolved_identifier = match op {
             Op::Write => {
-                let internal_name = Uuid::new_v4().to_string();
-                if let Some(m) = crate::mapper::get_mapper() {
-                    let _ = m.register(&bucket, &key, &internal_name).await;
+                // Reuse the existing object so overwrites and conditional
+                // writes act on what readers see
+                let mut internal_name = Uuid::new_v4().to_string();
+                if let Some(m) = crate::mapper::get_mapper()
+                    && let Ok((resolved, created)) = m.resolve_or_register(&bucket, &key).await
+                {
+                    if created {
+                        new_mapping = Some(resolved.clone());
+                    }
+                    internal_name = resolved;
                 }
                 Bytes::from(internal_name)
             }
@@ -457,6 +884,22 @@ async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
                 match crate::mapper::get_mapper() {
                     Some(m) => match m.resolve(&bucket, &key).await {
                         Ok(Some(internal)) => Bytes::from(internal),
+                        _ if federate => match crate::federation::lookup(&bucket, &key).await {
+                            Some(content) => {
+                                write_package_response(
+                                    &mut stream,
+                                    Status::Success,
+                                    message.payload.identifier.clone(),
+                                    content,
+                                )
+                                .await;
+                                continue;
+                            }
+                            None => {
+                                write_error_response(&mut stream, &log_id, Status::FileNotFound, None).await;
+                                return;
+                            }
+                        },
                         _ => {
                             event!(
                                 Level::ERROR,
@@ -482,70 +925,61 @@ async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
             data: file_data,
         };
 
-        // Register waiter before sending to conveyer
-        let con_queue = ConveyQueue::get_instance();
-        let receiver = match con_queue.register_waiter(uni_id) {
-            Some(rx) => rx,
-            None => {
-                event!(
-                    Level::ERROR,
-                    "[waitress {}] Failed to register waiter",
-                    &log_id
-                );
-                write_error_response(&mut stream, &log_id, Status::InternalError, None).await;
-                return;
-            }
-        };
-
-        // Send order to conveyer
-        match con_queue.produce_order(order_pkg) {
-            Ok(_) => {}
-            Err(err) => {
-                event!(Level::ERROR, "[waitress {}] {}", &log_id, err);
-                con_queue.unregister_waiter(uni_id);
-                con_queue.remove_order(uni_id);
-                write_error_response(&mut stream, &log_id, Status::InternalError, None).await;
-                return;
-            }
+        let result = dispatch_order(&log_id, order_pkg).await;
+        if let Some(internal_name) = &new_mapping
+            && let Some(m) = crate::mapper::get_mapper()
+        {
+            let status = match &result {
+                Ok(pkg) => &pkg.status,
+                Err(status) => status,
+            };
+            forget_failed_mapping(&m, &log_id, &bucket, &key, internal_name, status).await;
         }
-
-        // Wait for response via channel with timeout
-        let timeout = Duration::from_secs(10);
-        match tokio::time::timeout(timeout, receiver).await {
-            Ok(Ok(pkg)) => {
-                let mut response = LiNaProtocol::new();
-                response.status = pkg.status;
-                response.payload.identifier = pkg.content.identifier;
-                response.payload.ilen = response.payload.identifier.len() as u8;
-                response.payload.dlen = pkg.content.data.len() as u32;
-                response.payload.data = pkg.content.data;
-                // Calculate checksum after setting all the data
-                response.payload.checksum = response.calculate_checksum();
-                let resp_data = response.serialize_protocol_message();
-
-                if let Err(e) = stream.write_all(&resp_data).await {
-                    event!(tracing::Level::ERROR, "Error writing to stream: {}", e);
+        let (status, bytes) = match result {
+            Ok(mut pkg) => {
+                if federate
+                    && pkg.status == Status::FileNotFound
+                    && let Some(content) = crate::federation::lookup(&bucket, &key).await
+                {
+                    pkg.status = Status::Success;
+                    pkg.content.data = content;
                 }
+                if op == Op::Write && pkg.status == Status::Success {
+                    crate::report::note_write(&bucket, &key, &owner, &pkg.content.data).await;
+                }
+                // A deleted file no longer backs its bucket key
+                if op == Op::Delete && pkg.status == Status::Success
+                    && let Some(m) = crate::mapper::get_mapper()
+                    && let Err(e) = m.delete(&bucket, &key).await
+                {
+                    event!(
+                        Level::WARN,
+                        "[waitress {}] Failed to remove mapping {}/{}: {}",
+                        &log_id, &bucket, &key, e
+                    );
+                }
+                let (status, bytes) = (pkg.status.clone(), pkg.content.data.len());
+                write_package_response(
+                    &mut stream,
+                    pkg.status,
+                    pkg.content.identifier,
+                    pkg.content.data,
+                )
+                .await;
+                (status, bytes)
             }
-            Ok(Err(_)) => {
-                event!(
-                    Level::ERROR,
-                    "[waitress {}] Channel closed unexpectedly",
-                    &log_id
-                );
-                con_queue.unregister_waiter(uni_id);
-                con_queue.remove_order(uni_id);
-                write_error_response(&mut stream, &log_id, Status::InternalError, None).await;
-            }
-            Err(_) => {
-                event!(Level::ERROR, "[waitress {}] Timeout exceeded", &log_id);
-                con_queue.unregister_waiter(uni_id);
-                con_queue.remove_order(uni_id);
-                write_error_response(&mut stream, &log_id, Status::InternalError, None).await;
+            Err(status) => {
+                write_error_response(&mut stream, &log_id, status.clone(), None).await;
+                (status, 0)
             }
-        }
+        };
         event!(
             Level::INFO,
+            request_id = %log_id,
+            operation = ?op,
+            status = ?status,
+            bytes,
+            duration_ms = started.elapsed().as_secs_f64() * 1000.0,
             "[waitress {}] Handled request from {}",
             &log_id,
             peer_addr
@@ -554,14 +988,81 @@ async fn waitress<T: AsyncReadExt + AsyncWriteExt + Unpin + std::fmt::Debug>(
     }
 }
 
+/// Undo the mapping a write of `bucket`/`key` created for `internal_name`
+/// when the write was refused outright (such as an unmet precondition), so
+/// no key names an object that was never stored. A write that may still
+/// land, like one that timed out, keeps its mapping.
+async fn forget_failed_mapping(
+    mapper: &BucketMapper,
+    log_id: &str,
+    bucket: &str,
+    key: &str,
+    internal_name: &str,
+    status: &Status,
+) {
+    if !status.write_refused() {
+        return;
+    }
+    if let Err(e) = mapper.unregister(bucket, key, internal_name).await {
+        event!(
+            Level::WARN,
+            "[waitress {}] Failed to remove mapping {}/{}: {}",
+            log_id, bucket, key, e
+        );
+    }
+}
+
+/// Complete the TLS handshake when the front is configured for it, then
+/// hand the connection to a waitress.
+async fn serve_waitress(
+    stream: TcpStream,
+    addr: SocketAddr,
+    tls_acceptor: Option<tokio_rustls::TlsAcceptor>,
+) {
+    let Some(acceptor) = tls_acceptor else {
+        waitress(stream, addr, None).await;
+        return;
+    };
+    match tokio::time::timeout(tls::HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => {
+            let client_cert = tls::client_identity(stream.get_ref().1.peer_certificates());
+            waitress(stream, addr, client_cert).await;
+        }
+        Ok(Err(e)) => {
+            event!(Level::DEBUG, "TLS handshake with {} failed: {}", addr, e);
+        }
+        Err(_) => {
+            event!(Level::DEBUG, "TLS handshake with {} timed out", addr);
+        }
+    }
+}
+
 #[instrument(skip_all)]
-pub async fn run_advanced_server(addr: &str) {
+pub async fn run_advanced_server(addrs: &[String]) {
     event!(Level::INFO, "Waitress starting");
 
-    let listener = match TcpListener::bind(addr).await {
+    let env = vars::EnvVar::get_instance();
+    let tls_acceptor = match (&env.advanced_tls_cert, &env.advanced_tls_key) {
+        (Some(cert), Some(key)) => {
+            let client_ca = env.tls_client_ca.as_deref().map(Path::new);
+            match tls::load_acceptor(Path::new(cert), Path::new(key), client_ca, &[]) {
+                Ok(acceptor) => {
+                    event!(Level::INFO, "Advanced TLS enabled with certificate {}", cert);
+                    Some(acceptor)
+                }
+                Err(e) => {
+                    event!(Level::ERROR, "Failed to load TLS configuration: {}", e);
+                    return;
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let listener = match listen("advanced", addrs).await {
         Ok(listener) => listener,
         Err(err) => {
-            event!(Level::ERROR, "Failed to bind to address {}: {}", addr, err);
+            event!(Level::ERROR, "Failed to bind to address {}", err);
             return;
         }
     };
@@ -583,10 +1084,79 @@ pub async fn run_advanced_server(addr: &str) {
                     }
                 };
 
+                let tls_acceptor = tls_acceptor.clone();
+                let in_flight = shutdown_status.track();
                 tokio::task::spawn(async move {
-                    waitress(stream, addr).await;
+                    let _in_flight = in_flight;
+                    serve_waitress(stream, addr, tls_acceptor).await;
                 });
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_bucket_key() {
+        assert_eq!(
+            split_bucket_key(b"photos\0cat.png"),
+            ("photos".to_string(), "cat.png".to_string())
+        );
+        assert_eq!(
+            split_bucket_key(b"cat.png"),
+            (crate::mapper::DEFAULT_BUCKET.to_string(), "cat.png".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refused_write_unmaps_new_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let mapper = BucketMapper::new(&dir.path().join("mappings.db")).await.unwrap();
+
+        let (internal_name, created) = mapper.resolve_or_register("b", "new.txt").await.unwrap();
+        assert!(created);
+        forget_failed_mapping(&mapper, "test", "b", "new.txt", &internal_name, &Status::PreconditionFailed)
+            .await;
+        assert_eq!(mapper.resolve("b", "new.txt").await.unwrap(), None);
+
+        let (internal_name, _) = mapper.resolve_or_register("b", "new.txt").await.unwrap();
+        forget_failed_mapping(&mapper, "test", "b", "new.txt", &internal_name, &Status::Throttled)
+            .await;
+        assert_eq!(mapper.resolve("b", "new.txt").await.unwrap(), None);
+
+        // A write that timed out may still land, and a successful one
+        // obviously did, so both keep their mapping
+        let (internal_name, _) = mapper.resolve_or_register("b", "new.txt").await.unwrap();
+        for status in [Status::Timeout, Status::Success] {
+            forget_failed_mapping(&mapper, "test", "b", "new.txt", &internal_name, &status).await;
+            assert_eq!(
+                mapper.resolve_or_register("b", "new.txt").await.unwrap(),
+                (internal_name.clone(), false)
+            );
+        }
+    }
+
+    #[test]
+    fn test_batch_bucket() {
+        assert_eq!(batch_bucket(b"photos"), "photos");
+        assert_eq!(batch_bucket(b"photos\0ignored"), "photos");
+        assert_eq!(batch_bucket(b""), crate::mapper::DEFAULT_BUCKET);
+    }
+
+    #[test]
+    fn test_delete_bucket() {
+        let delete = FlagType::Delete as u8;
+        let pattern = delete | FlagType::Pattern as u8;
+        let batch = delete | FlagType::Batch as u8;
+        // A pattern without a bucket acts in the default bucket, not in one
+        // named after the pattern
+        assert_eq!(delete_bucket(pattern, b"*.log"), crate::mapper::DEFAULT_BUCKET);
+        assert_eq!(delete_bucket(pattern, b"logs\0*.log"), "logs");
+        assert_eq!(delete_bucket(delete, b"photos\0a.jpg"), "photos");
+        assert_eq!(delete_bucket(batch, b"photos"), "photos");
+        assert_eq!(delete_bucket(batch | FlagType::Pattern as u8, b"*.log"), "*.log");
+    }
+}
@@ -1,16 +1,51 @@
-use std::{path::Path, time::Duration};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use super::{
+    access_log::{AccessEntry, REQUEST_ID_HEADER},
+    manager::listen,
+    multipart,
+    rate_limit::{RateLimiter, retry_after_secs},
+    tls,
+};
 
 use crate::{
-    conveyer::ConveyQueue,
-    dtos::{Behavior, Package},
-    mapper,
+    admin,
+    auth::{
+        Action, HandshakeStatus, Principal, Role, get_auth_manager, get_handshake_rate_limiter,
+    },
+    conveyer::{ConveyQueue, RequestError, order_deadline},
+    dtos::{
+        Behavior, FileMetaDto, FlagType, MAX_BATCH_ITEMS, Package, ReadRange, Status,
+        encode_read_range,
+    },
+    mapper, metrics,
     shutdown::Shutdown,
+    vars::EnvVar,
 };
-use http_body_util::Full;
-use hyper::{Method, Request, Response, body::Bytes as HyperBytes, server::conn::http1, service::service_fn};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64_STANDARD};
 use bytes::Bytes;
-use hyper_util::rt::TokioIo;
-use tokio::net::TcpListener;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited, combinators::BoxBody};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Body, Bytes as HyperBytes, Frame, SizeHint},
+    header::HeaderValue,
+    service::service_fn,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+};
+use linabase::service::DataStream;
+use serde::{Deserialize, Serialize};
 use tracing::{Level, event, instrument};
 use uuid::Uuid;
 
@@ -34,176 +69,2215 @@ fn get_mime_type(filename: &str) -> &'static str {
     }
 }
 
+type HttpBody = BoxBody<Bytes, io::Error>;
+
+/// Response body fed from a [`DataStream`] as the store reads it, so a
+/// download holds a few chunks in memory rather than the whole file.
+struct ChunkBody {
+    stream: DataStream,
+}
+
+impl Body for ChunkBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        self.stream
+            .chunks
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|c| c.map(Frame::data)))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.stream.gzip {
+            SizeHint::default()
+        } else {
+            SizeHint::with_exact(self.stream.size)
+        }
+    }
+}
+
+fn boxed(resp: Response<Full<Bytes>>) -> Response<HttpBody> {
+    resp.map(|body| body.map_err(|never| match never {}).boxed())
+}
+
+const CORS_EXPOSED_HEADERS: &str = "Content-Length, Content-Encoding, ETag";
+
+/// Cross-origin policy built from `LINASTORE_CORS_*`.
+#[derive(Debug, Clone)]
+struct CorsPolicy {
+    origins: Vec<String>,
+    methods: String,
+    headers: String,
+    max_age: u64,
+}
+
+impl CorsPolicy {
+    fn from_env(env: &EnvVar) -> Self {
+        CorsPolicy {
+            origins: env.cors_origins.clone(),
+            methods: env.cors_methods.clone(),
+            headers: env.cors_headers.clone(),
+            max_age: env.cors_max_age,
+        }
+    }
+
+    /// Value for `Access-Control-Allow-Origin`, or `None` if `origin` is not allowed.
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        if self.origins.iter().any(|o| o == "*") {
+            return Some("*".to_string());
+        }
+        let origin = origin.trim_end_matches('/');
+        self.origins
+            .iter()
+            .find(|o| o.eq_ignore_ascii_case(origin))
+            .map(|_| origin.to_string())
+    }
+
+    fn allows_method(&self, method: &str) -> bool {
+        self.methods
+            .split(',')
+            .any(|m| m.trim().eq_ignore_ascii_case(method.trim()))
+    }
+
+    fn apply(&self, allowed_origin: &str, headers: &mut hyper::HeaderMap) {
+        if let Ok(value) = allowed_origin.parse() {
+            headers.insert(hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+        }
+        if allowed_origin != "*" {
+            headers.append(
+                hyper::header::VARY,
+                hyper::header::HeaderValue::from_static("Origin"),
+            );
+        }
+        headers.insert(
+            hyper::header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            hyper::header::HeaderValue::from_static(CORS_EXPOSED_HEADERS),
+        );
+    }
+
+    fn preflight(&self, allowed_origin: &str, requested_method: &str) -> Response<Full<Bytes>> {
+        if !self.allows_method(requested_method) {
+            return empty_response(StatusCode::FORBIDDEN);
+        }
+        let mut resp = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(
+                hyper::header::ACCESS_CONTROL_ALLOW_METHODS,
+                self.methods.as_str(),
+            )
+            .header(
+                hyper::header::ACCESS_CONTROL_ALLOW_HEADERS,
+                self.headers.as_str(),
+            )
+            .header(
+                hyper::header::ACCESS_CONTROL_MAX_AGE,
+                self.max_age.to_string(),
+            )
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        self.apply(allowed_origin, resp.headers_mut());
+        resp
+    }
+}
+
+/// Whether a MIME type benefits from on-the-fly compression.
+fn is_compressible(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json" | "application/javascript" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// Whether an `Accept-Encoding` header allows gzip (an explicit `q=0` refuses it).
+fn accepts_gzip(header: &str) -> bool {
+    header.split(',').any(|coding| {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        if !name.eq_ignore_ascii_case("gzip") && name != "*" {
+            return false;
+        }
+        parts
+            .filter_map(|p| p.strip_prefix("q="))
+            .all(|q| q.parse::<f32>().map(|q| q > 0.0).unwrap_or(false))
+    })
+}
+
+/// Decode `%XX` escapes, and `+` as a space when `plus_as_space` (query
+/// strings). `None` for malformed escapes or a result that isn't UTF-8.
+fn percent_decode(input: &str, plus_as_space: bool) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)?;
+                let hex = std::str::from_utf8(hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Split a raw request path into decoded `(bucket, key)`; a single segment
+/// addresses the default bucket. Keys may span several segments, so
+/// `/docs/2024/report.pdf` is key `2024/report.pdf` in bucket `docs`.
+fn split_path(path: &str) -> Option<(String, String)> {
+    let (bucket, key) = match path.split_once('/') {
+        Some((bucket, key)) => (percent_decode(bucket, false)?, key),
+        None => (mapper::DEFAULT_BUCKET.to_string(), path),
+    };
+    let key = percent_decode(key, false)?;
+    if bucket.is_empty() || bucket.contains('/') || key.is_empty() {
+        return None;
+    }
+    Some((bucket, key))
+}
+
+/// The bucket a file route addresses, for checking bucket grants. Blob
+/// fetches by hash and unparsable paths address none.
+fn request_bucket(path: &str, query: &str) -> Option<String> {
+    if path.starts_with(BLOB_PREFIX) {
+        return None;
+    }
+    match path.strip_prefix(API_FILES_PREFIX) {
+        Some("" | "/") => Some(parse_list_query(query).bucket),
+        Some(rest) => {
+            let rest = rest.strip_prefix('/')?;
+            split_path(rest.strip_suffix("/meta").unwrap_or(rest)).map(|(bucket, _)| bucket)
+        }
+        None => split_path(path).map(|(bucket, _)| bucket),
+    }
+}
+
+/// Whether the query asks for `?download=1` (or `true`/`yes`).
+fn wants_download(query: &str) -> bool {
+    query
+        .split('&')
+        .filter_map(|p| p.split_once('=').or(Some((p, ""))))
+        .any(|(name, value)| name == "download" && matches!(value, "" | "1" | "true" | "yes"))
+}
+
+/// `Content-Disposition: attachment` for the last segment of `key`, with an
+/// ASCII `filename` fallback and the exact name as RFC 5987 `filename*`.
+fn content_disposition(key: &str) -> String {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let fallback: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let mut encoded = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+fn text_response(status: StatusCode, body: &'static str) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .body(Full::new(HyperBytes::from(body)))
+        .unwrap()
+}
+
 async fn resolve_with_mapper(bucket: &str, key: &str) -> Result<String, Response<Full<Bytes>>> {
     match mapper::get_mapper() {
         Some(m) => match m.resolve(bucket, key).await {
             Ok(Some(internal)) => Ok(internal),
-            _ => Err(Response::builder()
-                .status(hyper::StatusCode::NOT_FOUND)
-                .body(Full::new(HyperBytes::from("Not Found")))
-                .unwrap()),
+            _ => Err(text_response(StatusCode::NOT_FOUND, "Not Found")),
         },
-        None => Err(Response::builder()
-            .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
-            .body(Full::new(HyperBytes::from("Mapper unavailable")))
-            .unwrap()),
+        None => Err(text_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Mapper unavailable",
+        )),
     }
 }
 
-#[instrument(skip_all)]
-async fn handle_http(
-    req: Request<hyper::body::Incoming>,
-) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
-    if req.method() != &Method::GET {
-        return Ok(Response::builder()
-            .status(hyper::StatusCode::METHOD_NOT_ALLOWED)
-            .body(Full::new(HyperBytes::from("Method Not Allowed")))?);
+/// Split the payload of `Authorization: Basic <base64(user:password)>`.
+fn parse_basic_credentials(encoded: &str) -> Option<(String, String)> {
+    let decoded = BASE64_STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// Who the request is authenticated as: the user named by the connection's
+/// client certificate, else from `X-API-Key: <key>`, `Authorization: Bearer
+/// <session token or key>` or `Basic <user:password>`; anyone is when
+/// authentication is off.
+async fn authenticate(
+    req: &Request<hyper::body::Incoming>,
+    peer: IpAddr,
+    client_cert: Option<&str>,
+) -> Option<Principal> {
+    let auth_manager = get_auth_manager();
+    if !auth_manager.is_password_enabled() {
+        return Some(Principal::anonymous());
+    }
+    if let Some(principal) = match client_cert {
+        Some(name) => auth_manager.principal_for_user(name).await,
+        None => None,
+    } {
+        return Some(principal);
     }
+    if let Some(key) = req.headers().get(API_KEY_HEADER) {
+        return auth_manager.validate_credential(key.to_str().ok()?.trim(), 0).await;
+    }
+    let header = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
 
-    let uri = req.uri().to_string();
-    let path = uri.strip_prefix('/').unwrap_or(&uri);
-    if path.is_empty() {
-        return Ok(Response::builder()
-            .status(hyper::StatusCode::OK)
-            .body(Full::new(HyperBytes::from("LiNastore is running")))?);
+    if let Some(token) = header.strip_prefix("Bearer ") {
+        let token = token.trim();
+        if token.is_empty() {
+            return None;
+        }
+        return auth_manager.validate_credential(token, 0).await;
     }
 
-    let path_vec: Vec<&str> = path.split('/').collect();
-    let file_identifier: String = if path_vec.len() >= 2 {
-        let bucket = path_vec[0];
-        let key = path_vec[1..].join("/");
-        match resolve_with_mapper(bucket, &key).await {
-            Ok(id) => id,
-            Err(resp) => return Ok(resp),
+    let (user, password) = parse_basic_credentials(header.strip_prefix("Basic ")?)?;
+    // Basic credentials are password guesses too, so they share the
+    // handshake's per-IP failure limit
+    let rate_limiter = get_handshake_rate_limiter();
+    if !rate_limiter.check(peer) {
+        return None;
+    }
+    if let Some(principal) = auth_manager.verify_credentials(&user, &password).await {
+        rate_limiter.record_success(peer);
+        Some(principal)
+    } else {
+        rate_limiter.record_failure(peer);
+        None
+    }
+}
+
+/// The credential an authenticated request carries, as its rate-limit key.
+fn credential_key(req: &Request<hyper::body::Incoming>) -> Option<String> {
+    if let Some(key) = req.headers().get(API_KEY_HEADER) {
+        return Some(key.to_str().ok()?.trim().to_string());
+    }
+    let header = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+    let (_, credential) = header.split_once(' ')?;
+    Some(credential.trim().to_string())
+}
+
+/// Per-IP and per-credential request limits from `LINASTORE_HTTP_*RATE*`.
+struct HttpRateLimits {
+    ip: Option<RateLimiter<IpAddr>>,
+    token: Option<RateLimiter<String>>,
+}
+
+fn rate_limits() -> &'static HttpRateLimits {
+    static LIMITS: OnceLock<HttpRateLimits> = OnceLock::new();
+    LIMITS.get_or_init(|| {
+        let env = EnvVar::get_instance();
+        let (ip_rate, token_rate) = (env.http_rate_limit_ip, env.http_rate_limit_token);
+        HttpRateLimits {
+            ip: (ip_rate > 0.0).then(|| RateLimiter::new(ip_rate, env.http_rate_burst)),
+            token: (token_rate > 0.0).then(|| RateLimiter::new(token_rate, env.http_rate_burst)),
         }
-    } else if path_vec.len() == 1 {
-        let key = path_vec[0];
-        match resolve_with_mapper(mapper::DEFAULT_BUCKET, key).await {
-            Ok(id) => id,
-            Err(resp) => return Ok(resp),
+    })
+}
+
+/// A `429` once the client is over its limit. Authenticated requests count
+/// against their credential's quota, all others against the client IP's.
+fn rate_limited(peer: IpAddr, credential: Option<String>) -> Option<Response<HttpBody>> {
+    let limits = rate_limits();
+    let (limited, counter) = match credential {
+        Some(credential) => (
+            limits.token.as_ref().map(|l| l.check(credential)),
+            &metrics::HTTP_RATE_LIMITED_TOKEN,
+        ),
+        None => (
+            limits.ip.as_ref().map(|l| l.check(peer)),
+            &metrics::HTTP_RATE_LIMITED_IP,
+        ),
+    };
+    match limited {
+        Some(Err(wait)) => {
+            counter.inc();
+            Some(too_many_requests(retry_after_secs(wait)))
+        }
+        _ => None,
+    }
+}
+
+fn too_many_requests(retry_after: u64) -> Response<HttpBody> {
+    let mut resp = boxed(text_response(
+        StatusCode::TOO_MANY_REQUESTS,
+        "Too Many Requests",
+    ));
+    resp.headers_mut()
+        .insert(hyper::header::RETRY_AFTER, retry_after.into());
+    resp
+}
+
+const LOGIN_PATH: &str = "api/login";
+const METRICS_PATH: &str = "metrics";
+
+/// Methods `path` answers to, for `Allow` headers and the `405` check.
+fn allowed_methods(path: &str) -> &'static str {
+    if path.is_empty() || path == UI_PATH || path == "ui/" || path.starts_with(BLOB_PREFIX) {
+        "GET, HEAD, OPTIONS"
+    } else if path == LOGIN_PATH {
+        "POST, OPTIONS"
+    } else if path == METRICS_PATH {
+        "GET, OPTIONS"
+    } else if path == API_FILES_PREFIX || path == "api/files/" {
+        "GET, POST, OPTIONS"
+    } else if let Some(endpoint) = path.strip_prefix(ADMIN_PREFIX) {
+        ADMIN_ENDPOINTS
+            .iter()
+            .find(|(name, _)| *name == endpoint)
+            .map_or("GET, OPTIONS", |(_, allow)| *allow)
+    } else if path.starts_with("api/files/") {
+        if path.ends_with("/meta") {
+            "GET, PUT, OPTIONS"
+        } else {
+            "PUT, OPTIONS"
         }
     } else {
-        return Ok(Response::builder()
-            .status(hyper::StatusCode::BAD_REQUEST)
-            .body(Full::new(HyperBytes::from("Invalid URL")))?);
+        "GET, HEAD, PUT, DELETE, OPTIONS"
+    }
+}
+
+fn method_allowed(allow: &str, method: &Method) -> bool {
+    allow.split(", ").any(|m| m == method.as_str())
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+struct LoginResponse {
+    token: String,
+    /// Unix timestamp in seconds.
+    expires_at: u64,
+}
+
+/// `POST /api/login` with `{"username", "password"}`: issue a session token
+/// usable as `Authorization: Bearer <token>` here and on the advanced service.
+async fn handle_login(
+    body: Bytes,
+    peer: IpAddr,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    let rate_limiter = get_handshake_rate_limiter();
+    if !rate_limiter.check(peer) {
+        return Ok(text_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many failed attempts",
+        ));
+    }
+    let Ok(login) = serde_json::from_slice::<LoginRequest>(&body) else {
+        return Ok(text_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid login request",
+        ));
     };
 
-    let log_id = Uuid::new_v4().to_string();
+    match get_auth_manager()
+        .handle_handshake(&login.username, &login.password)
+        .await
+    {
+        Ok((token, expires_at)) => {
+            rate_limiter.record_success(peer);
+            match serde_json::to_vec(&LoginResponse { token, expires_at }) {
+                Ok(body) => json_response(StatusCode::OK, body),
+                Err(_) => Ok(text_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to encode session",
+                )),
+            }
+        }
+        Err(HandshakeStatus::InvalidPassword) => {
+            rate_limiter.record_failure(peer);
+            unauthorized_response(false)
+        }
+        Err(HandshakeStatus::AuthDisabled) => Ok(text_response(
+            StatusCode::NOT_FOUND,
+            "Authentication is disabled",
+        )),
+        Err(_) => Ok(text_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to create session",
+        )),
+    }
+}
 
-    let uuid = Uuid::new_v4();
-    let uni_id = uuid.into_bytes();
-    let mut package = Package::new_with_id(&uuid);
-    package.behavior = Behavior::GetFile;
-    package.content.identifier = Bytes::copy_from_slice(file_identifier.as_bytes());
-
-    let con_queue = ConveyQueue::get_instance();
-    let receiver = match con_queue.register_waiter(uni_id) {
-        Some(rx) => rx,
-        None => {
-            event!(Level::ERROR, "Failed to register waiter for request");
-            return Ok(Response::builder()
-                .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Full::new(HyperBytes::from("Failed to process request")))?);
-        }
-    };
-
-    if let Err(e) = con_queue.produce_order(package) {
-        event!(Level::ERROR, "Failed to produce order: {}", e);
-        con_queue.unregister_waiter(uni_id);
-        return Ok(Response::builder()
-            .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
-            .body(Full::new(HyperBytes::from("Failed to process request")))?);
-    }
-
-    let timeout = Duration::from_secs(10);
-    match tokio::time::timeout(timeout, receiver).await {
-        Ok(Ok(pkg)) => {
-            let valid_data_end = pkg
-                .content
-                .identifier
-                .iter()
-                .position(|&b| b == 0)
-                .unwrap_or(pkg.content.identifier.len());
-
-            let content_type = get_mime_type(
-                &String::from_utf8_lossy(&pkg.content.identifier[..valid_data_end]).to_string(),
-            );
-            Ok(Response::builder()
-                .status(hyper::StatusCode::OK)
-                .header("X-Content-Type-Options", "nosniff")
-                .header("X-Frame-Options", "DENY")
-                .header("Content-Type", content_type)
-                .header("Content-Length", pkg.content.data.len().to_string())
-                .body(Full::new(Bytes::from(pkg.content.data)))?)
+const ADMIN_PREFIX: &str = "api/admin/";
+const MAX_ADMIN_BODY: usize = 4096;
+/// A scrub reads back the whole store, so it may take far longer than any
+/// single file order.
+const MAINTENANCE_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Endpoints under `/api/admin/` and the methods each answers to.
+const ADMIN_ENDPOINTS: &[(&str, &str)] = &[
+    ("stats", "GET, OPTIONS"),
+    ("sessions", "GET, OPTIONS"),
+    ("gc", "POST, OPTIONS"),
+    ("scrub", "POST, OPTIONS"),
+    ("report", "GET, OPTIONS"),
+    ("read-only", "PUT, OPTIONS"),
+    ("shutdown", "POST, OPTIONS"),
+];
+
+#[derive(Deserialize)]
+struct ReadOnlyRequest {
+    enabled: bool,
+}
+
+#[derive(Serialize)]
+struct ReadOnlyResponse {
+    read_only: bool,
+}
+
+fn admin_json<T: Serialize>(value: &T) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    match serde_json::to_vec(value) {
+        Ok(body) => json_response(StatusCode::OK, body),
+        Err(_) => Ok(text_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to encode response",
+        )),
+    }
+}
+
+/// `/api/admin/<endpoint>`, for a caller already known to be an admin.
+async fn handle_admin(
+    req: Request<hyper::body::Incoming>,
+    endpoint: &str,
+    principal: &Principal,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    match endpoint {
+        "stats" => admin_json(&admin::stats()),
+        "sessions" => match get_auth_manager().active_sessions().await {
+            Ok(sessions) => admin_json(&sessions),
+            Err(e) => {
+                event!(Level::ERROR, "{}", e);
+                Ok(text_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to list sessions",
+                ))
+            }
+        },
+        "gc" => run_maintenance(Behavior::CollectGarbage).await,
+        "scrub" => run_maintenance(Behavior::Scrub).await,
+        "report" => usage_report(req.uri().query().unwrap_or("")).await,
+        "read-only" => {
+            let body = match read_body(req, MAX_ADMIN_BODY).await {
+                Ok(body) => body,
+                Err(resp) => return Ok(resp),
+            };
+            let Ok(request) = serde_json::from_slice::<ReadOnlyRequest>(&body) else {
+                return Ok(text_response(
+                    StatusCode::BAD_REQUEST,
+                    "Invalid read-only request",
+                ));
+            };
+            if admin::set_read_only(request.enabled) != request.enabled {
+                event!(
+                    Level::WARN,
+                    "Read-only mode turned {} by {}",
+                    if request.enabled { "on" } else { "off" },
+                    principal.user_id
+                );
+            }
+            admin_json(&ReadOnlyResponse {
+                read_only: request.enabled,
+            })
         }
-        Ok(Err(_)) => {
+        "shutdown" => {
             event!(
-                Level::ERROR,
-                "[waitress {}] Channel closed unexpectedly",
-                &log_id
+                Level::WARN,
+                "Graceful shutdown requested by {}",
+                principal.user_id
             );
-            con_queue.unregister_waiter(uni_id);
-            con_queue.remove_order(uni_id);
-            Ok(Response::builder()
-                .status(hyper::StatusCode::INTERNAL_SERVER_ERROR)
-                .body(Full::new(Bytes::from("Channel closed unexpectedly")))?)
+            Shutdown::get_instance().shutdown();
+            Ok(empty_response(StatusCode::ACCEPTED))
+        }
+        _ => Ok(text_response(StatusCode::NOT_FOUND, "Not Found")),
+    }
+}
+
+/// Have the porter collect garbage or scrub, answering with its report.
+async fn run_maintenance(
+    behavior: Behavior,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    let mut package = Package::new_with_id(&Uuid::new_v4());
+    package.behavior = behavior;
+    package.deadline = Some(Instant::now() + MAINTENANCE_TIMEOUT);
+
+    match ConveyQueue::get_instance().request(package).await {
+        Ok(pkg) if pkg.status == Status::Success => {
+            json_response(StatusCode::OK, pkg.content.data.to_vec())
         }
-        Err(_) => {
-            event!(Level::ERROR, "[waitress {}] Timeout exceeded", &log_id);
-            con_queue.unregister_waiter(uni_id);
-            con_queue.remove_order(uni_id);
-            Ok(Response::builder()
-                .status(hyper::StatusCode::REQUEST_TIMEOUT)
-                .body(Full::new(Bytes::from("Request timeout")))?)
+        Ok(pkg) => Ok(status_response(pkg.status)),
+        Err(RequestError::Busy) => Ok(status_response(Status::Throttled)),
+        Err(e) => {
+            event!(Level::ERROR, "Maintenance request failed: {}", e);
+            Ok(status_response(Status::InternalError))
         }
     }
 }
 
-#[instrument(skip_all)]
-pub async fn run_http_server(addr: &str) {
-    event!(Level::INFO, "Self service starting");
+/// Usage by extension, bucket and owner with ingest over time; `query` may
+/// set `interval` and `since` (see [`crate::report::ReportOptions`]).
+async fn usage_report(query: &str) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let options = match crate::report::ReportOptions::from_query(query, now) {
+        Ok(options) => options,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Full::new(Bytes::from(e)));
+        }
+    };
+    let Some(m) = mapper::get_mapper() else {
+        return Ok(text_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Mapper unavailable",
+        ));
+    };
 
-    let listener = match TcpListener::bind(addr).await {
-        Ok(listener) => listener,
-        Err(_) => {
-            event!(Level::ERROR, "Failed to bind to address {}", addr);
-            return;
+    let mut package = Package::new_with_id(&Uuid::new_v4());
+    package.behavior = Behavior::Sizes;
+    package.deadline = Some(Instant::now() + MAINTENANCE_TIMEOUT);
+    let sizes = match ConveyQueue::get_instance().request(package).await {
+        Ok(pkg) if pkg.status == Status::Success => {
+            match serde_json::from_slice::<HashMap<String, u64>>(&pkg.content.data) {
+                Ok(sizes) => sizes,
+                Err(_) => {
+                    return Ok(text_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "Invalid sizes",
+                    ));
+                }
+            }
+        }
+        Ok(pkg) => return Ok(status_response(pkg.status)),
+        Err(RequestError::Busy) => return Ok(status_response(Status::Throttled)),
+        Err(e) => {
+            event!(Level::ERROR, "Listing sizes failed: {}", e);
+            return Ok(status_response(Status::InternalError));
         }
     };
 
-    let shutdown_status = Shutdown::get_instance();
+    match crate::report::build(&m, &sizes, options, now).await {
+        Ok(report) => admin_json(&report),
+        Err(e) => {
+            event!(Level::ERROR, "{}", e);
+            Ok(text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to build report",
+            ))
+        }
+    }
+}
 
-    loop {
-        tokio::select! {
-            _ = shutdown_status.wait() => {
-                break;
+const API_FILES_PREFIX: &str = "api/files";
+const UI_PATH: &str = "ui";
+const UI_PAGE: &str = include_str!("ui/index.html");
+const DEFAULT_LIST_LIMIT: usize = 50;
+
+/// Parameters of `GET /api/files`.
+#[derive(Debug, PartialEq)]
+struct ListQuery {
+    bucket: String,
+    pattern: String,
+    limit: usize,
+}
+
+fn parse_list_query(query: &str) -> ListQuery {
+    let mut list_query = ListQuery {
+        bucket: mapper::DEFAULT_BUCKET.to_string(),
+        pattern: "*".to_string(),
+        limit: DEFAULT_LIST_LIMIT,
+    };
+    for (name, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+        let Some(value) = percent_decode(value, true) else {
+            continue;
+        };
+        let value = value.as_str();
+        match name {
+            "bucket" if !value.is_empty() => list_query.bucket = value.to_string(),
+            "pattern" if !value.is_empty() => list_query.pattern = value.to_string(),
+            "limit" => {
+                if let Ok(limit) = value.parse::<usize>() {
+                    list_query.limit = limit.clamp(1, MAX_BATCH_ITEMS);
+                }
             }
-            accepted = listener.accept() => {
-                let (stream, _) = match accepted {
-                    Ok(req) => req,
-                    Err(_) => {
-                        event!(Level::ERROR, "Failed to accept connection");
-                        continue;
-                    }
-                };
+            _ => {}
+        }
+    }
+    list_query
+}
 
-                let io = TokioIo::new(stream);
+/// Present porter metadata under the public key instead of the internal name.
+fn public_meta(mut meta: FileMetaDto, key: &str) -> FileMetaDto {
+    meta.name = key.to_string();
+    meta.ext = Path::new(key)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_string();
+    meta
+}
 
-                tokio::task::spawn(async move {
-                    if let Err(err) = http1::Builder::new()
-                        .serve_connection(io, service_fn(handle_http))
-                        .await
-                    {
-                        event!(Level::ERROR, "Error serving connection: {:?}", err);
-                    }
-                });
+fn json_response(
+    status: StatusCode,
+    body: Vec<u8>,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Content-Length", body.len().to_string())
+        .body(Full::new(Bytes::from(body)))
+}
+
+/// `GET /api/files?bucket=&pattern=&limit=` and `GET /api/files/<bucket>/<key>/meta`.
+async fn handle_api(path: &str, query: &str) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    let rest = path.strip_prefix(API_FILES_PREFIX).unwrap_or("");
+
+    if rest.is_empty() || rest == "/" {
+        let list_query = parse_list_query(query);
+        let Some(m) = mapper::get_mapper() else {
+            return Ok(text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Mapper unavailable",
+            ));
+        };
+        let matched = match m
+            .match_keys(&list_query.bucket, &list_query.pattern, list_query.limit)
+            .await
+        {
+            Ok(matched) => matched,
+            Err(e) => {
+                event!(
+                    Level::ERROR,
+                    "Failed to list bucket {}: {}",
+                    &list_query.bucket,
+                    e
+                );
+                return Ok(text_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to list files",
+                ));
             }
+        };
+        if matched.is_empty() {
+            return json_response(StatusCode::OK, b"[]".to_vec());
         }
+
+        let names: Vec<&str> = matched
+            .iter()
+            .map(|(_, internal)| internal.as_str())
+            .collect();
+        let metas: Vec<FileMetaDto> = match process_through_queue_with_data(
+            Behavior::ListFiles,
+            "",
+            Bytes::from(names.join("\0")),
+        )
+        .await
+        {
+            Ok(pkg) => serde_json::from_slice(&pkg.content.data).unwrap_or_default(),
+            Err(status) => return Ok(status_response(status)),
+        };
+
+        let keys: HashMap<&str, &str> = matched
+            .iter()
+            .map(|(key, internal)| (internal.as_str(), key.as_str()))
+            .collect();
+        let listed: Vec<FileMetaDto> = metas
+            .into_iter()
+            .filter_map(|meta| {
+                let key = keys.get(meta.name.as_str())?;
+                Some(public_meta(meta, key))
+            })
+            .collect();
+        return match serde_json::to_vec(&listed) {
+            Ok(body) => json_response(StatusCode::OK, body),
+            Err(_) => Ok(text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to encode listing",
+            )),
+        };
+    }
+
+    let target = rest
+        .strip_prefix('/')
+        .and_then(|r| r.strip_suffix("/meta"))
+        .and_then(split_path);
+    let Some((bucket, key)) = target else {
+        return Ok(text_response(StatusCode::NOT_FOUND, "Not Found"));
+    };
+
+    let file_identifier = match resolve_with_mapper(&bucket, &key).await {
+        Ok(id) => id,
+        Err(resp) => return Ok(resp),
+    };
+    match process_through_queue(Behavior::StatFile, &file_identifier).await {
+        Ok(pkg) => match serde_json::from_slice::<FileMetaDto>(&pkg.content.data) {
+            Ok(meta) => match serde_json::to_vec(&public_meta(meta, &key)) {
+                Ok(body) => json_response(StatusCode::OK, body),
+                Err(_) => Ok(text_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to encode metadata",
+                )),
+            },
+            Err(_) => Ok(text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Invalid metadata",
+            )),
+        },
+        Err(status) => Ok(status_response(status)),
     }
 }
 
-#[cfg(test)]
-mod tests {
+/// `GET /ui`: the embedded single-page file browser.
+fn handle_ui() -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/html; charset=utf-8")
+        .header("Content-Length", UI_PAGE.len().to_string())
+        .header("Cache-Control", "no-cache")
+        .header("X-Content-Type-Options", "nosniff")
+        .header("X-Frame-Options", "DENY")
+        .header(
+            "Content-Security-Policy",
+            "default-src 'self'; script-src 'unsafe-inline'; style-src 'unsafe-inline'",
+        )
+        .body(Full::new(Bytes::from_static(UI_PAGE.as_bytes())))
+}
+
+/// Where a `PUT` stores its body: `/api/files/<bucket>/<key>` or the short
+/// `/<bucket>/<key>`.
+fn upload_target(path: &str) -> Option<(String, String)> {
+    match path.strip_prefix("api/files/") {
+        Some(rest) => split_path(rest),
+        None => split_path(path),
+    }
+}
+
+/// Store flags of an upload: `?cover=` and `?compress=`, or the
+/// `X-LiNa-Cover` and `X-LiNa-Compress` headers when the query leaves them
+/// out. Uploads cover and don't compress unless asked otherwise.
+fn upload_flags(query: &str, headers: &hyper::HeaderMap) -> Option<u8> {
+    let option = |param: &str, header: &str, default: bool| {
+        let raw = query_param(query, param).or_else(|| {
+            headers
+                .get(header)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        });
+        match raw {
+            Some(raw) => crate::vars::parse_truthy(&raw),
+            None => Some(default),
+        }
+    };
+    let mut flags = 0;
+    if option("cover", "x-lina-cover", true)? {
+        flags |= FlagType::Cover as u8;
+    }
+    if option("compress", "x-lina-compress", false)? {
+        flags |= FlagType::Compress as u8;
+    }
+    Some(flags)
+}
+
+/// `PUT /api/files/<bucket>/<key>` or `PUT /<bucket>/<key>`: store the body
+/// under the key and answer with its metadata.
+async fn handle_upload(
+    bucket: &str,
+    key: &str,
+    body: Bytes,
+    flags: u8,
+    owner: &str,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    match store_upload(bucket, key, body, flags, owner).await {
+        Ok(meta) => match serde_json::to_vec(&meta) {
+            Ok(body) => json_response(StatusCode::CREATED, body),
+            Err(_) => Ok(text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Invalid metadata",
+            )),
+        },
+        Err(resp) => Ok(resp),
+    }
+}
+
+/// `POST /api/files?bucket=<bucket>` with a `multipart/form-data` body:
+/// store every file part under its file name and answer with their
+/// metadata, in form order. Files before a failed one stay stored.
+async fn handle_form_upload(
+    bucket: &str,
+    content_type: &str,
+    body: Bytes,
+    flags: u8,
+    owner: &str,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    let Some(boundary) = multipart::boundary(content_type) else {
+        return Ok(text_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Expected multipart/form-data",
+        ));
+    };
+    let parts = match multipart::parse(&body, &boundary) {
+        Ok(parts) => parts,
+        Err(msg) => return Ok(text_response(StatusCode::BAD_REQUEST, msg)),
+    };
+    let files: Vec<_> = parts
+        .into_iter()
+        .filter_map(|part| Some((part.filename.filter(|f| !f.is_empty())?, part.data)))
+        .collect();
+    if files.is_empty() {
+        return Ok(text_response(StatusCode::BAD_REQUEST, "No files in form"));
+    }
+
+    let mut stored = Vec::with_capacity(files.len());
+    for (key, data) in files {
+        match store_upload(bucket, &key, data, flags, owner).await {
+            Ok(meta) => stored.push(meta),
+            Err(resp) => return Ok(resp),
+        }
+    }
+    match serde_json::to_vec(&stored) {
+        Ok(body) => json_response(StatusCode::CREATED, body),
+        Err(_) => Ok(text_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Invalid metadata",
+        )),
+    }
+}
+
+/// Store `body` under `bucket`/`key` with `flags` and return its public
+/// metadata.
+async fn store_upload(
+    bucket: &str,
+    key: &str,
+    body: Bytes,
+    flags: u8,
+    owner: &str,
+) -> Result<FileMetaDto, Response<Full<Bytes>>> {
+    let Some(m) = mapper::get_mapper() else {
+        return Err(text_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Mapper unavailable",
+        ));
+    };
+
+    let (file_identifier, created) = match m.resolve_or_register(bucket, key).await {
+        Ok(mapping) => mapping,
+        Err(e) => {
+            event!(Level::ERROR, "Failed to map {}/{}: {}", bucket, key, e);
+            return Err(text_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Mapper unavailable",
+            ));
+        }
+    };
+
+    match process_through_queue_with_flags(Behavior::PutFile, &file_identifier, body, flags).await
+    {
+        Ok(pkg) => crate::report::note_write(bucket, key, owner, &pkg.content.data).await,
+        Err(status) => {
+            // Don't leave a fresh key pointing at an object never stored
+            if created
+                && status.write_refused()
+                && let Err(e) = m.unregister(bucket, key, &file_identifier).await {
+                event!(Level::WARN, "Failed to remove mapping {}/{}: {}", bucket, key, e);
+            }
+            return Err(status_response(status));
+        }
+    }
+
+    match process_through_queue(Behavior::StatFile, &file_identifier).await {
+        Ok(pkg) => serde_json::from_slice::<FileMetaDto>(&pkg.content.data)
+            .map(|meta| public_meta(meta, key))
+            .map_err(|_| {
+                text_response(StatusCode::INTERNAL_SERVER_ERROR, "Invalid metadata")
+            }),
+        Err(status) => Err(status_response(status)),
+    }
+}
+
+async fn process_through_queue(behavior: Behavior, identifier: &str) -> Result<Package, Status> {
+    process_through_queue_with_data(behavior, identifier, Bytes::new()).await
+}
+
+async fn process_through_queue_with_data(
+    behavior: Behavior,
+    identifier: &str,
+    data: Bytes,
+) -> Result<Package, Status> {
+    process_through_queue_with_flags(behavior, identifier, data, 0).await
+}
+
+async fn process_through_queue_with_flags(
+    behavior: Behavior,
+    identifier: &str,
+    data: Bytes,
+    flags: u8,
+) -> Result<Package, Status> {
+    let uuid = Uuid::new_v4();
+    let mut package = Package::new_with_id(&uuid);
+    package.behavior = behavior;
+    package.content.identifier = Bytes::copy_from_slice(identifier.as_bytes());
+    package.content.data = data;
+    package.content.flags = flags;
+    package.deadline = Some(order_deadline(package.content.data.len()));
+
+    match ConveyQueue::get_instance()
+        .request(package)
+        .await
+    {
+        Ok(pkg) if pkg.status == Status::Success => Ok(pkg),
+        Ok(pkg) => Err(pkg.status),
+        Err(RequestError::Busy) => Err(Status::Throttled),
+        Err(RequestError::ReadOnly) => Err(Status::ReadOnly),
+        Err(RequestError::Timeout) => {
+            event!(Level::ERROR, "Timed out waiting for the porter");
+            Err(Status::Timeout)
+        }
+        Err(e) => {
+            event!(Level::ERROR, "Request failed: {}", e);
+            Err(Status::InternalError)
+        }
+    }
+}
+
+/// Largest `/api/login` body accepted.
+const MAX_LOGIN_BODY: usize = 4096;
+
+fn payload_too_large() -> Response<Full<Bytes>> {
+    // The rest of the body is never read, so the connection can't be reused
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .header(hyper::header::CONNECTION, "close")
+        .body(Full::new(HyperBytes::from("Payload Too Large")))
+        .unwrap()
+}
+
+/// Buffer a request body of at most `limit` bytes. A declared
+/// `Content-Length` over the limit is refused before anything is read;
+/// otherwise reading stops as soon as the limit is crossed.
+async fn read_body<B>(req: Request<B>, limit: usize) -> Result<Bytes, Response<Full<Bytes>>>
+where
+    B: hyper::body::Body<Data = Bytes>,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let declared = req
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if declared.is_some_and(|len| len > limit as u64) {
+        return Err(payload_too_large());
+    }
+
+    match Limited::new(req.into_body(), limit).collect().await {
+        Ok(collected) => Ok(collected.to_bytes()),
+        Err(e) if e.is::<LengthLimitError>() => Err(payload_too_large()),
+        Err(_) => Err(text_response(
+            StatusCode::BAD_REQUEST,
+            "Failed to read request body",
+        )),
+    }
+}
+
+/// `401`; `basic` challenges for Basic credentials so browsers prompt for
+/// them on plain file URLs, where no script can attach a bearer token.
+fn unauthorized_response(basic: bool) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    let challenge = if basic {
+        "Basic realm=\"LiNaStore\", charset=\"UTF-8\""
+    } else {
+        "Bearer"
+    };
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(hyper::header::WWW_AUTHENTICATE, challenge)
+        .body(Full::new(HyperBytes::from("Unauthorized")))
+}
+
+fn method_not_allowed(allow: &'static str) -> Result<Response<HttpBody>, hyper::http::Error> {
+    Response::builder()
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .header(hyper::header::ALLOW, allow)
+        .body(Full::new(HyperBytes::from("Method Not Allowed")))
+        .map(boxed)
+}
+
+fn empty_response(status: StatusCode) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .body(Full::new(Bytes::new()))
+        .unwrap()
+}
+
+fn status_response(status: Status) -> Response<Full<Bytes>> {
+    match status {
+        Status::FileNotFound => text_response(StatusCode::NOT_FOUND, "Not Found"),
+        Status::Timeout => text_response(StatusCode::GATEWAY_TIMEOUT, "Request timeout"),
+        Status::Throttled => {
+            let mut resp = text_response(StatusCode::SERVICE_UNAVAILABLE, "Server busy");
+            resp.headers_mut()
+                .insert(hyper::header::RETRY_AFTER, 1u16.into());
+            resp
+        }
+        Status::ReadOnly => text_response(StatusCode::SERVICE_UNAVAILABLE, "Server is read-only"),
+        _ => text_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to process request",
+        ),
+    }
+}
+
+/// Per-request switches for [`handle_get`].
+struct GetOptions<'a> {
+    gzip_ok: bool,
+    download: bool,
+    if_none_match: Option<&'a str>,
+    range: Option<&'a str>,
+    if_range: Option<&'a str>,
+}
+
+/// The range a `Range` header asks for, or `None` when the whole content
+/// should be served: multiple ranges and malformed headers land here too, as
+/// RFC 9110 allows.
+fn parse_byte_range(header: &str) -> Option<ReadRange> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.trim().split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    if first.is_empty() {
+        // Suffix range: the last `n` bytes
+        return last.parse::<u64>().ok().map(ReadRange::Last);
+    }
+    let offset = first.parse::<u64>().ok()?;
+    let len = if last.is_empty() {
+        u64::MAX
+    } else {
+        match last.parse::<u64>() {
+            Ok(end) if end >= offset => (end - offset).saturating_add(1),
+            _ => return None,
+        }
+    };
+    Some(ReadRange::From { offset, len })
+}
+
+/// Entity tag of a representation; gzip bodies differ byte-wise from the
+/// plain content, so they get their own tag.
+fn entity_tag(hash: &str, gzip: bool) -> String {
+    if gzip {
+        format!("\"{}-gzip\"", hash)
+    } else {
+        format!("\"{}\"", hash)
+    }
+}
+
+/// Weak comparison of an `If-None-Match` list against `etag`.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+fn with_cache_control(builder: hyper::http::response::Builder) -> hyper::http::response::Builder {
+    let env = EnvVar::get_instance();
+    if env.http_cache_control.is_empty() {
+        builder
+    } else {
+        builder.header(
+            hyper::header::CACHE_CONTROL,
+            env.http_cache_control.as_str(),
+        )
+    }
+}
+
+const BLOB_PREFIX: &str = "blob/";
+
+fn is_content_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Decoded value of the first `name=` parameter in a query string.
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .find(|(n, _)| *n == name)
+        .and_then(|(_, value)| percent_decode(value, true))
+}
+
+/// `GET`/`HEAD /blob/<hash>[?name=<file name>]`: content by BLAKE3 hash.
+/// The optional name only picks the `Content-Type` and download file name.
+async fn handle_blob(
+    hash: &str,
+    query: &str,
+    options: GetOptions<'_>,
+    head: bool,
+) -> Result<Response<HttpBody>, hyper::http::Error> {
+    if !is_content_hash(hash) {
+        return Ok(boxed(text_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid hash",
+        )));
+    }
+    let hash = hash.to_ascii_lowercase();
+    let pkg = match process_through_queue(Behavior::StreamBlob, &hash).await {
+        Ok(pkg) => pkg,
+        Err(status) => return Ok(boxed(status_response(status))),
+    };
+    let Some(stream) = ConveyQueue::get_instance().take_stream(pkg.uni_id) else {
+        return Ok(boxed(status_response(Status::InternalError)));
+    };
+
+    // The URL pins the content, so the response may be cached for good
+    let etag = entity_tag(&stream.hash256, false);
+    let cache_control = &EnvVar::get_instance().http_blob_cache_control;
+    let mut builder = Response::builder().header(hyper::header::ETAG, etag.as_str());
+    if !cache_control.is_empty() {
+        builder = builder.header(hyper::header::CACHE_CONTROL, cache_control.as_str());
+    }
+    if options
+        .if_none_match
+        .is_some_and(|tags| etag_matches(tags, &etag))
+    {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Full::new(Bytes::new()))
+            .map(boxed);
+    }
+
+    let name = query_param(query, "name").filter(|n| !n.is_empty());
+    let mut builder = builder
+        .status(StatusCode::OK)
+        .header("X-Content-Type-Options", "nosniff")
+        .header("X-Frame-Options", "DENY")
+        .header(
+            "Content-Type",
+            name.as_deref()
+                .map_or("application/octet-stream", get_mime_type),
+        )
+        .header("Content-Length", stream.size.to_string());
+    if options.download {
+        builder = builder.header(
+            hyper::header::CONTENT_DISPOSITION,
+            content_disposition(name.as_deref().unwrap_or(&hash)),
+        );
+    }
+    if head {
+        // Dropping the stream stops the reader
+        return builder.body(Full::new(Bytes::new())).map(boxed);
+    }
+    builder.body(ChunkBody { stream }.boxed())
+}
+
+async fn handle_get(
+    bucket: &str,
+    key: &str,
+    options: GetOptions<'_>,
+) -> Result<Response<HttpBody>, hyper::http::Error> {
+    let file_identifier = match resolve_with_mapper(bucket, key).await {
+        Ok(id) => id,
+        Err(resp) if resp.status() == StatusCode::NOT_FOUND => {
+            return from_peers(bucket, key, &options, resp).await;
+        }
+        Err(resp) => return Ok(boxed(resp)),
+    };
+
+    if let Some(range) = options.range.and_then(parse_byte_range)
+        && let Some(resp) = handle_range(&file_identifier, key, range, &options).await?
+    {
+        return Ok(resp);
+    }
+
+    let content_type = get_mime_type(key);
+    let (behavior, flags) = if options.gzip_ok {
+        // Ask for on-the-fly compression only where it pays off; sources
+        // stored compressed are served gzipped regardless.
+        let flags = if is_compressible(content_type) {
+            FlagType::Compress as u8
+        } else {
+            0
+        };
+        (Behavior::StreamFileGzip, flags)
+    } else {
+        (Behavior::StreamFile, 0)
+    };
+
+    let pkg =
+        match process_through_queue_with_flags(behavior, &file_identifier, Bytes::new(), flags)
+            .await
+        {
+            Ok(pkg) => pkg,
+            Err(Status::FileNotFound) => {
+                return from_peers(bucket, key, &options, status_response(Status::FileNotFound))
+                    .await;
+            }
+            Err(status) => return Ok(boxed(status_response(status))),
+        };
+    let Some(stream) = ConveyQueue::get_instance().take_stream(pkg.uni_id) else {
+        return Ok(boxed(status_response(Status::InternalError)));
+    };
+
+    let etag = entity_tag(&stream.hash256, stream.gzip);
+    let builder = with_cache_control(Response::builder())
+        .header(hyper::header::ETAG, etag.as_str())
+        .header(hyper::header::VARY, "Accept-Encoding");
+    if options
+        .if_none_match
+        .is_some_and(|tags| etag_matches(tags, &etag))
+    {
+        // Dropping the stream stops the reader
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Full::new(Bytes::new()))
+            .map(boxed);
+    }
+
+    let mut builder = builder
+        .status(StatusCode::OK)
+        .header("X-Content-Type-Options", "nosniff")
+        .header("X-Frame-Options", "DENY")
+        .header("Content-Type", content_type);
+    if options.download {
+        builder = builder.header(hyper::header::CONTENT_DISPOSITION, content_disposition(key));
+    }
+    builder = if stream.gzip {
+        builder.header(hyper::header::CONTENT_ENCODING, "gzip")
+    } else {
+        builder
+            .header("Content-Length", stream.size.to_string())
+            .header(hyper::header::ACCEPT_RANGES, "bytes")
+    };
+    builder.body(ChunkBody { stream }.boxed())
+}
+
+/// Answer a `Range` request for `file_identifier` with `206` and only the
+/// bytes asked for, streamed as they are read, or `416`. `None` when the
+/// whole file should be served instead: `If-Range` names another version,
+/// or the file can't be looked up here.
+async fn handle_range(
+    file_identifier: &str,
+    key: &str,
+    range: ReadRange,
+    options: &GetOptions<'_>,
+) -> Result<Option<Response<HttpBody>>, hyper::http::Error> {
+    let Ok(pkg) = process_through_queue_with_data(
+        Behavior::StreamFileRange,
+        file_identifier,
+        encode_read_range(range),
+    )
+    .await
+    else {
+        return Ok(None);
+    };
+    // Taken up front so that any early return drops it, which stops the reader
+    let stream = ConveyQueue::get_instance().take_stream(pkg.uni_id);
+    let Ok(meta) = serde_json::from_slice::<FileMetaDto>(&pkg.content.data) else {
+        return Ok(None);
+    };
+    let etag = entity_tag(&meta.hash, false);
+    let builder = with_cache_control(Response::builder())
+        .header(hyper::header::ETAG, etag.as_str())
+        .header(hyper::header::VARY, "Accept-Encoding");
+    if options
+        .if_none_match
+        .is_some_and(|tags| etag_matches(tags, &etag))
+    {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Full::new(Bytes::new()))
+            .map(|resp| Some(boxed(resp)));
+    }
+    // Only a strong match on the current tag keeps the range; dates never
+    // match, since no Last-Modified is sent
+    if options.if_range.is_some_and(|tag| tag.trim() != etag) {
+        return Ok(None);
+    }
+
+    let Some((start, len)) = range.resolve(meta.size) else {
+        return builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(hyper::header::CONTENT_RANGE, format!("bytes */{}", meta.size))
+            .body(Full::new(Bytes::new()))
+            .map(|resp| Some(boxed(resp)));
+    };
+    // Replaced between the lookup and the read
+    let Some(stream) = stream else {
+        return Ok(None);
+    };
+
+    let mut builder = builder
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header("X-Content-Type-Options", "nosniff")
+        .header("X-Frame-Options", "DENY")
+        .header("Content-Type", get_mime_type(key))
+        .header(hyper::header::ACCEPT_RANGES, "bytes")
+        .header(
+            hyper::header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, start + len - 1, meta.size),
+        )
+        .header("Content-Length", len.to_string());
+    if options.download {
+        builder = builder.header(hyper::header::CONTENT_DISPOSITION, content_disposition(key));
+    }
+    builder.body(ChunkBody { stream }.boxed()).map(Some)
+}
+
+/// Answer a local miss with what a federation peer holds, else `miss`.
+async fn from_peers(
+    bucket: &str,
+    key: &str,
+    options: &GetOptions<'_>,
+    miss: Response<Full<Bytes>>,
+) -> Result<Response<HttpBody>, hyper::http::Error> {
+    let Some(content) = crate::federation::lookup(bucket, key).await else {
+        return Ok(boxed(miss));
+    };
+    let mut builder = Response::builder()
+        .status(StatusCode::OK)
+        .header("X-Content-Type-Options", "nosniff")
+        .header("X-Frame-Options", "DENY")
+        .header("Content-Type", get_mime_type(key))
+        .header("Content-Length", content.len().to_string());
+    if options.download {
+        builder = builder.header(hyper::header::CONTENT_DISPOSITION, content_disposition(key));
+    }
+    builder.body(Full::new(content)).map(boxed)
+}
+
+/// Answer HEAD from a `StatFile` lookup so no file content is read.
+async fn handle_head(bucket: &str, key: &str) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    let file_identifier = match resolve_with_mapper(bucket, key).await {
+        Ok(id) => id,
+        Err(_) => return Ok(empty_response(StatusCode::NOT_FOUND)),
+    };
+
+    match process_through_queue(Behavior::StatFile, &file_identifier).await {
+        Ok(pkg) => match serde_json::from_slice::<FileMetaDto>(&pkg.content.data) {
+            Ok(meta) => with_cache_control(Response::builder())
+                .status(StatusCode::OK)
+                .header("X-Content-Type-Options", "nosniff")
+                .header("Content-Type", get_mime_type(key))
+                .header("Content-Length", meta.size.to_string())
+                .header(hyper::header::ACCEPT_RANGES, "bytes")
+                .header("ETag", entity_tag(&meta.hash, false))
+                .body(Full::new(Bytes::new())),
+            Err(_) => Ok(empty_response(StatusCode::INTERNAL_SERVER_ERROR)),
+        },
+        Err(status) => Ok(empty_response(status_response(status).status())),
+    }
+}
+
+async fn handle_delete(
+    bucket: &str,
+    key: &str,
+) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
+    let file_identifier = match resolve_with_mapper(bucket, key).await {
+        Ok(id) => id,
+        Err(resp) => return Ok(resp),
+    };
+
+    match process_through_queue(Behavior::DeleteFile, &file_identifier).await {
+        Ok(_) => {
+            if let Some(m) = mapper::get_mapper()
+                && let Err(e) = m.delete(bucket, key).await
+            {
+                event!(
+                    Level::WARN,
+                    "Failed to remove mapping {}/{}: {}",
+                    bucket,
+                    key,
+                    e
+                );
+            }
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Full::new(Bytes::new()))
+        }
+        Err(status) => Ok(status_response(status)),
+    }
+}
+
+/// Tag the response with the request id and write the access log line.
+async fn handle_logged(
+    req: Request<hyper::body::Incoming>,
+    peer: IpAddr,
+    client_cert: Option<Arc<str>>,
+) -> Result<Response<HttpBody>, hyper::http::Error> {
+    let started = Instant::now();
+    let entry = AccessEntry::new(&req, peer);
+    let mut resp = handle_http(req, peer, client_cert.as_deref()).await?;
+    resp.headers_mut()
+        .insert(REQUEST_ID_HEADER, entry.request_id.clone());
+    entry.log(
+        EnvVar::get_instance().http_access_log,
+        resp.status(),
+        resp.body().size_hint().exact(),
+        started.elapsed(),
+    );
+    Ok(resp)
+}
+
+#[instrument(skip_all, fields(method = %req.method(), path = %req.uri().path()))]
+async fn handle_http(
+    req: Request<hyper::body::Incoming>,
+    peer: IpAddr,
+    client_cert: Option<&str>,
+) -> Result<Response<HttpBody>, hyper::http::Error> {
+    metrics::HTTP_REQUESTS.inc();
+    let cors = CorsPolicy::from_env(&EnvVar::get_instance());
+    let allowed_origin = req
+        .headers()
+        .get(hyper::header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|origin| cors.allow_origin(origin));
+
+    if req.method() == Method::OPTIONS {
+        let path = req.uri().path();
+        let allow = allowed_methods(path.strip_prefix('/').unwrap_or(path));
+        let requested_method = req
+            .headers()
+            .get(hyper::header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|v| v.to_str().ok());
+        let mut resp = match (&allowed_origin, requested_method) {
+            (Some(origin), Some(requested)) => cors.preflight(origin, requested),
+            _ => empty_response(StatusCode::NO_CONTENT),
+        };
+        resp.headers_mut()
+            .insert(hyper::header::ALLOW, HeaderValue::from_static(allow));
+        return Ok(boxed(resp));
+    }
+
+    let mut resp = route_http(req, peer, client_cert).await?;
+    if let Some(origin) = &allowed_origin {
+        cors.apply(origin, resp.headers_mut());
+    }
+    Ok(resp)
+}
+
+async fn route_http(
+    req: Request<hyper::body::Incoming>,
+    peer: IpAddr,
+    client_cert: Option<&str>,
+) -> Result<Response<HttpBody>, hyper::http::Error> {
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let path = uri.path().strip_prefix('/').unwrap_or(uri.path());
+    let query = uri.query().unwrap_or("");
+
+    let allow = allowed_methods(path);
+    if !method_allowed(allow, &method) {
+        return method_not_allowed(allow);
+    }
+
+    if path.is_empty() {
+        return Response::builder()
+            .status(StatusCode::OK)
+            .body(Full::new(HyperBytes::from("LiNastore is running")))
+            .map(boxed);
+    }
+
+    // The page itself is public; its API calls carry the credentials
+    if path == UI_PATH || path == "ui/" {
+        return handle_ui().map(boxed);
+    }
+
+    if path == LOGIN_PATH {
+        let body = match read_body(req, MAX_LOGIN_BODY).await {
+            Ok(body) => body,
+            Err(resp) => return Ok(boxed(resp)),
+        };
+        return handle_login(body, peer).await.map(boxed);
+    }
+
+    let is_api = path == API_FILES_PREFIX || path.starts_with("api/files/");
+
+    let action = match method {
+        Method::PUT => Action::Write,
+        Method::POST if is_api => Action::Write,
+        Method::DELETE => Action::Delete,
+        _ => Action::Read,
+    };
+    let auth_manager = get_auth_manager();
+    let principal = authenticate(&req, peer, client_cert).await;
+    let credential = if principal.is_some() && auth_manager.is_password_enabled() {
+        credential_key(&req)
+    } else {
+        None
+    };
+    if let Some(resp) = rate_limited(peer, credential) {
+        return Ok(resp);
+    }
+    let Some(principal) = principal.or_else(|| auth_manager.anonymous(action)) else {
+        return unauthorized_response(!is_api).map(boxed);
+    };
+    if let Some(endpoint) = path.strip_prefix(ADMIN_PREFIX) {
+        if principal.role != Role::Admin {
+            return Ok(boxed(text_response(StatusCode::FORBIDDEN, "Forbidden")));
+        }
+        return handle_admin(req, endpoint, &principal).await.map(boxed);
+    }
+    if !principal.role.permits(action) {
+        return Ok(boxed(text_response(StatusCode::FORBIDDEN, "Forbidden")));
+    }
+    // Refused before any mapping changes
+    if action != Action::Read && admin::is_read_only() {
+        return Ok(boxed(status_response(Status::ReadOnly)));
+    }
+
+    if path == METRICS_PATH {
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Full::new(Bytes::from(metrics::render())))
+            .map(boxed);
+    }
+
+    if !principal.may_access(request_bucket(path, query).as_deref()) {
+        return Ok(boxed(text_response(StatusCode::FORBIDDEN, "Forbidden")));
+    }
+
+    if method == Method::PUT || method == Method::POST {
+        let Some(flags) = upload_flags(query, req.headers()) else {
+            return Ok(boxed(text_response(
+                StatusCode::BAD_REQUEST,
+                "cover and compress must be true or false",
+            )));
+        };
+        let content_type = req
+            .headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        let target = upload_target(path);
+        let bucket = parse_list_query(query).bucket;
+        let body = match read_body(req, EnvVar::get_instance().max_payload_size).await {
+            Ok(body) => body,
+            Err(resp) => return Ok(boxed(resp)),
+        };
+        if method == Method::POST {
+            return handle_form_upload(&bucket, &content_type, body, flags, &principal.user_id)
+                .await
+                .map(boxed);
+        }
+        let Some((bucket, key)) = target else {
+            return Ok(boxed(text_response(StatusCode::BAD_REQUEST, "Invalid URL")));
+        };
+        return handle_upload(&bucket, &key, body, flags, &principal.user_id)
+            .await
+            .map(boxed);
+    }
+
+    if method == Method::GET && is_api {
+        return handle_api(path, query).await.map(boxed);
+    }
+
+    if let Some(hash) = path.strip_prefix(BLOB_PREFIX) {
+        let options = GetOptions {
+            gzip_ok: false,
+            download: wants_download(query),
+            if_none_match: req
+                .headers()
+                .get(hyper::header::IF_NONE_MATCH)
+                .and_then(|v| v.to_str().ok()),
+            range: None,
+            if_range: None,
+        };
+        return handle_blob(hash, query, options, method == Method::HEAD).await;
+    }
+
+    let Some((bucket, key)) = split_path(path) else {
+        return Ok(boxed(text_response(StatusCode::BAD_REQUEST, "Invalid URL")));
+    };
+
+    if method == Method::DELETE {
+        return handle_delete(&bucket, &key).await.map(boxed);
+    }
+
+    if method == Method::HEAD {
+        return handle_head(&bucket, &key).await.map(boxed);
+    }
+
+    let gzip_ok = req
+        .headers()
+        .get(hyper::header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(accepts_gzip);
+    let options = GetOptions {
+        gzip_ok,
+        download: wants_download(query),
+        if_none_match: req
+            .headers()
+            .get(hyper::header::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok()),
+        range: req
+            .headers()
+            .get(hyper::header::RANGE)
+            .and_then(|v| v.to_str().ok()),
+        if_range: req
+            .headers()
+            .get(hyper::header::IF_RANGE)
+            .and_then(|v| v.to_str().ok()),
+    };
+    handle_get(&bucket, &key, options).await
+}
+
+/// Serve HTTP/1.1 or HTTP/2 on one connection. Over TLS the protocol was
+/// negotiated with ALPN; plain connections may open with the h2c preface.
+/// `client_cert` names the user a verified client certificate identified.
+/// On shutdown the connection finishes its current requests and then closes.
+async fn serve_connection<I>(io: I, peer: SocketAddr, client_cert: Option<Arc<str>>)
+where
+    I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let shutdown = Shutdown::get_instance();
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let conn = builder.serve_connection(
+        io,
+        service_fn(move |req| handle_logged(req, peer.ip(), client_cert.clone())),
+    );
+    tokio::pin!(conn);
+
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = shutdown.wait() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(err) = result {
+        event!(Level::ERROR, "Error serving connection: {:?}", err);
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn run_http_server(addrs: &[String]) {
+    event!(Level::INFO, "Self service starting");
+
+    let env = EnvVar::get_instance();
+    let tls_acceptor = match (&env.http_tls_cert, &env.http_tls_key) {
+        (Some(cert), Some(key)) => {
+            let client_ca = env.tls_client_ca.as_deref().map(Path::new);
+            let alpn: &[&[u8]] = &[b"h2", b"http/1.1"];
+            match tls::load_acceptor(Path::new(cert), Path::new(key), client_ca, alpn) {
+                Ok(acceptor) => {
+                    event!(Level::INFO, "HTTPS enabled with certificate {}", cert);
+                    Some(acceptor)
+                }
+                Err(e) => {
+                    event!(Level::ERROR, "Failed to load TLS configuration: {}", e);
+                    return;
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let listener = match listen("http", addrs).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            event!(Level::ERROR, "Failed to bind to address {}", err);
+            return;
+        }
+    };
+
+    let shutdown_status = Shutdown::get_instance();
+
+    loop {
+        tokio::select! {
+            _ = shutdown_status.wait() => {
+                break;
+            }
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(req) => req,
+                    Err(_) => {
+                        event!(Level::ERROR, "Failed to accept connection");
+                        continue;
+                    }
+                };
+
+                let tls_acceptor = tls_acceptor.clone();
+                let in_flight = shutdown_status.track();
+                tokio::task::spawn(async move {
+                    let _in_flight = in_flight;
+                    let Some(acceptor) = tls_acceptor else {
+                        serve_connection(TokioIo::new(stream), peer, None).await;
+                        return;
+                    };
+                    let handshake = acceptor.accept(stream);
+                    match tokio::time::timeout(tls::HANDSHAKE_TIMEOUT, handshake).await {
+                        Ok(Ok(stream)) => {
+                            let certs = stream.get_ref().1.peer_certificates();
+                            let client_cert = tls::client_identity(certs).map(Arc::from);
+                            serve_connection(TokioIo::new(stream), peer, client_cert).await
+                        }
+                        Ok(Err(e)) => {
+                            event!(Level::DEBUG, "TLS handshake with {} failed: {}", peer, e);
+                        }
+                        Err(_) => {
+                            event!(Level::DEBUG, "TLS handshake with {} timed out", peer);
+                        }
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(
+            split_path("photos/2024/cat.png"),
+            Some(("photos".to_string(), "2024/cat.png".to_string()))
+        );
+        assert_eq!(
+            split_path("cat.png"),
+            Some((mapper::DEFAULT_BUCKET.to_string(), "cat.png".to_string()))
+        );
+        assert_eq!(split_path(""), None);
+        assert_eq!(split_path("docs/"), None);
+        assert_eq!(split_path("/cat.png"), None);
+    }
+
+    #[test]
+    fn test_split_path_decodes() {
+        assert_eq!(
+            split_path("my%20docs/caf%C3%A9%20menu.txt"),
+            Some(("my docs".to_string(), "café menu.txt".to_string()))
+        );
+        assert_eq!(
+            split_path("docs/a%2Fb.txt"),
+            Some(("docs".to_string(), "a/b.txt".to_string()))
+        );
+        assert_eq!(
+            split_path("a+b.txt"),
+            Some((mapper::DEFAULT_BUCKET.to_string(), "a+b.txt".to_string()))
+        );
+        assert_eq!(split_path("a%2Fb/key"), None);
+        assert_eq!(split_path("docs/bad%zz"), None);
+        assert_eq!(split_path("docs/truncated%2"), None);
+        assert_eq!(split_path("docs/%FF"), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_body_limits() {
+        let req = Request::new(Full::new(Bytes::from_static(b"small")));
+        assert_eq!(read_body(req, 16).await.unwrap(), "small");
+
+        let req = Request::builder()
+            .header(hyper::header::CONTENT_LENGTH, "1000000")
+            .body(Full::new(Bytes::from_static(b"lying")))
+            .unwrap();
+        let resp = read_body(req, 16).await.unwrap_err();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(resp.headers()[hyper::header::CONNECTION], "close");
+
+        let req = Request::new(Full::new(Bytes::from(vec![0u8; 17])));
+        let resp = read_body(req, 16).await.unwrap_err();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = entity_tag("abc", false);
+        assert_eq!(etag, "\"abc\"");
+        assert!(etag_matches("\"abc\"", &etag));
+        assert!(etag_matches("\"zzz\", W/\"abc\"", &etag));
+        assert!(etag_matches("*", &etag));
+        assert!(!etag_matches("\"abc-gzip\"", &etag));
+        assert!(etag_matches("\"abc-gzip\"", &entity_tag("abc", true)));
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        assert_eq!(allowed_methods(""), "GET, HEAD, OPTIONS");
+        assert_eq!(allowed_methods("api/login"), "POST, OPTIONS");
+        assert_eq!(allowed_methods("api/files"), "GET, POST, OPTIONS");
+        assert_eq!(allowed_methods("metrics"), "GET, OPTIONS");
+        assert_eq!(allowed_methods("api/files/docs/a.txt"), "PUT, OPTIONS");
+        assert_eq!(
+            allowed_methods("api/files/docs/a.txt/meta"),
+            "GET, PUT, OPTIONS"
+        );
+        assert_eq!(
+            allowed_methods("docs/a.txt"),
+            "GET, HEAD, PUT, DELETE, OPTIONS"
+        );
+        assert_eq!(allowed_methods("api/admin/stats"), "GET, OPTIONS");
+        assert_eq!(allowed_methods("api/admin/scrub"), "POST, OPTIONS");
+        assert_eq!(allowed_methods("api/admin/read-only"), "PUT, OPTIONS");
+
+        assert!(method_allowed("GET, HEAD, OPTIONS", &Method::HEAD));
+        assert!(!method_allowed("GET, HEAD, OPTIONS", &Method::DELETE));
+        assert!(!method_allowed("GET, HEAD, OPTIONS", &Method::PATCH));
+    }
+
+    #[test]
+    fn test_parse_byte_range() {
+        // `(offset, len)` served of a 100-byte file; `Some(None)` is a 416
+        let range = |header| parse_byte_range(header).map(|r| r.resolve(100));
+        assert_eq!(range("bytes=0-9"), Some(Some((0, 10))));
+        assert_eq!(range("bytes=90-"), Some(Some((90, 10))));
+        assert_eq!(range("bytes=90-200"), Some(Some((90, 10))));
+        assert_eq!(range("bytes=-10"), Some(Some((90, 10))));
+        assert_eq!(range("bytes=-500"), Some(Some((0, 100))));
+        assert_eq!(range("bytes=0-18446744073709551615"), Some(Some((0, 100))));
+        assert_eq!(range("bytes=100-"), Some(None));
+        assert_eq!(range("bytes=-0"), Some(None));
+        assert_eq!(parse_byte_range("bytes=0-").map(|r| r.resolve(0)), Some(None));
+        assert_eq!(range("bytes=0-1,5-6"), None);
+        assert_eq!(range("bytes=9-1"), None);
+        assert_eq!(range("items=0-1"), None);
+        assert_eq!(range("bytes=x-"), None);
+    }
+
+    #[test]
+    fn test_upload_target() {
+        assert_eq!(
+            upload_target("api/files/docs/a/b.txt"),
+            Some(("docs".to_string(), "a/b.txt".to_string()))
+        );
+        assert_eq!(
+            upload_target("docs/a.txt"),
+            Some(("docs".to_string(), "a.txt".to_string()))
+        );
+        assert_eq!(
+            upload_target("a.txt"),
+            Some((mapper::DEFAULT_BUCKET.to_string(), "a.txt".to_string()))
+        );
+        assert_eq!(upload_target("docs/"), None);
+    }
+
+    #[test]
+    fn test_upload_flags() {
+        let cover = FlagType::Cover as u8;
+        let compress = FlagType::Compress as u8;
+        let mut headers = hyper::HeaderMap::new();
+        assert_eq!(upload_flags("", &headers), Some(cover));
+        assert_eq!(upload_flags("cover=false&compress=1", &headers), Some(compress));
+        headers.insert("x-lina-compress", HeaderValue::from_static("true"));
+        assert_eq!(upload_flags("", &headers), Some(cover | compress));
+        // The query wins over the header
+        assert_eq!(upload_flags("compress=no", &headers), Some(cover));
+        assert_eq!(upload_flags("cover=maybe", &headers), None);
+    }
+
+    #[test]
+    fn test_blob_helpers() {
+        assert!(is_content_hash(&"ab".repeat(32)));
+        assert!(is_content_hash(&"AB".repeat(32)));
+        assert!(!is_content_hash(&"ab".repeat(31)));
+        assert!(!is_content_hash(&"zz".repeat(32)));
+        assert_eq!(
+            query_param("download=1&name=my%20cat.png", "name"),
+            Some("my cat.png".to_string())
+        );
+        assert_eq!(query_param("download=1", "name"), None);
+    }
+
+    #[test]
+    fn test_request_bucket() {
+        assert_eq!(request_bucket("docs/a/b.txt", ""), Some("docs".to_string()));
+        assert_eq!(
+            request_bucket("cat.png", ""),
+            Some(mapper::DEFAULT_BUCKET.to_string())
+        );
+        assert_eq!(
+            request_bucket("api/files/docs/a.txt/meta", ""),
+            Some("docs".to_string())
+        );
+        assert_eq!(request_bucket("api/files/docs/a.txt", ""), Some("docs".to_string()));
+        assert_eq!(
+            request_bucket("api/files", "bucket=photos&limit=5"),
+            Some("photos".to_string())
+        );
+        assert_eq!(request_bucket(&format!("{}abc", BLOB_PREFIX), ""), None);
+    }
+
+    #[test]
+    fn test_wants_download() {
+        assert!(wants_download("download=1"));
+        assert!(wants_download("x=2&download"));
+        assert!(wants_download("download=true"));
+        assert!(!wants_download("download=0"));
+        assert!(!wants_download("downloads=1"));
+        assert!(!wants_download(""));
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("2024/report.pdf"),
+            "attachment; filename=\"report.pdf\"; filename*=UTF-8''report.pdf"
+        );
+        assert_eq!(
+            content_disposition("café \"menu\".txt"),
+            "attachment; filename=\"caf_ _menu_.txt\"; filename*=UTF-8''caf%C3%A9%20%22menu%22.txt"
+        );
+    }
+
+    #[test]
+    fn test_percent_decode_query() {
+        assert_eq!(
+            percent_decode("my+file%2A", true),
+            Some("my file*".to_string())
+        );
+        assert_eq!(
+            percent_decode("my+file", false),
+            Some("my+file".to_string())
+        );
+    }
+
+    fn cors(origins: &[&str]) -> CorsPolicy {
+        CorsPolicy {
+            origins: origins.iter().map(|o| o.to_string()).collect(),
+            methods: "GET, HEAD".to_string(),
+            headers: "Authorization".to_string(),
+            max_age: 60,
+        }
+    }
+
+    #[test]
+    fn test_cors_allow_origin() {
+        let policy = cors(&["https://app.example.com"]);
+        assert_eq!(
+            policy.allow_origin("https://app.example.com"),
+            Some("https://app.example.com".to_string())
+        );
+        assert_eq!(policy.allow_origin("https://evil.example.com"), None);
+        assert_eq!(cors(&[]).allow_origin("https://app.example.com"), None);
+        assert_eq!(
+            cors(&["*"]).allow_origin("https://any.example.com"),
+            Some("*".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cors_preflight() {
+        let policy = cors(&["https://app.example.com"]);
+        let resp = policy.preflight("https://app.example.com", "get");
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let headers = resp.headers();
+        assert_eq!(
+            headers[hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(
+            headers[hyper::header::ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, HEAD"
+        );
+        assert_eq!(headers[hyper::header::ACCESS_CONTROL_MAX_AGE], "60");
+        assert_eq!(headers[hyper::header::VARY], "Origin");
+
+        let resp = policy.preflight("https://app.example.com", "DELETE");
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_body_streams_in_order() {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let body = ChunkBody {
+            stream: DataStream {
+                size: 10,
+                hash256: String::new(),
+                gzip: false,
+                chunks: rx,
+            },
+        };
+        assert_eq!(hyper::body::Body::size_hint(&body).exact(), Some(10));
+
+        tokio::spawn(async move {
+            tx.send(Ok(Bytes::from_static(b"hello "))).await.unwrap();
+            tx.send(Ok(Bytes::from_static(b"lina"))).await.unwrap();
+        });
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(&collected[..], b"hello lina");
+    }
+
+    #[tokio::test]
+    async fn test_chunk_body_propagates_errors() {
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        tx.send(Ok(Bytes::from_static(b"partial"))).await.unwrap();
+        tx.send(Err(io::Error::other("integrity"))).await.unwrap();
+        drop(tx);
+        let body = ChunkBody {
+            stream: DataStream {
+                size: 100,
+                hash256: String::new(),
+                gzip: true,
+                chunks: rx,
+            },
+        };
+        assert_eq!(hyper::body::Body::size_hint(&body).exact(), None);
+        assert!(body.collect().await.is_err());
+    }
+
+    #[test]
+    fn test_parse_basic_credentials() {
+        let encoded = BASE64_STANDARD.encode("admin:s3cret:with-colon");
+        assert_eq!(
+            parse_basic_credentials(&encoded),
+            Some(("admin".to_string(), "s3cret:with-colon".to_string()))
+        );
+        assert_eq!(
+            parse_basic_credentials(&BASE64_STANDARD.encode("no-colon")),
+            None
+        );
+        assert_eq!(parse_basic_credentials("!!not base64!!"), None);
+    }
+
+    #[test]
+    fn test_unauthorized_challenge() {
+        let resp = unauthorized_response(true).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(
+            resp.headers()[hyper::header::WWW_AUTHENTICATE]
+                .to_str()
+                .unwrap()
+                .starts_with("Basic ")
+        );
+        let resp = unauthorized_response(false).unwrap();
+        assert_eq!(resp.headers()[hyper::header::WWW_AUTHENTICATE], "Bearer");
+    }
+
+    #[test]
+    fn test_ui_page() {
+        let resp = handle_ui().unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["Content-Type"], "text/html; charset=utf-8");
+        assert!(UI_PAGE.contains("/api/files"));
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(accepts_gzip("gzip"));
+        assert!(accepts_gzip("deflate, gzip;q=0.5, br"));
+        assert!(accepts_gzip("GZIP"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip("gzip;q=0"));
+        assert!(!accepts_gzip("deflate, br"));
+        assert!(!accepts_gzip(""));
+    }
+
+    #[test]
+    fn test_is_compressible() {
+        assert!(is_compressible("text/plain"));
+        assert!(is_compressible("application/json"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/octet-stream"));
+    }
+
+    #[test]
+    fn test_parse_list_query() {
+        assert_eq!(
+            parse_list_query(""),
+            ListQuery {
+                bucket: mapper::DEFAULT_BUCKET.to_string(),
+                pattern: "*".to_string(),
+                limit: DEFAULT_LIST_LIMIT,
+            }
+        );
+        assert_eq!(
+            parse_list_query("bucket=photos&pattern=*.png&limit=5000"),
+            ListQuery {
+                bucket: "photos".to_string(),
+                pattern: "*.png".to_string(),
+                limit: MAX_BATCH_ITEMS,
+            }
+        );
+        assert_eq!(parse_list_query("limit=abc").limit, DEFAULT_LIST_LIMIT);
+        assert_eq!(
+            parse_list_query("bucket=my%20docs&pattern=%2A.txt").bucket,
+            "my docs"
+        );
+        assert_eq!(parse_list_query("pattern=%2A.txt").pattern, "*.txt");
+    }
+
+    #[test]
+    fn test_public_meta_uses_key() {
+        let meta = FileMetaDto {
+            name: "2f1c6c1e-internal".to_string(),
+            size: 1,
+            ext: String::new(),
+            hash: String::new(),
+            compressed: false,
+            created_at: String::new(),
+            updated_at: String::new(),
+        };
+        let meta = public_meta(meta, "docs/readme.md");
+        assert_eq!(meta.name, "docs/readme.md");
+        assert_eq!(meta.ext, "md");
+    }
+
+    #[test]
+    fn test_status_response_codes() {
+        assert_eq!(
+            status_response(Status::FileNotFound).status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status_response(Status::Timeout).status(),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert_eq!(
+            status_response(Status::StoreFailed).status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            status_response(Status::ReadOnly).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
 
     #[test]
     fn test_string_to_static_bytes_array() {
@@ -1,37 +1,79 @@
+use std::{io, net::SocketAddr, task::Poll};
+
+use tokio::net::{TcpListener, TcpStream};
 use tracing::{event, instrument};
 
-use crate::vars;
+use crate::{systemd, vars};
 
 use super::advanced_service::run_advanced_server;
 use super::http_service::run_http_server;
 use super::s3_service::run_s3_server;
 
+/// The sockets one service listens on.
+pub(super) struct Listeners(Vec<TcpListener>);
+
+impl Listeners {
+    /// Accept the next connection on any of the sockets.
+    pub(super) async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        std::future::poll_fn(|cx| {
+            for listener in &self.0 {
+                if let Poll::Ready(accepted) = listener.poll_accept(cx) {
+                    return Poll::Ready(accepted);
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Listen on each of `addrs`, or on the sockets systemd passed for `front`.
+pub(super) async fn listen(front: &str, addrs: &[String]) -> io::Result<Listeners> {
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        let listener = match systemd::take_listener(front, addr) {
+            Some(listener) => {
+                event!(
+                    tracing::Level::INFO,
+                    "Using the {} socket passed by systemd on {}",
+                    front,
+                    listener.local_addr()?
+                );
+                TcpListener::from_std(listener)?
+            }
+            None => TcpListener::bind(addr)
+                .await
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", addr, e)))?,
+        };
+        event!(
+            tracing::Level::INFO,
+            "The {} service listens on {}",
+            front,
+            addr
+        );
+        listeners.push(listener);
+    }
+    systemd::started();
+    Ok(Listeners(listeners))
+}
+
 #[instrument(skip_all)]
 pub async fn front() {
     event!(tracing::Level::INFO, "Front started");
 
     let envars = vars::EnvVar::get_instance();
 
-    let ip = envars.ip_address.clone();
-    let http_port = envars.http_port.clone();
-    let advanced_port = envars.advanced_port.clone();
-    let s3_port = envars.s3_port.clone();
-
-    let http_addr = format!("{}:{}", ip, http_port);
-    let advanced_addr = format!("{}:{}", ip, advanced_port);
-    let s3_addr = format!("{}:{}", ip, s3_port);
-
     tokio::join!(
         async {
-            run_http_server(&http_addr).await;
+            run_http_server(&envars.http_addrs).await;
             event!(tracing::Level::WARN, "HTTP server exited");
         },
         async {
-            run_advanced_server(&advanced_addr).await;
+            run_advanced_server(&envars.advanced_addrs).await;
             event!(tracing::Level::WARN, "Advanced server exited");
         },
         async {
-            run_s3_server(&s3_addr).await;
+            run_s3_server(&envars.s3_addrs).await;
             event!(tracing::Level::WARN, "S3 server exited");
         },
     );
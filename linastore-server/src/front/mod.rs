@@ -1,6 +1,10 @@
+mod access_log;
 mod advanced_service;
 mod http_service;
 mod manager;
+mod multipart;
+mod rate_limit;
 mod s3_service;
+mod tls;
 
 pub use manager::front;
@@ -0,0 +1,146 @@
+use bytes::Bytes;
+
+/// One part of a `multipart/form-data` body.
+#[derive(Debug, PartialEq)]
+pub(super) struct Part {
+    /// The form field the part belongs to.
+    pub name: Option<String>,
+    /// The uploaded file's name; form fields other than files have none.
+    pub filename: Option<String>,
+    pub data: Bytes,
+}
+
+/// The boundary of a `multipart/form-data` `Content-Type`, if it is one.
+pub(super) fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params
+        .filter_map(|p| p.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| unquote(value.trim()).to_string())
+        .filter(|b| !b.is_empty() && b.len() <= 70)
+}
+
+/// Split `body` into its parts (RFC 7578). Part contents are slices of
+/// `body`, so nothing is copied.
+pub(super) fn parse(body: &Bytes, boundary: &str) -> Result<Vec<Part>, &'static str> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let mut pos = find(body, delimiter, 0).ok_or("Missing multipart boundary")? + delimiter.len();
+    let mut parts = Vec::new();
+    loop {
+        if body[pos..].starts_with(b"--") {
+            return Ok(parts);
+        }
+        if !body[pos..].starts_with(b"\r\n") {
+            return Err("Malformed multipart boundary");
+        }
+        pos += 2;
+        let header_end = find(body, b"\r\n\r\n", pos).ok_or("Malformed multipart headers")?;
+        let headers = std::str::from_utf8(&body[pos..header_end])
+            .map_err(|_| "Malformed multipart headers")?;
+        let data_start = header_end + 4;
+        let mut close = Vec::with_capacity(delimiter.len() + 2);
+        close.extend_from_slice(b"\r\n");
+        close.extend_from_slice(delimiter);
+        let data_end = find(body, &close, data_start).ok_or("Unterminated multipart body")?;
+
+        let (name, filename) = headers
+            .split("\r\n")
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-disposition"))
+            .map(|(_, value)| disposition_names(value))
+            .ok_or("Multipart part without Content-Disposition")?;
+        parts.push(Part {
+            name,
+            filename,
+            data: body.slice(data_start..data_end),
+        });
+        pos = data_end + close.len();
+    }
+}
+
+/// `name` and `filename` of a `form-data` `Content-Disposition` value.
+fn disposition_names(value: &str) -> (Option<String>, Option<String>) {
+    let mut name = None;
+    let mut filename = None;
+    for (key, value) in value.split(';').skip(1).filter_map(|p| p.split_once('=')) {
+        let value = unquote(value.trim()).to_string();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "name" => name = Some(value),
+            "filename" => filename = Some(value),
+            _ => {}
+        }
+    }
+    (name, filename)
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| from + i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("application/octet-stream; boundary=abc"), None);
+    }
+
+    #[test]
+    fn test_parse_parts() {
+        let body = Bytes::from_static(
+            b"preamble\r\n--xyz\r\n\
+              Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+              hello\r\n--xyz\r\n\
+              Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+              Content-Type: text/plain\r\n\r\n\
+              line 1\r\nline 2\r\n--xyz--\r\n",
+        );
+        let parts = parse(&body, "xyz").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name.as_deref(), Some("note"));
+        assert_eq!(parts[0].filename, None);
+        assert_eq!(&parts[0].data[..], b"hello");
+        assert_eq!(parts[1].filename.as_deref(), Some("a.txt"));
+        assert_eq!(&parts[1].data[..], b"line 1\r\nline 2");
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        assert!(parse(&Bytes::from_static(b"no boundary here"), "xyz").is_err());
+        let unterminated =
+            Bytes::from_static(b"--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\ndata");
+        assert!(parse(&unterminated, "xyz").is_err());
+        let no_disposition =
+            Bytes::from_static(b"--xyz\r\nContent-Type: text/plain\r\n\r\nx\r\n--xyz--");
+        assert!(parse(&no_disposition, "xyz").is_err());
+    }
+}
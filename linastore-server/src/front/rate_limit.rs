@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Token-bucket rate limiter keyed by client.
+///
+/// Each key starts with `burst` tokens and earns `rate` tokens per second up
+/// to `burst`; a request spends one token. Buckets that have refilled
+/// completely carry no state, so they are swept once the map grows past
+/// `gc_threshold`.
+#[derive(Debug)]
+pub(super) struct RateLimiter<K> {
+    rate: f64,
+    burst: f64,
+    gc_threshold: usize,
+    state: Mutex<HashMap<K, Bucket>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    /// `rate` requests per second with bursts of up to `burst` requests.
+    pub(super) fn new(rate: f64, burst: u32) -> Self {
+        Self::with_gc_threshold(rate, burst, 4096)
+    }
+
+    pub(super) fn with_gc_threshold(rate: f64, burst: u32, gc_threshold: usize) -> Self {
+        Self {
+            rate,
+            burst: f64::from(burst.max(1)),
+            gc_threshold,
+            state: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Spend a token for `key`, or return how long until one is available.
+    pub(super) fn check(&self, key: K) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let mut guard = match self.state.lock() {
+            Ok(g) => g,
+            Err(_) => return Ok(()), // poisoned: fail-open rather than DoS ourselves
+        };
+
+        if guard.len() > self.gc_threshold {
+            let (rate, burst) = (self.rate, self.burst);
+            guard.retain(|_, b| refill(b, rate, burst, now) < burst);
+        }
+
+        let bucket = guard.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let tokens = refill(bucket, self.rate, self.burst, now);
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            bucket.updated = now;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - tokens) / self.rate))
+        }
+    }
+
+    #[cfg(test)]
+    fn tracked_key_count(&self) -> usize {
+        self.state.lock().map(|g| g.len()).unwrap_or(0)
+    }
+}
+
+/// Tokens in `bucket` at `now`, without updating it.
+fn refill(bucket: &Bucket, rate: f64, burst: f64, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * rate).min(burst)
+}
+
+/// Whole seconds for a `Retry-After` header, rounded up.
+pub(super) fn retry_after_secs(wait: Duration) -> u64 {
+    wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_refill() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at("a", start).is_ok());
+        }
+        let wait = limiter.check_at("a", start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        // Other clients have their own bucket
+        assert!(limiter.check_at("b", start).is_ok());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_at("a", later).is_ok());
+        assert!(limiter.check_at("a", later).is_err());
+    }
+
+    #[test]
+    fn test_gc_drops_full_buckets() {
+        let limiter = RateLimiter::with_gc_threshold(1.0, 1, 2);
+        let start = Instant::now();
+        for key in 0..3 {
+            limiter.check_at(key, start).unwrap();
+        }
+        assert_eq!(limiter.tracked_key_count(), 3);
+
+        limiter
+            .check_at(99, start + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(limiter.tracked_key_count(), 1);
+    }
+
+    #[test]
+    fn test_retry_after_rounds_up() {
+        assert_eq!(retry_after_secs(Duration::from_millis(1)), 1);
+        assert_eq!(retry_after_secs(Duration::from_secs(2)), 2);
+        assert_eq!(retry_after_secs(Duration::from_millis(2500)), 3);
+    }
+}
@@ -1,8 +1,9 @@
-use std::time::Duration;
+use std::sync::Arc;
 
+use super::manager::listen;
 use crate::{
-    conveyer::ConveyQueue,
-    dtos::{Behavior, Package, Status},
+    conveyer::{ConveyQueue, RequestError, order_deadline},
+    dtos::{Behavior, FileMetaDto, Package, Status},
     mapper,
     shutdown::Shutdown,
 };
@@ -10,7 +11,6 @@ use bytes::Bytes;
 use http_body_util::{BodyExt, Full};
 use hyper::{Method, Request, Response, StatusCode, server::conn::http1, service::service_fn};
 use hyper_util::rt::TokioIo;
-use tokio::net::TcpListener;
 use tracing::{Level, event, instrument};
 use uuid::Uuid;
 
@@ -98,6 +98,18 @@ fn build_response(status: StatusCode, body: String, content_type: &str) -> Respo
         .unwrap()
 }
 
+/// `503 SlowDown`, which S3 clients retry with backoff.
+fn slow_down_response(resource: &str) -> Response<Full<Bytes>> {
+    let mut resp = build_response(StatusCode::SERVICE_UNAVAILABLE, s3_error_xml("SlowDown", "Please reduce your request rate.", resource), "application/xml");
+    resp.headers_mut().insert(hyper::header::RETRY_AFTER, 1u16.into());
+    resp
+}
+
+/// `503 ServiceUnavailable` while the server is read-only.
+fn read_only_response(resource: &str) -> Response<Full<Bytes>> {
+    build_response(StatusCode::SERVICE_UNAVAILABLE, s3_error_xml("ServiceUnavailable", "The server is in read-only mode.", resource), "application/xml")
+}
+
 fn build_empty_response(status: StatusCode) -> Response<Full<Bytes>> {
     Response::builder()
         .status(status)
@@ -107,52 +119,41 @@ fn build_empty_response(status: StatusCode) -> Response<Full<Bytes>> {
 
 async fn process_through_queue(behavior: Behavior, identifier: &str, data: Bytes) -> Result<Package, Status> {
     let uuid = Uuid::new_v4();
-    let uni_id = uuid.into_bytes();
     let mut package = Package::new_with_id(&uuid);
     package.behavior = behavior;
     package.content.identifier = Bytes::copy_from_slice(identifier.as_bytes());
     package.content.data = data;
+    package.deadline = Some(order_deadline(package.content.data.len()));
 
-    let con_queue = ConveyQueue::get_instance();
-    let receiver = match con_queue.register_waiter(uni_id) {
-        Some(rx) => rx,
-        None => return Err(Status::InternalError),
-    };
-
-    if let Err(e) = con_queue.produce_order(package) {
-        event!(Level::ERROR, "Failed to produce order: {}", e);
-        con_queue.unregister_waiter(uni_id);
-        return Err(Status::InternalError);
-    }
-
-    match tokio::time::timeout(Duration::from_secs(10), receiver).await {
-        Ok(Ok(pkg)) => {
-            if pkg.status == Status::Success {
-                Ok(pkg)
-            } else {
-                Err(pkg.status)
-            }
-        }
-        Ok(Err(_)) => {
-            con_queue.unregister_waiter(uni_id);
-            con_queue.remove_order(uni_id);
-            Err(Status::InternalError)
-        }
-        Err(_) => {
-            event!(Level::ERROR, "S3 request timeout");
-            con_queue.unregister_waiter(uni_id);
-            con_queue.remove_order(uni_id);
+    match ConveyQueue::get_instance()
+        .request(package)
+        .await
+    {
+        Ok(pkg) if pkg.status == Status::Success => Ok(pkg),
+        Ok(pkg) => Err(pkg.status),
+        Err(RequestError::Busy) => Err(Status::Throttled),
+        Err(RequestError::ReadOnly) => Err(Status::ReadOnly),
+        Err(RequestError::Timeout) => Err(Status::Timeout),
+        Err(e) => {
+            event!(Level::ERROR, "S3 request failed: {}", e);
             Err(Status::InternalError)
         }
     }
 }
 
+#[instrument(skip_all, fields(method = %req.method(), path = %req.uri().path()))]
 async fn handle_s3(req: Request<hyper::body::Incoming>) -> Result<Response<Full<Bytes>>, hyper::http::Error> {
     let method = req.method().clone();
     let uri = req.uri().to_string();
     let path = uri.split('?').next().unwrap_or(&uri);
     let query = uri.split('?').nth(1).unwrap_or("");
 
+    // Refused before any mapping changes
+    if (method == Method::PUT || method == Method::DELETE) && crate::admin::is_read_only() {
+        let (_, key) = parse_s3_path(path);
+        return Ok(read_only_response(key.unwrap_or(path)));
+    }
+
     let some_mapper = mapper::get_mapper();
 
     let resp = match method {
@@ -188,28 +189,37 @@ async fn handle_s3(req: Request<hyper::body::Incoming>) -> Result<Response<Full<
                         Some(m) => m.resolve(bucket, key).await.unwrap_or(None),
                         None => None,
                     };
-                    match internal_name {
-                        Some(ref name) => {
-                            match process_through_queue(Behavior::GetFile, &name, Bytes::new()).await {
-                                Ok(pkg) => {
-                                    let content_type = get_mime_type(key);
-                                    Response::builder()
-                                        .status(StatusCode::OK)
-                                        .header("Content-Type", content_type)
-                                        .header("Content-Length", pkg.content.data.len().to_string())
-                                        .header("ETag", format!("\"{}\"", Uuid::new_v4().simple()))
-                                        .body(Full::new(Bytes::from(pkg.content.data)))
-                                        .unwrap()
-                                }
-                                Err(Status::FileNotFound) => {
-                                    build_response(StatusCode::NOT_FOUND, s3_error_xml("NoSuchKey", "The specified key does not exist.", key), "application/xml")
-                                }
-                                Err(_) => {
-                                    build_response(StatusCode::INTERNAL_SERVER_ERROR, s3_error_xml("InternalError", "Internal server error", key), "application/xml")
-                                }
-                            }
+                    let local = match internal_name {
+                        Some(ref name) => process_through_queue(Behavior::GetFile, name, Bytes::new())
+                            .await
+                            .map(|pkg| pkg.content.data),
+                        None => Err(Status::FileNotFound),
+                    };
+                    // Local misses may be held by a federation peer
+                    let found = match local {
+                        Err(Status::FileNotFound) => crate::federation::lookup(bucket, key)
+                            .await
+                            .ok_or(Status::FileNotFound),
+                        other => other,
+                    };
+                    match found {
+                        Ok(data) => {
+                            let content_type = get_mime_type(key);
+                            Response::builder()
+                                .status(StatusCode::OK)
+                                .header("Content-Type", content_type)
+                                .header("Content-Length", data.len().to_string())
+                                .header("ETag", format!("\"{}\"", Uuid::new_v4().simple()))
+                                .body(Full::new(data))
+                                .unwrap()
+                        }
+                        Err(Status::FileNotFound) => {
+                            build_response(StatusCode::NOT_FOUND, s3_error_xml("NoSuchKey", "The specified key does not exist.", key), "application/xml")
+                        }
+                        Err(Status::Throttled) => slow_down_response(key),
+                        Err(_) => {
+                            build_response(StatusCode::INTERNAL_SERVER_ERROR, s3_error_xml("InternalError", "Internal server error", key), "application/xml")
                         }
-                        None => build_response(StatusCode::NOT_FOUND, s3_error_xml("NoSuchKey", "The specified key does not exist.", key), "application/xml"),
                     }
                 }
             }
@@ -225,16 +235,17 @@ async fn handle_s3(req: Request<hyper::body::Incoming>) -> Result<Response<Full<
                     };
                     match internal_name {
                         Some(name) => {
-                            match process_through_queue(Behavior::GetFile, &name, Bytes::new()).await {
-                                Ok(pkg) => {
-                                    Response::builder()
+                            match process_through_queue(Behavior::StatFile, &name, Bytes::new()).await {
+                                Ok(pkg) => match serde_json::from_slice::<FileMetaDto>(&pkg.content.data) {
+                                    Ok(meta) => Response::builder()
                                         .status(StatusCode::OK)
                                         .header("Content-Type", get_mime_type(k))
-                                        .header("Content-Length", pkg.content.data.len().to_string())
-                                        .header("ETag", format!("\"{}\"", Uuid::new_v4().simple()))
+                                        .header("Content-Length", meta.size.to_string())
+                                        .header("ETag", format!("\"{}\"", meta.hash))
                                         .body(Full::new(Bytes::new()))
-                                        .unwrap()
-                                }
+                                        .unwrap(),
+                                    Err(_) => build_empty_response(StatusCode::INTERNAL_SERVER_ERROR),
+                                },
                                 Err(_) => build_empty_response(StatusCode::NOT_FOUND),
                             }
                         }
@@ -266,13 +277,17 @@ async fn handle_s3(req: Request<hyper::body::Incoming>) -> Result<Response<Full<
             }
 
             match process_through_queue(Behavior::PutFile, &internal_name, body_bytes).await {
-                Ok(_) => {
+                Ok(pkg) => {
+                    // The S3 service does not authenticate its callers
+                    let owner = crate::auth::Principal::anonymous().user_id;
+                    crate::report::note_write(bucket, key, &owner, &pkg.content.data).await;
                     Response::builder()
                         .status(StatusCode::OK)
                         .header("ETag", format!("\"{}\"", Uuid::new_v4().simple()))
                         .body(Full::new(Bytes::new()))
                         .unwrap()
                 }
+                Err(Status::Throttled) => slow_down_response(key),
                 Err(_) => {
                     build_response(StatusCode::INTERNAL_SERVER_ERROR, s3_error_xml("InternalError", "Failed to store object", key), "application/xml")
                 }
@@ -304,13 +319,13 @@ async fn handle_s3(req: Request<hyper::body::Incoming>) -> Result<Response<Full<
 }
 
 #[instrument(skip_all)]
-pub async fn run_s3_server(addr: &str) {
-    event!(Level::INFO, "S3-compatible service starting on {}", addr);
+pub async fn run_s3_server(addrs: &[String]) {
+    event!(Level::INFO, "S3-compatible service starting");
 
-    let listener = match TcpListener::bind(addr).await {
+    let listener = match listen("s3", addrs).await {
         Ok(listener) => listener,
-        Err(_) => {
-            event!(Level::ERROR, "Failed to bind to address {}", addr);
+        Err(err) => {
+            event!(Level::ERROR, "Failed to bind to address {}", err);
             return;
         }
     };
@@ -327,11 +342,21 @@ pub async fn run_s3_server(addr: &str) {
                 };
 
                 let io = TokioIo::new(stream);
+                let in_flight = shutdown_status.track();
+                let shutdown_status = Arc::clone(&shutdown_status);
                 tokio::task::spawn(async move {
-                    if let Err(err) = http1::Builder::new()
-                        .serve_connection(io, service_fn(handle_s3))
-                        .await
-                    {
+                    let _in_flight = in_flight;
+                    let conn = http1::Builder::new().serve_connection(io, service_fn(handle_s3));
+                    tokio::pin!(conn);
+                    // On shutdown, finish the current request and close
+                    let result = tokio::select! {
+                        result = conn.as_mut() => result,
+                        _ = shutdown_status.wait() => {
+                            conn.as_mut().graceful_shutdown();
+                            conn.await
+                        }
+                    };
+                    if let Err(err) = result {
                         event!(Level::ERROR, "Error serving S3 connection: {:?}", err);
                     }
                 });
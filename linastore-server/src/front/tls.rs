@@ -0,0 +1,174 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{RootCertStore, ServerConfig, server::WebPkiClientVerifier},
+};
+
+use crate::error::{Context, Result, err_msg};
+
+/// How long a client gets to complete the TLS handshake.
+pub(super) const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Build a TLS acceptor from a PEM certificate chain and private key.
+///
+/// With `client_ca`, clients are asked for a certificate issued by one of the
+/// PEM CAs in that file; those without one may still connect and sign in
+/// otherwise. `alpn` lists the protocols offered during the handshake, most
+/// preferred first.
+pub(super) fn load_acceptor(
+    cert_path: &Path,
+    key_path: &Path,
+    client_ca: Option<&Path>,
+    alpn: &[&[u8]],
+) -> Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .with_context(|| format!("Failed to read TLS certificate {}", cert_path.display()))?
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid TLS certificate {}", cert_path.display()))?;
+    if certs.is_empty() {
+        return Err(err_msg(format!(
+            "No certificate found in {}",
+            cert_path.display()
+        )));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("Failed to read TLS private key {}", key_path.display()))?;
+
+    let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("Unsupported TLS protocol versions")?;
+    let builder = match client_ca {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(ca_path)
+                .with_context(|| format!("Failed to read client CA {}", ca_path.display()))?
+            {
+                let cert = cert
+                    .with_context(|| format!("Invalid client CA {}", ca_path.display()))?;
+                roots
+                    .add(cert)
+                    .with_context(|| format!("Unusable client CA in {}", ca_path.display()))?;
+            }
+            if roots.is_empty() {
+                return Err(err_msg(format!(
+                    "No certificate found in {}",
+                    ca_path.display()
+                )));
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()
+                .context("Failed to build client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .context("TLS certificate and key do not match")?;
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// The name a verified client certificate identifies its holder by: the
+/// subject's common name, or failing that its first DNS subject alternative
+/// name.
+pub(super) fn client_identity(certs: Option<&[CertificateDer<'_>]>) -> Option<String> {
+    let cert = webpki::EndEntityCert::try_from(certs?.first()?).ok()?;
+    common_name(cert.subject())
+        .or_else(|| cert.valid_dns_names().next().map(str::to_string))
+        .filter(|name| !name.is_empty())
+}
+
+/// OID 2.5.4.3 (`commonName`), DER-encoded with its tag and length.
+const CN_OID: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+
+/// The first common name in a DER `Name` without its outer SEQUENCE header:
+/// a run of `SET { SEQUENCE { OID, value } }`.
+fn common_name(mut name: &[u8]) -> Option<String> {
+    while !name.is_empty() {
+        let (rdn, rest) = der_element(name, 0x31)?;
+        name = rest;
+        let mut attrs = rdn;
+        while !attrs.is_empty() {
+            let (attr, rest) = der_element(attrs, 0x30)?;
+            attrs = rest;
+            if let Some(value) = attr.strip_prefix(CN_OID) {
+                // Any string type; the tag is not checked
+                let (value, _) = der_element(value, value.first().copied()?)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Split one DER element with tag `tag` off the front of `buf`, returning its
+/// contents and the bytes after it.
+fn der_element(buf: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&first, rest) = buf.split_first()?;
+    if first != tag {
+        return None;
+    }
+    let (&len_byte, mut rest) = rest.split_first()?;
+    let len = if len_byte < 0x80 {
+        len_byte as usize
+    } else {
+        let octets = (len_byte & 0x7f) as usize;
+        if octets == 0 || octets > 4 || rest.len() < octets {
+            return None;
+        }
+        let (len_bytes, after) = rest.split_at(octets);
+        rest = after;
+        len_bytes.iter().fold(0usize, |len, &b| (len << 8) | b as usize)
+    };
+    (rest.len() >= len).then(|| rest.split_at(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_acceptor_missing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = load_acceptor(
+            &dir.path().join("cert.pem"),
+            &dir.path().join("key.pem"),
+            None,
+            &[],
+        )
+        .err()
+        .expect("missing files must fail");
+        assert!(err.to_string().contains("cert.pem"));
+    }
+
+    #[test]
+    fn test_load_acceptor_rejects_empty_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = dir.path().join("cert.pem");
+        std::fs::write(&cert, "not a certificate\n").unwrap();
+        let err = load_acceptor(&cert, &dir.path().join("key.pem"), None, &[])
+            .err()
+            .expect("empty chain must fail");
+        assert!(err.to_string().contains("No certificate"));
+    }
+
+    #[test]
+    fn test_common_name() {
+        // SET { SEQUENCE { countryName, "SE" } } SET { SEQUENCE { commonName, "backup-bot" } }
+        let name = [
+            0x31, 0x0b, 0x30, 0x09, 0x06, 0x03, 0x55, 0x04, 0x06, 0x13, 0x02, b'S', b'E', //
+            0x31, 0x13, 0x30, 0x11, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x0a, b'b', b'a', b'c',
+            b'k', b'u', b'p', b'-', b'b', b'o', b't',
+        ];
+        assert_eq!(common_name(&name), Some("backup-bot".to_string()));
+        assert_eq!(common_name(&name[..13]), None);
+        // Truncated input is rejected rather than read past its end
+        assert_eq!(common_name(&name[..20]), None);
+    }
+}
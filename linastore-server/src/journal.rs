@@ -0,0 +1,269 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use bytes::Bytes;
+use tracing::{Level, event};
+
+use crate::{
+    dtos::{Behavior, Content, Package, Status},
+    error::{Context, Result},
+};
+
+const RECORD_ORDER: u8 = 1;
+const RECORD_DONE: u8 = 2;
+
+/// Append-only journal of accepted writes, so a crash between accepting a
+/// put and storing it does not lose the data.
+///
+/// Records are `kind(1) + uni_id(16) [+ order fields] + crc32(4)`, where an
+/// order record carries `flags(1) + created_at(8) + ilen(4) + dlen(4) +
+/// identifier + data` (integers little-endian). A `done` record retires
+/// the order with the same uni_id. The file is truncated whenever no order
+/// is outstanding.
+pub struct OrderJournal {
+    file: Mutex<File>,
+    pending: Mutex<HashSet<[u8; 16]>>,
+}
+
+impl OrderJournal {
+    /// Open (or create) the journal at `path` and return the orders it holds
+    /// that were never completed, oldest first. The file is rewritten to
+    /// hold just those orders.
+    pub fn open(path: &Path) -> Result<(Self, Vec<Package>)> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create journal directory {}", parent.display())
+            })?;
+        }
+
+        let mut raw = Vec::new();
+        match File::open(path) {
+            Ok(mut file) => {
+                file.read_to_end(&mut raw)
+                    .with_context(|| format!("Failed to read journal {}", path.display()))?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to open journal {}", path.display()));
+            }
+        }
+        let backlog = replay(&raw);
+
+        // Compact: rewrite the outstanding orders, then swap the file in
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut tmp = File::create(&tmp_path)
+                .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+            for pkg in &backlog {
+                tmp.write_all(&encode_order(pkg))
+                    .context("Failed to compact journal")?;
+            }
+            tmp.sync_all().context("Failed to compact journal")?;
+        }
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to replace journal {}", path.display()))?;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open journal {}", path.display()))?;
+        let journal = OrderJournal {
+            file: Mutex::new(file),
+            pending: Mutex::new(backlog.iter().map(|pkg| pkg.uni_id).collect()),
+        };
+        Ok((journal, backlog))
+    }
+
+    /// Whether orders with this behavior are journaled.
+    pub fn covers(behavior: &Behavior) -> bool {
+        *behavior == Behavior::PutFile
+    }
+
+    /// Durably record `pkg` before it is acknowledged to the queue.
+    pub fn append(&self, pkg: &Package) -> io::Result<()> {
+        let mut file = self.lock_file()?;
+        file.write_all(&encode_order(pkg))?;
+        file.sync_data()?;
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.insert(pkg.uni_id);
+        }
+        Ok(())
+    }
+
+    /// Retire the order with `uni_id`, whether it was stored or withdrawn.
+    pub fn complete(&self, uni_id: [u8; 16]) -> io::Result<()> {
+        let mut file = self.lock_file()?;
+        let Ok(mut pending) = self.pending.lock() else {
+            return Err(io::Error::other("journal state poisoned"));
+        };
+        if !pending.remove(&uni_id) {
+            return Ok(());
+        }
+        if pending.is_empty() {
+            file.set_len(0)
+        } else {
+            file.write_all(&encode_done(uni_id))
+        }
+    }
+
+    fn lock_file(&self) -> io::Result<std::sync::MutexGuard<'_, File>> {
+        self.file
+            .lock()
+            .map_err(|_| io::Error::other("journal file lock poisoned"))
+    }
+}
+
+fn encode_order(pkg: &Package) -> Vec<u8> {
+    let content = &pkg.content;
+    let mut record = Vec::with_capacity(38 + content.identifier.len() + content.data.len());
+    record.push(RECORD_ORDER);
+    record.extend_from_slice(&pkg.uni_id);
+    record.push(content.flags);
+    record.extend_from_slice(&pkg.created_at.to_le_bytes());
+    record.extend_from_slice(&(content.identifier.len() as u32).to_le_bytes());
+    record.extend_from_slice(&(content.data.len() as u32).to_le_bytes());
+    record.extend_from_slice(&content.identifier);
+    record.extend_from_slice(&content.data);
+    seal(record)
+}
+
+fn encode_done(uni_id: [u8; 16]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(21);
+    record.push(RECORD_DONE);
+    record.extend_from_slice(&uni_id);
+    seal(record)
+}
+
+fn seal(mut record: Vec<u8>) -> Vec<u8> {
+    let crc = crc32fast::hash(&record);
+    record.extend_from_slice(&crc.to_le_bytes());
+    record
+}
+
+/// Orders in `raw` without a matching `done` record. Reading stops at the
+/// first torn or corrupt record, which can only be the tail of a crashed
+/// write.
+fn replay(raw: &[u8]) -> Vec<Package> {
+    let mut orders: Vec<Package> = Vec::new();
+    let mut pos = 0;
+    while pos < raw.len() {
+        let Some((record, len)) = decode_record(&raw[pos..]) else {
+            event!(
+                Level::WARN,
+                "Ignoring {} trailing journal bytes that do not form a record",
+                raw.len() - pos
+            );
+            break;
+        };
+        pos += len;
+        match record {
+            Record::Order(pkg) => orders.push(pkg),
+            Record::Done(uni_id) => orders.retain(|pkg| pkg.uni_id != uni_id),
+        }
+    }
+    orders
+}
+
+enum Record {
+    Order(Package),
+    Done([u8; 16]),
+}
+
+fn decode_record(buf: &[u8]) -> Option<(Record, usize)> {
+    let kind = *buf.first()?;
+    let uni_id: [u8; 16] = buf.get(1..17)?.try_into().ok()?;
+    let (record, body_len) = match kind {
+        RECORD_DONE => (Record::Done(uni_id), 17),
+        RECORD_ORDER => {
+            let flags = *buf.get(17)?;
+            let created_at = i64::from_le_bytes(buf.get(18..26)?.try_into().ok()?);
+            let ilen = u32::from_le_bytes(buf.get(26..30)?.try_into().ok()?) as usize;
+            let dlen = u32::from_le_bytes(buf.get(30..34)?.try_into().ok()?) as usize;
+            let identifier = buf.get(34..34 + ilen)?;
+            let data = buf.get(34 + ilen..34 + ilen + dlen)?;
+            let pkg = Package {
+                status: Status::None,
+                uni_id,
+                behavior: Behavior::PutFile,
+                content: Content {
+                    flags,
+                    identifier: Bytes::copy_from_slice(identifier),
+                    data: Bytes::copy_from_slice(data),
+                },
+                created_at,
+                // Replayed orders have nobody waiting on them
+                deadline: None,
+                span: tracing::Span::none(),
+            };
+            (Record::Order(pkg), 34 + ilen + dlen)
+        }
+        _ => return None,
+    };
+    let crc = u32::from_le_bytes(buf.get(body_len..body_len + 4)?.try_into().ok()?);
+    (crc32fast::hash(&buf[..body_len]) == crc).then_some((record, body_len + 4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn put_order(name: &str, data: &'static [u8]) -> Package {
+        let mut pkg = Package::new_with_id(&Uuid::new_v4());
+        pkg.behavior = Behavior::PutFile;
+        pkg.content.identifier = Bytes::copy_from_slice(name.as_bytes());
+        pkg.content.data = Bytes::from_static(data);
+        pkg
+    }
+
+    #[test]
+    fn test_uncompleted_orders_are_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal").join("orders.journal");
+        let (journal, backlog) = OrderJournal::open(&path).unwrap();
+        assert!(backlog.is_empty());
+
+        let stored = put_order("a.txt", b"stored");
+        let lost = put_order("b.txt", b"lost in a crash");
+        journal.append(&stored).unwrap();
+        journal.append(&lost).unwrap();
+        journal.complete(stored.uni_id).unwrap();
+        drop(journal);
+
+        let (journal, backlog) = OrderJournal::open(&path).unwrap();
+        assert_eq!(backlog.len(), 1);
+        assert_eq!(backlog[0].uni_id, lost.uni_id);
+        assert_eq!(backlog[0].content.identifier, lost.content.identifier);
+        assert_eq!(backlog[0].content.data, lost.content.data);
+        assert_eq!(backlog[0].behavior, Behavior::PutFile);
+
+        // Completing the last order empties the file
+        journal.complete(lost.uni_id).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_torn_tail_is_ignored() {
+        let kept = put_order("a.txt", b"kept");
+        let mut raw = encode_order(&kept);
+        let torn = encode_order(&put_order("b.txt", b"torn"));
+        raw.extend_from_slice(&torn[..torn.len() - 3]);
+
+        let backlog = replay(&raw);
+        assert_eq!(backlog.len(), 1);
+        assert_eq!(backlog[0].uni_id, kept.uni_id);
+    }
+
+    #[test]
+    fn test_corrupt_record_stops_replay() {
+        let mut raw = encode_order(&put_order("a.txt", b"data"));
+        raw[20] ^= 0xff;
+        assert!(replay(&raw).is_empty());
+    }
+}
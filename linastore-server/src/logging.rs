@@ -0,0 +1,510 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{NaiveDate, SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use tracing::{
+    Event, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_subscriber::{
+    Registry,
+    filter::{LevelFilter, Targets},
+    fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter, format},
+    registry::LookupSpan,
+    reload,
+};
+
+use crate::{
+    config,
+    error::{Context, Result, err_msg},
+};
+
+/// Name of the active log file; rotated files carry a timestamp suffix.
+const LOG_FILE: &str = "linastore.log";
+
+/// Default levels, from quietest to most verbose, that SIGUSR1 and SIGUSR2
+/// step through.
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::OFF,
+    LevelFilter::ERROR,
+    LevelFilter::WARN,
+    LevelFilter::INFO,
+    LevelFilter::DEBUG,
+    LevelFilter::TRACE,
+];
+
+static FILTER: OnceLock<reload::Handle<Targets, Registry>> = OnceLock::new();
+
+/// Where log lines go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogOutput {
+    Stdout,
+    File,
+    Both,
+}
+
+/// How each log event is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, see [`JsonFormat`].
+    Json,
+}
+
+/// When the active log file is closed and a new one started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+    /// At the first write of each UTC day.
+    Daily,
+    /// Before a write would take the file past this many bytes.
+    Size(u64),
+}
+
+/// Log sink settings, read before the rest of the configuration so that its
+/// warnings are logged.
+#[derive(Debug, Clone)]
+pub struct LogSettings {
+    pub dir: Option<String>,
+    /// Levels by target, from `RUST_LOG` or `LINASTORE_LOG_LEVEL`.
+    pub filter: Targets,
+    pub output: LogOutput,
+    pub format: LogFormat,
+    pub rotation: Rotation,
+    /// Rotated files kept besides the active one; 0 keeps all of them.
+    pub retention: usize,
+}
+
+impl LogSettings {
+    pub fn load() -> Result<Self> {
+        let filter = match std::env::var("RUST_LOG") {
+            Ok(raw) => parse_filter("RUST_LOG", &raw)?,
+            Err(_) => match config::var("LINASTORE_LOG_LEVEL") {
+                Ok(raw) => parse_filter("LINASTORE_LOG_LEVEL", &raw)?,
+                Err(_) if cfg!(debug_assertions) => Targets::new().with_default(LevelFilter::DEBUG),
+                Err(_) => Targets::new().with_default(LevelFilter::INFO),
+            },
+        };
+
+        let output = match config::var("LINASTORE_LOG_OUTPUT") {
+            Ok(raw) => match raw.trim().to_ascii_lowercase().as_str() {
+                "stdout" => LogOutput::Stdout,
+                "file" => LogOutput::File,
+                "both" => LogOutput::Both,
+                _ => {
+                    return Err(invalid(format!(
+                        "LINASTORE_LOG_OUTPUT has unrecognized value {:?} (expected stdout, file or both)",
+                        raw
+                    )));
+                }
+            },
+            // Debug builds echo to the terminal as well
+            Err(_) if cfg!(debug_assertions) => LogOutput::Both,
+            Err(_) => LogOutput::File,
+        };
+
+        let format = match config::var("LINASTORE_LOG_FORMAT") {
+            Ok(raw) => match raw.trim().to_ascii_lowercase().as_str() {
+                "" | "text" => LogFormat::Text,
+                "json" => LogFormat::Json,
+                _ => {
+                    return Err(invalid(format!(
+                        "LINASTORE_LOG_FORMAT has unrecognized value {:?} (expected text or json)",
+                        raw
+                    )));
+                }
+            },
+            Err(_) => LogFormat::Text,
+        };
+
+        let max_size = match config::var("LINASTORE_LOG_MAX_SIZE") {
+            Ok(raw) => match raw.trim().parse::<u64>() {
+                Ok(v) if v > 0 => v,
+                _ => {
+                    return Err(invalid(format!(
+                        "LINASTORE_LOG_MAX_SIZE must be a positive number of bytes: {:?}",
+                        raw
+                    )));
+                }
+            },
+            Err(_) => 100 * 1024 * 1024,
+        };
+        let rotation = match config::var("LINASTORE_LOG_ROTATION") {
+            Ok(raw) => match raw.trim().to_ascii_lowercase().as_str() {
+                "" | "never" => Rotation::Never,
+                "daily" => Rotation::Daily,
+                "size" => Rotation::Size(max_size),
+                _ => {
+                    return Err(invalid(format!(
+                        "LINASTORE_LOG_ROTATION has unrecognized value {:?} (expected never, daily or size)",
+                        raw
+                    )));
+                }
+            },
+            Err(_) => Rotation::Never,
+        };
+
+        let retention = match config::var("LINASTORE_LOG_RETENTION") {
+            Ok(raw) => raw.trim().parse::<usize>().map_err(|_| {
+                invalid(format!(
+                    "LINASTORE_LOG_RETENTION is not a valid file count: {:?}",
+                    raw
+                ))
+            })?,
+            Err(_) => 7,
+        };
+
+        Ok(LogSettings {
+            dir: config::var("LINASTORE_LOG_DIR")
+                .ok()
+                .filter(|v| !v.trim().is_empty()),
+            filter,
+            output,
+            format,
+            rotation,
+            retention,
+        })
+    }
+}
+
+fn invalid(message: String) -> crate::error::Error {
+    err_msg(config::attribute(message))
+}
+
+/// Parse `info` or `warn,linastore_server=debug` style directives.
+fn parse_filter(name: &str, raw: &str) -> Result<Targets> {
+    raw.trim().parse::<Targets>().map_err(|e| {
+        invalid(format!(
+            "{} is not a valid log filter ({}): {:?}",
+            name, e, raw
+        ))
+    })
+}
+
+/// Wrap `filter` so that [`adjust_level`] can change it later; install the
+/// returned layer first on the registry.
+pub fn reloadable(filter: Targets) -> reload::Layer<Targets, Registry> {
+    let (layer, handle) = reload::Layer::new(filter);
+    let _ = FILTER.set(handle);
+    layer
+}
+
+/// Make logging one step more or less verbose for targets without a
+/// directive of their own, returning the new default level.
+pub fn adjust_level(more: bool) -> Result<LevelFilter> {
+    let handle = FILTER
+        .get()
+        .ok_or_else(|| err_msg("Logging is not initialized"))?;
+    let mut level = LevelFilter::OFF;
+    handle
+        .modify(|filter| {
+            *filter = shifted(filter, more);
+            level = filter.default_level().unwrap_or(LevelFilter::OFF);
+        })
+        .map_err(|e| err_msg(format!("Failed to change the log level: {}", e)))?;
+    Ok(level)
+}
+
+fn shifted(filter: &Targets, more: bool) -> Targets {
+    let current = filter.default_level().unwrap_or(LevelFilter::OFF);
+    let step = LEVELS.iter().position(|l| *l == current).unwrap_or(0);
+    let step = if more {
+        (step + 1).min(LEVELS.len() - 1)
+    } else {
+        step.saturating_sub(1)
+    };
+    filter.clone().with_default(LEVELS[step])
+}
+
+/// `linastore.log` in a directory, rotated by [`Rotation`] and pruned to
+/// the newest `retention` rotated files.
+pub struct RollingFile {
+    state: Mutex<State>,
+}
+
+struct State {
+    dir: PathBuf,
+    rotation: Rotation,
+    retention: usize,
+    file: File,
+    size: u64,
+    /// UTC day of the last write, for daily rotation
+    day: NaiveDate,
+}
+
+impl RollingFile {
+    pub fn open(dir: &Path, rotation: Rotation, retention: usize) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create log directory {}", dir.display()))?;
+        let path = dir.join(LOG_FILE);
+        let file = open_append(&path)
+            .with_context(|| format!("Failed to open log file {}", path.display()))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        // A file left from an earlier day rotates on the first write
+        let day = file
+            .metadata()
+            .and_then(|m| m.modified())
+            .map(|t| chrono::DateTime::<Utc>::from(t).date_naive())
+            .unwrap_or_else(|_| Utc::now().date_naive());
+        Ok(RollingFile {
+            state: Mutex::new(State {
+                dir: dir.to_path_buf(),
+                rotation,
+                retention,
+                file,
+                size,
+                day,
+            }),
+        })
+    }
+}
+
+impl State {
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        let today = Utc::now().date_naive();
+        let due = match self.rotation {
+            Rotation::Never => false,
+            Rotation::Daily => today != self.day,
+            Rotation::Size(max) => self.size > 0 && self.size + buf.len() as u64 > max,
+        };
+        if due {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        self.day = today;
+        Ok(())
+    }
+
+    /// Move the active file aside under a name that sorts by age, start a
+    /// new one and drop the oldest rotated files beyond the retention.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let stamp = match self.rotation {
+            Rotation::Daily => self.day.format("%Y-%m-%d").to_string(),
+            _ => Utc::now().format("%Y-%m-%dT%H-%M-%S%.3f").to_string(),
+        };
+        let active = self.dir.join(LOG_FILE);
+        let mut rotated = self.dir.join(format!("{}.{}", LOG_FILE, stamp));
+        let mut n = 1;
+        while rotated.exists() {
+            rotated = self.dir.join(format!("{}.{}.{}", LOG_FILE, stamp, n));
+            n += 1;
+        }
+        fs::rename(&active, &rotated)?;
+        self.file = open_append(&active)?;
+        self.size = 0;
+        self.prune()
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        if self.retention == 0 {
+            return Ok(());
+        }
+        let prefix = format!("{}.", LOG_FILE);
+        let mut rotated: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| entry.path())
+            .collect();
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.retention);
+        for path in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Write for &RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| io::Error::other("log file lock poisoned"))?;
+        state.write(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| io::Error::other("log file lock poisoned"))?;
+        state.file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RollingFile {
+    type Writer = &'a RollingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
+
+/// Writes each event as one JSON object: `timestamp`, `level`, `target`,
+/// `message`, the names of the enclosing `spans`, and the event's own
+/// fields (such as `request_id`, `status`, `bytes`, `duration_ms`) as
+/// top-level keys.
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".into(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+                .into(),
+        );
+        line.insert("level".into(), meta.level().as_str().into());
+        line.insert("target".into(), meta.target().into());
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<Value> = scope.from_root().map(|span| span.name().into()).collect();
+            line.insert("spans".into(), spans.into());
+        }
+        let mut fields = JsonFields(Map::new());
+        event.record(&mut fields);
+        for (name, value) in fields.0 {
+            line.entry(name).or_insert(value);
+        }
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+/// Collects an event's or span's fields as JSON values.
+pub(crate) struct JsonFields(pub(crate) Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotated(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name != LOG_FILE)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_size_rotation_keeps_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = RollingFile::open(dir.path(), Rotation::Size(10), 2).unwrap();
+        for _ in 0..5 {
+            (&log).write_all(b"0123456789").unwrap();
+        }
+        // Each write filled a file, so four were rotated and two kept
+        assert_eq!(rotated(dir.path()).len(), 2);
+        assert_eq!(fs::read(dir.path().join(LOG_FILE)).unwrap(), b"0123456789");
+    }
+
+    #[test]
+    fn test_shifted_moves_default_level_only() {
+        let filter = parse_filter("LINASTORE_LOG_LEVEL", "info,sqlx=warn").unwrap();
+        let louder = shifted(&filter, true);
+        assert_eq!(louder.default_level(), Some(LevelFilter::DEBUG));
+        assert!(louder.would_enable("linastore_server", &tracing::Level::DEBUG));
+        assert!(!louder.would_enable("sqlx::query", &tracing::Level::INFO));
+
+        let quiet = shifted(&shifted(&filter, false), false);
+        assert_eq!(quiet.default_level(), Some(LevelFilter::ERROR));
+        let off = shifted(&shifted(&quiet, false), false);
+        assert_eq!(off.default_level(), Some(LevelFilter::OFF));
+        assert_eq!(
+            shifted(&Targets::new().with_default(LevelFilter::TRACE), true).default_level(),
+            Some(LevelFilter::TRACE)
+        );
+
+        assert!(parse_filter("LINASTORE_LOG_LEVEL", "sqlx=loud").is_err());
+    }
+
+    #[test]
+    fn test_json_format_flattens_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = RollingFile::open(dir.path(), Rotation::Never, 0).unwrap();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(log)
+            .event_format(JsonFormat)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("waitress").entered();
+            tracing::info!(request_id = "r-1", bytes = 42u64, "Handled request");
+        });
+
+        let text = fs::read_to_string(dir.path().join(LOG_FILE)).unwrap();
+        let line: Value = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "Handled request");
+        assert_eq!(line["request_id"], "r-1");
+        assert_eq!(line["bytes"], 42);
+        assert_eq!(line["spans"], serde_json::json!(["waitress"]));
+    }
+
+    #[test]
+    fn test_daily_rotation_names_file_by_day() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = RollingFile::open(dir.path(), Rotation::Daily, 0).unwrap();
+        (&log).write_all(b"yesterday\n").unwrap();
+        let yesterday = Utc::now().date_naive().pred_opt().unwrap();
+        log.state.lock().unwrap().day = yesterday;
+
+        (&log).write_all(b"today\n").unwrap();
+        assert_eq!(
+            rotated(dir.path()),
+            vec![format!("{}.{}", LOG_FILE, yesterday.format("%Y-%m-%d"))]
+        );
+        assert_eq!(fs::read(dir.path().join(LOG_FILE)).unwrap(), b"today\n");
+    }
+}
@@ -1,12 +1,23 @@
+mod admin;
 mod auth;
+mod blob;
+mod config;
 mod conveyer;
 mod db;
 mod dtos;
 mod error;
+mod federation;
 mod front;
+mod journal;
+mod logging;
 mod mapper;
+mod metrics;
 mod porter;
+mod replica;
+mod report;
 mod shutdown;
+mod systemd;
+mod telemetry;
 mod utils;
 mod vars;
 
@@ -17,6 +28,14 @@ use crate::error::Result;
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct ServerCli {
+    /// Config file to read (default: linastore.toml in the working directory, if present)
+    #[arg(long = "config", global = true)]
+    config: Option<std::path::PathBuf>,
+
+    /// Override a config file setting, e.g. `--set server.http_port=9000`; repeatable
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    set: Vec<String>,
+
     #[command(subcommand)]
     command: Option<ServerCommands>,
 }
@@ -27,6 +46,57 @@ enum ServerCommands {
     Start(StartArgs),
     /// Stop the LiNaStore server
     Stop(StopArgs),
+    /// Make the running server re-read its credentials, keeping sessions
+    Reload,
+    /// Manage user accounts
+    #[command(subcommand)]
+    User(UserCommands),
+    /// Manage API keys
+    #[command(subcommand)]
+    Key(KeyCommands),
+    /// Print storage usage by bucket, extension and owner, and recent ingest
+    Report(ReportArgs),
+}
+
+#[derive(Subcommand, Clone)]
+enum UserCommands {
+    /// Add a user; the password is read from stdin unless given
+    Add {
+        username: String,
+        #[arg(long = "password")]
+        password: Option<String>,
+        /// `reader`, `writer` or `admin`
+        #[arg(long = "role", default_value = "writer")]
+        role: auth::Role,
+    },
+    /// Change the role of a user
+    SetRole { username: String, role: auth::Role },
+    /// Limit a user to a bucket; a user with grants reaches only those buckets
+    Grant { username: String, bucket: String },
+    /// Withdraw a bucket grant
+    Ungrant { username: String, bucket: String },
+    /// Remove a user and end its sessions
+    Remove { username: String },
+    /// List user accounts
+    List,
+}
+
+#[derive(Subcommand, Clone)]
+enum KeyCommands {
+    /// Issue an API key for a user and print it once
+    Create {
+        username: String,
+        /// Label to recognise the key by
+        #[arg(long = "name", default_value = "")]
+        name: String,
+        /// `read` or `read-write`
+        #[arg(long = "scope", default_value = "read-write")]
+        scope: auth::KeyScope,
+    },
+    /// Revoke an API key by id
+    Revoke { id: String },
+    /// List API keys
+    List,
 }
 
 /// Arguments for the start command
@@ -36,9 +106,29 @@ struct StartArgs {
     #[arg(long = "foreground")]
     foreground: bool,
 
-    /// Directory to store log files (default: linastore/logs)
+    /// Directory to store log files (default: LINASTORE_LOG_DIR, else linadata/logs)
     #[arg(long = "log-dir")]
     log_dir: Option<String>,
+
+    /// Create a new store if the root holds none yet
+    #[arg(long = "init")]
+    init: bool,
+}
+
+/// Arguments for the report command
+#[derive(Parser, Clone)]
+struct ReportArgs {
+    /// Seconds per ingest interval
+    #[arg(long = "interval", default_value_t = report::DEFAULT_INTERVAL)]
+    interval: u64,
+
+    /// Count writes since this Unix time (default: the last 30 days)
+    #[arg(long = "since")]
+    since: Option<i64>,
+
+    /// Print the report as JSON, as `GET /api/admin/report` answers
+    #[arg(long = "json")]
+    json: bool,
 }
 
 /// Arguments for the stop command
@@ -49,17 +139,38 @@ struct StopArgs {
     force: bool,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     let cli = ServerCli::parse();
+    config::install(cli.config.as_deref(), &cli.set)?;
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Ok(raw) = config::var("LINASTORE_WORKER_THREADS") {
+        match raw.trim().parse::<usize>() {
+            Ok(threads) if threads > 0 => {
+                runtime.worker_threads(threads);
+            }
+            _ => {
+                return Err(error::err_msg(config::attribute(format!(
+                    "LINASTORE_WORKER_THREADS must be a positive integer: {:?}",
+                    raw
+                ))));
+            }
+        }
+    }
+    runtime.build()?.block_on(run(cli))
+}
 
+async fn run(cli: ServerCli) -> Result<()> {
     match &cli.command {
         Some(ServerCommands::Start(args)) => {
-            // Write PID file
-            // Run the server
-            utils::run_server(args.log_dir.clone(), !args.foreground).await
+            utils::run_server(args.log_dir.clone(), !args.foreground, args.init).await
         }
         Some(ServerCommands::Stop(args)) => utils::handle_stop(args.force),
+        Some(ServerCommands::Reload) => utils::handle_reload(),
+        Some(ServerCommands::User(command)) => utils::handle_user_command(command.clone()).await,
+        Some(ServerCommands::Key(command)) => utils::handle_key_command(command.clone()).await,
+        Some(ServerCommands::Report(args)) => utils::handle_report(args.clone()).await,
         None => {
             // No subcommand provided: show help
             let mut cmd = ServerCli::command();
@@ -1,6 +1,7 @@
 use std::path::Path;
 use std::sync::{Arc, OnceLock};
 use sqlx::sqlite::SqlitePoolOptions;
+use uuid::Uuid;
 
 pub const DEFAULT_BUCKET: &str = "default";
 
@@ -36,6 +37,27 @@ impl BucketMapper {
         .execute(&pool)
         .await?;
 
+        // Migration: who last wrote each key, for usage reports
+        let _ = sqlx::query("ALTER TABLE bucket_mappings ADD COLUMN owner TEXT")
+            .execute(&pool)
+            .await;
+
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS ingest_log (
+                at     INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+                bucket TEXT NOT NULL,
+                key    TEXT NOT NULL,
+                owner  TEXT NOT NULL,
+                bytes  INTEGER NOT NULL
+            )",
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_ingest_at ON ingest_log(at)")
+            .execute(&pool)
+            .await?;
+
         Ok(Self { pool })
     }
 
@@ -66,6 +88,105 @@ impl BucketMapper {
         Ok(())
     }
 
+    /// The internal name `bucket`/`key` maps to, registering a new one when
+    /// the key has none. The flag is `true` when this call's registration is
+    /// the one in place rather than an existing or concurrent one, so a
+    /// write that then fails can [`unregister`](Self::unregister) it.
+    pub async fn resolve_or_register(
+        &self,
+        bucket: &str,
+        key: &str,
+    ) -> Result<(String, bool), sqlx::Error> {
+        if let Some(existing) = self.resolve(bucket, key).await? {
+            return Ok((existing, false));
+        }
+        let internal_name = Uuid::new_v4().to_string();
+        self.register(bucket, key, &internal_name).await?;
+        // Re-read: a concurrent writer may have won the insert
+        match self.resolve(bucket, key).await? {
+            Some(winner) => {
+                let created = winner == internal_name;
+                Ok((winner, created))
+            }
+            None => Ok((internal_name, false)),
+        }
+    }
+
+    /// Remove the mapping of `bucket`/`key` if it still names `internal_name`.
+    pub async fn unregister(
+        &self,
+        bucket: &str,
+        key: &str,
+        internal_name: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "DELETE FROM bucket_mappings WHERE bucket = ?1 AND key = ?2 AND internal_name = ?3",
+        )
+        .bind(bucket)
+        .bind(key)
+        .bind(internal_name)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Note a client's write of `bytes` to `bucket`/`key`: `owner` becomes
+    /// the key's owner and the write counts towards ingest volume.
+    pub async fn record_write(
+        &self,
+        bucket: &str,
+        key: &str,
+        owner: &str,
+        bytes: u64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE bucket_mappings SET owner = ?3 WHERE bucket = ?1 AND key = ?2")
+            .bind(bucket)
+            .bind(key)
+            .bind(owner)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("INSERT INTO ingest_log (bucket, key, owner, bytes) VALUES (?1, ?2, ?3, ?4)")
+            .bind(bucket)
+            .bind(key)
+            .bind(owner)
+            .bind(bytes as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Every mapping as `(bucket, key, internal_name, owner)`; keys stored
+    /// before owners were recorded, or by replication, have none.
+    pub async fn list_owned(
+        &self,
+    ) -> Result<Vec<(String, String, String, Option<String>)>, sqlx::Error> {
+        sqlx::query_as("SELECT bucket, key, internal_name, owner FROM bucket_mappings")
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Writes and bytes written since `since` (Unix seconds), summed per
+    /// `interval` seconds as `(start, writes, bytes)`, oldest first; intervals
+    /// without writes are left out.
+    pub async fn ingest_volume(
+        &self,
+        since: i64,
+        interval: i64,
+    ) -> Result<Vec<(i64, u64, u64)>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, (i64, i64, i64)>(
+            "SELECT (at / ?2) * ?2 AS start, COUNT(*), COALESCE(SUM(bytes), 0)
+             FROM ingest_log WHERE at >= ?1 GROUP BY start ORDER BY start",
+        )
+        .bind(since)
+        .bind(interval)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(start, writes, bytes)| (start, writes as u64, bytes as u64))
+            .collect())
+    }
+
     pub async fn delete(&self, bucket: &str, key: &str) -> Result<(), sqlx::Error> {
         sqlx::query("DELETE FROM bucket_mappings WHERE bucket = ?1 AND key = ?2")
             .bind(bucket)
@@ -75,6 +196,26 @@ impl BucketMapper {
         Ok(())
     }
 
+    /// Match keys in `bucket` against a glob (`*` any run, `?` one character),
+    /// case-sensitively. Returns at most `limit` `(key, internal_name)` pairs
+    /// ordered by key.
+    pub async fn match_keys(
+        &self,
+        bucket: &str,
+        pattern: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, String)>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, (String, String)>(
+            "SELECT key, internal_name FROM bucket_mappings WHERE bucket = ?1 AND key GLOB ?2 ORDER BY key LIMIT ?3",
+        )
+        .bind(bucket)
+        .bind(escape_glob(pattern))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
     pub async fn list_buckets(&self) -> Result<Vec<String>, sqlx::Error> {
         let rows = sqlx::query_scalar::<_, String>(
             "SELECT DISTINCT bucket FROM bucket_mappings ORDER BY bucket",
@@ -99,6 +240,39 @@ impl BucketMapper {
         .await?;
         Ok(rows)
     }
+
+    /// Mappings of every bucket ordered by bucket and key, at most `limit`
+    /// `(bucket, key, internal_name)` rows after `after` (from the start when
+    /// `None`), for walking the whole store page by page.
+    pub async fn list_after(
+        &self,
+        after: Option<(&str, &str)>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, String)>, sqlx::Error> {
+        let (bucket, key) = after.unwrap_or(("", ""));
+        let rows = sqlx::query_as::<_, (String, String, String)>(
+            "SELECT bucket, key, internal_name FROM bucket_mappings WHERE (bucket, key) > (?1, ?2) ORDER BY bucket, key LIMIT ?3",
+        )
+        .bind(bucket)
+        .bind(key)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+}
+
+/// Make a client glob safe for SQLite `GLOB`, which also treats `[...]` as a
+/// character class: only `*` and `?` stay wildcards.
+fn escape_glob(pattern: &str) -> String {
+    let mut glob = String::with_capacity(pattern.len());
+    for ch in pattern.chars() {
+        match ch {
+            '[' => glob.push_str("[[]"),
+            _ => glob.push(ch),
+        }
+    }
+    glob
 }
 
 pub async fn init_mapper(root: &Path) -> Result<(), sqlx::Error> {
@@ -113,3 +287,85 @@ pub async fn init_mapper(root: &Path) -> Result<(), sqlx::Error> {
 pub fn get_mapper() -> Option<Arc<BucketMapper>> {
     MAPPER.get().cloned()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_glob() {
+        assert_eq!(escape_glob("logs/*.txt"), "logs/*.txt");
+        assert_eq!(escape_glob("file?.bin"), "file?.bin");
+        assert_eq!(escape_glob("[a]%_done"), "[[]a]%_done");
+    }
+
+    #[tokio::test]
+    async fn test_match_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let mapper = BucketMapper::new(&dir.path().join("mappings.db")).await.unwrap();
+        mapper.register("b", "logs/a.txt", "id-1").await.unwrap();
+        mapper.register("b", "logs/b.txt", "id-2").await.unwrap();
+        mapper.register("b", "logs/c.bin", "id-3").await.unwrap();
+        mapper.register("b", "logs_x.txt", "id-4").await.unwrap();
+        mapper.register("other", "logs/d.txt", "id-5").await.unwrap();
+
+        let matched = mapper.match_keys("b", "logs/*.txt", 100).await.unwrap();
+        assert_eq!(
+            matched,
+            vec![
+                ("logs/a.txt".to_string(), "id-1".to_string()),
+                ("logs/b.txt".to_string(), "id-2".to_string()),
+            ]
+        );
+
+        // `_` is literal in globs, so it must not match `/`.
+        let literal = mapper.match_keys("b", "logs_*", 100).await.unwrap();
+        assert_eq!(literal.len(), 1);
+        assert_eq!(literal[0].0, "logs_x.txt");
+
+        let limited = mapper.match_keys("b", "*", 2).await.unwrap();
+        assert_eq!(limited.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_match_keys_is_case_sensitive() {
+        let dir = tempfile::tempdir().unwrap();
+        let mapper = BucketMapper::new(&dir.path().join("mappings.db")).await.unwrap();
+        mapper.register("b", "a.txt", "id-1").await.unwrap();
+        mapper.register("b", "A.txt", "id-2").await.unwrap();
+        mapper.register("b", "b.TXT", "id-3").await.unwrap();
+        mapper.register("b", "[x].log", "id-4").await.unwrap();
+        mapper.register("b", "x.log", "id-5").await.unwrap();
+
+        // What a pattern delete would remove
+        let matched = mapper.match_keys("b", "*.TXT", 100).await.unwrap();
+        for (key, _) in &matched {
+            mapper.delete("b", key).await.unwrap();
+        }
+        assert_eq!(matched, vec![("b.TXT".to_string(), "id-3".to_string())]);
+        assert_eq!(mapper.resolve("b", "a.txt").await.unwrap().as_deref(), Some("id-1"));
+        assert_eq!(mapper.resolve("b", "A.txt").await.unwrap().as_deref(), Some("id-2"));
+        assert_eq!(mapper.resolve("b", "b.TXT").await.unwrap(), None);
+
+        // Brackets are literal, not a character class
+        let bracket = mapper.match_keys("b", "[x]*", 100).await.unwrap();
+        assert_eq!(bracket, vec![("[x].log".to_string(), "id-4".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_list_after_pages_through_every_bucket() {
+        let dir = tempfile::tempdir().unwrap();
+        let mapper = BucketMapper::new(&dir.path().join("mappings.db")).await.unwrap();
+        mapper.register("b", "y", "id-1").await.unwrap();
+        mapper.register("a", "z", "id-2").await.unwrap();
+        mapper.register("b", "x", "id-3").await.unwrap();
+
+        let first = mapper.list_after(None, 2).await.unwrap();
+        let keys: Vec<_> = first.iter().map(|(b, k, _)| (b.as_str(), k.as_str())).collect();
+        assert_eq!(keys, vec![("a", "z"), ("b", "x")]);
+
+        let rest = mapper.list_after(Some(("b", "x")), 2).await.unwrap();
+        assert_eq!(rest, vec![("b".to_string(), "y".to_string(), "id-1".to_string())]);
+        assert!(mapper.list_after(Some(("b", "y")), 2).await.unwrap().is_empty());
+    }
+}
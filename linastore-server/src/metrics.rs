@@ -0,0 +1,357 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use serde_json::{Value, json};
+use tokio::net::UdpSocket;
+use tracing::{Level, event};
+
+use crate::{
+    config,
+    error::{Result, err_msg},
+    telemetry::{self, Endpoint},
+};
+
+/// Monotonic counter exported in the Prometheus text format.
+#[derive(Debug)]
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+pub static HTTP_REQUESTS: Counter =
+    Counter::new("linastore_http_requests_total", "HTTP requests received");
+pub static HTTP_RATE_LIMITED_IP: Counter = Counter::new(
+    "linastore_http_rate_limited_ip_total",
+    "HTTP requests rejected by the per-IP rate limit",
+);
+pub static HTTP_RATE_LIMITED_TOKEN: Counter = Counter::new(
+    "linastore_http_rate_limited_token_total",
+    "HTTP requests rejected by the per-credential rate limit",
+);
+
+pub static QUEUE_EXPIRED: Counter = Counter::new(
+    "linastore_queue_expired_total",
+    "Orders that expired before the porter answered them",
+);
+pub static QUEUE_UNDELIVERABLE: Counter = Counter::new(
+    "linastore_queue_undeliverable_total",
+    "Porter responses dropped because nobody was waiting for them",
+);
+
+pub static QUEUE_CANCELLED: Counter = Counter::new(
+    "linastore_queue_cancelled_total",
+    "Orders withdrawn because the client stopped waiting",
+);
+
+pub static TIER_RECALLS: Counter = Counter::new(
+    "linastore_tier_recalls_total",
+    "Files read back from the cold storage tier",
+);
+pub static TIER_DEMOTIONS: Counter = Counter::new(
+    "linastore_tier_demotions_total",
+    "Idle files moved to the cold storage tier",
+);
+
+pub static FEDERATION_HITS: Counter = Counter::new(
+    "linastore_federation_hits_total",
+    "Local misses answered by a federation peer",
+);
+
+static COUNTERS: &[&Counter] = &[
+    &HTTP_REQUESTS,
+    &HTTP_RATE_LIMITED_IP,
+    &HTTP_RATE_LIMITED_TOKEN,
+    &QUEUE_EXPIRED,
+    &QUEUE_UNDELIVERABLE,
+    &QUEUE_CANCELLED,
+    &TIER_RECALLS,
+    &TIER_DEMOTIONS,
+    &FEDERATION_HITS,
+];
+
+/// All counters in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    for counter in COUNTERS {
+        let _ = writeln!(out, "# HELP {} {}", counter.name, counter.help);
+        let _ = writeln!(out, "# TYPE {} counter", counter.name);
+        let _ = writeln!(out, "{} {}", counter.name, counter.get());
+    }
+    out
+}
+
+/// Every counter's name and current value.
+pub fn values() -> impl Iterator<Item = (&'static str, u64)> {
+    COUNTERS.iter().map(|counter| (counter.name, counter.get()))
+}
+
+/// Counter lines that fit one UDP datagram on common networks.
+const STATSD_DATAGRAM: usize = 1400;
+const STATSD_PORT: u16 = 8125;
+const DEFAULT_PUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Where counters are pushed; off without `LINASTORE_METRICS_PUSH`.
+#[derive(Debug, Clone)]
+pub struct PushSettings {
+    target: PushTarget,
+    interval: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PushTarget {
+    /// `host:port` of a StatsD daemon
+    Statsd(String),
+    /// An OTLP/HTTP collector
+    Otlp(Endpoint),
+}
+
+impl PushSettings {
+    pub fn load() -> Result<Option<Self>> {
+        let Some(raw) = config::var("LINASTORE_METRICS_PUSH")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let target = parse_target(raw.trim()).map_err(|reason| {
+            err_msg(config::attribute(format!(
+                "LINASTORE_METRICS_PUSH {}: {:?}",
+                reason, raw
+            )))
+        })?;
+        let interval = match config::var("LINASTORE_METRICS_PUSH_INTERVAL") {
+            Ok(raw) => match raw.trim().parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                _ => {
+                    return Err(err_msg(config::attribute(format!(
+                        "LINASTORE_METRICS_PUSH_INTERVAL must be a positive number of seconds: {:?}",
+                        raw
+                    ))));
+                }
+            },
+            Err(_) => DEFAULT_PUSH_INTERVAL,
+        };
+        Ok(Some(PushSettings { target, interval }))
+    }
+
+    /// Where and how often counters go, for the startup log.
+    pub fn describe(&self) -> String {
+        let url = match &self.target {
+            PushTarget::Statsd(address) => format!("statsd://{}", address),
+            PushTarget::Otlp(endpoint) => endpoint.url(),
+        };
+        format!("{} every {}s", url, self.interval.as_secs())
+    }
+}
+
+/// `statsd://host[:port]`, or an `http://` OTLP collector URL posting to
+/// `/v1/metrics`.
+fn parse_target(raw: &str) -> std::result::Result<PushTarget, String> {
+    if let Some(host) = raw.strip_prefix("statsd://") {
+        let host = host.trim_end_matches('/');
+        if host.is_empty() {
+            return Err("has no host".to_string());
+        }
+        let address = if host.ends_with(']') || !host.contains(':') {
+            format!("{}:{}", host, STATSD_PORT)
+        } else {
+            host.to_string()
+        };
+        return Ok(PushTarget::Statsd(address));
+    }
+    if raw.starts_with("http://") {
+        return telemetry::parse_endpoint(raw, "/v1/metrics").map(PushTarget::Otlp);
+    }
+    Err("must be a statsd:// or http:// URL".to_string())
+}
+
+/// Push the counters every interval until the process exits.
+pub async fn push(settings: PushSettings) {
+    let started = SystemTime::now();
+    let mut pushed = vec![0u64; COUNTERS.len()];
+    let mut ticker = tokio::time::interval(settings.interval);
+    // The first tick is immediate and would only send zeros
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let values: Vec<u64> = COUNTERS.iter().map(|counter| counter.get()).collect();
+        let sent = match &settings.target {
+            PushTarget::Statsd(address) => {
+                // StatsD counters add up what each push reports
+                let deltas: Vec<u64> = values
+                    .iter()
+                    .zip(&pushed)
+                    .map(|(value, last)| value.saturating_sub(*last))
+                    .collect();
+                send_statsd(address, &statsd_datagrams(&deltas)).await
+            }
+            PushTarget::Otlp(endpoint) => {
+                let body = encode_otlp(&telemetry::service_name(), started, &values).to_string();
+                telemetry::post(endpoint, body.as_bytes()).await
+            }
+        };
+        match sent {
+            Ok(()) => pushed = values,
+            Err(e) => event!(
+                Level::WARN,
+                "Failed to push metrics to {}: {}",
+                settings.describe(),
+                e
+            ),
+        }
+    }
+}
+
+/// `name:delta|c` lines, packed into datagrams.
+fn statsd_datagrams(deltas: &[u64]) -> Vec<String> {
+    let mut datagrams = vec![String::new()];
+    for (counter, delta) in COUNTERS.iter().zip(deltas) {
+        let line = format!("{}:{}|c", counter.name, delta);
+        let current = datagrams.last_mut().expect("never empty");
+        if !current.is_empty() && current.len() + 1 + line.len() > STATSD_DATAGRAM {
+            datagrams.push(line);
+        } else {
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(&line);
+        }
+    }
+    datagrams
+}
+
+async fn send_statsd(address: &str, datagrams: &[String]) -> std::io::Result<()> {
+    let target = tokio::net::lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::other("host did not resolve"))?;
+    let local = if target.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    };
+    let socket = UdpSocket::bind(local).await?;
+    for datagram in datagrams {
+        socket.send_to(datagram.as_bytes(), target).await?;
+    }
+    Ok(())
+}
+
+/// An `ExportMetricsServiceRequest` in the OTLP JSON encoding, with each
+/// counter as a cumulative monotonic sum since `started`.
+fn encode_otlp(service_name: &str, started: SystemTime, values: &[u64]) -> Value {
+    let start = telemetry::unix_nanos(started);
+    let now = telemetry::unix_nanos(SystemTime::now());
+    let metrics: Vec<Value> = COUNTERS
+        .iter()
+        .zip(values)
+        .map(|(counter, value)| {
+            json!({
+                "name": counter.name,
+                "description": counter.help,
+                "sum": {
+                    "dataPoints": [{
+                        "asInt": value.to_string(),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": now,
+                    }],
+                    // AGGREGATION_TEMPORALITY_CUMULATIVE
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                },
+            })
+        })
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [telemetry::attribute("service.name", &Value::from(service_name))],
+            },
+            "scopeMetrics": [{
+                "scope": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_includes_every_counter() {
+        static TEST_COUNTER: Counter = Counter::new("test_total", "test");
+        TEST_COUNTER.inc();
+        TEST_COUNTER.inc();
+        assert_eq!(TEST_COUNTER.get(), 2);
+
+        let text = render();
+        for counter in COUNTERS {
+            assert!(text.contains(&format!("# TYPE {} counter\n", counter.name)));
+        }
+    }
+
+    #[test]
+    fn test_parse_push_target() {
+        assert_eq!(
+            parse_target("statsd://localhost").unwrap(),
+            PushTarget::Statsd("localhost:8125".to_string())
+        );
+        assert_eq!(
+            parse_target("statsd://10.0.0.5:9125").unwrap(),
+            PushTarget::Statsd("10.0.0.5:9125".to_string())
+        );
+        let PushTarget::Otlp(endpoint) = parse_target("http://collector").unwrap() else {
+            panic!("expected an OTLP target");
+        };
+        assert_eq!(endpoint.url(), "http://collector/v1/metrics");
+        assert!(parse_target("udp://localhost").is_err());
+        assert!(parse_target("statsd://").is_err());
+    }
+
+    #[test]
+    fn test_push_encodings() {
+        let deltas: Vec<u64> = (0..COUNTERS.len() as u64).collect();
+        let datagrams = statsd_datagrams(&deltas);
+        assert_eq!(datagrams.len(), 1);
+        let lines: Vec<&str> = datagrams[0].lines().collect();
+        assert_eq!(lines[0], format!("{}:0|c", COUNTERS[0].name));
+        assert_eq!(lines[1], format!("{}:1|c", COUNTERS[1].name));
+
+        let encoded = encode_otlp("linastore", SystemTime::now(), &deltas);
+        let metric = &encoded["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][1];
+        assert_eq!(metric["name"], COUNTERS[1].name);
+        assert_eq!(metric["sum"]["dataPoints"][0]["asInt"], "1");
+        assert_eq!(metric["sum"]["isMonotonic"], true);
+    }
+}
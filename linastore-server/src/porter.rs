@@ -1,24 +1,32 @@
 use bytes::Bytes;
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use linabase::service::StoreManager;
+use linabase::{
+    StoreError,
+    blob::BlobBackend,
+    service::{BatchPut, DataStream, StoreManager, StoreOptions},
+};
 use tokio::{sync::Semaphore, task::JoinSet};
-use tracing::{Level, event, instrument};
+use tracing::{Instrument, Level, event, instrument};
 
 use crate::{
     conveyer::ConveyQueue,
-    dtos::{Behavior, FlagType, Package, Status},
+    dtos::{
+        Behavior, FileMetaDto, FlagType, GcReportDto, Package, ScrubReportDto, Status,
+        encode_batch_statuses, encode_put_receipt, flag_set, parse_put_condition,
+        parse_read_range, split_batch_names,
+    },
     shutdown::Shutdown,
 };
 
-#[inline]
-fn flag_set(flags: u8, bit: FlagType) -> bool {
-    (flags & bit as u8) != 0
-}
-
 // Error logging interval to avoid log flooding
 const ERROR_LOG_INTERVAL: u32 = 100;
 const MAX_PORTER_CONCURRENCY: usize = 8;
+// Queued puts stored together in one database transaction
+const PUT_BATCH_SIZE: usize = 32;
 
 fn porter_concurrency() -> usize {
     std::thread::available_parallelism()
@@ -28,17 +36,37 @@ fn porter_concurrency() -> usize {
 }
 
 #[instrument(skip_all)]
-pub async fn porter(root: &str) {
+pub async fn porter(
+    root: &str,
+    blobs: Option<Arc<dyn BlobBackend>>,
+    demote_every: Option<Duration>,
+    keep_versions: usize,
+) {
     event!(
         tracing::Level::INFO,
         "Porter started with transaction-based order processing"
     );
 
-    let store_manager = match StoreManager::new(root).await {
+    let mut options = StoreOptions::new(root).keep_versions(keep_versions);
+    if let Some(blobs) = blobs {
+        options = options.backend(blobs);
+    }
+    let store_manager = match options.open().await {
         Ok(store_manager) => Arc::new(store_manager),
-        Err(e) => panic!("{}", e.to_string()),
+        Err(e) => {
+            // Without storage no order can be answered; take the server down
+            event!(Level::ERROR, "[porter] Failed to open the store: {}", e);
+            Shutdown::get_instance().shutdown();
+            return;
+        }
     };
 
+    crate::systemd::started();
+
+    if let Some(interval) = demote_every {
+        tokio::spawn(demote_idle(Arc::clone(&store_manager), interval));
+    }
+
     let mut error_count = 0u32;
     let concurrency_limit = porter_concurrency();
     let in_flight_limit = Arc::new(Semaphore::new(concurrency_limit));
@@ -50,21 +78,78 @@ pub async fn porter(root: &str) {
     let mut order_notifier = conveyers.subscribe_orders();
 
     loop {
-        while !shutting_down && workers.len() < concurrency_limit {
+        // Once the front is drained, keep going until the queue is empty so
+        // no accepted order is dropped
+        let mut queue_empty = false;
+        while workers.len() < concurrency_limit {
             match conveyers.consume_order() {
                 Ok(Some(pkg)) => {
                     let Ok(permit) = in_flight_limit.clone().acquire_owned().await else {
                         shutting_down = true;
                         break;
                     };
+                    // A burst of puts shares one transaction
+                    let mut batch = vec![pkg];
+                    if batch[0].behavior == Behavior::PutFile {
+                        match conveyers.consume_orders_while(PUT_BATCH_SIZE - 1, |next| {
+                            next.behavior == Behavior::PutFile
+                        }) {
+                            Ok(more) => batch.extend(more),
+                            Err(e) => event!(Level::WARN, "[porter] Queue error: {}", e),
+                        }
+                    }
                     let store_manager = Arc::clone(&store_manager);
                     let conveyers = Arc::clone(&conveyers);
                     workers.spawn(async move {
                         let _permit = permit;
-                        process_package(&pkg, store_manager.as_ref(), &conveyers).await
+                        // Nobody is waiting for the answer any more
+                        let now = Instant::now();
+                        batch.retain(|pkg| {
+                            let abandoned = conveyers.is_cancelled(pkg.uni_id)
+                                || pkg.deadline.is_some_and(|deadline| deadline <= now);
+                            if abandoned {
+                                conveyers.complete_order(pkg.uni_id);
+                            }
+                            !abandoned
+                        });
+                        let span = match batch.first() {
+                            Some(first) => {
+                                let span = tracing::info_span!(
+                                    parent: &first.span,
+                                    "porter_process",
+                                    orders = batch.len()
+                                );
+                                for pkg in &batch[1..] {
+                                    span.follows_from(pkg.span.id());
+                                }
+                                span
+                            }
+                            None => tracing::Span::none(),
+                        };
+                        let result = match batch.as_slice() {
+                            [] => Ok(()),
+                            [pkg] => {
+                                process_package(pkg, store_manager.as_ref(), &conveyers)
+                                    .instrument(span)
+                                    .await
+                            }
+                            puts => {
+                                process_put_batch(puts, store_manager.as_ref(), &conveyers)
+                                    .instrument(span)
+                                    .await
+                            }
+                        };
+                        // Answered; a journaled put is not replayed
+                        for pkg in &batch {
+                            conveyers.complete_order(pkg.uni_id);
+                        }
+                        result
                     });
                 }
-                Ok(None) => break,
+                Ok(None) => {
+                    queue_empty = true;
+                    break;
+                }
                 Err(e) => {
                     error_count += 1;
                     if error_count % ERROR_LOG_INTERVAL == 0 {
@@ -81,12 +166,12 @@ pub async fn porter(root: &str) {
             }
         }
 
-        if shutting_down && workers.is_empty() {
+        if shutting_down && queue_empty && workers.is_empty() {
             break;
         }
 
         tokio::select! {
-            _ = shutdown_status.wait(), if !shutting_down => {
+            _ = shutdown_status.wait_front_drained(), if !shutting_down => {
                 shutting_down = true;
             }
             changed = order_notifier.changed(), if !shutting_down && workers.len() < concurrency_limit => {
@@ -121,9 +206,11 @@ pub async fn porter(root: &str) {
                     }
                 }
             }
+            // Draining and the queue keeps failing: nothing left to wait for
+            else => break,
         }
 
-        if shutdown_status.is_shutdown() {
+        if shutdown_status.is_front_drained() {
             shutting_down = true;
         }
     }
@@ -140,26 +227,75 @@ async fn process_package(
     res_pkg.content.identifier = pkg.content.identifier.clone();
     res_pkg.content.flags = pkg.content.flags;
 
-    // Optimize filename validation: use iterator to avoid repeated computation
-    let valid_data_end = pkg
-        .content
-        .identifier
-        .iter()
-        .position(|&b| b == 0)
-        .unwrap_or(pkg.content.identifier.len());
+    // Listings carry their names in the data field, not the identifier
+    if pkg.behavior == Behavior::ListFiles {
+        let mut metas = Vec::new();
+        for name in split_batch_names(&pkg.content.data) {
+            match store_manager.stat(&name).await {
+                Ok(Some(meta)) => metas.push(FileMetaDto::from(meta)),
+                Ok(None) => {}
+                Err(_) => {
+                    res_pkg.status = Status::InternalError;
+                    return send_response(res_pkg, conveyers);
+                }
+            }
+        }
+        match serde_json::to_vec(&metas) {
+            Ok(json) => {
+                res_pkg.status = Status::Success;
+                res_pkg.content.data = json.into();
+            }
+            Err(_) => res_pkg.status = Status::InternalError,
+        }
+        return send_response(res_pkg, conveyers);
+    }
 
-    if valid_data_end == 0 {
-        res_pkg.status = Status::FileNameInvalid;
-        return send_response(&res_pkg, conveyers);
+    // Bulk deletes carry their names in the data field, not the identifier
+    if pkg.behavior == Behavior::DeleteFiles {
+        let mut items = Vec::new();
+        for name in split_batch_names(&pkg.content.data) {
+            let status = delete_status(store_manager.delete(&name, false).await);
+            items.push((status, name));
+        }
+        res_pkg.status = Status::Success;
+        res_pkg.content.data = encode_batch_statuses(&items);
+        return send_response(res_pkg, conveyers);
     }
 
-    let identifier_bytes = &pkg.content.identifier[..valid_data_end];
-    let identifier = match std::str::from_utf8(identifier_bytes) {
-        Ok(s) => s.to_string(),
-        Err(_) => {
-            res_pkg.status = Status::FileNameInvalid;
-            return send_response(&res_pkg, conveyers);
+    // Usage reports address the whole store rather than one file
+    if pkg.behavior == Behavior::Sizes {
+        match store_manager.sizes().await.map(|sizes| serde_json::to_vec(&sizes)) {
+            Ok(Ok(json)) => {
+                res_pkg.status = Status::Success;
+                res_pkg.content.data = json.into();
+            }
+            Ok(Err(_)) => res_pkg.status = Status::InternalError,
+            Err(e) => {
+                event!(Level::ERROR, "[porter] Listing sizes failed: {}", e);
+                res_pkg.status = Status::InternalError;
+            }
         }
+        return send_response(res_pkg, conveyers);
+    }
+
+    // Maintenance addresses the whole store rather than one file
+    if matches!(pkg.behavior, Behavior::CollectGarbage | Behavior::Scrub) {
+        match run_maintenance(&pkg.behavior, store_manager).await {
+            Ok(json) => {
+                res_pkg.status = Status::Success;
+                res_pkg.content.data = json.into();
+            }
+            Err(e) => {
+                event!(Level::ERROR, "[porter] {:?} failed: {}", pkg.behavior, e);
+                res_pkg.status = Status::InternalError;
+            }
+        }
+        return send_response(res_pkg, conveyers);
+    }
+
+    let Some(identifier) = package_identifier(pkg) else {
+        res_pkg.status = Status::FileNameInvalid;
+        return send_response(res_pkg, conveyers);
     };
 
     // SQLite serial processing: each operation is independent to avoid transaction conflicts
@@ -169,19 +305,42 @@ async fn process_package(
             let should_cover = flag_set(flags, FlagType::Cover);
             let should_compress = flag_set(flags, FlagType::Compress);
 
-            match store_manager.put_binary_data(
-                &identifier,
-                &pkg.content.data,
-                should_cover,
-                should_compress,
-            ).await {
-                Ok(_) => {
+            if flag_set(flags, FlagType::Conditional) {
+                let Some((condition, data)) = parse_put_condition(&pkg.content.data) else {
+                    res_pkg.status = Status::BadRequest;
+                    return send_response(res_pkg, conveyers);
+                };
+                res_pkg.status = match store_manager
+                    .put_binary_data_if(&identifier, &data, should_compress, condition)
+                    .await
+                {
+                    Ok(Some(outcome)) => {
+                        res_pkg.content.data = encode_put_receipt(&outcome);
+                        Status::Success
+                    }
+                    Ok(None) => Status::PreconditionFailed,
+                    Err(_) => Status::StoreFailed,
+                };
+                return send_response(res_pkg, conveyers);
+            }
+
+            match store_manager
+                .put_binary_data(
+                    &identifier,
+                    &pkg.content.data,
+                    should_cover,
+                    should_compress,
+                )
+                .await
+            {
+                Ok(outcome) => {
                     res_pkg.status = Status::Success;
-                    send_response(&res_pkg, conveyers)
+                    res_pkg.content.data = encode_put_receipt(&outcome);
+                    send_response(res_pkg, conveyers)
                 }
                 Err(_) => {
                     res_pkg.status = Status::StoreFailed;
-                    send_response(&res_pkg, conveyers)
+                    send_response(res_pkg, conveyers)
                 }
             }
         }
@@ -189,33 +348,330 @@ async fn process_package(
             Ok(data) => {
                 res_pkg.status = Status::Success;
                 res_pkg.content.data = Bytes::from(data);
-                send_response(&res_pkg, conveyers)
+                send_response(res_pkg, conveyers)
             }
             Err(_) => {
                 res_pkg.status = Status::FileNotFound;
-                send_response(&res_pkg, conveyers)
+                send_response(res_pkg, conveyers)
             }
         },
-        Behavior::DeleteFile => match store_manager.delete(&identifier, false).await {
-            Ok(_) => {
-                res_pkg.status = Status::Success;
-                send_response(&res_pkg, conveyers)
+        Behavior::StreamFile | Behavior::StreamFileGzip => {
+            let gzip = if pkg.behavior == Behavior::StreamFileGzip {
+                match store_manager.stat(&identifier).await {
+                    Ok(Some(meta)) => {
+                        meta.compressed || flag_set(pkg.content.flags, FlagType::Compress)
+                    }
+                    Ok(None) => {
+                        res_pkg.status = Status::FileNotFound;
+                        return send_response(res_pkg, conveyers);
+                    }
+                    Err(_) => {
+                        res_pkg.status = Status::InternalError;
+                        return send_response(res_pkg, conveyers);
+                    }
+                }
+            } else {
+                false
+            };
+            match store_manager.get_binary_stream(&identifier, gzip).await {
+                Ok(stream) => {
+                    res_pkg.content.flags = if gzip {
+                        pkg.content.flags | FlagType::Compress as u8
+                    } else {
+                        pkg.content.flags & !(FlagType::Compress as u8)
+                    };
+                    send_stream_response(res_pkg, stream, conveyers)
+                }
+                Err(_) => {
+                    res_pkg.status = Status::FileNotFound;
+                    send_response(res_pkg, conveyers)
+                }
+            }
+        }
+        Behavior::StreamFileRange => {
+            let Some(range) = parse_read_range(&pkg.content.data) else {
+                res_pkg.status = Status::BadRequest;
+                return send_response(res_pkg, conveyers);
+            };
+            let meta = match store_manager.stat(&identifier).await {
+                Ok(Some(meta)) => meta,
+                Ok(None) => {
+                    res_pkg.status = Status::FileNotFound;
+                    return send_response(res_pkg, conveyers);
+                }
+                Err(_) => {
+                    res_pkg.status = Status::InternalError;
+                    return send_response(res_pkg, conveyers);
+                }
+            };
+            let (size, hash256) = (meta.size, meta.hash256.clone());
+            let Ok(json) = serde_json::to_vec(&FileMetaDto::from(meta)) else {
+                res_pkg.status = Status::InternalError;
+                return send_response(res_pkg, conveyers);
+            };
+            res_pkg.status = Status::Success;
+            res_pkg.content.data = json.into();
+            // The metadata alone lets the caller refuse a range with no bytes
+            let Some((offset, len)) = range.resolve(size) else {
+                return send_response(res_pkg, conveyers);
+            };
+            match store_manager.get_range_stream(&identifier, offset, len).await {
+                Ok(stream) if stream.hash256 == hash256 => {
+                    send_stream_response(res_pkg, stream, conveyers)
+                }
+                // Replaced since the lookup, so the bytes would not match it
+                Ok(_) | Err(StoreError::InvalidInput(_)) => send_response(res_pkg, conveyers),
+                Err(StoreError::NotFound(_)) => {
+                    res_pkg.status = Status::FileNotFound;
+                    send_response(res_pkg, conveyers)
+                }
+                Err(_) => {
+                    res_pkg.status = Status::InternalError;
+                    send_response(res_pkg, conveyers)
+                }
             }
+        }
+        Behavior::StreamBlob => match store_manager.get_blob_stream(&identifier, false).await {
+            Ok(stream) => send_stream_response(res_pkg, stream, conveyers),
             Err(_) => {
                 res_pkg.status = Status::FileNotFound;
-                send_response(&res_pkg, conveyers)
+                send_response(res_pkg, conveyers)
             }
         },
+        Behavior::StatFile => match store_manager.stat(&identifier).await {
+            Ok(Some(meta)) => match serde_json::to_vec(&FileMetaDto::from(meta)) {
+                Ok(json) => {
+                    res_pkg.status = Status::Success;
+                    res_pkg.content.data = json.into();
+                    send_response(res_pkg, conveyers)
+                }
+                Err(_) => {
+                    res_pkg.status = Status::InternalError;
+                    send_response(res_pkg, conveyers)
+                }
+            },
+            Ok(None) => {
+                res_pkg.status = Status::FileNotFound;
+                send_response(res_pkg, conveyers)
+            }
+            Err(_) => {
+                res_pkg.status = Status::InternalError;
+                send_response(res_pkg, conveyers)
+            }
+        },
+        Behavior::DeleteFile => {
+            res_pkg.status = delete_status(store_manager.delete(&identifier, false).await);
+            send_response(res_pkg, conveyers)
+        }
         _ => {
             res_pkg.status = Status::InternalError;
-            send_response(&res_pkg, conveyers)
+            send_response(res_pkg, conveyers)
         }
     }
 }
 
+/// Store several `PutFile` orders in one transaction and answer each.
+async fn process_put_batch(
+    pkgs: &[Package],
+    store_manager: &StoreManager,
+    conveyers: &ConveyQueue,
+) -> Result<(), String> {
+    let mut responses = Vec::with_capacity(pkgs.len());
+    let mut puts = Vec::with_capacity(pkgs.len());
+    for pkg in pkgs {
+        let mut res_pkg = Package::new();
+        res_pkg.uni_id = pkg.uni_id;
+        res_pkg.content.identifier = pkg.content.identifier.clone();
+        res_pkg.content.flags = pkg.content.flags;
+
+        let flags = pkg.content.flags;
+        let conditional = flag_set(flags, FlagType::Conditional);
+        let parsed = if conditional {
+            parse_put_condition(&pkg.content.data).map(|(condition, data)| (Some(condition), data))
+        } else {
+            Some((None, pkg.content.data.clone()))
+        };
+        match (package_identifier(pkg), parsed) {
+            (None, _) => res_pkg.status = Status::FileNameInvalid,
+            (Some(_), None) => res_pkg.status = Status::BadRequest,
+            (Some(file_name), Some((condition, input))) => puts.push((
+                responses.len(),
+                BatchPut {
+                    file_name,
+                    input,
+                    cover: flag_set(flags, FlagType::Cover),
+                    compressed: flag_set(flags, FlagType::Compress),
+                    condition,
+                },
+            )),
+        }
+        responses.push(res_pkg);
+    }
+
+    let (slots, puts): (Vec<usize>, Vec<BatchPut>) = puts.into_iter().unzip();
+    match store_manager.put_batch(&puts).await {
+        Ok(results) => {
+            for (slot, result) in slots.into_iter().zip(results) {
+                let res_pkg = &mut responses[slot];
+                res_pkg.status = match result {
+                    Ok(Some(outcome)) => {
+                        res_pkg.content.data = encode_put_receipt(&outcome);
+                        Status::Success
+                    }
+                    Ok(None) => Status::PreconditionFailed,
+                    Err(_) => Status::StoreFailed,
+                };
+            }
+        }
+        Err(_) => {
+            for slot in slots {
+                responses[slot].status = Status::StoreFailed;
+            }
+        }
+    }
+
+    let mut outcome = Ok(());
+    for res_pkg in responses {
+        if let Err(e) = send_response(res_pkg, conveyers) {
+            outcome = Err(e);
+        }
+    }
+    outcome
+}
+
+/// Collect garbage or scrub the store, answering with the JSON report.
+async fn run_maintenance(
+    behavior: &Behavior,
+    store_manager: &StoreManager,
+) -> Result<Vec<u8>, String> {
+    let json = if *behavior == Behavior::CollectGarbage {
+        let report = store_manager
+            .collect_garbage()
+            .await
+            .map_err(|e| e.to_string())?;
+        event!(
+            Level::INFO,
+            "[porter] Garbage collection removed {} temp file(s), {} tombstone(s), {} orphan(s)",
+            report.removed_tmp,
+            report.removed_tombstones,
+            report.removed_orphans
+        );
+        serde_json::to_vec(&GcReportDto::from(report))
+    } else {
+        let report = store_manager.scrub().await.map_err(|e| e.to_string())?;
+        if report.damaged.is_empty() {
+            event!(
+                Level::INFO,
+                "[porter] Scrub verified {} source(s)",
+                report.checked
+            );
+        } else {
+            event!(
+                Level::WARN,
+                "[porter] Scrub verified {} source(s), damaged files: {}",
+                report.checked,
+                report.damaged.join(", ")
+            );
+        }
+        serde_json::to_vec(&ScrubReportDto::from(report))
+    };
+    json.map_err(|e| e.to_string())
+}
+
+/// The file name in `pkg`'s identifier, up to the first NUL; `None` when it
+/// is empty or not UTF-8.
+fn package_identifier(pkg: &Package) -> Option<String> {
+    let valid_data_end = pkg
+        .content
+        .identifier
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(pkg.content.identifier.len());
+    if valid_data_end == 0 {
+        return None;
+    }
+    std::str::from_utf8(&pkg.content.identifier[..valid_data_end])
+        .ok()
+        .map(str::to_string)
+}
+
+/// Status of a delete: only a missing file reads as `FileNotFound`, so a
+/// failed store isn't mistaken for a file that is already gone.
+fn delete_status(result: Result<(), StoreError>) -> Status {
+    match result {
+        Ok(()) => Status::Success,
+        Err(StoreError::NotFound(_)) => Status::FileNotFound,
+        Err(StoreError::ReadOnly) => Status::ReadOnly,
+        Err(e) => {
+            event!(Level::ERROR, "[porter] Delete failed: {}", e);
+            Status::InternalError
+        }
+    }
+}
+
+/// Attach `stream` to the queue and answer with `Success`.
+fn send_stream_response(
+    mut res_pkg: Package,
+    stream: DataStream,
+    conveyers: &ConveyQueue,
+) -> Result<(), String> {
+    let uni_id = res_pkg.uni_id;
+    res_pkg.status = Status::Success;
+    conveyers.attach_stream(uni_id, stream);
+    let sent = send_response(res_pkg, conveyers);
+    if sent.is_err() {
+        // Nobody is waiting; close the reader
+        conveyers.take_stream(uni_id);
+    }
+    sent
+}
+
 /// Unified response sending function to reduce code duplication
-fn send_response(res_pkg: &Package, conveyers: &ConveyQueue) -> Result<(), String> {
+fn send_response(res_pkg: Package, conveyers: &ConveyQueue) -> Result<(), String> {
     conveyers
-        .produce_service(res_pkg.clone())
+        .produce_service(res_pkg)
         .map_err(|e| format!("Failed to send response: {}", e))
 }
+
+/// Move content nobody has accessed lately to the cold storage tier, every
+/// `interval` until shutdown.
+async fn demote_idle(store_manager: Arc<StoreManager>, interval: Duration) {
+    let shutdown = Shutdown::get_instance();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick completes at once; nothing is idle yet
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return,
+        }
+        match store_manager.demote_idle().await {
+            Ok(0) => {}
+            Ok(moved) => {
+                crate::metrics::TIER_DEMOTIONS.add(moved);
+                event!(Level::INFO, "[porter] Moved {} idle file(s) to the cold tier", moved);
+            }
+            Err(e) => event!(Level::WARN, "[porter] Failed to move idle files to the cold tier: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delete_status() {
+        assert_eq!(delete_status(Ok(())), Status::Success);
+        assert_eq!(
+            delete_status(Err(StoreError::NotFound("gone".into()))),
+            Status::FileNotFound
+        );
+        assert_eq!(delete_status(Err(StoreError::ReadOnly)), Status::ReadOnly);
+        assert_eq!(
+            delete_status(Err(StoreError::Io(std::io::Error::other("disk")))),
+            Status::InternalError
+        );
+    }
+}
@@ -0,0 +1,515 @@
+//! Replication: a follower pulls every key of its leader over the advanced
+//! protocol, so it stays a warm standby without external tooling.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::MissedTickBehavior;
+use tracing::{Level, event};
+use uuid::Uuid;
+
+use crate::{
+    config,
+    conveyer::ConveyQueue,
+    dtos::{
+        Behavior, Content, FileMetaDto, FlagType, LOCAL_ONLY, LiNaProtocol, MANIFEST_PAGE_LEN,
+        ManifestEntry, ManifestPage, Package, Status,
+    },
+    error::{Context, Result, err_msg},
+    mapper::BucketMapper,
+    shutdown::Shutdown,
+};
+
+const DEFAULT_LEADER_PORT: &str = "8096";
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the leader may take to answer one request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Which server to follow; off without `LINASTORE_REPLICA_OF`.
+#[derive(Debug, Clone)]
+pub struct ReplicaSettings {
+    /// `host:port` of the leader's advanced service
+    leader: String,
+    interval: Duration,
+    /// Account to sign in to the leader with, when it requires credentials
+    credentials: Option<(String, String)>,
+}
+
+impl ReplicaSettings {
+    pub fn load() -> Result<Option<Self>> {
+        let Some(raw) = config::var("LINASTORE_REPLICA_OF")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let leader = crate::vars::with_port(raw.trim(), DEFAULT_LEADER_PORT);
+        let interval = match config::var("LINASTORE_REPLICA_INTERVAL") {
+            Ok(raw) => match raw.trim().parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                _ => {
+                    return Err(err_msg(config::attribute(format!(
+                        "LINASTORE_REPLICA_INTERVAL must be a positive number of seconds: {:?}",
+                        raw
+                    ))));
+                }
+            },
+            Err(_) => DEFAULT_INTERVAL,
+        };
+        let user = config::var("LINASTORE_REPLICA_USER")
+            .ok()
+            .filter(|v| !v.trim().is_empty());
+        let password = config::var("LINASTORE_REPLICA_PASSWORD")
+            .ok()
+            .filter(|v| !v.is_empty());
+        let credentials = match (user, password) {
+            (Some(user), Some(password)) => Some((user, password)),
+            (None, None) => None,
+            _ => {
+                return Err(err_msg(config::attribute(
+                    "LINASTORE_REPLICA_USER and LINASTORE_REPLICA_PASSWORD must be set together"
+                        .to_string(),
+                )));
+            }
+        };
+        Ok(Some(ReplicaSettings {
+            leader,
+            interval,
+            credentials,
+        }))
+    }
+
+    /// Whom and how often the follower pulls from, for the startup log.
+    pub fn describe(&self) -> String {
+        format!("{} every {}s", self.leader, self.interval.as_secs())
+    }
+}
+
+/// A connection to another server's advanced service: the leader, or a
+/// federation peer.
+pub(crate) struct Peer {
+    stream: TcpStream,
+    token: Option<String>,
+}
+
+impl Peer {
+    /// Connect to `address` (`host:port`), signing in when `credentials` are given.
+    pub(crate) async fn connect(
+        address: &str,
+        credentials: Option<&(String, String)>,
+    ) -> Result<Self> {
+        let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address))
+            .await
+            .map_err(|_| err_msg("timed out connecting"))?
+            .context("failed to connect")?;
+        let mut peer = Peer {
+            stream,
+            token: None,
+        };
+        if let Some((user, password)) = credentials {
+            let (status, data) = peer
+                .call(FlagType::Auth as u8, user.as_bytes(), password.as_bytes())
+                .await?;
+            if status != Status::Success as u8 {
+                return Err(err_msg(format!(
+                    "sign-in as {} refused with status {}",
+                    user, status
+                )));
+            }
+            let token = handshake_token(&data).context("sign-in answer carries no token")?;
+            peer.token = Some(token);
+        }
+        Ok(peer)
+    }
+
+    /// The session token, then `rest`, as read and manifest requests carry them.
+    fn with_token(&self, rest: &[u8]) -> Vec<u8> {
+        let token = self.token.as_deref().unwrap_or_default();
+        let mut data = Vec::with_capacity(token.len() + 1 + rest.len());
+        data.extend_from_slice(token.as_bytes());
+        data.push(0);
+        data.extend_from_slice(rest);
+        data
+    }
+
+    /// Send one request and read the status and data of its response.
+    async fn call(&mut self, flags: u8, identifier: &[u8], data: &[u8]) -> Result<(u8, Bytes)> {
+        let mut request = LiNaProtocol::new();
+        request.flags = flags;
+        request.payload.ilen = u8::try_from(identifier.len())
+            .map_err(|_| err_msg("identifier longer than 255 bytes"))?;
+        request.payload.identifier = Bytes::copy_from_slice(identifier);
+        request.payload.dlen = data.len() as u32;
+        request.payload.data = Bytes::copy_from_slice(data);
+        request.payload.checksum = request.calculate_checksum();
+        self.stream
+            .write_all(&request.serialize_request_message())
+            .await
+            .context("failed to send request")?;
+        tokio::time::timeout(RESPONSE_TIMEOUT, self.read_response())
+            .await
+            .map_err(|_| err_msg("timed out waiting for a response"))?
+    }
+
+    async fn read_response(&mut self) -> Result<(u8, Bytes)> {
+        let mut response = LiNaProtocol::new();
+        let status = self
+            .stream
+            .read_u8()
+            .await
+            .context("failed to read response")?;
+        response.payload.ilen = self
+            .stream
+            .read_u8()
+            .await
+            .context("failed to read response")?;
+        let mut identifier = vec![0u8; response.payload.ilen as usize];
+        self.stream
+            .read_exact(&mut identifier)
+            .await
+            .context("failed to read response")?;
+        response.payload.identifier = Bytes::from(identifier);
+        response.payload.dlen = self
+            .stream
+            .read_u32_le()
+            .await
+            .context("failed to read response")?;
+        let max_payload_size = crate::vars::EnvVar::get_instance().max_payload_size;
+        if response.payload.dlen as usize > max_payload_size {
+            return Err(err_msg(format!(
+                "response of {} bytes exceeds LINASTORE_MAX_PAYLOAD_SIZE",
+                response.payload.dlen
+            )));
+        }
+        response.payload.checksum = self
+            .stream
+            .read_u32_le()
+            .await
+            .context("failed to read response")?;
+        let mut data = vec![0u8; response.payload.dlen as usize];
+        self.stream
+            .read_exact(&mut data)
+            .await
+            .context("failed to read response")?;
+        response.payload.data = Bytes::from(data);
+        if !response.verify() {
+            return Err(err_msg("response has an invalid checksum"));
+        }
+        Ok((status, response.payload.data))
+    }
+
+    /// The page of the leader's keys after `cursor`.
+    async fn manifest(&mut self, cursor: Option<&(String, String)>) -> Result<ManifestPage> {
+        let cursor = cursor
+            .map(|(bucket, key)| format!("{}\0{}", bucket, key))
+            .unwrap_or_default();
+        let data = self.with_token(cursor.as_bytes());
+        let flags = FlagType::Read as u8 | FlagType::Batch as u8;
+        let (status, page) = self.call(flags, b"", &data).await?;
+        if status != Status::Success as u8 {
+            return Err(err_msg(format!(
+                "manifest read refused with status {}",
+                status
+            )));
+        }
+        serde_json::from_slice(&page).context("invalid manifest")
+    }
+
+    /// What the server itself holds under `bucket`/`key`, or `None` when it
+    /// has nothing there; it does not ask its own peers.
+    pub(crate) async fn fetch(&mut self, bucket: &str, key: &str) -> Result<Option<Bytes>> {
+        let identifier = format!("{}\0{}", bucket, key);
+        let data = self.with_token(LOCAL_ONLY);
+        let (status, content) = self
+            .call(FlagType::Read as u8, identifier.as_bytes(), &data)
+            .await
+            .map_err(|e| err_msg(format!("failed to fetch {}/{}: {}", bucket, key, e)))?;
+        match status {
+            s if s == Status::Success as u8 => Ok(Some(content)),
+            s if s == Status::FileNotFound as u8 => Ok(None),
+            s => Err(err_msg(format!(
+                "fetching {}/{} refused with status {}",
+                bucket, key, s
+            ))),
+        }
+    }
+}
+
+/// The token in a successful handshake answer:
+/// `status(1) + token + '\0' + expires_at`.
+fn handshake_token(data: &[u8]) -> Option<String> {
+    let rest = data.get(1..)?;
+    let token = rest.split(|&b| b == 0).next()?;
+    std::str::from_utf8(token)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+}
+
+/// What one replication round changed.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RoundReport {
+    copied: u64,
+    removed: u64,
+    unchanged: u64,
+}
+
+/// Keep this server a read-only copy of the leader, one round every
+/// interval, until shutdown. Turning read-only off through the admin API
+/// promotes the follower: no rounds run until it is read-only again.
+pub async fn follow(settings: ReplicaSettings) {
+    crate::admin::set_read_only(true);
+    let shutdown = Shutdown::get_instance();
+    let mut ticker = tokio::time::interval(settings.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut promoted = false;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait() => return,
+        }
+        if !crate::admin::is_read_only() {
+            if !promoted {
+                event!(
+                    Level::WARN,
+                    "Read-only mode is off, no longer replicating from {}",
+                    settings.leader
+                );
+                promoted = true;
+            }
+            continue;
+        }
+        if promoted {
+            event!(
+                Level::INFO,
+                "Read-only again, replicating from {}",
+                settings.leader
+            );
+            promoted = false;
+        }
+
+        let started = Instant::now();
+        match replicate(&settings).await {
+            Ok(report) => {
+                let summary = format!(
+                    "Replicated from {} in {:.1}s: {} copied, {} removed, {} unchanged",
+                    settings.leader,
+                    started.elapsed().as_secs_f64(),
+                    report.copied,
+                    report.removed,
+                    report.unchanged
+                );
+                // A round that changed nothing is not worth a line at info
+                if report.copied + report.removed > 0 {
+                    event!(Level::INFO, "{}", summary);
+                } else {
+                    event!(Level::DEBUG, "{}", summary);
+                }
+            }
+            Err(e) => event!(
+                Level::WARN,
+                "Replication from {} failed: {}",
+                settings.leader,
+                e
+            ),
+        }
+    }
+}
+
+/// One round: copy every key the leader holds differently, then remove the
+/// keys it no longer has.
+async fn replicate(settings: &ReplicaSettings) -> Result<RoundReport> {
+    let mapper = crate::mapper::get_mapper().context("bucket mapper unavailable")?;
+    let shutdown = Shutdown::get_instance();
+    let mut leader = Peer::connect(&settings.leader, settings.credentials.as_ref()).await?;
+    let mut report = RoundReport::default();
+    let mut on_leader = HashSet::new();
+    let mut cursor = None;
+    loop {
+        let page = leader.manifest(cursor.as_ref()).await?;
+        let local = local_hashes(&mapper, &page.entries).await?;
+        for entry in page.entries {
+            if shutdown.is_shutdown() {
+                return Err(err_msg("stopped for shutdown"));
+            }
+            let key = (entry.bucket.clone(), entry.key.clone());
+            if local.get(&key) == Some(&entry.hash) {
+                report.unchanged += 1;
+            } else if let Some(content) = leader.fetch(&entry.bucket, &entry.key).await? {
+                store(&mapper, &entry, content).await?;
+                report.copied += 1;
+            }
+            on_leader.insert(key);
+        }
+        match page.next {
+            Some(next) => cursor = Some(next),
+            None => break,
+        }
+    }
+    // Only a manifest read to the end shows which keys are gone
+    report.removed = remove_missing(&mapper, &on_leader).await?;
+    Ok(report)
+}
+
+/// Hash of what this server holds under each key of `entries` it has.
+async fn local_hashes(
+    mapper: &BucketMapper,
+    entries: &[ManifestEntry],
+) -> Result<HashMap<(String, String), String>> {
+    let mut keys_by_name = HashMap::new();
+    for entry in entries {
+        if let Some(internal) = mapper
+            .resolve(&entry.bucket, &entry.key)
+            .await
+            .context("mapping lookup failed")?
+        {
+            keys_by_name.insert(internal, (entry.bucket.clone(), entry.key.clone()));
+        }
+    }
+    if keys_by_name.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let names: Vec<&str> = keys_by_name.keys().map(String::as_str).collect();
+    let mut order = Package::new_with_id(&Uuid::new_v4());
+    order.behavior = Behavior::ListFiles;
+    order.content.data = Bytes::from(names.join("\0"));
+    let response = ConveyQueue::get_instance()
+        .request(order)
+        .await
+        .map_err(|e| err_msg(format!("failed to stat local files: {}", e)))?;
+    if response.status != Status::Success {
+        return Err(err_msg(format!(
+            "failed to stat local files: {:?}",
+            response.status
+        )));
+    }
+    let metas: Vec<FileMetaDto> =
+        serde_json::from_slice(&response.content.data).context("invalid local file listing")?;
+    Ok(metas
+        .into_iter()
+        .filter_map(|meta| keys_by_name.remove(&meta.name).map(|key| (key, meta.hash)))
+        .collect())
+}
+
+/// Store `content` under the entry's key, compressed if the leader's copy is.
+async fn store(mapper: &BucketMapper, entry: &ManifestEntry, content: Bytes) -> Result<()> {
+    // Overwrite the object already behind the key, as a client's write would
+    let internal_name = match mapper
+        .resolve(&entry.bucket, &entry.key)
+        .await
+        .context("mapping lookup failed")?
+    {
+        Some(existing) => existing,
+        None => {
+            let internal_name = Uuid::new_v4().to_string();
+            mapper
+                .register(&entry.bucket, &entry.key, &internal_name)
+                .await
+                .context("failed to register mapping")?;
+            internal_name
+        }
+    };
+
+    let mut flags = FlagType::Write as u8 | FlagType::Cover as u8;
+    if entry.compressed {
+        flags |= FlagType::Compress as u8;
+    }
+    let mut order = Package::new_with_id(&Uuid::new_v4());
+    order.behavior = Behavior::PutFile;
+    order.content = Content {
+        flags,
+        identifier: Bytes::from(internal_name),
+        data: content,
+    };
+    let response = ConveyQueue::get_instance()
+        .request_replicated(order)
+        .await
+        .map_err(|e| {
+            err_msg(format!(
+                "failed to store {}/{}: {}",
+                entry.bucket, entry.key, e
+            ))
+        })?;
+    if response.status != Status::Success {
+        return Err(err_msg(format!(
+            "failed to store {}/{}: {:?}",
+            entry.bucket, entry.key, response.status
+        )));
+    }
+    Ok(())
+}
+
+/// Remove every key this server holds that is not in `on_leader`.
+async fn remove_missing(
+    mapper: &BucketMapper,
+    on_leader: &HashSet<(String, String)>,
+) -> Result<u64> {
+    let mut removed = 0;
+    let mut after: Option<(String, String)> = None;
+    loop {
+        let rows = mapper
+            .list_after(
+                after
+                    .as_ref()
+                    .map(|(bucket, key)| (bucket.as_str(), key.as_str())),
+                MANIFEST_PAGE_LEN,
+            )
+            .await
+            .context("mapping listing failed")?;
+        let Some((bucket, key, _)) = rows.last() else {
+            break;
+        };
+        after = Some((bucket.clone(), key.clone()));
+
+        for (bucket, key, internal_name) in rows {
+            let key = (bucket, key);
+            if on_leader.contains(&key) {
+                continue;
+            }
+            let (bucket, key) = key;
+            let mut order = Package::new_with_id(&Uuid::new_v4());
+            order.behavior = Behavior::DeleteFile;
+            order.content.identifier = Bytes::from(internal_name);
+            let response = ConveyQueue::get_instance()
+                .request_replicated(order)
+                .await
+                .map_err(|e| err_msg(format!("failed to remove {}/{}: {}", bucket, key, e)))?;
+            // A file already gone only leaves its mapping to drop
+            if !matches!(response.status, Status::Success | Status::FileNotFound) {
+                return Err(err_msg(format!(
+                    "failed to remove {}/{}: {:?}",
+                    bucket, key, response.status
+                )));
+            }
+            mapper
+                .delete(&bucket, &key)
+                .await
+                .context("failed to remove mapping")?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake_token() {
+        assert_eq!(
+            handshake_token(b"\0tok-123\x001700000000").as_deref(),
+            Some("tok-123")
+        );
+        assert_eq!(handshake_token(b"\0tok-123").as_deref(), Some("tok-123"));
+        assert_eq!(handshake_token(b"\0\x001700000000"), None);
+        assert_eq!(handshake_token(b""), None);
+    }
+}
@@ -0,0 +1,372 @@
+//! Usage reports: what the store holds broken down by extension, bucket and
+//! owner, with ingest volume over time, for `GET /api/admin/report` and the
+//! `report` command.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use linabase::service::StoreOptions;
+use serde::Serialize;
+use tracing::{Level, event};
+
+use crate::{
+    dtos::put_receipt_size,
+    error::{Context, Result, err_msg},
+    mapper::BucketMapper,
+};
+
+/// Files without an extension are counted under this name.
+const NO_EXTENSION: &str = "(none)";
+/// Keys nobody is recorded as writing are counted under this owner.
+const UNKNOWN_OWNER: &str = "(unknown)";
+
+pub const DEFAULT_INTERVAL: u64 = 24 * 3600;
+pub const DEFAULT_WINDOW: u64 = 30 * 24 * 3600;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// Client writes within one interval.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IngestInterval {
+    /// Start of the interval, Unix seconds.
+    pub start: i64,
+    pub writes: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageReport {
+    pub generated_at: i64,
+    pub total: Usage,
+    pub by_extension: BTreeMap<String, Usage>,
+    pub by_bucket: BTreeMap<String, Usage>,
+    pub by_owner: BTreeMap<String, Usage>,
+    /// Length of each ingest interval, seconds.
+    pub interval: u64,
+    pub ingest: Vec<IngestInterval>,
+}
+
+/// Which ingest history a report covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportOptions {
+    /// Seconds per ingest interval.
+    pub interval: u64,
+    /// Earliest write counted, Unix seconds.
+    pub since: i64,
+}
+
+impl ReportOptions {
+    /// Daily intervals over the last 30 days.
+    pub fn new(now: i64) -> Self {
+        ReportOptions {
+            interval: DEFAULT_INTERVAL,
+            since: now - DEFAULT_WINDOW as i64,
+        }
+    }
+
+    /// Options from an `interval=<secs>&since=<unix secs>` query, defaults
+    /// for what it leaves out.
+    pub fn from_query(query: &str, now: i64) -> std::result::Result<Self, String> {
+        let mut options = ReportOptions::new(now);
+        for (name, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+            match name {
+                "interval" => {
+                    options.interval =
+                        value.parse().ok().filter(|secs| *secs > 0).ok_or_else(|| {
+                            format!("interval must be a positive number of seconds: {:?}", value)
+                        })?;
+                }
+                "since" => {
+                    options.since = value
+                        .parse()
+                        .map_err(|_| format!("since must be Unix seconds: {:?}", value))?;
+                }
+                _ => {}
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Note a client's successful write of `bucket`/`key` from its `receipt`,
+/// for the ingest history; failures only cost the report accuracy.
+pub async fn note_write(bucket: &str, key: &str, owner: &str, receipt: &[u8]) {
+    let (Some(mapper), Some(bytes)) = (crate::mapper::get_mapper(), put_receipt_size(receipt))
+    else {
+        return;
+    };
+    if let Err(e) = mapper.record_write(bucket, key, owner, bytes).await {
+        event!(
+            Level::WARN,
+            "Failed to log write of {}/{}: {}",
+            bucket,
+            key,
+            e
+        );
+    }
+}
+
+/// Report on the store behind `mapper`, given the size of every stored file
+/// by internal name.
+pub async fn build(
+    mapper: &BucketMapper,
+    sizes: &HashMap<String, u64>,
+    options: ReportOptions,
+    now: i64,
+) -> Result<UsageReport> {
+    let mappings = mapper
+        .list_owned()
+        .await
+        .context("Failed to list bucket mappings")?;
+    let ingest = mapper
+        .ingest_volume(options.since, options.interval as i64)
+        .await
+        .context("Failed to read the ingest log")?
+        .into_iter()
+        .map(|(start, writes, bytes)| IngestInterval {
+            start,
+            writes,
+            bytes,
+        })
+        .collect();
+    let mut report = aggregate(&mappings, sizes);
+    report.generated_at = now;
+    report.interval = options.interval;
+    report.ingest = ingest;
+    Ok(report)
+}
+
+/// Report on the store at `root` without a running server, as the `report`
+/// command does.
+pub async fn build_offline(root: &Path, options: ReportOptions, now: i64) -> Result<UsageReport> {
+    let linadata = root.join("linadata");
+    let meta_db = linadata.join("meta.db");
+    if !meta_db.is_file() {
+        return Err(err_msg(format!(
+            "{} holds no LiNaStore store (linadata/meta.db is missing); set LINASTORE_ROOT \
+             to the store's directory",
+            root.display()
+        )));
+    }
+    // Read-only, so the report can run beside a live server
+    let store = StoreOptions::new(root)
+        .read_only(true)
+        .open()
+        .await
+        .map_err(|e| err_msg(format!("Failed to open {}: {}", meta_db.display(), e)))?;
+    let sizes = store
+        .sizes()
+        .await
+        .context("Failed to read file sizes")?;
+    let mapper = BucketMapper::new(&linadata.join("mappings.db"))
+        .await
+        .context("Failed to open bucket mappings")?;
+    build(&mapper, &sizes, options, now).await
+}
+
+/// Sum up the mapped keys that hold content; the ingest fields stay empty.
+fn aggregate(
+    mappings: &[(String, String, String, Option<String>)],
+    sizes: &HashMap<String, u64>,
+) -> UsageReport {
+    let mut report = UsageReport {
+        generated_at: 0,
+        total: Usage::default(),
+        by_extension: BTreeMap::new(),
+        by_bucket: BTreeMap::new(),
+        by_owner: BTreeMap::new(),
+        interval: 0,
+        ingest: Vec::new(),
+    };
+    for (bucket, key, internal_name, owner) in mappings {
+        // Mapped but never stored, such as a write still in flight
+        let Some(&bytes) = sizes.get(internal_name) else {
+            continue;
+        };
+        let ext = Path::new(key)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase)
+            .unwrap_or_else(|| NO_EXTENSION.to_string());
+        report.total.add(bytes);
+        report.by_extension.entry(ext).or_default().add(bytes);
+        report
+            .by_bucket
+            .entry(bucket.clone())
+            .or_default()
+            .add(bytes);
+        report
+            .by_owner
+            .entry(owner.clone().unwrap_or_else(|| UNKNOWN_OWNER.to_string()))
+            .or_default()
+            .add(bytes);
+    }
+    report
+}
+
+/// The report as plain-text tables.
+pub fn render_text(report: &UsageReport) -> String {
+    fn table(out: &mut String, title: &str, rows: &BTreeMap<String, Usage>) {
+        out.push_str(&format!("\n{}\n", title));
+        for (name, usage) in rows {
+            out.push_str(&format!(
+                "  {:<32} {:>10} {:>16}\n",
+                name, usage.files, usage.bytes
+            ));
+        }
+    }
+
+    let mut out = format!(
+        "Total: {} file(s), {} byte(s)\n",
+        report.total.files, report.total.bytes
+    );
+    table(&mut out, "By bucket (files, bytes):", &report.by_bucket);
+    table(
+        &mut out,
+        "By extension (files, bytes):",
+        &report.by_extension,
+    );
+    table(&mut out, "By owner (files, bytes):", &report.by_owner);
+    out.push_str(&format!(
+        "\nIngest per {}s (writes, bytes):\n",
+        report.interval
+    ));
+    for interval in &report.ingest {
+        let start = chrono::DateTime::from_timestamp(interval.start, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        out.push_str(&format!(
+            "  {:<32} {:>10} {:>16}\n",
+            start, interval.writes, interval.bytes
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate_groups_by_extension_bucket_and_owner() {
+        let mappings = vec![
+            (
+                "photos".to_string(),
+                "a.JPG".to_string(),
+                "id-1".to_string(),
+                Some("ann".to_string()),
+            ),
+            (
+                "photos".to_string(),
+                "b.jpg".to_string(),
+                "id-2".to_string(),
+                None,
+            ),
+            (
+                "docs".to_string(),
+                "README".to_string(),
+                "id-3".to_string(),
+                Some("ann".to_string()),
+            ),
+            (
+                "docs".to_string(),
+                "pending.txt".to_string(),
+                "id-4".to_string(),
+                None,
+            ),
+        ];
+        let sizes = HashMap::from([
+            ("id-1".to_string(), 10),
+            ("id-2".to_string(), 20),
+            ("id-3".to_string(), 5),
+        ]);
+        let report = aggregate(&mappings, &sizes);
+        assert_eq!(
+            report.total,
+            Usage {
+                files: 3,
+                bytes: 35
+            }
+        );
+        assert_eq!(
+            report.by_extension["jpg"],
+            Usage {
+                files: 2,
+                bytes: 30
+            }
+        );
+        assert_eq!(
+            report.by_extension[NO_EXTENSION],
+            Usage { files: 1, bytes: 5 }
+        );
+        assert_eq!(report.by_bucket["docs"], Usage { files: 1, bytes: 5 });
+        assert_eq!(
+            report.by_owner["ann"],
+            Usage {
+                files: 2,
+                bytes: 15
+            }
+        );
+        assert_eq!(
+            report.by_owner[UNKNOWN_OWNER],
+            Usage {
+                files: 1,
+                bytes: 20
+            }
+        );
+    }
+
+    #[test]
+    fn test_report_options_from_query() {
+        let defaults = ReportOptions::from_query("", 1_000_000_000).unwrap();
+        assert_eq!(defaults, ReportOptions::new(1_000_000_000));
+        let options = ReportOptions::from_query("interval=3600&since=42", 0).unwrap();
+        assert_eq!(
+            options,
+            ReportOptions {
+                interval: 3600,
+                since: 42
+            }
+        );
+        assert!(ReportOptions::from_query("interval=0", 0).is_err());
+        assert!(ReportOptions::from_query("since=yesterday", 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ingest_volume_is_bucketed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mapper = BucketMapper::new(&dir.path().join("mappings.db"))
+            .await
+            .unwrap();
+        mapper.register("b", "k", "id-1").await.unwrap();
+        for (at, bytes) in [(100, 1), (150, 2), (250, 4)] {
+            sqlx::query("INSERT INTO ingest_log (at, bucket, key, owner, bytes) VALUES (?1, 'b', 'k', 'ann', ?2)")
+                .bind(at as i64)
+                .bind(bytes as i64)
+                .execute(&mapper.pool)
+                .await
+                .unwrap();
+        }
+        mapper.record_write("b", "k", "bob", 8).await.unwrap();
+
+        let volume = mapper.ingest_volume(0, 100).await.unwrap();
+        assert_eq!(volume[0], (100, 2, 3));
+        assert_eq!(volume[1], (200, 1, 4));
+        assert_eq!(volume.len(), 3);
+        let owned = mapper.list_owned().await.unwrap();
+        assert_eq!(owned[0].3.as_deref(), Some("bob"));
+    }
+}
@@ -1,27 +1,53 @@
 use std::sync::{
     Arc, OnceLock,
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
+#[cfg(unix)]
+use tokio::signal::unix::{Signal, SignalKind, signal};
 use tokio::sync::Notify;
 
 static SHUTDOWN: OnceLock<Arc<Shutdown>> = OnceLock::new();
 
+/// Shutdown runs in two phases: `shutdown()` stops the servers from accepting
+/// while connections already being served finish, then `front_drained()`
+/// lets the porter work off the remaining orders and exit.
 pub struct Shutdown {
     is_shutdown: AtomicBool,
     notify: Notify,
+    in_flight: AtomicUsize,
+    idle: Notify,
+    is_front_drained: AtomicBool,
+    drained: Notify,
+}
+
+/// Marks one connection as in flight until dropped.
+pub struct InFlight {
+    shutdown: Arc<Shutdown>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.shutdown.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown.idle.notify_waiters();
+        }
+    }
 }
 
 impl Shutdown {
+    fn new() -> Self {
+        Shutdown {
+            is_shutdown: AtomicBool::new(false),
+            notify: Notify::new(),
+            in_flight: AtomicUsize::new(0),
+            idle: Notify::new(),
+            is_front_drained: AtomicBool::new(false),
+            drained: Notify::new(),
+        }
+    }
+
     pub fn get_instance() -> Arc<Shutdown> {
-        SHUTDOWN
-            .get_or_init(|| {
-                Arc::new(Shutdown {
-                    is_shutdown: AtomicBool::new(false),
-                    notify: Notify::new(),
-                })
-            })
-            .clone()
+        SHUTDOWN.get_or_init(|| Arc::new(Shutdown::new())).clone()
     }
 
     /// Checks if shutdown has been triggered
@@ -45,4 +71,178 @@ impl Shutdown {
         }
         notified.await;
     }
+
+    /// Count a connection as in flight for as long as the guard lives.
+    pub fn track(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight {
+            shutdown: Arc::clone(self),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Waits until no connection is in flight
+    pub async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            if self.in_flight() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn is_front_drained(&self) -> bool {
+        self.is_front_drained.load(Ordering::SeqCst)
+    }
+
+    /// Signals that the front sends no more orders
+    pub fn front_drained(&self) {
+        let already_drained = self.is_front_drained.swap(true, Ordering::SeqCst);
+        if !already_drained {
+            self.drained.notify_waiters();
+        }
+    }
+
+    /// Waits until the front sends no more orders
+    pub async fn wait_front_drained(&self) {
+        let notified = self.drained.notified();
+        if self.is_front_drained() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// What a process signal asks the server to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessSignal {
+    /// Shut down gracefully; carries the signal's name for the log.
+    Terminate(&'static str),
+    /// Reload credentials without restarting.
+    Reload,
+    /// Log more (SIGUSR1) or less (SIGUSR2).
+    LogLevel { more: bool },
+}
+
+/// Listens for SIGINT (Ctrl-C), SIGTERM and SIGQUIT, which shut the server
+/// down gracefully, SIGHUP, which reloads it, and SIGUSR1 and SIGUSR2, which
+/// change the log level. Platforms without POSIX signals only deliver
+/// Ctrl-C.
+pub struct ProcessSignals {
+    #[cfg(unix)]
+    interrupt: Signal,
+    #[cfg(unix)]
+    terminate: Signal,
+    #[cfg(unix)]
+    quit: Signal,
+    #[cfg(unix)]
+    hangup: Signal,
+    #[cfg(unix)]
+    user1: Signal,
+    #[cfg(unix)]
+    user2: Signal,
+}
+
+impl ProcessSignals {
+    #[cfg(unix)]
+    pub fn new() -> std::io::Result<Self> {
+        Ok(ProcessSignals {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+            quit: signal(SignalKind::quit())?,
+            hangup: signal(SignalKind::hangup())?,
+            user1: signal(SignalKind::user_defined1())?,
+            user2: signal(SignalKind::user_defined2())?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> std::io::Result<Self> {
+        Ok(ProcessSignals {})
+    }
+
+    /// Waits for the next signal.
+    #[cfg(unix)]
+    pub async fn recv(&mut self) -> ProcessSignal {
+        tokio::select! {
+            Some(()) = self.interrupt.recv() => ProcessSignal::Terminate("SIGINT"),
+            Some(()) = self.terminate.recv() => ProcessSignal::Terminate("SIGTERM"),
+            Some(()) = self.quit.recv() => ProcessSignal::Terminate("SIGQUIT"),
+            Some(()) = self.hangup.recv() => ProcessSignal::Reload,
+            Some(()) = self.user1.recv() => ProcessSignal::LogLevel { more: true },
+            Some(()) = self.user2.recv() => ProcessSignal::LogLevel { more: false },
+            else => std::future::pending().await,
+        }
+    }
+
+    /// Waits for the next signal.
+    #[cfg(not(unix))]
+    pub async fn recv(&mut self) -> ProcessSignal {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => ProcessSignal::Terminate("Ctrl-C"),
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_wait_idle_returns_after_last_guard() {
+        let shutdown = Arc::new(Shutdown::new());
+        shutdown.wait_idle().await;
+
+        let first = shutdown.track();
+        let second = shutdown.track();
+        assert_eq!(shutdown.in_flight(), 2);
+
+        let waiter = tokio::spawn({
+            let shutdown = Arc::clone(&shutdown);
+            async move { shutdown.wait_idle().await }
+        });
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+
+        drop(second);
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("wait_idle must return once idle")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_front_drained_wakes_waiters() {
+        let shutdown = Arc::new(Shutdown::new());
+        let waiter = tokio::spawn({
+            let shutdown = Arc::clone(&shutdown);
+            async move { shutdown.wait_front_drained().await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        shutdown.front_drained();
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("waiter must wake")
+            .unwrap();
+        // Late waiters return at once
+        shutdown.wait_front_drained().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sighup_requests_reload() {
+        let mut signals = ProcessSignals::new().unwrap();
+        unsafe { libc::kill(libc::getpid(), libc::SIGHUP) };
+        let signal = tokio::time::timeout(Duration::from_secs(1), signals.recv())
+            .await
+            .expect("SIGHUP must be delivered");
+        assert_eq!(signal, ProcessSignal::Reload);
+    }
 }
@@ -0,0 +1,192 @@
+//! systemd integration: listening sockets passed in by socket activation
+//! (`LISTEN_FDS`) and readiness notifications (`sd_notify`). Both do
+//! nothing when the server was not started by systemd.
+
+use std::{
+    net::TcpListener,
+    sync::{
+        Mutex, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use tracing::{Level, event};
+
+use crate::error::Result;
+
+/// A socket passed in by systemd, with its `FileDescriptorName=`.
+struct Inherited {
+    name: Option<String>,
+    listener: TcpListener,
+}
+
+static INHERITED: Mutex<Vec<Inherited>> = Mutex::new(Vec::new());
+
+/// Parts that must be up before the server is ready: the porter with its
+/// store open, and the HTTP, advanced and S3 fronts listening.
+const READY_PARTS: usize = 4;
+
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Adopt the sockets systemd passed to this process, returning how many
+/// there were. Call once at startup, before the fronts listen.
+#[cfg(unix)]
+pub fn inherit_listeners() -> Result<usize> {
+    use std::os::fd::FromRawFd;
+
+    use crate::error::err_msg;
+
+    /// First descriptor systemd passes (`SD_LISTEN_FDS_START`).
+    const LISTEN_FDS_START: i32 = 3;
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        == Some(std::process::id());
+    if !for_us {
+        return Ok(0);
+    }
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.trim().parse::<i32>().ok())
+        .unwrap_or(0);
+    let names: Vec<String> = std::env::var("LISTEN_FDNAMES")
+        .map(|names| names.split(':').map(String::from).collect())
+        .unwrap_or_default();
+
+    let mut inherited = INHERITED.lock().unwrap_or_else(PoisonError::into_inner);
+    for i in 0..count.max(0) {
+        let fd = LISTEN_FDS_START + i;
+        // SAFETY: systemd hands these descriptors to this process and
+        // nothing else in it has opened or claimed them.
+        let listener = unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            TcpListener::from_raw_fd(fd)
+        };
+        listener.local_addr().map_err(|e| {
+            err_msg(format!(
+                "Socket {} passed by systemd is not a TCP listener: {}",
+                fd, e
+            ))
+        })?;
+        listener.set_nonblocking(true)?;
+        inherited.push(Inherited {
+            name: names.get(i as usize).cloned(),
+            listener,
+        });
+    }
+    Ok(inherited.len())
+}
+
+#[cfg(not(unix))]
+pub fn inherit_listeners() -> Result<usize> {
+    Ok(0)
+}
+
+/// The socket systemd passed for `front`: the one named after it with
+/// `FileDescriptorName=`, else the one listening on the port of `addr`.
+pub fn take_listener(front: &str, addr: &str) -> Option<TcpListener> {
+    let port = addr.rsplit(':').next().and_then(|p| p.parse::<u16>().ok());
+    let mut inherited = INHERITED.lock().unwrap_or_else(PoisonError::into_inner);
+    let index = inherited
+        .iter()
+        .position(|s| s.name.as_deref() == Some(front))
+        .or_else(|| {
+            inherited
+                .iter()
+                .position(|s| s.listener.local_addr().ok().map(|a| a.port()) == port)
+        })?;
+    Some(inherited.swap_remove(index).listener)
+}
+
+/// Record that one part of the server is up; the last one sends `READY=1`.
+pub fn started() {
+    if STARTED.fetch_add(1, Ordering::SeqCst) + 1 == READY_PARTS {
+        event!(Level::INFO, "Server ready");
+        notify("READY=1");
+    }
+}
+
+/// Tell systemd about a state change, such as `READY=1`, when it started
+/// the server with `Type=notify`.
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    if let Some(path) = std::env::var_os("NOTIFY_SOCKET")
+        && let Err(e) = send_notification(&path, state)
+    {
+        event!(Level::WARN, "Failed to notify systemd of {}: {}", state, e);
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+#[cfg(unix)]
+fn send_notification(path: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    // A leading `@` names a socket in the abstract namespace
+    if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "abstract notify sockets need Linux",
+            ));
+        }
+    }
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_listener_by_name_then_port() {
+        let named = TcpListener::bind("127.0.0.1:0").unwrap();
+        let named_port = named.local_addr().unwrap().port();
+        let unnamed = TcpListener::bind("127.0.0.1:0").unwrap();
+        let unnamed_port = unnamed.local_addr().unwrap().port();
+        INHERITED.lock().unwrap().extend([
+            Inherited {
+                name: Some("test-http".to_string()),
+                listener: named,
+            },
+            Inherited {
+                name: None,
+                listener: unnamed,
+            },
+        ]);
+
+        let taken = take_listener("test-http", "0.0.0.0:1").unwrap();
+        assert_eq!(taken.local_addr().unwrap().port(), named_port);
+        assert!(take_listener("test-http", "0.0.0.0:1").is_none());
+
+        let addr = format!("0.0.0.0:{}", unnamed_port);
+        let taken = take_listener("test-s3", &addr).unwrap();
+        assert_eq!(taken.local_addr().unwrap().port(), unnamed_port);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notification_reaches_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        send_notification(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0u8; 64];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+    }
+}
@@ -0,0 +1,463 @@
+//! OpenTelemetry trace export: finished `tracing` spans are batched and
+//! posted as OTLP/HTTP JSON to a collector such as Jaeger, Tempo or the
+//! OpenTelemetry Collector.
+
+use std::{
+    io,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Map, Value, json};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+};
+use tracing::{
+    Event, Level, Subscriber, event,
+    span::{Attributes, Id, Record},
+};
+use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
+use uuid::Uuid;
+
+use crate::{
+    config,
+    error::{Result, err_msg},
+    logging::JsonFields,
+};
+
+/// Spans sent in one request at most.
+const BATCH_SIZE: usize = 512;
+/// How long finished spans wait for a batch to fill.
+const BATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// Finished spans held while the collector is slow; later ones are dropped.
+const QUEUE_CAPACITY: usize = 4096;
+/// Events kept per span, so that long-lived spans stay small.
+const MAX_SPAN_EVENTS: usize = 64;
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Port of OTLP over HTTP.
+const DEFAULT_PORT: u16 = 4318;
+
+static EXPORTER: OnceLock<mpsc::Sender<Export>> = OnceLock::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Where traces go; export is off without `LINASTORE_OTLP_ENDPOINT`.
+#[derive(Debug, Clone)]
+pub struct TelemetrySettings {
+    endpoint: Endpoint,
+    service_name: String,
+}
+
+/// An OTLP/HTTP collector URL, also used to push metrics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Endpoint {
+    /// `host:port` to connect to
+    address: String,
+    /// Value of the `Host` header
+    host: String,
+    path: String,
+}
+
+impl TelemetrySettings {
+    pub fn load() -> Result<Option<Self>> {
+        let Some(raw) = config::var("LINASTORE_OTLP_ENDPOINT")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let endpoint = parse_endpoint(raw.trim(), "/v1/traces").map_err(|reason| {
+            err_msg(config::attribute(format!(
+                "LINASTORE_OTLP_ENDPOINT {}: {:?}",
+                reason, raw
+            )))
+        })?;
+        Ok(Some(TelemetrySettings {
+            endpoint,
+            service_name: service_name(),
+        }))
+    }
+
+    /// The URL spans are posted to, for the startup log.
+    pub fn url(&self) -> String {
+        self.endpoint.url()
+    }
+}
+
+impl Endpoint {
+    pub(crate) fn url(&self) -> String {
+        format!("http://{}{}", self.host, self.path)
+    }
+}
+
+/// The service name shown in the collector's UI.
+pub(crate) fn service_name() -> String {
+    config::var("LINASTORE_OTLP_SERVICE_NAME")
+        .ok()
+        .filter(|v| !v.trim().is_empty())
+        .unwrap_or_else(|| "linastore".to_string())
+}
+
+/// `http://host[:port][/base]`, posting to `signal` (such as `/v1/traces`)
+/// under the base unless the path already ends with it.
+pub(crate) fn parse_endpoint(raw: &str, signal: &str) -> std::result::Result<Endpoint, String> {
+    let rest = raw
+        .strip_prefix("http://")
+        .ok_or("must be an http:// URL")?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+        None => (rest, ""),
+    };
+    if host.is_empty() {
+        return Err("has no host".to_string());
+    }
+    // A bare host or IPv6 literal gets the OTLP port
+    let address = if host.ends_with(']') || !host.contains(':') {
+        format!("{}:{}", host, DEFAULT_PORT)
+    } else {
+        host.to_string()
+    };
+    let path = if path.ends_with(signal) {
+        path.to_string()
+    } else {
+        format!("{}{}", path, signal)
+    };
+    Ok(Endpoint {
+        address,
+        host: host.to_string(),
+        path,
+    })
+}
+
+enum Export {
+    Span(FinishedSpan),
+    Flush(oneshot::Sender<()>),
+}
+
+/// A span's state, kept in the registry while it is open.
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_id: Option<[u8; 8]>,
+    start: SystemTime,
+    attributes: Map<String, Value>,
+    events: Vec<(SystemTime, Map<String, Value>)>,
+}
+
+struct FinishedSpan {
+    name: &'static str,
+    target: &'static str,
+    end: SystemTime,
+    data: SpanData,
+}
+
+/// Hands every closed span to the exporter task.
+pub struct OtlpLayer {
+    sender: mpsc::Sender<Export>,
+}
+
+/// Start the exporter task and return the layer that feeds it. Must be
+/// called from within the runtime.
+pub fn layer(settings: TelemetrySettings) -> OtlpLayer {
+    let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+    let _ = EXPORTER.set(sender.clone());
+    tokio::spawn(export(settings, receiver));
+    OtlpLayer { sender }
+}
+
+/// Send the spans still waiting for their batch; called on shutdown.
+pub async fn flush() {
+    let Some(sender) = EXPORTER.get() else {
+        return;
+    };
+    let (done, finished) = oneshot::channel();
+    if sender.send(Export::Flush(done)).await.is_ok() {
+        let _ = tokio::time::timeout(EXPORT_TIMEOUT, finished).await;
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanData>()
+                .map(|data| (data.trace_id, data.span_id))
+        });
+        let mut fields = JsonFields(Map::new());
+        attrs.record(&mut fields);
+        let span_id = Uuid::new_v4().into_bytes();
+        span.extensions_mut().insert(SpanData {
+            trace_id: parent.map_or_else(|| Uuid::new_v4().into_bytes(), |(trace, _)| trace),
+            span_id: span_id[..8].try_into().unwrap_or_default(),
+            parent_id: parent.map(|(_, span)| span),
+            start: SystemTime::now(),
+            attributes: fields.0,
+            events: Vec::new(),
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(data) = span.extensions_mut().get_mut::<SpanData>()
+        {
+            let mut fields = JsonFields(std::mem::take(&mut data.attributes));
+            values.record(&mut fields);
+            data.attributes = fields.0;
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.event_span(event)
+            && let Some(data) = span.extensions_mut().get_mut::<SpanData>()
+            && data.events.len() < MAX_SPAN_EVENTS
+        {
+            let mut fields = JsonFields(Map::new());
+            event.record(&mut fields);
+            fields
+                .0
+                .insert("level".into(), event.metadata().level().as_str().into());
+            data.events.push((SystemTime::now(), fields.0));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        let finished = FinishedSpan {
+            name: span.name(),
+            target: span.metadata().target(),
+            end: SystemTime::now(),
+            data,
+        };
+        if self.sender.try_send(Export::Span(finished)).is_err() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+async fn export(settings: TelemetrySettings, mut receiver: mpsc::Receiver<Export>) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut ticker = tokio::time::interval(BATCH_INTERVAL);
+    loop {
+        let (flushed, closed) = tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Export::Span(span)) => {
+                    batch.push(span);
+                    if batch.len() < BATCH_SIZE {
+                        continue;
+                    }
+                    (None, false)
+                }
+                Some(Export::Flush(done)) => (Some(done), false),
+                None => (None, true),
+            },
+            _ = ticker.tick() => (None, false),
+        };
+        if !batch.is_empty() {
+            let body = encode(&settings.service_name, &batch).to_string();
+            if let Err(e) = post(&settings.endpoint, body.as_bytes()).await {
+                event!(
+                    Level::WARN,
+                    "Failed to export {} span(s) to {}: {}",
+                    batch.len(),
+                    settings.endpoint.url(),
+                    e
+                );
+            }
+            batch.clear();
+        }
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            event!(
+                Level::WARN,
+                "Dropped {} span(s) while the trace exporter was behind",
+                dropped
+            );
+        }
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+pub(crate) async fn post(endpoint: &Endpoint, body: &[u8]) -> io::Result<()> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(&endpoint.address).await?;
+        let head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            endpoint.path,
+            endpoint.host,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body).await?;
+        let mut response = [0u8; 64];
+        let n = stream.read(&mut response).await?;
+        let status_line = String::from_utf8_lossy(&response[..n]);
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            Some(status) => Err(io::Error::other(format!("collector answered {}", status))),
+            None => Err(io::Error::other("collector sent no HTTP response")),
+        }
+    };
+    tokio::time::timeout(EXPORT_TIMEOUT, exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "collector did not answer"))?
+}
+
+/// An `ExportTraceServiceRequest` in the OTLP JSON encoding.
+fn encode(service_name: &str, spans: &[FinishedSpan]) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", &Value::from(service_name))],
+            },
+            "scopeSpans": [{
+                "scope": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+                "spans": spans.iter().map(encode_span).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+fn encode_span(span: &FinishedSpan) -> Value {
+    let data = &span.data;
+    let mut span_attributes = attributes(&data.attributes);
+    span_attributes.push(attribute("code.namespace", &Value::from(span.target)));
+    let events: Vec<Value> = data
+        .events
+        .iter()
+        .map(|(time, fields)| {
+            let mut fields = fields.clone();
+            let name = match fields.remove("message") {
+                Some(Value::String(message)) => message,
+                _ => "event".to_string(),
+            };
+            json!({
+                "timeUnixNano": unix_nanos(*time),
+                "name": name,
+                "attributes": attributes(&fields),
+            })
+        })
+        .collect();
+
+    let mut encoded = json!({
+        "traceId": hex::encode(data.trace_id),
+        "spanId": hex::encode(data.span_id),
+        "name": span.name,
+        // SPAN_KIND_INTERNAL
+        "kind": 1,
+        "startTimeUnixNano": unix_nanos(data.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": span_attributes,
+        "events": events,
+    });
+    if let Some(parent) = data.parent_id {
+        encoded["parentSpanId"] = hex::encode(parent).into();
+    }
+    encoded
+}
+
+fn attributes(fields: &Map<String, Value>) -> Vec<Value> {
+    fields
+        .iter()
+        .map(|(key, value)| attribute(key, value))
+        .collect()
+}
+
+pub(crate) fn attribute(key: &str, value: &Value) -> Value {
+    let value = match value {
+        Value::Bool(b) => json!({ "boolValue": b }),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!({ "intValue": n.to_string() }),
+        Value::Number(n) => json!({ "doubleValue": n.as_f64() }),
+        Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+pub(crate) fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_parse_endpoint() {
+        let endpoint = parse_endpoint("http://collector", "/v1/traces").unwrap();
+        assert_eq!(endpoint.address, "collector:4318");
+        assert_eq!(endpoint.host, "collector");
+        assert_eq!(endpoint.path, "/v1/traces");
+
+        let endpoint = parse_endpoint("http://127.0.0.1:9000/otlp/", "/v1/metrics").unwrap();
+        assert_eq!(endpoint.address, "127.0.0.1:9000");
+        assert_eq!(endpoint.path, "/otlp/v1/metrics");
+
+        let endpoint = parse_endpoint("http://[::1]/v1/traces", "/v1/traces").unwrap();
+        assert_eq!(endpoint.address, "[::1]:4318");
+        assert_eq!(endpoint.path, "/v1/traces");
+
+        assert!(parse_endpoint("https://collector", "/v1/traces").is_err());
+        assert!(parse_endpoint("http:///v1/traces", "/v1/traces").is_err());
+    }
+
+    #[test]
+    fn test_child_spans_join_parent_trace() {
+        let (sender, mut receiver) = mpsc::channel(16);
+        let subscriber = tracing_subscriber::registry().with(OtlpLayer { sender });
+        tracing::subscriber::with_default(subscriber, || {
+            let parent = tracing::info_span!("handle_http", path = "/b/k");
+            let child = tracing::info_span!(parent: &parent, "porter_process", orders = 2u64);
+            child.in_scope(|| tracing::info!(status = 200u64, "Stored"));
+            drop(child);
+            drop(parent);
+        });
+
+        let mut spans = Vec::new();
+        while let Ok(Export::Span(span)) = receiver.try_recv() {
+            spans.push(span);
+        }
+        let [child, parent] = spans.as_slice() else {
+            panic!("expected two spans");
+        };
+        assert_eq!(child.data.trace_id, parent.data.trace_id);
+        assert_eq!(child.data.parent_id, Some(parent.data.span_id));
+        assert_eq!(parent.data.parent_id, None);
+
+        let encoded = encode("linastore", &spans);
+        let span = &encoded["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["name"], "porter_process");
+        assert_eq!(span["parentSpanId"], hex::encode(parent.data.span_id));
+        assert_eq!(span["attributes"][0]["key"], "orders");
+        assert_eq!(span["attributes"][0]["value"]["intValue"], "2");
+        assert_eq!(span["events"][0]["name"], "Stored");
+    }
+}
@@ -7,59 +7,51 @@ use nix::sys::stat::Mode;
 use nix::unistd::{ForkResult, close, fork, setsid};
 #[cfg(unix)]
 use std::os::fd::IntoRawFd;
-use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use tracing::{Level, event};
-use tracing_subscriber::fmt::writer::MakeWriterExt;
-
-use crate::shutdown::Shutdown;
-
-/// Initialize logging with file output
-fn init_logging(log_dir: &str) -> Result<()> {
-    // Create log directory if it doesn't exist
-    let log_path = Path::new(log_dir);
-    if !log_path.exists() {
-        fs::create_dir_all(log_path).context("Failed to create log directory")?;
-    }
-
-    // Open log file (append mode) to validate path early
-    let log_file_path = log_path.join("linastore.log");
-    fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_file_path)
-        .context("Failed to open log file")?;
-
-    let file_path = log_file_path.clone();
-    let file_writer = move || {
-        fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&file_path)
-            .expect("Failed to open log file")
-    };
-
-    let max_level = if cfg!(debug_assertions) {
-        Level::DEBUG
-    } else {
-        Level::INFO
+use tracing::event;
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::logging::{self, JsonFormat, LogFormat, LogOutput, LogSettings, RollingFile};
+use crate::metrics::PushSettings;
+use crate::shutdown::{ProcessSignal, ProcessSignals, Shutdown};
+use crate::telemetry::TelemetrySettings;
+
+/// Initialize logging to stdout and/or a rotating file in `log_dir`
+fn init_logging(
+    log_dir: &str,
+    settings: &LogSettings,
+    telemetry: Option<TelemetrySettings>,
+) -> Result<()> {
+    let writer = match settings.output {
+        LogOutput::Stdout => BoxMakeWriter::new(std::io::stdout),
+        output => {
+            let file =
+                RollingFile::open(Path::new(log_dir), settings.rotation, settings.retention)?;
+            if output == LogOutput::Both {
+                BoxMakeWriter::new(std::io::stdout.and(file))
+            } else {
+                BoxMakeWriter::new(file)
+            }
+        }
     };
 
-    let builder = tracing_subscriber::fmt()
+    let layer = tracing_subscriber::fmt::layer()
         .with_thread_ids(false)
         .with_file(false)
         .with_ansi(false)
         .with_target(false)
-        .with_max_level(max_level);
-
-    if cfg!(debug_assertions) {
-        let writer = std::io::stdout.and(file_writer);
-        builder.with_writer(writer).init();
-    } else {
-        builder.with_writer(file_writer).init();
+        .with_writer(writer);
+    let registry = tracing_subscriber::registry()
+        .with(logging::reloadable(settings.filter.clone()))
+        .with(telemetry.map(crate::telemetry::layer));
+
+    match settings.format {
+        LogFormat::Text => registry.with(layer).init(),
+        LogFormat::Json => registry.with(layer.event_format(JsonFormat)).init(),
     }
 
     Ok(())
@@ -98,41 +90,81 @@ fn daemonize() -> Result<()> {
     ))
 }
 
-pub async fn run_server(log_dir: Option<String>, daemon: bool) -> Result<()> {
+/// Check that `root` holds a store, or with `init` create the directories
+/// for a new one there.
+fn open_store_root(root: &Path, init: bool) -> Result<()> {
+    if root.join("linadata").join("meta.db").is_file() {
+        return Ok(());
+    }
+    if !init {
+        return Err(err_msg(format!(
+            "{} holds no LiNaStore store (linadata/meta.db is missing); set LINASTORE_ROOT \
+             to the store's directory, or pass --init to create a new store here",
+            root.display()
+        )));
+    }
+    fs::create_dir_all(root.join("linadata"))
+        .with_context(|| format!("Failed to create a store in {}", root.display()))?;
+    Ok(())
+}
+
+pub async fn run_server(log_dir: Option<String>, daemon: bool, init: bool) -> Result<()> {
+    // Checked before daemonizing so that a wrong root is reported
+    let root = crate::vars::store_root()?;
+    open_store_root(&root, init)?;
+    let root_str = root
+        .to_str()
+        .map(String::from)
+        .context("Store root is not valid UTF-8")?;
+
+    // Locked before daemonizing so a second server fails in the foreground;
+    // the daemon inherits the lock along with the file.
+    let mut instance = InstanceLock::acquire(&root)?;
+
     if daemon {
         daemonize()?;
     }
 
-    // Write PID file after daemonizing so it contains the correct PID.
-    write_pid_file()?;
+    // Written after daemonizing so it holds the daemon's PID.
+    instance.write_pid()?;
 
     // Initialize logging
-    let log_directory = log_dir.unwrap_or_else(|| {
-        let current_dir = env::current_dir()
-            .expect("Failed to get current directory")
-            .to_str()
-            .expect("Failed to convert path")
-            .to_string();
-        format!("{}/linadata/logs", current_dir)
-    });
-
-    init_logging(&log_directory)?;
-    event!(
-        tracing::Level::INFO,
-        "Logging initialized, logs stored in: {}",
-        log_directory
-    );
-
-    let current_dir = env::current_dir()
-        .context("Failed to get current directory")?
-        .to_str()
-        .map(String::from)
-        .context("Failed to convert current directory to string")?;
+    let log_settings = LogSettings::load()?;
+    let log_directory = log_dir
+        .or_else(|| log_settings.dir.clone())
+        .unwrap_or_else(|| format!("{}/linadata/logs", root_str));
+
+    let telemetry = TelemetrySettings::load()?;
+    let metrics_push = PushSettings::load()?;
+    let replica = crate::replica::ReplicaSettings::load()?;
+    let federation = crate::federation::FederationSettings::load()?;
+    let blob_settings = crate::blob::BlobSettings::load()?;
+    let blobs = blob_settings.backend(&root)?;
+    let demote_every = blob_settings.demote_interval();
+    let traces_url = telemetry.as_ref().map(TelemetrySettings::url);
+    init_logging(&log_directory, &log_settings, telemetry)?;
+    if log_settings.output == LogOutput::Stdout {
+        event!(tracing::Level::INFO, "Logging initialized, writing to stdout");
+    } else {
+        event!(
+            tracing::Level::INFO,
+            "Logging initialized, logs stored in: {}",
+            log_directory
+        );
+    }
 
-    let current_path = std::path::Path::new(&current_dir);
+    if let Some(url) = traces_url {
+        event!(tracing::Level::INFO, "Exporting traces to {}", url);
+    }
+    if let Some(push) = metrics_push {
+        event!(tracing::Level::INFO, "Pushing metrics to {}", push.describe());
+        tokio::spawn(crate::metrics::push(push));
+    }
+    event!(tracing::Level::INFO, "Store root: {}", root_str);
+    event!(tracing::Level::INFO, "File content kept in {}", blob_settings.describe());
 
     // Initialize bucket mapper
-    if let Err(e) = crate::mapper::init_mapper(current_path).await {
+    if let Err(e) = crate::mapper::init_mapper(&root).await {
         event!(tracing::Level::ERROR, "Failed to initialize bucket mapper: {:?}", e);
         return Err(crate::error::err_msg(format!("Bucket mapper init failed: {}", e)));
     }
@@ -140,14 +172,26 @@ pub async fn run_server(log_dir: Option<String>, daemon: bool) -> Result<()> {
 
     // Initialize Shutdown Manager
     let shutdown_state = Shutdown::get_instance();
+    crate::admin::mark_started();
 
     // Initialize the order queue
     crate::conveyer::ConveyQueue::init();
     event!(tracing::Level::INFO, "Message queue initialized");
 
-    // Initialize database
     let env_vars = crate::vars::EnvVar::get_instance();
     env_vars.validate()?;
+    if env_vars.durable_queue {
+        let journal_path = root.join("linadata").join("orders.journal");
+        let replayed = crate::conveyer::ConveyQueue::get_instance().open_journal(&journal_path)?;
+        event!(
+            tracing::Level::INFO,
+            "Order journal opened at {}, {} unstored put(s) replayed",
+            journal_path.display(),
+            replayed
+        );
+    }
+
+    // Initialize database
     let db_conn = Arc::new(crate::db::get_db_connection(&env_vars.db_url).await?);
     event!(tracing::Level::INFO, "Database initialized");
 
@@ -173,40 +217,86 @@ pub async fn run_server(log_dir: Option<String>, daemon: bool) -> Result<()> {
         crate::auth::cleanup_expired_sessions().await;
     });
 
+    let keep_versions = env_vars.keep_versions;
     let mut porter_handle = tokio::task::spawn(async move {
-        crate::porter::porter(&current_dir).await;
+        crate::porter::porter(&root_str, blobs, demote_every, keep_versions).await;
     });
 
+    if let Some(replica) = replica {
+        event!(tracing::Level::INFO, "Replicating from {}, read-only", replica.describe());
+        tokio::spawn(crate::replica::follow(replica));
+    }
+
+    if let Some(federation) = federation {
+        event!(tracing::Level::INFO, "Federating misses to {}", federation.describe());
+        crate::federation::init(federation);
+    }
+
+    let inherited = crate::systemd::inherit_listeners()?;
+    if inherited > 0 {
+        event!(tracing::Level::INFO, "Inherited {} socket(s) from systemd", inherited);
+    }
     let mut front_handle = tokio::task::spawn(async move {
         crate::front::front().await;
     });
 
     // Graceful shutdown
-    let shutdown_signal = tokio::signal::ctrl_c();
-    tokio::pin!(shutdown_signal);
-
-    match shutdown_signal.await {
-        Ok(()) => {
-            event!(tracing::Level::INFO, "Graceful shutdown");
-            shutdown_state.shutdown();
+    let mut signals = ProcessSignals::new().context("Failed to listen for signals")?;
+    let mut porter_running = true;
+    loop {
+        tokio::select! {
+            signal = signals.recv() => match signal {
+                ProcessSignal::Terminate(name) => {
+                    event!(tracing::Level::INFO, "Graceful shutdown on {}", name);
+                    crate::systemd::notify("STOPPING=1");
+                    shutdown_state.shutdown();
+                    break;
+                }
+                ProcessSignal::Reload => {
+                    event!(tracing::Level::INFO, "Reloading credentials");
+                    if let Err(e) = crate::auth::reload_credentials().await {
+                        event!(tracing::Level::ERROR, "Failed to reload credentials: {}", e);
+                    }
+                }
+                ProcessSignal::LogLevel { more } => match logging::adjust_level(more) {
+                    Ok(level) => event!(tracing::Level::WARN, "Log level is now {}", level),
+                    Err(e) => event!(tracing::Level::ERROR, "{}", e),
+                },
+            },
+            // Asked for over the admin API, or by a part that cannot go on
+            _ = shutdown_state.wait() => {
+                event!(tracing::Level::INFO, "Graceful shutdown requested");
+                crate::systemd::notify("STOPPING=1");
+                break;
+            }
+            // The porter only returns early when it cannot serve orders at all
+            _ = &mut porter_handle => {
+                event!(tracing::Level::ERROR, "Porter stopped unexpectedly, shutting down");
+                porter_running = false;
+                shutdown_state.shutdown();
+                break;
+            }
         }
-        Err(e) => event!(tracing::Level::ERROR, "Shutdown signal error: {:?}", e),
     }
 
     let shutdown_timeout = Duration::from_secs(5);
+    let grace_deadline =
+        tokio::time::Instant::now() + Duration::from_secs(env_vars.shutdown_grace_secs);
 
-    if tokio::time::timeout(shutdown_timeout, &mut porter_handle)
+    // The servers have stopped accepting; let the requests they already
+    // took finish before the porter is told to wind down
+    if tokio::time::timeout_at(grace_deadline, shutdown_state.wait_idle())
         .await
         .is_err()
     {
         event!(
             tracing::Level::WARN,
-            "Porter did not shut down in time, aborting"
+            "{} connection(s) still in flight after the grace period",
+            shutdown_state.in_flight()
         );
-        porter_handle.abort();
     }
 
-    if tokio::time::timeout(shutdown_timeout, &mut front_handle)
+    if tokio::time::timeout_at(grace_deadline, &mut front_handle)
         .await
         .is_err()
     {
@@ -217,6 +307,19 @@ pub async fn run_server(log_dir: Option<String>, daemon: bool) -> Result<()> {
         front_handle.abort();
     }
 
+    shutdown_state.front_drained();
+    if porter_running
+        && tokio::time::timeout_at(grace_deadline, &mut porter_handle)
+            .await
+            .is_err()
+    {
+        event!(
+            tracing::Level::WARN,
+            "Porter did not drain the order queue in time, aborting"
+        );
+        porter_handle.abort();
+    }
+
     if tokio::time::timeout(shutdown_timeout, &mut cleanup_handle)
         .await
         .is_err()
@@ -228,9 +331,34 @@ pub async fn run_server(log_dir: Option<String>, daemon: bool) -> Result<()> {
         cleanup_handle.abort();
     }
 
+    crate::telemetry::flush().await;
     Ok(())
 }
 
+/// Ask the running server to re-read its credentials
+pub fn handle_reload() -> Result<()> {
+    let Some(pid) = running_pid()? else {
+        return Err(err_msg("No running LiNaStore server found"));
+    };
+    reload_process(pid)?;
+    println!("Asked LiNaStore server (PID: {}) to reload credentials", pid);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn reload_process(pid: i32) -> Result<()> {
+    if unsafe { libc::kill(pid, libc::SIGHUP) } != 0 {
+        let err = std::io::Error::last_os_error();
+        return Err(err_msg(format!("Failed to signal server: {}", err)));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn reload_process(_pid: i32) -> Result<()> {
+    Err(err_msg("Reloading a running server needs SIGHUP, which this platform lacks"))
+}
+
 /// Handle stop command
 pub fn handle_stop(force: bool) -> Result<()> {
     // Initialize basic logging for stop command
@@ -241,19 +369,11 @@ pub fn handle_stop(force: bool) -> Result<()> {
         .with_target(false)
         .init();
 
-    // Look for PID file
-    let pid_file = env::current_dir()
-        .context("Failed to get current directory")?
-        .join("linastore")
-        .join("linastore.pid");
-
-    if !pid_file.exists() {
-        eprintln!("No running LiNaStore server found (PID file not found)");
+    let pid_file = pid_file_path(&crate::vars::store_root()?);
+    let Some(pid) = running_pid()? else {
+        eprintln!("No running LiNaStore server found");
         return Ok(());
-    }
-
-    let pid_str = fs::read_to_string(&pid_file).context("Failed to read PID file")?;
-    let pid: i32 = pid_str.trim().parse().context("Invalid PID in file")?;
+    };
 
     event!(
         tracing::Level::INFO,
@@ -264,6 +384,16 @@ pub fn handle_stop(force: bool) -> Result<()> {
     // Signal the server process to terminate.
     stop_process(pid, force, &pid_file)?;
 
+    // SIGTERM starts a graceful shutdown; wait for it to finish draining
+    let grace = Duration::from_secs(crate::vars::EnvVar::get_instance().shutdown_grace_secs);
+    if !force && !wait_for_exit(pid, grace + Duration::from_secs(10)) {
+        eprintln!(
+            "LiNaStore server (PID: {}) is still shutting down; use --force to kill it",
+            pid
+        );
+        return Ok(());
+    }
+
     // Remove PID file
     fs::remove_file(&pid_file).ok();
 
@@ -276,6 +406,144 @@ pub fn handle_stop(force: bool) -> Result<()> {
     Ok(())
 }
 
+/// Handle the `user` subcommands against the configured database
+pub async fn handle_user_command(command: crate::UserCommands) -> Result<()> {
+    let env_vars = crate::vars::EnvVar::get_instance();
+    let db_conn = crate::db::get_db_connection(&env_vars.db_url).await?;
+
+    match command {
+        crate::UserCommands::Add {
+            username,
+            password,
+            role,
+        } => {
+            let password = match password {
+                Some(password) => password,
+                None => read_password()?,
+            };
+            crate::auth::add_user(&db_conn, &username, &password, role).await?;
+            println!("User '{}' added as {}", username, role.as_str());
+        }
+        crate::UserCommands::SetRole { username, role } => {
+            crate::auth::set_user_role(&db_conn, &username, role).await?;
+            println!("User '{}' is now {}", username, role.as_str());
+        }
+        crate::UserCommands::Grant { username, bucket } => {
+            crate::auth::grant_bucket(&db_conn, &username, &bucket).await?;
+            println!("User '{}' may reach bucket '{}'", username, bucket);
+        }
+        crate::UserCommands::Ungrant { username, bucket } => {
+            crate::auth::revoke_bucket(&db_conn, &username, &bucket).await?;
+            println!("User '{}' no longer holds bucket '{}'", username, bucket);
+        }
+        crate::UserCommands::Remove { username } => {
+            crate::auth::remove_user(&db_conn, &username).await?;
+            println!("User '{}' removed", username);
+        }
+        crate::UserCommands::List => {
+            for (username, role, created_at) in db_conn.auth_list_users().await? {
+                let grants = match db_conn.auth_get_user_id_by_username(&username).await? {
+                    Some(user_id) => db_conn.auth_list_grants(&user_id).await?,
+                    None => Vec::new(),
+                };
+                let buckets = if grants.is_empty() {
+                    "*".to_string()
+                } else {
+                    grants.join(",")
+                };
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    username,
+                    role,
+                    buckets,
+                    format_unix(created_at)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handle the `key` subcommands against the configured database
+pub async fn handle_key_command(command: crate::KeyCommands) -> Result<()> {
+    let env_vars = crate::vars::EnvVar::get_instance();
+    let db_conn = crate::db::get_db_connection(&env_vars.db_url).await?;
+
+    match command {
+        crate::KeyCommands::Create {
+            username,
+            name,
+            scope,
+        } => {
+            let (key_id, key) =
+                crate::auth::create_api_key(&db_conn, &username, &name, scope).await?;
+            eprintln!(
+                "Created {} key {} for '{}'; it is shown only once:",
+                scope.as_str(),
+                key_id,
+                username
+            );
+            println!("{}", key);
+        }
+        crate::KeyCommands::Revoke { id } => {
+            crate::auth::revoke_api_key(&db_conn, &id).await?;
+            println!("API key {} revoked", id);
+        }
+        crate::KeyCommands::List => {
+            for (key_id, username, name, scope, created_at) in db_conn.auth_list_api_keys().await? {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    key_id,
+                    username,
+                    scope,
+                    format_unix(created_at),
+                    name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+pub async fn handle_report(args: crate::ReportArgs) -> Result<()> {
+    if args.interval == 0 {
+        return Err(err_msg("--interval must be a positive number of seconds"));
+    }
+    let now = chrono::Utc::now().timestamp();
+    let mut options = crate::report::ReportOptions::new(now);
+    options.interval = args.interval;
+    if let Some(since) = args.since {
+        options.since = since;
+    }
+    let root = crate::vars::store_root()?;
+    let report = crate::report::build_offline(&root, options, now).await?;
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).context("Failed to encode report")?
+        );
+    } else {
+        print!("{}", crate::report::render_text(&report));
+    }
+    Ok(())
+}
+
+fn format_unix(secs: i64) -> String {
+    chrono::DateTime::from_timestamp(secs, 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// Read a password from the first line of stdin
+fn read_password() -> Result<String> {
+    eprint!("Password: ");
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .context("Failed to read password from stdin")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
 #[cfg(unix)]
 fn stop_process(pid: i32, force: bool, pid_file: &Path) -> Result<()> {
     let kill_result =
@@ -293,6 +561,24 @@ fn stop_process(pid: i32, force: bool, pid_file: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Poll until process `pid` is gone; false if it outlived `timeout`.
+#[cfg(unix)]
+fn wait_for_exit(pid: i32, timeout: Duration) -> bool {
+    let deadline = std::time::Instant::now() + timeout;
+    while unsafe { libc::kill(pid, 0) } == 0 {
+        if std::time::Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    true
+}
+
+#[cfg(not(unix))]
+fn wait_for_exit(_pid: i32, _timeout: Duration) -> bool {
+    true
+}
+
 #[cfg(not(unix))]
 fn stop_process(pid: i32, force: bool, pid_file: &Path) -> Result<()> {
     // On Windows we don't have POSIX signals. `taskkill` without /F asks the
@@ -321,19 +607,125 @@ fn stop_process(pid: i32, force: bool, pid_file: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Write PID file for process management
-pub fn write_pid_file() -> Result<()> {
-    let pid_dir = env::current_dir()
-        .context("Failed to get current directory")?
-        .join("linastore");
+/// `linadata/linastore.pid` under the store root
+fn pid_file_path(root: &Path) -> PathBuf {
+    root.join("linadata").join("linastore.pid")
+}
 
-    if !pid_dir.exists() {
-        fs::create_dir_all(&pid_dir).context("Failed to create linastore directory")?;
+/// The PID file of a running server. It stays locked for as long as the
+/// server runs, so a second server on the same store refuses to start, and
+/// is removed when the server exits gracefully.
+struct InstanceLock {
+    file: fs::File,
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// Lock the PID file of the store at `root`. A file left behind by a
+    /// server that crashed is not locked and is taken over.
+    fn acquire(root: &Path) -> Result<Self> {
+        let path = pid_file_path(root);
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open PID file {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { file, path }),
+            Err(fs::TryLockError::WouldBlock) => {
+                let pid = fs::read_to_string(&path).unwrap_or_default();
+                Err(err_msg(format!(
+                    "Another LiNaStore server (PID: {}) is already running on {}",
+                    pid.trim(),
+                    root.display()
+                )))
+            }
+            Err(fs::TryLockError::Error(e)) => Err(err_msg(format!(
+                "Failed to lock PID file {}: {}",
+                path.display(),
+                e
+            ))),
+        }
     }
 
-    let pid_file = pid_dir.join("linastore.pid");
-    let pid = std::process::id();
-    fs::write(&pid_file, pid.to_string()).context("Failed to write PID file")?;
+    /// Record this process's PID in the file.
+    fn write_pid(&mut self) -> Result<()> {
+        use std::io::{Seek, Write};
 
-    Ok(())
+        self.file.set_len(0).context("Failed to write PID file")?;
+        self.file.rewind().context("Failed to write PID file")?;
+        write!(self.file, "{}", std::process::id()).context("Failed to write PID file")?;
+        Ok(())
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Removed while still locked, so no other server can have taken it
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// The PID of the server running on the configured store, or `None` when
+/// there is none. A PID file nobody holds locked is stale and ignored.
+fn running_pid() -> Result<Option<i32>> {
+    let pid_file = pid_file_path(&crate::vars::store_root()?);
+    let file = match fs::File::open(&pid_file) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(err_msg(format!("Failed to open PID file: {}", e))),
+    };
+    if file.try_lock_shared().is_ok() {
+        return Ok(None);
+    }
+    let pid_str = fs::read_to_string(&pid_file).context("Failed to read PID file")?;
+    let pid = pid_str.trim().parse().context("Invalid PID in file")?;
+    Ok(Some(pid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_store_root_needs_init_for_new_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("store");
+
+        let err = open_store_root(&root, false).unwrap_err();
+        assert!(err.to_string().contains("--init"), "{}", err);
+        assert!(!root.exists());
+
+        open_store_root(&root, true).unwrap();
+        assert!(root.join("linadata").is_dir());
+
+        fs::write(root.join("linadata").join("meta.db"), b"").unwrap();
+        open_store_root(&root, false).unwrap();
+    }
+
+    #[test]
+    fn test_instance_lock_refuses_second_server() {
+        let dir = tempfile::tempdir().unwrap();
+        open_store_root(dir.path(), true).unwrap();
+
+        let mut first = InstanceLock::acquire(dir.path()).unwrap();
+        first.write_pid().unwrap();
+        let pid_file = pid_file_path(dir.path());
+        assert_eq!(
+            fs::read_to_string(&pid_file).unwrap(),
+            std::process::id().to_string()
+        );
+
+        let err = InstanceLock::acquire(dir.path()).err().unwrap();
+        assert!(err.to_string().contains("already running"), "{}", err);
+
+        drop(first);
+        assert!(!pid_file.exists());
+
+        // A file left behind by a crashed server does not block a new one
+        fs::write(&pid_file, b"12345").unwrap();
+        InstanceLock::acquire(dir.path()).unwrap();
+    }
 }
@@ -259,7 +259,7 @@ impl TestEnvironment {
             let lina_port = pick_unused_port()?;
 
             let mut child = Command::new(linastore_server_binary())
-                .args(["start", "--foreground"])
+                .args(["start", "--foreground", "--init"])
                 .env("LINASTORE_HTTP_PORT", http_port.to_string())
                 .env("LINASTORE_ADVANCED_PORT", lina_port.to_string())
                 .env("LINASTORE_AUTH_REQUIRED", "") // Disable authentication for tests
@@ -155,7 +155,7 @@ start_server() {
             LINASTORE_DB_URL="sqlite:$TEST_DB_PATH" \
             LINASTORE_HTTP_PORT="$try_http_port" \
             LINASTORE_ADVANCED_PORT="$try_server_port" \
-            ./target/debug/linastore-server start --foreground > /tmp/linastore_test.log 2>&1 &
+            ./target/debug/linastore-server start --foreground --init > /tmp/linastore_test.log 2>&1 &
         
         SERVER_PID=$!
         disown $SERVER_PID 2>/dev/null || true